@@ -27,7 +27,12 @@
       craneLib = (crane.mkLib pkgs).overrideToolchain rustToolchain;
 
       commonArgs = {
-        src = craneLib.cleanCargoSource ./.;
+        # The integration tests' fixture packages, responses and fake tools aren't Cargo sources
+        src = pkgs.lib.cleanSourceWith {
+          src = ./.;
+          filter = path: type: (pkgs.lib.hasInfix "/tests/fixtures" path) || (craneLib.filterCargoSources path type);
+          name = "source";
+        };
         strictDeps = true;
         pname = "nix-package-updater";
       };
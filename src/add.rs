@@ -1,11 +1,14 @@
 use std::collections::BTreeMap;
 use std::fs;
+use std::io::{self, IsTerminal};
 use std::path::PathBuf;
 use std::process::Command;
 
 use clap::Parser;
 use colored::Colorize;
 use etcetera::base_strategy::{BaseStrategy, choose_base_strategy};
+use nix_package_updater::clients::GitHubClient;
+use nix_package_updater::tools::Tools;
 use reqwest::blocking::Client;
 use reqwest::header::{ACCEPT, AUTHORIZATION, HeaderMap, HeaderValue, USER_AGENT};
 use rootcause::{Result, report};
@@ -71,6 +74,10 @@ struct Args {
     /// Always delegate to nix-init instead of generating a binary package.
     #[arg(long)]
     nix_init: bool,
+
+    /// Plain output without colors; also the default when NO_COLOR is set or stderr isn't a terminal.
+    #[arg(long)]
+    no_color: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -106,6 +113,14 @@ struct PlatformAsset {
 
 fn main() -> Result<()> {
     let args = Args::parse();
+
+    // Messages go to stderr; `colored` itself already honors NO_COLOR
+    if args.no_color || !io::stderr().is_terminal() {
+        colored::control::set_override(false);
+    }
+
+    load_shared_config()?;
+
     let repo = parse_github_url(&args.url);
     let pname = args
         .pname
@@ -175,13 +190,42 @@ fn github_client() -> Result<Client> {
     headers.insert(USER_AGENT, HeaderValue::from_static("nix-package-add"));
     headers.insert(ACCEPT, HeaderValue::from_static("application/vnd.github+json"));
 
-    if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+    if let Some(token) = github_token() {
         headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {token}"))?);
     }
 
     Ok(Client::builder().default_headers(headers).build()?)
 }
 
+/// The token nix-package-updater would use, in the same order, see [`GitHubClient::token`]
+fn github_token() -> Option<String> {
+    GitHubClient::token().map(|(token, _)| token)
+}
+
+/// What nix-package-add shares with nix-package-updater's config.toml: the GitHub token and where tools like `gh` are
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SharedConfig {
+    github_token: Option<String>,
+    tools: Tools,
+}
+
+/// Read the shared settings from nix-package-updater's config.toml, if there is one
+fn load_shared_config() -> Result<()> {
+    let strategy = choose_base_strategy().expect("Unable to find base strategy");
+    let path = strategy.config_dir().join("nix-updater").join("config.toml");
+
+    let config = match fs::read_to_string(&path) {
+        Ok(content) => toml::from_str::<SharedConfig>(&content).map_err(|e| report!("Invalid {}: {e}", path.display()))?,
+        Err(_) => SharedConfig::default(),
+    };
+
+    config.tools.install();
+    GitHubClient::configure(config.github_token);
+
+    Ok(())
+}
+
 fn latest_release(client: &Client, repo: &GitHubRepo) -> Result<GitHubRelease> {
     let url = format!("https://api.github.com/repos/{}/{}/releases/latest", repo.owner, repo.repo);
     Ok(client.get(url).send()?.error_for_status()?.json()?)
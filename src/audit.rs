@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use rootcause::{Result, bail};
+use walkdir::WalkDir;
+
+use nix_package_updater::exclude::{Exclusions, IGNORE_FILE};
+use nix_package_updater::nix::ast::{Ast, Origin};
+use nix_package_updater::package::{Package, PackageKind};
+
+/// Attributes the updaters read or rewrite
+const ATTRIBUTES: [&str; 15] = [
+    "pname",
+    "version",
+    "homepage",
+    "url",
+    "owner",
+    "repo",
+    "rev",
+    "tag",
+    "hash",
+    "cargoHash",
+    "vendorHash",
+    "npmDepsHash",
+    "fetchSubmodules",
+    "leaveDotGit",
+    "deepClone",
+];
+
+/// Find a package file by path, or by `pname` under the package directories.
+///
+/// Also returns the package's scope when the file defines several.
+pub fn find_package_file(name: &str) -> Result<(PathBuf, Option<usize>)> {
+    let path = Path::new(name);
+
+    if path.extension().is_some_and(|ext| ext == "nix") && path.is_file() {
+        return Ok((path.to_path_buf(), None));
+    }
+
+    for root in ["packages/", "nix/packages/"] {
+        for entry in WalkDir::new(root)
+            .into_iter()
+            .filter_map(std::result::Result::ok)
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "nix") && e.file_type().is_file())
+        {
+            let Ok(content) = std::fs::read_to_string(entry.path()) else {
+                continue;
+            };
+
+            let parsed = rnix::Root::parse(&content);
+
+            let scopes = match Ast::from_ast(parsed.clone()).package_count() {
+                0 | 1 => vec![None],
+                count => (0..count).map(Some).collect(),
+            };
+
+            if let Some(scope) = scopes
+                .into_iter()
+                .find(|&scope| Ast::from_ast(parsed.clone()).scoped(scope).get("pname").as_deref() == Some(name))
+            {
+                return Ok((entry.into_path(), scope));
+            }
+        }
+    }
+
+    bail!("No package file with pname '{name}' under packages/ or nix/packages/")
+}
+
+/// Print what the updater can see and change in a package file, and whether discovery would pick it up
+pub fn print_audit(path: &Path, scope: Option<usize>) -> Result<()> {
+    let ast = Ast::from_ast(rnix::Root::parse(&std::fs::read_to_string(path)?)).scoped(scope);
+    let kind = Package::detect_package_kind(&ast.node(), &ast.node().text().to_string());
+
+    println!("{} {} ({})\n", "Auditing".bright_white().bold(), path.display(), kind.to_string().magenta());
+
+    println!("{:<16} {:<13} Value", "Attribute".bright_white().bold(), "Written as".bright_white().bold());
+    println!("{}", "-".repeat(60));
+
+    for name in ATTRIBUTES {
+        if let Some(origin) = ast.origin(name) {
+            println!("{name:<16} {:<13} {}", origin.to_string(), ast.get(name).unwrap_or_else(|| "-".to_string()));
+        }
+    }
+
+    println!("\n{}", "Discovery".bright_white().bold());
+
+    let mut problems = discovery_problems(&ast);
+
+    let ignored = Exclusions::load(&[], Path::new(IGNORE_FILE))?;
+
+    if ignored.excludes_file(path) || ast.get("pname").is_some_and(|pname| ignored.excludes_name(&pname)) {
+        problems.push(format!("excluded by {IGNORE_FILE}"));
+    }
+
+    if problems.is_empty() {
+        println!("  {} picked up by the updater", "✓".green());
+    } else {
+        for problem in problems {
+            println!("  {} {problem}", "✗".red());
+        }
+    }
+
+    println!("\n{}", "Updates".bright_white().bold());
+
+    for (supported, operation) in operations(&ast, kind) {
+        let mark = if supported { "✓".green() } else { "-".yellow() };
+        println!("  {mark} {operation}");
+    }
+
+    Ok(())
+}
+
+/// Reasons `Package::discover` would skip this file
+fn discovery_problems(ast: &Ast) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if ast.get("pname").is_none() {
+        problems.push("no `pname` attribute".to_string());
+    }
+
+    match ast.get("homepage") {
+        None => problems.push("no `homepage` attribute".to_string()),
+        Some(homepage) if git_url_parse::GitUrl::parse(&homepage).is_err() => problems.push(format!("homepage '{homepage}' is not a repository URL")),
+        Some(_) => {}
+    }
+
+    if ast.get("version").is_none() {
+        problems.push("no `version` attribute".to_string());
+    }
+
+    if ast.has_local_src() {
+        problems.push("local source (`src = ./...`) has no upstream to track".to_string());
+    }
+
+    if !Package::supported_on_current_platform(ast) {
+        problems.push("not supported on this platform (meta.platforms)".to_string());
+    }
+
+    problems
+}
+
+/// Which update steps can be applied, given how the attributes are written
+fn operations(ast: &Ast, kind: PackageKind) -> Vec<(bool, String)> {
+    let mut operations = Vec::new();
+
+    operations.push(match ast.origin("version") {
+        Some(Origin::Literal | Origin::LetBound) => (true, "bump `version`".to_string()),
+        Some(Origin::Reference) if ast.resolves("version") => (true, "bump `version` where it is bound".to_string()),
+        Some(Origin::Interpolated) if ast.get("version").is_some_and(|version| !version.contains("${")) => (true, "bump `version`, keeping its interpolations".to_string()),
+        Some(origin) => (false, format!("bump `version`: written as {origin} that can't be resolved")),
+        None => (false, "bump `version`: not found".to_string()),
+    });
+
+    match ast.origin("rev") {
+        Some(Origin::Interpolated) => operations.push((true, "`rev` follows `version`".to_string())),
+        Some(Origin::Literal | Origin::LetBound) => operations.push((true, "update `rev` to the latest commit".to_string())),
+        Some(Origin::Reference) if ast.resolves("rev") => operations.push((true, "update `rev` where it is bound".to_string())),
+        Some(origin) => operations.push((false, format!("update `rev`: written as {origin}"))),
+        None => {}
+    }
+
+    match ast.origin("hash") {
+        Some(Origin::Literal | Origin::LetBound) => operations.push((true, "recompute source `hash`".to_string())),
+        Some(origin) => operations.push((false, format!("recompute source `hash`: written as {origin}"))),
+        None if kind == PackageKind::Git => operations.push((true, "no source `hash` (fetchGit)".to_string())),
+        None => operations.push((false, "recompute source `hash`: not found".to_string())),
+    }
+
+    for vendor in ["cargoHash", "vendorHash", "npmDepsHash"] {
+        if ast.origin(vendor).is_some() {
+            operations.push((true, format!("rebuild to recompute `{vendor}`")));
+        }
+    }
+
+    let platforms = ast.platforms();
+
+    if !platforms.is_empty() {
+        operations.push((true, format!("refresh hashes for {} platform block(s)", platforms.len())));
+    }
+
+    let flags = ast.fetch_flags();
+
+    if flags.leave_dot_git || flags.deep_clone {
+        operations.push((false, "leaveDotGit/deepClone hashes may not reproduce".to_string()));
+    }
+
+    operations
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::{env, fs, process};
+
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use itertools::Itertools;
+use rootcause::{Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::package::Package;
+
+/// How many runs' backups are kept; older ones are removed when a run starts
+const KEEP_RUNS: usize = 20;
+
+/// Held while a backup file is read, added to and written back, which packages committing at once would race on
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// A file as it was before a run changed it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Original {
+    /// Relative to the repository root, so a run in a since removed worktree reverts in the checkout
+    path: PathBuf,
+    /// `None` when the run created the file
+    content: Option<String>,
+    saved_at: DateTime<Utc>,
+}
+
+/// Where a run keeps the original content of the files it changes, `<data dir>/backups/<run id>/`, one JSON file per
+/// package and file it's defined in, for `revert`
+pub struct RunBackup {
+    dir: PathBuf,
+    /// The repository the run changes files in
+    root: PathBuf,
+}
+
+impl RunBackup {
+    /// Backups for a run starting now, removing all but the most recent runs' backups under `root`
+    pub fn start(root: &Path) -> Self {
+        for old in runs(root).into_iter().skip(KEEP_RUNS - 1) {
+            let _ = fs::remove_dir_all(old);
+        }
+
+        // Runs in the same second, e.g. a `--watch` round right after a manual run, each get their own
+        let run = format!("{}-{}", Utc::now().format("%Y%m%dT%H%M%S%.6f"), process::id());
+
+        Self {
+            dir: root.join(run),
+            root: repository_root(),
+        }
+    }
+
+    /// Back up the files each package's transaction commits
+    pub fn cover(&self, packages: &mut [Package]) {
+        for package in packages {
+            package.transaction.backup = Some(self.package(&package.name, &package.path));
+        }
+    }
+
+    /// Where this run backs up the files of `package`, defined in `path`. Packages of the same name in other files, or
+    /// package sets, have backups of their own.
+    pub fn package(&self, package: &str, path: &Path) -> Backup {
+        let path = format!("{:x}", Sha256::digest(path.to_string_lossy().as_bytes()));
+
+        Backup {
+            file: self.dir.join(format!("{}-{}.json", package.replace('/', "_"), &path[..PATH_DIGEST])),
+            root: self.root.clone(),
+        }
+    }
+}
+
+/// How many hex digits of the package file's path digest a backup's name ends with
+const PATH_DIGEST: usize = 12;
+
+/// The package a backup file named `stem` is of, without the digest of the file it's defined in
+fn backup_package(stem: &str) -> &str {
+    match stem.rsplit_once('-') {
+        Some((package, digest)) if digest.len() == PATH_DIGEST && digest.bytes().all(|byte| byte.is_ascii_hexdigit()) => package,
+        _ => stem,
+    }
+}
+
+/// The root of the repository in the current directory, the closest one with a `.git` or `.jj`, or else the current
+/// directory itself
+fn repository_root() -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_default();
+
+    cwd.ancestors()
+        .find(|dir| dir.join(".git").exists() || dir.join(".jj").exists())
+        .map_or_else(|| cwd.clone(), Path::to_path_buf)
+}
+
+/// One package's backed up files within a run
+#[derive(Clone, Debug)]
+pub struct Backup {
+    file: PathBuf,
+    root: PathBuf,
+}
+
+impl Backup {
+    /// Save files' content before they are overwritten, `None` for files that don't exist yet. A file saved earlier in
+    /// the run keeps its first, pre-run, content.
+    pub fn save(&self, originals: &BTreeMap<PathBuf, Option<String>>) -> Result<()> {
+        let _saving = SAVING.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let mut saved = self.load();
+        let now = Utc::now();
+
+        for (path, content) in originals {
+            let path = std::path::absolute(path)?;
+            let path = path.strip_prefix(&self.root).map_or_else(|_| path.clone(), Path::to_path_buf);
+
+            if !saved.iter().any(|entry| entry.path == path) {
+                saved.push(Original {
+                    path,
+                    content: content.clone(),
+                    saved_at: now,
+                });
+            }
+        }
+
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.file, serde_json::to_string_pretty(&saved)?)?;
+
+        Ok(())
+    }
+
+    /// Forget the backup, for edits that were rolled back
+    pub fn discard(&self) {
+        let _ = fs::remove_file(&self.file);
+    }
+
+    fn load(&self) -> Vec<Original> {
+        fs::read_to_string(&self.file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// A file `revert` put back
+#[derive(Debug)]
+pub struct Restored {
+    pub package: String,
+    pub run: String,
+    pub path: PathBuf,
+    pub removed: bool,
+}
+
+/// Run directories under `root`, newest first
+fn runs(root: &Path) -> Vec<PathBuf> {
+    fs::read_dir(root)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).filter(|path| path.is_dir()).collect_vec())
+        .unwrap_or_default()
+        .into_iter()
+        .sorted()
+        .rev()
+        .collect()
+}
+
+/// Restore the files the most recent run with backups changed, or only those of `package` from the most recent run
+/// that changed it. Each backup is used once, so reverting again goes back another run.
+pub fn revert(root: &Path, package: Option<&str>) -> Result<Vec<Restored>> {
+    let chosen = runs(root).into_iter().find_map(|run| {
+        let backups = fs::read_dir(&run)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .filter(|path| package.is_none_or(|name| path.file_stem().is_some_and(|stem| backup_package(&stem.to_string_lossy()) == name.replace('/', "_"))))
+            .collect_vec();
+
+        (!backups.is_empty()).then_some((run, backups))
+    });
+
+    let Some((run, backups)) = chosen else {
+        match package {
+            Some(name) => bail!("No backups of {name} to revert"),
+            None => bail!("No backups to revert"),
+        }
+    };
+
+    let run_id = run.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    let mut saved = Vec::new();
+
+    for backup in &backups {
+        let name = backup.file_stem().map(|stem| backup_package(&stem.to_string_lossy()).to_string()).unwrap_or_default();
+        let entries: Vec<Original> = serde_json::from_str(&fs::read_to_string(backup)?)?;
+
+        saved.extend(entries.into_iter().map(|entry| (name.clone(), entry)));
+    }
+
+    // Newest first, so a file several packages changed ends up with the content from before the first of them
+    saved.sort_by_key(|(_, original)| std::cmp::Reverse(original.saved_at));
+
+    let root = repository_root();
+    let mut restored = Vec::new();
+
+    for (name, mut entry) in saved {
+        entry.path = root.join(&entry.path);
+
+        match &entry.content {
+            Some(content) => fs::write(&entry.path, content)?,
+            None if entry.path.exists() => fs::remove_file(&entry.path)?,
+            None => {}
+        }
+
+        restored.push(Restored {
+            package: name,
+            run: run_id.clone(),
+            removed: entry.content.is_none(),
+            path: entry.path,
+        });
+    }
+
+    for backup in backups {
+        fs::remove_file(backup)?;
+    }
+
+    let _ = fs::remove_dir(&run);
+
+    Ok(restored)
+}
+
+/// What `revert` put back, with paths relative to the current directory where they can be
+pub fn print_restored(restored: &[Restored]) {
+    let cwd = std::env::current_dir().unwrap_or_default();
+
+    for entry in restored {
+        let path = entry.path.strip_prefix(&cwd).unwrap_or(&entry.path).display();
+        let action = if entry.removed { "Removed" } else { "Restored" };
+
+        println!("{} {path} {}", action.green(), format!("({}, run {})", entry.package, entry.run).dimmed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use super::{RunBackup, backup_package, revert};
+
+    #[test]
+    fn reverts_the_latest_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let file = root.join("foo.nix");
+        let created = root.join("foo.lock");
+
+        fs::write(&file, "new").unwrap();
+        fs::write(&created, "lock").unwrap();
+
+        let backup = RunBackup::start(&root.join("runs")).package("foo", &file);
+        backup.save(&BTreeMap::from([(file.clone(), Some("old".to_string())), (created.clone(), None)])).unwrap();
+        backup.save(&BTreeMap::from([(file.clone(), Some("intermediate".to_string()))])).unwrap();
+
+        assert!(revert(&root.join("runs"), Some("bar")).is_err());
+
+        let restored = revert(&root.join("runs"), Some("foo")).unwrap();
+
+        assert_eq!(restored.len(), 2);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "old");
+        assert!(!created.exists());
+        assert!(revert(&root.join("runs"), None).is_err());
+    }
+
+    #[test]
+    fn keeps_same_named_packages_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let (first, second) = (root.join("a.nix"), root.join("b.nix"));
+
+        fs::write(&first, "new a").unwrap();
+        fs::write(&second, "new b").unwrap();
+
+        let run = RunBackup::start(&root.join("runs"));
+        run.package("foo", &first).save(&BTreeMap::from([(first.clone(), Some("old a".to_string()))])).unwrap();
+        run.package("foo", &second).save(&BTreeMap::from([(second.clone(), Some("old b".to_string()))])).unwrap();
+
+        assert_ne!(run.dir, RunBackup::start(&root.join("runs")).dir);
+
+        let restored = revert(&root.join("runs"), Some("foo")).unwrap();
+
+        assert_eq!(restored.len(), 2);
+        assert!(restored.iter().all(|entry| entry.package == "foo"));
+        assert_eq!(fs::read_to_string(&first).unwrap(), "old a");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "old b");
+    }
+
+    #[test]
+    fn names_backups_after_their_package() {
+        assert_eq!(backup_package("foo-0123456789ab"), "foo");
+        assert_eq!(backup_package("python3Packages.foo-bar-0123456789ab"), "python3Packages.foo-bar");
+        assert_eq!(backup_package("foo-bar"), "foo-bar");
+    }
+}
@@ -0,0 +1,453 @@
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+use chrono::Local;
+use rootcause::{Result, bail};
+use tracing::warn;
+use walkdir::WalkDir;
+
+use nix_package_updater::hooks::vcs;
+use nix_package_updater::tools::{Tool, command, run};
+
+/// Where the run keeps its build logs, which never go into the branch
+const BUILD_LOGS: &str = "build-results";
+
+/// The provenance log among the build logs, a hash chain each run appends to
+const PROVENANCE: &str = "provenance.jsonl";
+
+/// What a `--branch` run needs from the repository's version control: git, or Jujutsu when there's a `.jj`
+trait Vcs {
+    /// The branch the working copy is on (in jj, the bookmark on its parent), `None` when there's none to return to
+    fn current(&self) -> Result<Option<String>>;
+
+    /// Whether the branch exists already, from a previous run
+    fn exists(&self, name: &str) -> bool;
+
+    /// Whether anything but the build logs changed in the working copy
+    fn has_changes(&self) -> Result<bool>;
+
+    /// Move the working copy onto `name`, a new branch off the current commit unless it `exists`
+    fn switch(&self, name: &str, exists: bool) -> Result<()>;
+
+    /// Check `name` out in a new worktree (jj workspace) at `dir`
+    fn add_worktree(&self, dir: &Path, name: &str, exists: bool) -> Result<()>;
+
+    /// Remove the worktree at `dir`, build logs and all
+    fn remove_worktree(&self, dir: &Path) -> Result<()>;
+
+    /// Commit everything but the build logs to `name` with `message`, returning whether there was anything to commit
+    fn commit(&self, name: &str, message: &str) -> Result<bool>;
+
+    /// Whether `name` has commits `base` doesn't
+    fn has_commits(&self, base: &str, name: &str) -> Result<bool>;
+
+    /// Push `name` to `origin`, returning the remote's URL
+    fn push(&self, name: &str) -> Result<String>;
+}
+
+/// The repository in the current directory's version control
+fn detect() -> Box<dyn Vcs> {
+    match vcs() {
+        Some("jj") => Box::new(Jujutsu),
+        _ => Box::new(Git),
+    }
+}
+
+/// Where a `--branch` run makes its edits: the branch checked out in place, or with `--worktree` in a temporary
+/// worktree the run moves into, leaving the current checkout alone. Dropping it goes back to where the run started.
+pub struct UpdateBranch {
+    pub name: String,
+    /// The branch checked out when the run started, which the updates are for
+    pub base: Option<String>,
+    vcs: Box<dyn Vcs>,
+    /// The branch to switch back to after an in-place checkout
+    original: Option<String>,
+    /// The temporary worktree, and the directory the run started in
+    worktree: Option<(PathBuf, PathBuf)>,
+}
+
+/// Run `tool` with `args`, failing with its stderr, and return its trimmed stdout
+fn output(tool: Tool, args: &[&str]) -> Result<String> {
+    let output = run(command(tool).args(args), tool)?;
+
+    if !output.status.success() {
+        bail!("{tool} {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn git(args: &[&str]) -> Result<String> {
+    output(Tool::Git, args)
+}
+
+fn jj(args: &[&str]) -> Result<String> {
+    output(Tool::Jj, args)
+}
+
+/// The branch name with `{date}` replaced by today's date
+fn branch_name(name: &str) -> String {
+    name.replace("{date}", &Local::now().format("%Y-%m-%d").to_string())
+}
+
+impl UpdateBranch {
+    /// Check out `name` (`{date}` standing for today's date), creating it from the current commit unless a previous run
+    /// did. In place, the working tree has to be clean so nothing unrelated ends up in the update commit.
+    pub fn start(name: &str, worktree: bool) -> Result<Self> {
+        let vcs = detect();
+        let name = branch_name(name);
+        let exists = vcs.exists(&name);
+        let base = vcs.current()?;
+
+        if worktree {
+            // Unique to the run, so one that was kept with uncommitted changes doesn't stand in the next one's way
+            let dir = env::temp_dir().join(format!("nix-package-updater-{}-{}", name.replace('/', "-"), process::id()));
+
+            vcs.add_worktree(&dir, &name, exists)?;
+
+            let started_in = env::current_dir()?;
+
+            // The run's provenance entries chain on from the checkout's
+            let provenance = started_in.join(BUILD_LOGS).join(PROVENANCE);
+
+            if provenance.is_file() {
+                fs::create_dir_all(dir.join(BUILD_LOGS))?;
+                fs::copy(&provenance, dir.join(BUILD_LOGS).join(PROVENANCE))?;
+            }
+
+            env::set_current_dir(&dir)?;
+
+            return Ok(Self {
+                name,
+                base,
+                vcs,
+                original: None,
+                worktree: Some((dir, started_in)),
+            });
+        }
+
+        if vcs.has_changes()? {
+            bail!("The working tree has uncommitted changes: commit or stash them first, or use --worktree");
+        }
+
+        let Some(original) = base.clone() else {
+            bail!("There's no branch checked out to return to after the run: use --worktree");
+        };
+
+        vcs.switch(&name, exists)?;
+
+        Ok(Self {
+            name,
+            base,
+            vcs,
+            original: Some(original),
+            worktree: None,
+        })
+    }
+
+    /// Commit everything the run changed, except its build logs, to the branch. Returns whether there was anything to
+    /// commit; hooks may have committed each update already.
+    pub fn commit(&self, title: &str, body: &str) -> Result<bool> {
+        let message = if body.is_empty() { title.to_string() } else { format!("{title}\n\n{body}") };
+
+        self.vcs.commit(&self.name, &message)
+    }
+
+    /// Whether the branch has commits its base doesn't, from this run or an earlier one
+    pub fn has_commits(&self) -> Result<bool> {
+        let Some(base) = &self.base else {
+            return Ok(true);
+        };
+
+        self.vcs.has_commits(base, &self.name)
+    }
+
+    /// Push the branch to `origin`, returning the remote's URL
+    pub fn push(&self) -> Result<String> {
+        self.vcs.push(&self.name)
+    }
+}
+
+impl Drop for UpdateBranch {
+    fn drop(&mut self) {
+        // Anything left uncommitted (a run that failed halfway) stays where it is rather than being lost or carried over
+        let dirty = self.vcs.has_changes().unwrap_or(true);
+
+        if let Some((dir, started_in)) = &self.worktree {
+            let _ = env::set_current_dir(started_in);
+
+            // The build logs and provenance go back to the checkout the run started in, with the worktree or without
+            if let Err(e) = copy_logs(&dir.join(BUILD_LOGS), &started_in.join(BUILD_LOGS)) {
+                warn!("Failed to copy the build logs out of the worktree {}: {e}", dir.display());
+            }
+
+            if dirty {
+                warn!("Keeping the worktree {}: it has uncommitted changes", dir.display());
+            } else if let Err(e) = self.vcs.remove_worktree(dir) {
+                warn!("Failed to remove the worktree {}: {e}", dir.display());
+            }
+        }
+
+        if let Some(original) = &self.original {
+            if dirty {
+                warn!("Staying on {}: it has uncommitted changes", self.name);
+            } else if let Err(e) = self.vcs.switch(original, true) {
+                warn!("Failed to switch back to {original}: {e}");
+            }
+        }
+    }
+}
+
+/// Copy the build logs in `from` over those in `to`
+fn copy_logs(from: &Path, to: &Path) -> Result<()> {
+    if !from.is_dir() {
+        return Ok(());
+    }
+
+    for entry in WalkDir::new(from) {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+struct Git;
+
+/// Excludes the run's build logs from git pathspecs
+fn git_exclude_logs() -> String {
+    format!(":(exclude){BUILD_LOGS}")
+}
+
+impl Vcs for Git {
+    fn current(&self) -> Result<Option<String>> {
+        Ok(Some(git(&["branch", "--show-current"])?).filter(|current| !current.is_empty()))
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        git(&["branch", "--list", name]).is_ok_and(|listed| !listed.is_empty())
+    }
+
+    fn has_changes(&self) -> Result<bool> {
+        Ok(!git(&["status", "--porcelain", "--", ".", &git_exclude_logs()])?.is_empty())
+    }
+
+    fn switch(&self, name: &str, exists: bool) -> Result<()> {
+        if exists {
+            git(&["switch", name])?;
+        } else {
+            git(&["switch", "-c", name])?;
+        }
+
+        Ok(())
+    }
+
+    fn add_worktree(&self, dir: &Path, name: &str, exists: bool) -> Result<()> {
+        let path = dir.to_string_lossy();
+
+        if exists {
+            git(&["worktree", "add", &path, name])?;
+        } else {
+            git(&["worktree", "add", "-b", name, &path])?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_worktree(&self, dir: &Path) -> Result<()> {
+        // Only the untracked build logs are left by now, which a plain remove refuses to delete
+        git(&["worktree", "remove", "--force", &dir.to_string_lossy()])?;
+
+        Ok(())
+    }
+
+    fn commit(&self, name: &str, message: &str) -> Result<bool> {
+        // A hook could have switched branches under the run
+        let current = git(&["branch", "--show-current"])?;
+
+        if current != name {
+            bail!("Expected {name} to be checked out, not {current}");
+        }
+
+        git(&["add", "--all", "--", ".", &git_exclude_logs()])?;
+
+        if git(&["diff", "--cached", "--name-only"])?.is_empty() {
+            return Ok(false);
+        }
+
+        git(&["commit", "--quiet", "-m", message])?;
+
+        Ok(true)
+    }
+
+    fn has_commits(&self, base: &str, name: &str) -> Result<bool> {
+        Ok(git(&["rev-list", "--count", &format!("{base}..{name}")])? != "0")
+    }
+
+    fn push(&self, name: &str) -> Result<String> {
+        git(&["push", "--quiet", "--set-upstream", "origin", name])?;
+        git(&["remote", "get-url", "origin"])
+    }
+}
+
+/// A Jujutsu repository, colocated with git or not. The working copy is a change on top of the branch's bookmark,
+/// which follows each commit.
+struct Jujutsu;
+
+impl Vcs for Jujutsu {
+    fn current(&self) -> Result<Option<String>> {
+        let bookmarks = jj(&["log", "--no-graph", "-r", "@-", "-T", "local_bookmarks.map(|b| b.name()).join(\"\\n\")"])?;
+
+        Ok(bookmarks.lines().next().map(String::from))
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        jj(&["bookmark", "list", name]).is_ok_and(|listed| !listed.is_empty())
+    }
+
+    fn has_changes(&self) -> Result<bool> {
+        let changed = jj(&["diff", "--name-only"])?;
+
+        Ok(changed.lines().any(|path| !Path::new(path).starts_with(BUILD_LOGS)))
+    }
+
+    fn switch(&self, name: &str, exists: bool) -> Result<()> {
+        // A new bookmark is only created by the first commit; until then the run works on top of the current one
+        jj(&["new", &jj_revision(name, exists)])?;
+
+        Ok(())
+    }
+
+    fn add_worktree(&self, dir: &Path, name: &str, exists: bool) -> Result<()> {
+        jj(&["workspace", "add", "--name", &workspace_name(dir), "-r", &jj_revision(name, exists), &dir.to_string_lossy()])?;
+
+        Ok(())
+    }
+
+    fn remove_worktree(&self, dir: &Path) -> Result<()> {
+        jj(&["workspace", "forget", &workspace_name(dir)])?;
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    fn commit(&self, name: &str, message: &str) -> Result<bool> {
+        if !self.has_changes()? {
+            return Ok(false);
+        }
+
+        // `jj commit` describes the working-copy change and starts a new one on top, like `jj describe` and `jj new`,
+        // but leaves the build logs behind in the new one
+        jj(&["commit", "-m", message, &jj_without_logs()])?;
+        jj(&jj_bookmark(name, self.exists(name)))?;
+
+        Ok(true)
+    }
+
+    fn has_commits(&self, base: &str, name: &str) -> Result<bool> {
+        if !self.exists(name) {
+            return Ok(false);
+        }
+
+        Ok(!jj(&["log", "--no-graph", "-r", &jj_range(base, name), "-T", "commit_id ++ \"\\n\""])?.is_empty())
+    }
+
+    fn push(&self, name: &str) -> Result<String> {
+        // Pushing a new bookmark takes tracking it on the remote first; `--allow-new` did that, but is deprecated
+        jj(&["bookmark", "track", &format!("{name}@origin")])?;
+        jj(&["git", "push", "--remote", "origin", "--bookmark", name])?;
+
+        let remotes = jj(&["git", "remote", "list"])?;
+
+        match remotes.lines().find_map(|line| line.strip_prefix("origin ")) {
+            Some(url) => Ok(url.trim().to_string()),
+            None => bail!("No origin remote to push {name} to"),
+        }
+    }
+}
+
+/// A jj revset or fileset string literal, so a bookmark like `updates/2026-10-17` is taken as a name, not an expression
+fn jj_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The revision to work on top of: the branch's bookmark when it `exists`, otherwise the current one's commit
+fn jj_revision(name: &str, exists: bool) -> String {
+    if exists { jj_string(name) } else { "@-".to_string() }
+}
+
+/// The commits on `name` that `base` doesn't have
+fn jj_range(base: &str, name: &str) -> String {
+    format!("{}..{}", jj_string(base), jj_string(name))
+}
+
+/// Every path but the run's build logs
+fn jj_without_logs() -> String {
+    format!("~root:{}", jj_string(BUILD_LOGS))
+}
+
+/// Point `name` at the commit just made, creating it unless it `exists`
+fn jj_bookmark(name: &str, exists: bool) -> [&str; 5] {
+    ["bookmark", if exists { "set" } else { "create" }, name, "-r", "@-"]
+}
+
+/// The jj workspace for a worktree directory, named after it
+fn workspace_name(dir: &Path) -> String {
+    dir.file_name()
+        .map_or_else(|| "nix-package-updater".to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::Local;
+
+    use super::{branch_name, copy_logs, jj_bookmark, jj_range, jj_revision, jj_without_logs};
+
+    #[test]
+    fn dates_branch_names() {
+        assert_eq!(branch_name("updates/{date}"), format!("updates/{}", Local::now().format("%Y-%m-%d")));
+        assert_eq!(branch_name("nix-updates"), "nix-updates");
+    }
+
+    #[test]
+    fn builds_jj_revsets_and_filesets() {
+        assert_eq!(jj_range("main", "updates/2026-10-17"), r#""main".."updates/2026-10-17""#);
+        assert_eq!(jj_range("main", r#"odd"name"#), r#""main".."odd\"name""#);
+        assert_eq!(jj_revision("nix-updates", true), r#""nix-updates""#);
+        assert_eq!(jj_revision("nix-updates", false), "@-");
+        assert_eq!(jj_without_logs(), r#"~root:"build-results""#);
+    }
+
+    #[test]
+    fn creates_or_moves_jj_bookmarks() {
+        assert_eq!(jj_bookmark("nix-updates", false), ["bookmark", "create", "nix-updates", "-r", "@-"]);
+        assert_eq!(jj_bookmark("nix-updates", true), ["bookmark", "set", "nix-updates", "-r", "@-"]);
+    }
+
+    #[test]
+    fn copies_build_logs_over_the_checkouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let (from, to) = (root.join("worktree"), root.join("checkout"));
+
+        fs::create_dir_all(from.join("x86_64-linux")).unwrap();
+        fs::create_dir_all(&to).unwrap();
+        fs::write(from.join("provenance.jsonl"), "{}\n{}\n").unwrap();
+        fs::write(from.join("x86_64-linux").join("tool.log"), "built").unwrap();
+        fs::write(to.join("provenance.jsonl"), "{}\n").unwrap();
+
+        copy_logs(&from, &to).unwrap();
+        copy_logs(&root.join("missing"), &to).unwrap();
+
+        assert_eq!(fs::read_to_string(to.join("provenance.jsonl")).unwrap(), "{}\n{}\n");
+        assert_eq!(fs::read_to_string(to.join("x86_64-linux").join("tool.log")).unwrap(), "built");
+    }
+}
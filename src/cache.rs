@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use rootcause::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    url: String,
+    etag: Option<String>,
+    fetched_at: DateTime<Utc>,
+}
+
+/// A cached response body
+#[derive(Debug)]
+pub struct CachedResponse {
+    pub etag: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+    pub body: Vec<u8>,
+}
+
+impl CachedResponse {
+    /// Whether the entry is young enough to use without revalidating
+    pub fn is_fresh(&self, ttl: TimeDelta, now: DateTime<Utc>) -> bool {
+        now - self.fetched_at < ttl
+    }
+}
+
+/// Disk-backed cache of HTTP response bodies, keyed by URL, so repeated runs don't re-hit registries
+#[derive(Clone, Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+
+        (self.dir.join(format!("{key}.json")), self.dir.join(format!("{key}.body")))
+    }
+
+    pub fn get(&self, url: &str) -> Option<CachedResponse> {
+        let (meta_path, body_path) = self.paths(url);
+        let metadata = serde_json::from_str::<Metadata>(&fs::read_to_string(meta_path).ok()?).ok()?;
+
+        // Guard against (astronomically unlikely) key collisions
+        if metadata.url != url {
+            return None;
+        }
+
+        Some(CachedResponse {
+            etag: metadata.etag,
+            fetched_at: metadata.fetched_at,
+            body: fs::read(body_path).ok()?,
+        })
+    }
+
+    pub fn put(&self, url: &str, etag: Option<String>, body: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let (meta_path, body_path) = self.paths(url);
+
+        fs::write(body_path, body)?;
+        fs::write(
+            meta_path,
+            serde_json::to_string(&Metadata {
+                url: url.to_string(),
+                etag,
+                fetched_at: Utc::now(),
+            })?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Mark an entry as just revalidated (e.g. after a 304 Not Modified)
+    pub fn touch(&self, url: &str) -> Result<()> {
+        let (meta_path, _) = self.paths(url);
+        let mut metadata = serde_json::from_str::<Metadata>(&fs::read_to_string(&meta_path)?)?;
+
+        metadata.fetched_at = Utc::now();
+        fs::write(meta_path, serde_json::to_string(&metadata)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, Utc};
+
+    use super::ResponseCache;
+
+    #[test]
+    fn cached_responses_round_trip_and_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path().to_path_buf());
+
+        cache.put("https://pypi.org/pypi/ruff/json", Some("\"abc\"".to_string()), b"{}").unwrap();
+
+        let cached = cache.get("https://pypi.org/pypi/ruff/json").unwrap();
+
+        assert_eq!(cached.body, b"{}");
+        assert_eq!(cached.etag.as_deref(), Some("\"abc\""));
+        assert!(cached.is_fresh(TimeDelta::hours(1), Utc::now()));
+        assert!(!cached.is_fresh(TimeDelta::hours(1), Utc::now() + TimeDelta::hours(2)));
+        assert!(cache.get("https://pypi.org/pypi/other/json").is_none());
+    }
+}
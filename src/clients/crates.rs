@@ -1,46 +1,147 @@
-use reqwest::blocking::Client;
-use rootcause::{Result, bail};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use rootcause::Result;
 use serde::Deserialize;
 
+use crate::clients::http::Http;
+use crate::error::UpdateError;
+use crate::provenance::{Source, digest};
+
 #[derive(Debug, Deserialize)]
 pub struct CrateResponse {
     #[serde(rename = "crate")]
     pub crate_data: CrateInfo,
+
+    #[serde(skip)]
+    pub source: Option<Source>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CrateInfo {
-    pub max_version: String,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl CrateInfo {
+    /// The repository and homepage URLs declared in the crate's manifest.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        self.repository.iter().chain(self.homepage.iter()).map(String::as_str)
+    }
+}
+
+/// One published version of a crate
+#[derive(Debug, Deserialize)]
+pub struct CrateVersion {
+    pub num: String,
+    pub yanked: bool,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl CrateVersion {
+    fn semver(&self) -> Option<semver::Version> {
+        semver::Version::parse(&self.num).ok()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionsResponse {
+    versions: Vec<CrateVersion>,
+}
+
+/// The newest version that isn't yanked, nor a prerelease unless `allow_prereleases`, and the newer yanked version
+/// passed over for it, if any
+pub fn newest_version(versions: &[CrateVersion], allow_prereleases: bool) -> (Option<&CrateVersion>, Option<&CrateVersion>) {
+    let candidates = versions
+        .iter()
+        .filter_map(|version| Some((version.semver()?, version)))
+        .filter(|(semver, _)| allow_prereleases || semver.pre.is_empty())
+        .sorted_by(|(a, _), (b, _)| b.cmp(a))
+        .map(|(_, version)| version)
+        .collect_vec();
+
+    let newest = candidates.iter().find(|version| !version.yanked).copied();
+    let skipped = candidates.first().filter(|version| version.yanked).copied();
+
+    (newest, skipped)
 }
 
 pub struct CratesIoClient {
-    client: Client,
+    client: Http,
 }
 
 impl CratesIoClient {
     pub fn new() -> Result<Self> {
-        Ok(Self {
-            client: Client::builder()
-                .timeout(std::time::Duration::from_secs(30))
-                .user_agent(format!("nix-updater/{}", env!("CARGO_PKG_VERSION")))
-                .build()?,
-        })
+        Ok(Self { client: Http::shared()? })
     }
 
     pub fn crate_info(&self, name: &str) -> Result<Option<CrateResponse>> {
         let url = format!("https://crates.io/api/v1/crates/{name}");
 
-        match self.client.get(&url).send() {
-            Ok(response) => {
-                if response.status().is_success() {
-                    Ok(Some(response.json()?))
-                } else if response.status().as_u16() == 404 {
-                    Ok(None)
-                } else {
-                    bail!("crates.io API returned status: {}", response.status())
-                }
-            }
-            Err(e) => bail!("Failed to fetch crates.io data: {e}"),
+        let response = self.client.fetch(&url)?;
+
+        if response.status.as_u16() == 404 {
+            return Ok(None);
         }
+
+        if !response.status.is_success() {
+            return Err(UpdateError::status("crates.io", &url, response.status).into());
+        }
+
+        let bytes = response.body;
+        let mut data: CrateResponse = serde_json::from_slice(&bytes)?;
+
+        data.source = Some(Source {
+            url,
+            digest: Some(digest(&bytes)),
+        });
+
+        Ok(Some(data))
+    }
+
+    /// Every published version, yanked ones included, with the response recorded as a provenance source
+    pub fn versions(&self, name: &str) -> Result<(Vec<CrateVersion>, Source)> {
+        let url = format!("https://crates.io/api/v1/crates/{name}/versions?per_page=100&sort=semver");
+        let response = self.client.fetch(&url)?;
+
+        if !response.status.is_success() {
+            return Err(UpdateError::status("crates.io", &url, response.status).into());
+        }
+
+        let data: VersionsResponse = serde_json::from_slice(&response.body)?;
+
+        Ok((
+            data.versions,
+            Source {
+                digest: Some(digest(&response.body)),
+                url,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(num: &str, yanked: bool) -> CrateVersion {
+        CrateVersion {
+            num: num.to_string(),
+            yanked,
+            created_at: None,
+        }
+    }
+
+    #[test]
+    fn skips_yanked_and_prerelease_versions() {
+        let versions = [version("1.2.0", true), version("1.3.0-rc.1", false), version("1.1.0", false), version("1.0.0", false)];
+
+        let (newest, skipped) = newest_version(&versions, false);
+        assert_eq!(newest.map(|version| version.num.as_str()), Some("1.1.0"));
+        assert_eq!(skipped.map(|version| version.num.as_str()), Some("1.2.0"));
+
+        let (newest, skipped) = newest_version(&versions, true);
+        assert_eq!(newest.map(|version| version.num.as_str()), Some("1.3.0-rc.1"));
+        assert!(skipped.is_none());
     }
 }
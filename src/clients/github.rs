@@ -1,11 +1,201 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, LazyLock, OnceLock, RwLock};
+use std::time::Duration;
+use std::{env, fmt, fs};
+
+use chrono::{DateTime, Utc};
 use git_url_parse::GitUrl;
 use git_url_parse::types::provider::GenericProvider;
+use itertools::Itertools;
 use octocrab::Octocrab;
+use octocrab::models::Rate;
 use rootcause::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::runtime::Runtime;
+use tracing::debug;
+
+use crate::clients::http::Http;
+use crate::error::UpdateError;
+use crate::recording::{Recording, recorded};
+use crate::tools::{Tool, command, run_secret};
+
+/// One runtime drives every GitHub request, whichever worker thread issues it
+static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| Runtime::new().expect("Failed to start the tokio runtime"));
+
+static SHARED: OnceLock<GitHubClient> = OnceLock::new();
+
+/// `github_token` from config.toml, see [`GitHubClient::configure`]
+static CONFIGURED_TOKEN: OnceLock<Option<String>> = OnceLock::new();
+
+/// The gh CLI's login, looked up once, see [`GitHubClient::token`]
+static GH_LOGIN: OnceLock<Option<(String, TokenSource)>> = OnceLock::new();
+
+/// The REST and GraphQL API, unless `[http] mirrors` sends it elsewhere
+const API_URL: &str = "https://api.github.com";
 
 const DEFAULT_BRANCHES: [&str; 2] = ["main", "master"];
 
+/// Repositories looked up per GraphQL query, well within GitHub's node limits
+const BATCH_SIZE: usize = 50;
+
+/// How often a throttled request is retried before giving up
+const MAX_RETRIES: u32 = 4;
+
+/// Pages of 100 releases looked through for one with a `tag_prefix`, so a repository with thousands of releases
+/// can't spend dozens of requests on one package
+const MAX_RELEASE_PAGES: usize = 5;
+
+/// Longest we'll sleep waiting for a rate limit to reset before skipping instead
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// The releases that are neither drafts nor prereleases, and have `tag_prefix` when given
+fn published(releases: Vec<octocrab::models::repos::Release>, tag_prefix: Option<&str>) -> Vec<Release> {
+    releases
+        .into_iter()
+        .filter(|release| !release.draft && !release.prerelease && tag_prefix.is_none_or(|prefix| release.tag_name.starts_with(prefix)))
+        .map(Release::from)
+        .collect()
+}
+
+fn is_rate_limited(error: &octocrab::Error) -> bool {
+    matches!(
+        error,
+        octocrab::Error::GitHub { source, .. }
+            if (source.status_code == 403 || source.status_code == 429) && source.message.to_lowercase().contains("rate limit")
+    )
+}
+
+/// Whether a GraphQL response was throttled, which GitHub reports as a 200 with a `RATE_LIMITED` error rather than
+/// a status
+fn is_graphql_rate_limited(response: &Value) -> bool {
+    response.get("errors").and_then(Value::as_array).is_some_and(|errors| {
+        errors.iter().any(|error| {
+            error.get("type").and_then(Value::as_str) == Some("RATE_LIMITED")
+                || error.pointer("/extensions/code").and_then(Value::as_str) == Some("graphql_rate_limit")
+        })
+    })
+}
+
+/// Exponential backoff with jitter: ~1s, 2s, 4s, 8s
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt) + Duration::from_millis(fastrand::u64(0..500))
+}
+
+/// Where the GitHub API token came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenSource {
+    GitHubToken,
+    GhToken,
+    Config,
+    GhCli,
+    GhHosts,
+}
+
+impl fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::GitHubToken => "GITHUB_TOKEN",
+            Self::GhToken => "GH_TOKEN",
+            Self::Config => "github_token in config.toml",
+            Self::GhCli => "gh auth token",
+            Self::GhHosts => "gh's hosts.yml",
+        })
+    }
+}
+
+/// The token `gh` is logged in to github.com with, which it may keep in the system keyring
+fn gh_auth_token() -> Option<String> {
+    let output = run_secret(command(Tool::Gh).args(["auth", "token", "--hostname", "github.com"]).stdin(Stdio::null()), Tool::Gh)
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string()).filter(|token| !token.is_empty())
+}
+
+/// gh's `hosts.yml`, under `GH_CONFIG_DIR`, `XDG_CONFIG_HOME/gh` or `~/.config/gh`
+fn gh_hosts_file() -> Option<PathBuf> {
+    let dir = env::var_os("GH_CONFIG_DIR")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("XDG_CONFIG_HOME").map(|config| PathBuf::from(config).join("gh")))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("gh")))?;
+
+    Some(dir.join("hosts.yml"))
+}
+
+/// The first `oauth_token` under `github.com:` in a `hosts.yml`, which older gh versions store tokens in
+fn hosts_token(content: &str) -> Option<String> {
+    let mut in_github = false;
+
+    for line in content.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            in_github = line.trim_end() == "github.com:";
+        } else if in_github && let Some(token) = line.trim().strip_prefix("oauth_token:") {
+            return Some(token.trim().trim_matches(['"', '\'']).to_string()).filter(|token| !token.is_empty());
+        }
+    }
+
+    None
+}
+
+/// The newest published release of a repository
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Release {
+    pub tag: String,
+    pub published_at: Option<DateTime<Utc>>,
+
+    /// Names of the files attached to the release
+    pub assets: Vec<String>,
+
+    /// The release's description, only known from REST lookups
+    pub notes: Option<String>,
+}
+
+impl From<octocrab::models::repos::Release> for Release {
+    fn from(release: octocrab::models::repos::Release) -> Self {
+        Self {
+            tag: release.tag_name,
+            published_at: release.published_at,
+            assets: release.assets.into_iter().map(|asset| asset.name).collect(),
+            notes: release.body.filter(|body| !body.trim().is_empty()),
+        }
+    }
+}
+
+/// The commits between two revisions, oldest first, as the compare API lists them (at most 250)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Comparison {
+    pub total_commits: usize,
+    pub html_url: String,
+    pub commits: Vec<ComparedCommit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComparedCommit {
+    pub sha: String,
+    pub commit: CommitDetail,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitDetail {
+    pub message: String,
+    pub author: Option<CommitAuthor>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitAuthor {
+    pub name: String,
+}
+
+impl ComparedCommit {
+    /// The first line of the commit message
+    pub fn title(&self) -> &str {
+        self.commit.message.lines().next().unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct CargoToml {
     package: CargoPackage,
@@ -16,27 +206,320 @@ struct CargoPackage {
     version: String,
 }
 
+/// What one GraphQL lookup learned about a repository, answering later requests for it without a REST call
+#[derive(Clone, Debug, Default)]
+struct Snapshot {
+    pushed_at: Option<DateTime<Utc>>,
+    release: Option<Release>,
+    /// Newest tag by commit date, and the commit it points to
+    tag: Option<(String, String)>,
+    /// Default branch name and head commit
+    default_branch: Option<(String, String)>,
+}
+
+#[derive(Deserialize)]
+struct Nodes<T> {
+    nodes: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphQlRelease {
+    tag_name: String,
+    published_at: Option<DateTime<Utc>>,
+    release_assets: Nodes<Named>,
+}
+
+/// A ref's target: a commit, or for annotated tags a tag object pointing at one
+#[derive(Deserialize)]
+struct GraphQlTarget {
+    oid: String,
+    target: Option<Box<GraphQlTarget>>,
+}
+
+impl GraphQlTarget {
+    fn commit(self) -> String {
+        self.target.map_or(self.oid, |target| target.commit())
+    }
+}
+
+#[derive(Deserialize)]
+struct GraphQlRef {
+    name: String,
+    target: Option<GraphQlTarget>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphQlRepository {
+    pushed_at: Option<DateTime<Utc>>,
+    latest_release: Option<GraphQlRelease>,
+    default_branch_ref: Option<GraphQlRef>,
+    refs: Option<Nodes<GraphQlRef>>,
+}
+
+impl From<GraphQlRepository> for Snapshot {
+    fn from(repository: GraphQlRepository) -> Self {
+        let with_commit = |git_ref: GraphQlRef| Some((git_ref.name, git_ref.target?.commit()));
+
+        Self {
+            pushed_at: repository.pushed_at,
+            release: repository.latest_release.map(|release| Release {
+                tag: release.tag_name,
+                published_at: release.published_at,
+                assets: release.release_assets.nodes.into_iter().map(|asset| asset.name).collect(),
+                notes: None,
+            }),
+            tag: repository.refs.and_then(|refs| refs.nodes.into_iter().next()).and_then(with_commit),
+            default_branch: repository.default_branch_ref.and_then(with_commit),
+        }
+    }
+}
+
+/// A GraphQL query looking up each `owner/repo`, aliased `r<index>`
+fn batch_query(repos: &[(String, String)]) -> String {
+    let fields = "pushedAt \
+                  latestRelease { tagName publishedAt releaseAssets(first: 100) { nodes { name } } } \
+                  defaultBranchRef { name target { oid } } \
+                  refs(refPrefix: \"refs/tags/\", first: 1, orderBy: { field: TAG_COMMIT_DATE, direction: DESC }) \
+                  { nodes { name target { oid ... on Tag { target { oid } } } } }";
+
+    let lookups = repos
+        .iter()
+        .enumerate()
+        .map(|(index, (owner, repo))| format!("r{index}: repository(owner: {}, name: {}) {{ {fields} }}", json!(owner), json!(repo)))
+        .join(" ");
+
+    format!("query {{ {lookups} }}")
+}
+
+/// Snapshots for the repositories a [`batch_query`] response found, keyed like [`repo_key`]. Missing or inaccessible
+/// repositories come back as `null` with an error, and are left to the REST lookups.
+fn batch_snapshots(repos: &[(String, String)], response: &Value) -> HashMap<String, Snapshot> {
+    repos
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (owner, repo))| {
+            let repository = response.get("data")?.get(format!("r{index}"))?;
+            let repository = serde_json::from_value::<GraphQlRepository>(repository.clone()).ok()?;
+
+            Some((repo_key(owner, repo), Snapshot::from(repository)))
+        })
+        .collect()
+}
+
+/// How a REST lookup is keyed in a `--record`ing: the call, the repository, then its arguments
+fn recording_key(call: &str, url: &GitUrl, args: &[Option<&str>]) -> String {
+    format!("github {call} {url}{}", args.iter().flatten().map(|arg| format!(" {arg}")).join(""))
+}
+
+fn repo_key(owner: &str, repo: &str) -> String {
+    format!("{owner}/{repo}").to_lowercase()
+}
+
+#[derive(Clone)]
 pub struct GitHubClient {
     client: Octocrab,
-    runtime: tokio::runtime::Runtime,
+    runtime: &'static Runtime,
+    /// Filled by [`GitHubClient::prefetch`]
+    snapshots: Arc<RwLock<HashMap<String, Snapshot>>>,
 }
 
 impl GitHubClient {
-    pub fn new() -> Result<Self> {
-        let runtime = tokio::runtime::Runtime::new()?;
+    fn new() -> Result<Self> {
+        let runtime = &*RUNTIME;
+        let api = Http::shared()?.resolve(API_URL);
 
         let client = runtime.block_on(async {
-            let mut builder = Octocrab::builder();
+            let mut builder = Octocrab::builder().base_uri(api)?;
 
             // Avoid GitHub rate limits.
-            if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+            if let Some((token, source)) = Self::token() {
+                debug!("Authenticating to GitHub with {source}");
                 builder = builder.personal_token(token);
             }
 
             builder.build()
         })?;
 
-        Ok(Self { client, runtime })
+        Ok(Self {
+            client,
+            runtime,
+            snapshots: Arc::default(),
+        })
+    }
+
+    /// Use `token`, `github_token` in config.toml, when neither `GITHUB_TOKEN` nor `GH_TOKEN` is set. Only the first call
+    /// has any effect, and only before the first request.
+    pub fn configure(token: Option<String>) {
+        let _ = CONFIGURED_TOKEN.set(token);
+    }
+
+    /// The token to authenticate with: `GITHUB_TOKEN`, `GH_TOKEN`, `github_token` in config.toml, then the gh CLI's
+    /// login, so machines already logged in with `gh auth login` avoid the 60 requests per hour anonymous limit
+    pub fn token() -> Option<(String, TokenSource)> {
+        let var = |name: &str| env::var(name).ok().filter(|token| !token.trim().is_empty());
+
+        var("GITHUB_TOKEN")
+            .map(|token| (token, TokenSource::GitHubToken))
+            .or_else(|| var("GH_TOKEN").map(|token| (token, TokenSource::GhToken)))
+            .or_else(|| CONFIGURED_TOKEN.get().cloned().flatten().map(|token| (token, TokenSource::Config)))
+            .or_else(|| {
+                // Asking gh spawns it, so its answer is kept for every client and request after the first
+                GH_LOGIN
+                    .get_or_init(|| {
+                        gh_auth_token().map(|token| (token, TokenSource::GhCli)).or_else(|| {
+                            let content = fs::read_to_string(gh_hosts_file()?).ok()?;
+                            hosts_token(&content).map(|token| (token, TokenSource::GhHosts))
+                        })
+                    })
+                    .clone()
+            })
+    }
+
+    /// The process-wide client; Octocrab pools its connections, so clones are cheap
+    pub fn shared() -> Result<Self> {
+        if let Some(client) = SHARED.get() {
+            return Ok(client.clone());
+        }
+
+        let client = Self::new()?;
+
+        Ok(SHARED.get_or_init(|| client).clone())
+    }
+
+    /// Remaining core API quota
+    pub fn rate_limit(&self) -> Result<Rate> {
+        recorded("github rate_limit", || {
+            self.runtime.block_on(async { Ok(self.client.ratelimit().get().await?.resources.core) })
+        })
+    }
+
+    /// Look up the latest release, newest tag, default branch head and last push of every GitHub repository in `urls`
+    /// with a few batched GraphQL queries, instead of two or three REST calls per package later. Replaces what an
+    /// earlier call fetched; repositories it couldn't look up still go through REST.
+    ///
+    /// GraphQL needs a token, so without one this does nothing. Neither does it when replaying, as the lookups it would
+    /// answer were recorded as such.
+    pub fn prefetch<'a>(&self, urls: impl IntoIterator<Item = &'a GitUrl>) -> Result<()> {
+        if Recording::replaying() || Self::token().is_none() {
+            return Ok(());
+        }
+
+        let repos = urls
+            .into_iter()
+            .filter(|url| url.host() == Some("github.com"))
+            .filter_map(|url| Self::owner_and_repo_from_url(url).ok())
+            .unique_by(|(owner, repo)| repo_key(owner, repo))
+            .collect_vec();
+
+        let mut snapshots = HashMap::new();
+
+        self.runtime.block_on(async {
+            for batch in repos.chunks(BATCH_SIZE) {
+                let query = json!({ "query": batch_query(batch) });
+                let response = self.graphql(&query).await?;
+
+                snapshots.extend(batch_snapshots(batch, &response));
+            }
+
+            Ok::<_, rootcause::Report>(())
+        })?;
+
+        debug!(repositories = repos.len(), found = snapshots.len(), "Prefetched GitHub repositories");
+
+        if let Ok(mut cached) = self.snapshots.write() {
+            *cached = snapshots;
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self, url: &GitUrl) -> Option<Snapshot> {
+        let (owner, repo) = Self::owner_and_repo_from_url(url).ok()?;
+
+        self.snapshots.read().ok()?.get(&repo_key(&owner, &repo)).cloned()
+    }
+
+    /// Run a request, backing off and retrying while GitHub throttles it.
+    ///
+    /// The outer error is [`UpdateError::RateLimited`] once retrying is pointless; the inner result is the request's own
+    /// outcome, so callers can still match on e.g. 404s.
+    async fn retry<T, F, Fut>(&self, call: F) -> Result<octocrab::Result<T>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = octocrab::Result<T>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            match call().await {
+                Err(e) if is_rate_limited(&e) => {
+                    let rate = self.client.ratelimit().get().await.ok().map(|limit| limit.resources.core);
+                    let reset = rate.as_ref().and_then(|rate| DateTime::from_timestamp(i64::try_from(rate.reset).ok()?, 0));
+
+                    // An exhausted primary quota only frees up at the reset time; secondary limits clear with backoff
+                    let wait = match (&rate, reset) {
+                        (Some(rate), Some(reset)) if rate.remaining == 0 => (reset - Utc::now()).to_std().unwrap_or_default(),
+                        _ => backoff(attempt),
+                    };
+
+                    if attempt >= MAX_RETRIES || wait > MAX_WAIT {
+                        return Err(UpdateError::RateLimited {
+                            service: "GitHub".to_string(),
+                            reset,
+                        }
+                        .into());
+                    }
+
+                    debug!(attempt, wait = ?wait, "GitHub rate limited, backing off");
+
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                }
+                result => return Ok(result),
+            }
+        }
+    }
+
+    /// Run a GraphQL query, backing off and retrying like [`GitHubClient::retry`] while the response says it was
+    /// throttled, and failing with [`UpdateError::RateLimited`] once retrying is pointless
+    async fn graphql(&self, query: &Value) -> Result<Value> {
+        let mut attempt = 0;
+
+        loop {
+            let response: Value = self.retry(|| async { self.client.graphql(query).await }).await??;
+
+            if !is_graphql_rate_limited(&response) {
+                return Ok(response);
+            }
+
+            let rate = self.client.ratelimit().get().await.ok().and_then(|limit| limit.resources.graphql);
+            let reset = rate.as_ref().and_then(|rate| DateTime::from_timestamp(i64::try_from(rate.reset).ok()?, 0));
+
+            let wait = match (&rate, reset) {
+                (Some(rate), Some(reset)) if rate.remaining == 0 => (reset - Utc::now()).to_std().unwrap_or_default(),
+                _ => backoff(attempt),
+            };
+
+            if attempt >= MAX_RETRIES || wait > MAX_WAIT {
+                return Err(UpdateError::RateLimited {
+                    service: "GitHub".to_string(),
+                    reset,
+                }
+                .into());
+            }
+
+            debug!(attempt, wait = ?wait, "GitHub GraphQL rate limited, backing off");
+
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
     }
 
     fn owner_and_repo_from_url(url: &GitUrl) -> Result<(String, String)> {
@@ -45,102 +528,300 @@ impl GitHubClient {
         Ok((provider.owner().clone(), provider.repo().clone()))
     }
 
-    pub fn latest_release(&self, url: &GitUrl) -> Result<Option<String>> {
-        let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+    /// The newest release, or with a `tag_prefix` (monorepos tagging e.g. `cli-v1.2.3`) the newest published
+    /// release whose tag has it
+    pub fn latest_release(&self, url: &GitUrl, tag_prefix: Option<&str>) -> Result<Option<Release>> {
+        recorded(&recording_key("latest_release", url, &[tag_prefix]), || {
+            if tag_prefix.is_some() {
+                return Ok(self.releases(url, tag_prefix)?.into_iter().next());
+            }
 
-        self.runtime.block_on(async {
-            match self.client.repos(owner, repo).releases().get_latest().await {
-                Ok(release) => Ok(Some(release.tag_name)),
-                Err(octocrab::Error::GitHub { source, .. }) if source.status_code == 404 => Ok(None),
-                Err(e) => Err(e.into()),
+            if let Some(snapshot) = self.snapshot(url) {
+                return Ok(snapshot.release);
             }
+
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+
+            self.runtime.block_on(async {
+                let release = match self.retry(|| async { self.client.repos(&owner, &repo).releases().get_latest().await }).await? {
+                    Ok(release) => Some(release),
+                    Err(octocrab::Error::GitHub { source, .. }) if source.status_code == 404 => None,
+                    Err(e) => return Err(e.into()),
+                };
+
+                Ok(release.map(Release::from))
+            })
         })
     }
 
-    #[allow(dead_code)]
-    pub fn latest_tag(&self, url: &GitUrl) -> Result<Option<(String, String)>> {
-        let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+    /// Published releases, newest first, only those whose tag has `tag_prefix` when given: the first page of 100 that
+    /// has any, and the pages before it, looking through at most [`MAX_RELEASE_PAGES`]
+    pub fn releases(&self, url: &GitUrl, tag_prefix: Option<&str>) -> Result<Vec<Release>> {
+        recorded(&recording_key("releases", url, &[tag_prefix]), || {
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
 
-        self.runtime.block_on(async {
-            // Get all tags sorted by commit date
-            let tags = self.client.repos(owner, repo).list_tags().send().await?;
-
-            if let Some(tag) = tags.items.first() {
-                // Return both tag name and commit SHA
-                Ok(Some((tag.name.clone(), tag.commit.sha.clone())))
-            } else {
-                Ok(None)
+            self.runtime.block_on(async {
+                let mut page = self
+                    .retry(|| async { self.client.repos(&owner, &repo).releases().list().per_page(100).send().await })
+                    .await??;
+
+                let mut releases = published(page.take_items(), tag_prefix);
+                let mut pages = 1;
+
+                // Repositories publishing several packages can have more releases than fit on a page, but once one
+                // has the prefix the newest are in hand, and older pages would only spend the rate limit
+                while releases.is_empty()
+                    && pages < MAX_RELEASE_PAGES
+                    && let Some(mut next) = self.retry(|| async { self.client.get_page(&page.next).await }).await??
+                {
+                    releases = published(next.take_items(), tag_prefix);
+                    pages += 1;
+                    page = next;
+                }
+
+                Ok(releases)
+            })
+        })
+    }
+
+    /// Names of the files attached to the release tagged `tag`, as GitHub lists them right now
+    pub fn release_assets(&self, url: &GitUrl, tag: &str) -> Result<Vec<String>> {
+        recorded(&recording_key("release_assets", url, &[Some(tag)]), || {
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+
+            self.runtime.block_on(async {
+                let release = self.retry(|| async { self.client.repos(&owner, &repo).releases().get_by_tag(tag).await }).await??;
+
+                Ok(release.assets.into_iter().map(|asset| asset.name).collect())
+            })
+        })
+    }
+
+    /// When anything was last pushed to the repository
+    pub fn last_activity(&self, url: &GitUrl) -> Result<Option<DateTime<Utc>>> {
+        recorded(&recording_key("last_activity", url, &[]), || {
+            if let Some(snapshot) = self.snapshot(url) {
+                return Ok(snapshot.pushed_at);
             }
+
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+
+            self.runtime
+                .block_on(async { Ok(self.retry(|| async { self.client.repos(&owner, &repo).get().await }).await??.pushed_at) })
         })
     }
 
-    pub fn latest_commit(&self, url: &GitUrl) -> Result<Option<String>> {
-        let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+    #[allow(dead_code)]
+    pub fn latest_tag(&self, url: &GitUrl) -> Result<Option<(String, String)>> {
+        recorded(&recording_key("latest_tag", url, &[]), || {
+            if let Some(snapshot) = self.snapshot(url) {
+                return Ok(snapshot.tag);
+            }
 
-        self.runtime.block_on(async {
-            // First try to get the default branch
-            if let Ok(repo_info) = self.client.repos(&owner, &repo).get().await {
-                let default_branch = repo_info.default_branch.as_deref().unwrap_or("main");
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
 
-                // Get the commit SHA for the default branch
-                match self
-                    .client
-                    .repos(&owner, &repo)
-                    .get_ref(&octocrab::params::repos::Reference::Branch(default_branch.to_string()))
-                    .await
-                {
-                    Ok(git_ref) => match &git_ref.object {
-                        octocrab::models::repos::Object::Commit { sha, .. } => Ok(Some(sha.clone())),
-                        _ => Ok(None),
-                    },
-                    Err(_) => Ok(None),
+            self.runtime.block_on(async {
+                // Get all tags sorted by commit date
+                let tags = self.retry(|| async { self.client.repos(&owner, &repo).list_tags().send().await }).await??;
+
+                if let Some(tag) = tags.items.first() {
+                    // Return both tag name and commit SHA
+                    Ok(Some((tag.name.clone(), tag.commit.sha.clone())))
+                } else {
+                    Ok(None)
                 }
-            } else {
-                // Fallback: try common branch names
-                for branch in &DEFAULT_BRANCHES {
-                    let Ok(git_ref) = self
-                        .client
-                        .repos(&owner, &repo)
-                        .get_ref(&octocrab::params::repos::Reference::Branch((*branch).to_string()))
-                        .await
-                    else {
-                        continue;
+            })
+        })
+    }
+
+    /// The head commit of `branch`, or of the default branch when not given
+    pub fn latest_commit(&self, url: &GitUrl, branch: Option<&str>) -> Result<Option<String>> {
+        recorded(&recording_key("latest_commit", url, &[branch]), || {
+            if let Some((default_branch, head)) = self.snapshot(url).and_then(|snapshot| snapshot.default_branch)
+                && branch.is_none_or(|branch| branch == default_branch)
+            {
+                return Ok(Some(head));
+            }
+
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+
+            self.runtime.block_on(async {
+                if let Some(branch) = branch {
+                    return match self
+                        .retry(|| async {
+                            self.client
+                                .repos(&owner, &repo)
+                                .get_ref(&octocrab::params::repos::Reference::Branch(branch.to_string()))
+                                .await
+                        })
+                        .await?
+                    {
+                        Ok(git_ref) => match &git_ref.object {
+                            octocrab::models::repos::Object::Commit { sha, .. } => Ok(Some(sha.clone())),
+                            _ => Ok(None),
+                        },
+                        Err(octocrab::Error::GitHub { source, .. }) if source.status_code == 404 => Ok(None),
+                        Err(e) => Err(e.into()),
                     };
+                }
 
-                    if let octocrab::models::repos::Object::Commit { sha, .. } = &git_ref.object {
-                        return Ok(Some(sha.clone()));
+                // First try to get the default branch
+                if let Ok(repo_info) = self.retry(|| async { self.client.repos(&owner, &repo).get().await }).await? {
+                    let default_branch = repo_info.default_branch.as_deref().unwrap_or("main");
+
+                    // Get the commit SHA for the default branch
+                    match self
+                        .retry(|| async {
+                            self.client
+                                .repos(&owner, &repo)
+                                .get_ref(&octocrab::params::repos::Reference::Branch(default_branch.to_string()))
+                                .await
+                        })
+                        .await?
+                    {
+                        Ok(git_ref) => match &git_ref.object {
+                            octocrab::models::repos::Object::Commit { sha, .. } => Ok(Some(sha.clone())),
+                            _ => Ok(None),
+                        },
+                        Err(_) => Ok(None),
+                    }
+                } else {
+                    // Fallback: try common branch names
+                    for branch in &DEFAULT_BRANCHES {
+                        let Ok(git_ref) = self
+                            .retry(|| async {
+                                self.client
+                                    .repos(&owner, &repo)
+                                    .get_ref(&octocrab::params::repos::Reference::Branch((*branch).to_string()))
+                                    .await
+                            })
+                            .await?
+                        else {
+                            continue;
+                        };
+
+                        if let octocrab::models::repos::Object::Commit { sha, .. } = &git_ref.object {
+                            return Ok(Some(sha.clone()));
+                        }
                     }
+                    Ok(None)
                 }
-                Ok(None)
-            }
+            })
+        })
+    }
+
+    /// When `commit` was committed
+    pub fn commit_date(&self, url: &GitUrl, commit: &str) -> Result<Option<DateTime<Utc>>> {
+        recorded(&recording_key("commit_date", url, &[Some(commit)]), || {
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+
+            self.runtime.block_on(async {
+                let commit = self.retry(|| async { self.client.commits(&owner, &repo).get(commit).await }).await??;
+
+                Ok(commit.commit.committer.and_then(|committer| committer.date))
+            })
+        })
+    }
+
+    /// The commits from `base` (a tag or commit) up to `head`, or `None` when GitHub doesn't know one of them
+    pub fn compare(&self, url: &GitUrl, base: &str, head: &str) -> Result<Option<Comparison>> {
+        recorded(&recording_key("compare", url, &[Some(base), Some(head)]), || {
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+            let route = format!("/repos/{owner}/{repo}/compare/{base}...{head}");
+
+            self.runtime.block_on(async {
+                match self.retry(|| async { self.client.get::<Comparison, _, ()>(&route, None).await }).await? {
+                    Ok(comparison) => Ok(Some(comparison)),
+                    Err(octocrab::Error::GitHub { source, .. }) if source.status_code == 404 => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            })
         })
     }
 
     /// Get version from Cargo.toml at a specific commit
     pub fn cargo_version(&self, url: &GitUrl, commit: &str) -> Result<Option<String>> {
-        let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+        Ok(self
+            .file_content(url, "Cargo.toml", commit)?
+            .and_then(|content| toml::from_str::<CargoToml>(&content).ok())
+            .map(|cargo_toml| cargo_toml.package.version))
+    }
 
-        self.runtime.block_on(async {
-            match self
-                .client
-                .repos(&owner, &repo)
-                .get_content()
-                .path("Cargo.toml")
-                .r#ref(commit)
-                .send()
-                .await
-            {
-                Ok(content) => {
-                    if let Some(item) = content.items.first()
-                        && let Some(decoded) = item.decoded_content()
-                        && let Ok(cargo_toml) = toml::from_str::<CargoToml>(&decoded)
-                    {
-                        return Ok(Some(cargo_toml.package.version));
-                    }
-                    Ok(None)
+    /// A file's content at a specific commit, or `None` if it doesn't exist there
+    pub fn file_content(&self, url: &GitUrl, path: &str, commit: &str) -> Result<Option<String>> {
+        recorded(&recording_key("file_content", url, &[Some(path), Some(commit)]), || {
+            let (owner, repo) = Self::owner_and_repo_from_url(url)?;
+
+            self.runtime.block_on(async {
+                match self
+                    .retry(|| async { self.client.repos(&owner, &repo).get_content().path(path).r#ref(commit).send().await })
+                    .await?
+                {
+                    Ok(content) => Ok(content.items.first().and_then(octocrab::models::repos::Content::decoded_content)),
+                    Err(_) => Ok(None),
                 }
-                Err(_) => Ok(None),
-            }
+            })
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_batched_repositories() {
+        let repos = [("BurntSushi".to_string(), "ripgrep".to_string()), ("gone".to_string(), "missing".to_string())];
+
+        assert!(batch_query(&repos).contains(r#"r1: repository(owner: "gone", name: "missing")"#));
+
+        let response = json!({
+            "data": {
+                "r0": {
+                    "pushedAt": "2026-10-01T12:00:00Z",
+                    "latestRelease": {
+                        "tagName": "14.1.1",
+                        "publishedAt": "2026-09-30T08:00:00Z",
+                        "releaseAssets": { "nodes": [{ "name": "ripgrep-14.1.1-x86_64-unknown-linux-musl.tar.gz" }] }
+                    },
+                    "defaultBranchRef": { "name": "master", "target": { "oid": "abc123" } },
+                    "refs": { "nodes": [{ "name": "14.1.1", "target": { "oid": "tag456", "target": { "oid": "def789" } } }] }
+                },
+                "r1": null
+            },
+            "errors": [{ "type": "NOT_FOUND", "message": "Could not resolve to a Repository" }]
+        });
+
+        let snapshots = batch_snapshots(&repos, &response);
+        let snapshot = &snapshots["burntsushi/ripgrep"];
+
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshot.release.as_ref().map(|release| release.tag.as_str()), Some("14.1.1"));
+        assert_eq!(snapshot.default_branch, Some(("master".to_string(), "abc123".to_string())));
+        assert_eq!(snapshot.tag, Some(("14.1.1".to_string(), "def789".to_string())));
+        assert!(!is_graphql_rate_limited(&response));
+    }
+
+    #[test]
+    fn detects_graphql_rate_limits() {
+        let limited = json!({
+            "data": null,
+            "errors": [{ "type": "RATE_LIMITED", "message": "API rate limit exceeded for user ID 1." }]
+        });
+        let coded = json!({
+            "errors": [{ "message": "API rate limit exceeded", "extensions": { "code": "graphql_rate_limit" } }]
+        });
+
+        assert!(is_graphql_rate_limited(&limited));
+        assert!(is_graphql_rate_limited(&coded));
+        assert!(!is_graphql_rate_limited(&json!({ "data": { "r0": null } })));
+    }
+
+    #[test]
+    fn reads_gh_hosts_tokens() {
+        let hosts = "gitlab.com:\n    oauth_token: glpat-other\ngithub.com:\n    user: me\n    oauth_token: \"gho_abc\"\n    git_protocol: https\n";
+
+        assert_eq!(hosts_token(hosts).as_deref(), Some("gho_abc"));
+        assert_eq!(hosts_token("github.com:\n    users:\n        me: {}\n"), None);
+    }
+}
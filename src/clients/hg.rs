@@ -0,0 +1,88 @@
+use chrono::{DateTime, Utc};
+use rootcause::Result;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::clients::http::Http;
+use crate::tools::{Tool, command, run};
+
+/// The head of a Mercurial branch
+#[derive(Debug)]
+pub struct Changeset {
+    pub node: String,
+    /// Only known when hgweb answered
+    pub date: Option<DateTime<Utc>>,
+}
+
+/// hgweb's `json-changeset` response. `date` is `[unix seconds, utc offset]`.
+#[derive(Debug, Deserialize)]
+struct HgwebChangeset {
+    node: String,
+    date: (f64, i64),
+}
+
+pub struct MercurialClient {
+    http: Http,
+}
+
+impl MercurialClient {
+    pub fn new() -> Result<Self> {
+        Ok(Self { http: Http::shared()? })
+    }
+
+    /// The head of `branch` (`default` when not given), from the hgweb JSON API or, for servers without it, `hg identify`
+    pub fn latest_changeset(&self, url: &str, branch: Option<&str>) -> Result<Option<Changeset>> {
+        let branch = branch.unwrap_or("default");
+
+        match self.hgweb_changeset(url, branch) {
+            Ok(Some(changeset)) => return Ok(Some(changeset)),
+            Ok(None) => debug!("{url}: no hgweb JSON API, asking hg"),
+            Err(e) => debug!("{url}: {e}, asking hg"),
+        }
+
+        let output = run(command(Tool::Hg).args(["identify", "--debug", "--id", "--rev", branch, url]), Tool::Hg)?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).split_whitespace().next().map(|node| Changeset {
+            node: node.to_string(),
+            date: None,
+        }))
+    }
+
+    fn hgweb_changeset(&self, url: &str, branch: &str) -> Result<Option<Changeset>> {
+        let fetched = self.http.fetch(&format!("{}/json-changeset/{branch}", url.trim_end_matches('/')))?;
+
+        if !fetched.status.is_success() {
+            return Ok(None);
+        }
+
+        let changeset: HgwebChangeset = serde_json::from_slice(&fetched.body)?;
+
+        Ok(Some(Changeset {
+            node: changeset.node,
+            date: timestamp(changeset.date.0),
+        }))
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn timestamp(seconds: f64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds as i64, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HgwebChangeset;
+
+    #[test]
+    fn parses_hgweb_changesets() {
+        let changeset: HgwebChangeset =
+            serde_json::from_str(r#"{"node": "8f3a2bb35e4c9c0bf1ab3fa1fd5c0c1d2e3f4a5b", "date": [1704412800.0, -3600], "desc": "Fix", "branch": "default"}"#).unwrap();
+
+        assert_eq!(changeset.node, "8f3a2bb35e4c9c0bf1ab3fa1fd5c0c1d2e3f4a5b");
+        assert_eq!(super::timestamp(changeset.date.0).unwrap().date_naive().to_string(), "2024-01-05");
+    }
+}
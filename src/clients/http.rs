@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::{Method, Proxy, StatusCode};
+use rootcause::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::cache::ResponseCache;
+use crate::error::UpdateError;
+use crate::recording::{Body, recorded};
+
+static SHARED: OnceLock<Http> = OnceLock::new();
+
+/// `[http]` settings shared by every registry client
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Per-request timeout in seconds
+    pub timeout: u64,
+
+    /// How many times a request is retried after a connection error or 5xx/429 response
+    pub retries: u32,
+
+    /// Proxy URL for all requests, e.g. `http://proxy:3128`
+    pub proxy: Option<String>,
+
+    /// Seconds a cached registry response is used without revalidating. 0 disables the on-disk cache, including the
+    /// cache of prefetched source hashes.
+    pub cache_ttl: u64,
+
+    /// URL prefixes fetched from another base instead, e.g. `"https://pypi.org" = "https://pypi.example.internal"` for
+    /// a registry mirror. The longest matching prefix wins; cached responses stay keyed by the original URL.
+    pub mirrors: BTreeMap<String, String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 30,
+            retries: 3,
+            proxy: None,
+            cache_ttl: 3600,
+            mirrors: BTreeMap::new(),
+        }
+    }
+}
+
+/// A pooled HTTP client that retries transient failures with jittered backoff
+#[derive(Clone, Debug)]
+pub struct Http {
+    client: Client,
+    retries: u32,
+    cache: Option<ResponseCache>,
+    cache_ttl: TimeDelta,
+    mirrors: BTreeMap<String, String>,
+}
+
+/// A response body with its status, either fresh from the network or from the cache
+#[derive(Debug)]
+pub struct Fetched {
+    pub status: StatusCode,
+    pub body: Vec<u8>,
+}
+
+/// A [`Fetched`] as `--record` keeps it
+#[derive(Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    body: Body,
+}
+
+impl From<Fetched> for RecordedResponse {
+    fn from(fetched: Fetched) -> Self {
+        Self {
+            status: fetched.status.as_u16(),
+            body: fetched.body.into(),
+        }
+    }
+}
+
+impl From<RecordedResponse> for Fetched {
+    fn from(recorded: RecordedResponse) -> Self {
+        Self {
+            status: StatusCode::from_u16(recorded.status).unwrap_or(StatusCode::OK),
+            body: recorded.body.into(),
+        }
+    }
+}
+
+impl Http {
+    fn build(config: &HttpConfig, cache_dir: Option<PathBuf>) -> Result<Self> {
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .user_agent(format!("nix-updater/{}", env!("CARGO_PKG_VERSION")));
+
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            retries: config.retries,
+            cache: cache_dir.filter(|_| config.cache_ttl > 0).map(ResponseCache::new),
+            cache_ttl: TimeDelta::seconds(i64::try_from(config.cache_ttl).unwrap_or(i64::MAX)),
+            mirrors: config.mirrors.clone(),
+        })
+    }
+
+    /// Build the shared client from configuration, caching responses under `cache_dir`. Only the first call has any effect.
+    pub fn configure(config: &HttpConfig, cache_dir: Option<PathBuf>) -> Result<()> {
+        let _ = SHARED.set(Self::build(config, cache_dir)?);
+        Ok(())
+    }
+
+    /// The shared client, with default settings if [`Http::configure`] wasn't called
+    pub fn shared() -> Result<Self> {
+        if let Some(http) = SHARED.get() {
+            return Ok(http.clone());
+        }
+
+        let http = Self::build(&HttpConfig::default(), None)?;
+
+        Ok(SHARED.get_or_init(|| http).clone())
+    }
+
+    /// GET a URL through the on-disk cache.
+    ///
+    /// Entries younger than the TTL are returned without a request; older ones are revalidated with their ETag.
+    pub fn fetch(&self, url: &str) -> Result<Fetched> {
+        recorded(&format!("GET {url}"), || self.fetch_live(url).map(RecordedResponse::from)).map(Fetched::from)
+    }
+
+    fn fetch_live(&self, url: &str) -> Result<Fetched> {
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(url));
+
+        if let Some(entry) = &cached
+            && entry.is_fresh(self.cache_ttl, Utc::now())
+        {
+            return Ok(Fetched {
+                status: StatusCode::OK,
+                body: entry.body.clone(),
+            });
+        }
+
+        let response = self.send(url, cached.as_ref().and_then(|entry| entry.etag.as_deref()))?;
+        let status = response.status();
+
+        if let (StatusCode::NOT_MODIFIED, Some(cache), Some(entry)) = (status, &self.cache, cached) {
+            if let Err(e) = cache.touch(url) {
+                warn!(url, "Failed to refresh cache entry: {e}");
+            }
+
+            return Ok(Fetched {
+                status: StatusCode::OK,
+                body: entry.body,
+            });
+        }
+
+        let etag = response.headers().get(ETAG).and_then(|etag| etag.to_str().ok()).map(String::from);
+        let body = response.bytes()?.to_vec();
+
+        if status.is_success()
+            && let Some(cache) = &self.cache
+            && let Err(e) = cache.put(url, etag, &body)
+        {
+            warn!(url, "Failed to cache response: {e}");
+        }
+
+        Ok(Fetched { status, body })
+    }
+
+    /// Where to actually send a request for `url`: its mirror, if one is configured for it
+    pub fn resolve(&self, url: &str) -> String {
+        self.mirrors
+            .iter()
+            .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or_else(|| url.to_string(), |(prefix, mirror)| format!("{mirror}{}", &url[prefix.len()..]))
+    }
+
+    /// GET a URL, retrying connection errors, timeouts, 5xx and 429 responses
+    fn send(&self, url: &str, etag: Option<&str>) -> reqwest::Result<Response> {
+        let url = self.resolve(url);
+
+        self.with_retries(|client| {
+            let request = client.get(&url);
+
+            match etag {
+                Some(etag) => request.header(IF_NONE_MATCH, etag),
+                None => request,
+            }
+        })
+    }
+
+    /// Send a JSON body (e.g. to a webhook), or none, with the same retries as GETs, failing unless the response is a
+    /// success. Returns the response's JSON, `Null` when it has none.
+    pub fn send_json(&self, method: &Method, url: &str, body: Option<&serde_json::Value>, bearer: Option<&str>) -> Result<serde_json::Value> {
+        recorded(&format!("{method} {url}"), || self.send_json_live(method, url, body, bearer))
+    }
+
+    fn send_json_live(&self, method: &Method, url: &str, body: Option<&serde_json::Value>, bearer: Option<&str>) -> Result<serde_json::Value> {
+        let target = self.resolve(url);
+
+        let response = self.with_retries(|client| {
+            let mut request = client.request(method.clone(), &target);
+
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+
+            match bearer {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            }
+        })?;
+
+        let status = response.status();
+
+        if !status.is_success() {
+            return Err(UpdateError::Http {
+                url: url.to_string(),
+                status: Some(status.as_u16()),
+                message: format!("{method} returned {status}: {}", response.text().unwrap_or_default().trim()),
+            }
+            .into());
+        }
+
+        Ok(response.json().unwrap_or(serde_json::Value::Null))
+    }
+
+    /// Send the request `request` builds, building it again for each retry of a connection error, timeout, 5xx or 429
+    fn with_retries(&self, request: impl Fn(&Client) -> RequestBuilder) -> reqwest::Result<Response> {
+        let mut attempt = 0;
+
+        loop {
+            let result = request(&self.client).send();
+
+            let transient = match &result {
+                Ok(response) => response.status().is_server_error() || response.status() == StatusCode::TOO_MANY_REQUESTS,
+                Err(e) => e.is_connect() || e.is_timeout(),
+            };
+
+            if !transient || attempt >= self.retries {
+                return result;
+            }
+
+            let wait = Duration::from_millis(500 << attempt) + Duration::from_millis(fastrand::u64(0..250));
+
+            debug!(attempt, wait = ?wait, "Transient HTTP failure, retrying");
+
+            thread::sleep(wait);
+            attempt += 1;
+        }
+    }
+}
@@ -1,5 +1,8 @@
 pub mod crates;
 pub mod github;
+pub mod gitlab;
+pub mod hg;
+pub mod http;
 pub mod nix;
 pub mod npm;
 pub mod pypi;
@@ -1,68 +1,562 @@
-use std::process::Command;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
 
-use rootcause::Result;
-use serde::Deserialize;
+use chrono::{TimeDelta, Utc};
+use rootcause::{Result, bail};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::cache::ResponseCache;
+use crate::error::UpdateError;
+use crate::tools::{Tool, command, installed, run};
+
+/// Hashes of sources prefetched before, see [`Nix::cache_prefetches`]
+static PREFETCHED: OnceLock<Prefetched> = OnceLock::new();
+
+/// The prefetch cache, and how long hashes of sources not pinned by a revision stay fresh in it
+struct Prefetched {
+    cache: ResponseCache,
+    ttl: TimeDelta,
+}
 
 #[derive(Debug, Deserialize)]
 struct NixPrefetchResult {
     pub hash: String,
 }
 
+/// What `nix-prefetch-git` prints; `hash` is missing from older versions
+#[derive(Debug, Deserialize)]
+struct NixPrefetchGitResult {
+    rev: String,
+    sha256: String,
+    hash: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct NurlResult {
     pub args: NurlArgs,
 }
 
+/// nurl's description of how to fetch a source: the fetcher function and its arguments
+#[derive(Debug, Deserialize)]
+pub struct Fetcher {
+    pub fetcher: String,
+    pub args: BTreeMap<String, serde_json::Value>,
+}
+
+impl Fetcher {
+    pub fn hash(&self) -> Option<&str> {
+        self.args.get("hash").and_then(serde_json::Value::as_str)
+    }
+
+    pub fn rev(&self) -> Option<&str> {
+        self.args.get("rev").and_then(serde_json::Value::as_str)
+    }
+
+    /// The fetcher call as Nix code, hash last as nurl prints it
+    pub fn expression(&self) -> String {
+        let mut expression = format!("{} {{\n", self.fetcher);
+
+        for (name, value) in self.args.iter().filter(|(name, _)| *name != "hash").chain(self.args.get_key_value("hash")) {
+            let _ = writeln!(expression, "  {name} = {value};");
+        }
+
+        expression.push('}');
+        expression
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct NurlArgs {
     pub hash: String,
     pub rev: Option<String>,
 }
 
+/// The kind of fetcher a source is written with, which decides how it's hashed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FetchKind {
+    /// `fetchurl` and what builds on it: the file as downloaded
+    File,
+    /// `fetchzip` and what builds on it: the unpacked archive
+    Archive,
+    /// `fetchFromGitHub` and the like: a checkout of the repository, which on GitHub its archive stands in for
+    #[default]
+    Git,
+    /// `fetchgit`: the checkout `nix-prefetch-git` makes, which an archive doesn't match once `.gitattributes` has
+    /// `export-ignore` or `export-subst` in it
+    Checkout,
+    /// Other forges' fetchers (`fetchFromGitLab`, `fetchFromGitea`, ...), which hash their own archives
+    Forge,
+}
+
+/// The fetcher, and its arguments that change what a git checkout contains, and so its hash
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FetchFlags {
+    pub kind: FetchKind,
+    pub submodules: bool,
+    pub leave_dot_git: bool,
+    pub deep_clone: bool,
+}
+
+impl FetchFlags {
+    /// Whether the source has to be cloned rather than fetched as a tarball
+    pub fn needs_clone(self) -> bool {
+        self.submodules || self.leave_dot_git || self.deep_clone
+    }
+}
+
+/// How many downloads [`Nix::prefetch_hashes`] runs at once
+const PREFETCH_JOBS: usize = 4;
+
 #[derive(Debug, Default)]
 pub struct Nix;
 
 impl Nix {
+    /// Remember prefetched hashes under `dir`, so that `--force` runs, retries and packages sharing a tarball don't
+    /// download it again. Sources pinned by a revision are cached for good, those fetched by URL alone (which upstream
+    /// may serve new contents at) for `ttl`. Only the first call has any effect.
+    pub fn cache_prefetches(dir: PathBuf, ttl: Duration) {
+        let _ = PREFETCHED.set(Prefetched {
+            cache: ResponseCache::new(dir),
+            ttl: TimeDelta::from_std(ttl).unwrap_or(TimeDelta::MAX),
+        });
+    }
+
     pub fn prefetch_hash(url: &str) -> Result<Option<String>> {
-        let output = Command::new("nix").args(["store", "prefetch-file", url, "--json"]).output()?;
+        cached(PREFETCHED.get(), &format!("prefetch-file {url}"), false, || Self::refetch_hash(url))
+    }
+
+    /// Download `url` and hash it, bypassing the prefetch cache, for when the point is to see what upstream serves now
+    pub fn refetch_hash(url: &str) -> Result<Option<String>> {
+        let output = run(command(Tool::Nix).args(["store", "prefetch-file", url, "--json"]), Tool::Nix)?;
 
-        if output.status.success() {
-            return Ok(Some(serde_json::from_slice::<NixPrefetchResult>(&output.stdout)?.hash));
+        if !output.status.success() {
+            return Err(UpdateError::prefetch(Tool::Nix, url, &output.stderr).into());
         }
 
-        Ok(None)
+        Ok(Some(serde_json::from_slice::<NixPrefetchResult>(&output.stdout)?.hash))
     }
 
-    pub fn hash_and_rev(url: &str, rev: Option<&str>) -> Result<Option<(String, Option<String>)>> {
-        let output = Command::new("nurl").arg("--json").arg(url).args(rev.as_ref()).output()?;
+    /// [`Nix::prefetch_hash`] for several URLs, a few at a time, with the results in the order of `urls`
+    pub fn prefetch_hashes(urls: &[&str]) -> Vec<Result<Option<String>>> {
+        let next = AtomicUsize::new(0);
+
+        let mut results = thread::scope(|scope| {
+            let workers = (0..PREFETCH_JOBS.min(urls.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+
+                            let Some(url) = urls.get(index) else {
+                                break done;
+                            };
+
+                            done.push((index, Self::prefetch_hash(url)));
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect::<Vec<_>>()
+        });
+
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
 
-        if output.status.success() {
-            return match String::from_utf8_lossy(&output.stdout).trim_end().lines().last() {
-                Some(last_line) if !last_line.is_empty() => {
-                    let result: NurlResult = serde_json::from_str(last_line)?;
-                    Ok(Some((result.args.hash, result.args.rev)))
+    /// Prefetch a source, as its fetcher would, returning its hash and the revision it was fetched at (the latest
+    /// when `rev` is `None`). `flags` must match the fetcher's arguments, or the hash won't.
+    ///
+    /// Only `fetchurl` and `fetchzip` sources are hashed as the file at `url`. GitHub repositories are hashed from their
+    /// archive tarballs as `fetchFromGitHub` does and other git sources with `nix-prefetch-git`, at the remote's `HEAD`
+    /// when there's no `rev`. Other forges' fetchers, and whatever that can't hash, are left to nurl when it's installed.
+    pub fn hash_and_rev(url: &str, rev: Option<&str>, flags: FetchFlags) -> Result<Option<(String, Option<String>)>> {
+        // Without a revision the latest one is resolved, which is what the caller wants to find out
+        let cache = PREFETCHED.get().filter(|_| rev.is_some());
+
+        if flags.leave_dot_git || flags.deep_clone {
+            warn!(
+                "{url}: leaveDotGit/deepClone hashes depend on the .git contents and may not reproduce across fetches; \
+                 consider recording what's needed from git in postFetch and dropping the flag"
+            );
+        }
+
+        cached(cache, &format!("hash-and-rev {url} {} {flags:?}", rev.unwrap_or_default()), true, || {
+            let native = Self::native_hash_and_rev(url, rev, flags);
+
+            match native {
+                Ok(Some(found)) => Ok(Some(found)),
+                _ if installed(Tool::Nurl) => {
+                    if let Err(e) = &native {
+                        debug!("{url}: {e}, falling back to nurl");
+                    }
+
+                    Self::nurl_hash_and_rev(url, rev, flags)
                 }
-                _ => Ok(None),
+                _ => native,
+            }
+        })
+    }
+
+    fn native_hash_and_rev(url: &str, rev: Option<&str>, flags: FetchFlags) -> Result<Option<(String, Option<String>)>> {
+        match flags.kind {
+            FetchKind::File => return Ok(Self::prefetch_hash(url)?.map(|hash| (hash, None))),
+            FetchKind::Archive => return Ok(Self::prefetch_unpacked_hash(url)?.map(|hash| (hash, None))),
+            FetchKind::Forge => return Ok(None),
+            FetchKind::Git | FetchKind::Checkout => {}
+        }
+
+        if flags.kind == FetchKind::Git
+            && let Some(repository) = github_repository(url)
+            && !flags.needs_clone()
+        {
+            let rev = match rev {
+                Some(rev) => rev.to_string(),
+                None => match Self::remote_ref(url, "HEAD")? {
+                    Some(head) => head,
+                    None => return Ok(None),
+                },
             };
+
+            let archive = format!("https://github.com/{repository}/archive/{rev}.tar.gz");
+
+            return Ok(Self::prefetch_unpacked_hash(&archive)?.map(|hash| (hash, Some(rev))));
         }
 
-        Ok(None)
+        // A homepage that isn't a repository has no HEAD, which leaves it to nurl rather than hashing the page
+        let rev = match rev {
+            Some(rev) => rev.to_string(),
+            None => match Self::remote_ref(url, "HEAD")? {
+                Some(head) => head,
+                None => return Ok(None),
+            },
+        };
+
+        Self::prefetch_git(url, Some(&rev), flags)
     }
 
-    pub fn prefetch_fetchcrate(pname: &str, version: &str) -> Result<Option<String>> {
-        let crate_url = format!("https://crates.io/crates/{pname}");
-        let output = Command::new("nurl").args(["--json", "--fetcher", "fetchCrate", &crate_url, version]).output()?;
+    /// The commit a remote ref points at, e.g. `HEAD` for the default branch or `refs/heads/<branch>`
+    pub fn remote_ref(url: &str, reference: &str) -> Result<Option<String>> {
+        let output = run(command(Tool::Git).args(["ls-remote", url, reference]), Tool::Git)?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).split_whitespace().next().map(String::from))
+    }
 
-        if output.status.success() {
-            return match String::from_utf8_lossy(&output.stdout).trim_end().lines().last() {
-                Some(last_line) if !last_line.is_empty() => {
-                    let result: NurlResult = serde_json::from_str(last_line)?;
-                    Ok(Some(result.args.hash))
+    /// Hash a git checkout as `fetchgit` does
+    fn prefetch_git(url: &str, rev: Option<&str>, flags: FetchFlags) -> Result<Option<(String, Option<String>)>> {
+        let mut prefetch = command(Tool::NixPrefetchGit);
+
+        prefetch.args(["--quiet", "--url", url]);
+
+        if let Some(rev) = rev {
+            prefetch.args(["--rev", rev]);
+        }
+
+        if flags.submodules {
+            prefetch.arg("--fetch-submodules");
+        }
+
+        if flags.leave_dot_git {
+            prefetch.arg("--leave-dotGit");
+        }
+
+        if flags.deep_clone {
+            prefetch.arg("--deepClone");
+        }
+
+        let output = run(&mut prefetch, Tool::NixPrefetchGit)?;
+
+        if !output.status.success() {
+            return Err(UpdateError::prefetch(Tool::NixPrefetchGit, url, &output.stderr).into());
+        }
+
+        let result: NixPrefetchGitResult = serde_json::from_slice(&output.stdout)?;
+
+        // Older versions only print the nix32 sha256
+        let hash = match result.hash {
+            Some(hash) => hash,
+            None => Self::convert_hash(&result.sha256, "sri")?,
+        };
+
+        Ok(Some((hash, Some(result.rev))))
+    }
+
+    /// A sha256 in any encoding `nix hash convert` reads, re-encoded `to` (`sri`, `nix32`, `base16` or `base64`)
+    pub fn convert_hash(sha256: &str, to: &str) -> Result<String> {
+        let convert = run(command(Tool::Nix).args(["hash", "convert", "--hash-algo", "sha256", "--to", to, sha256]), Tool::Nix)?;
+
+        // Older nix has no `hash convert`, and a malformed hash fails it; neither may end up in a package as its hash
+        if !convert.status.success() {
+            bail!("nix hash convert {sha256} failed: {}", String::from_utf8_lossy(&convert.stderr).trim());
+        }
+
+        let converted = String::from_utf8_lossy(&convert.stdout).trim().to_string();
+
+        if converted.is_empty() {
+            bail!("nix hash convert {sha256} printed no hash");
+        }
+
+        Ok(converted)
+    }
+
+    /// Hash a Mercurial checkout at `rev` as `fetchhg` does, with `nix-prefetch-hg` or else nurl
+    pub fn prefetch_hg(url: &str, rev: &str) -> Result<Option<String>> {
+        cached(PREFETCHED.get(), &format!("fetchhg {url} {rev}"), true, || {
+            if !installed(Tool::NixPrefetchHg) && installed(Tool::Nurl) {
+                let output = run(command(Tool::Nurl).args(["--json", "--fetcher", "fetchhg", url, rev]), Tool::Nurl)?;
+
+                if !output.status.success() {
+                    return Err(UpdateError::prefetch(Tool::Nurl, url, &output.stderr).into());
                 }
+
+                return match String::from_utf8_lossy(&output.stdout).trim_end().lines().last() {
+                    Some(last_line) if !last_line.is_empty() => Ok(Some(serde_json::from_str::<NurlResult>(last_line)?.args.hash)),
+                    _ => Ok(None),
+                };
+            }
+
+            let output = run(command(Tool::NixPrefetchHg).args([url, rev]), Tool::NixPrefetchHg)?;
+
+            if !output.status.success() {
+                return Err(UpdateError::prefetch(Tool::NixPrefetchHg, url, &output.stderr).into());
+            }
+
+            // The hash comes first, then the store path when asked for
+            match String::from_utf8_lossy(&output.stdout).lines().next().map(str::trim) {
+                Some(sha256) if !sha256.is_empty() => Self::convert_hash(sha256, "sri").map(Some),
                 _ => Ok(None),
-            };
+            }
+        })
+    }
+
+    fn nurl_hash_and_rev(url: &str, rev: Option<&str>, flags: FetchFlags) -> Result<Option<(String, Option<String>)>> {
+        let mut nurl = command(Tool::Nurl);
+
+        nurl.arg("--json");
+
+        // Left to itself nurl picks the forge's fetcher, and so its archive
+        if flags.kind == FetchKind::Checkout {
+            nurl.args(["--fetcher", "fetchgit"]);
+        }
+
+        if flags.submodules {
+            nurl.arg("--submodules=true");
+        }
+
+        if flags.leave_dot_git {
+            nurl.args(["--arg", "leaveDotGit", "true"]);
+        }
+
+        if flags.deep_clone {
+            nurl.args(["--arg", "deepClone", "true"]);
+        }
+
+        let output = run(nurl.arg(url).args(rev), Tool::Nurl)?;
+
+        if !output.status.success() {
+            return Err(UpdateError::prefetch(Tool::Nurl, url, &output.stderr).into());
+        }
+
+        match String::from_utf8_lossy(&output.stdout).trim_end().lines().last() {
+            Some(last_line) if !last_line.is_empty() => {
+                let result: NurlResult = serde_json::from_str(last_line)?;
+                Ok(Some((result.args.hash, result.args.rev)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Hash of a file once unpacked, as `fetchzip` computes it
+    pub fn prefetch_unpacked_hash(url: &str) -> Result<Option<String>> {
+        cached(PREFETCHED.get(), &format!("prefetch-file --unpack {url}"), false, || {
+            let output = run(command(Tool::Nix).args(["store", "prefetch-file", "--unpack", url, "--json"]), Tool::Nix)?;
+
+            if !output.status.success() {
+                return Err(UpdateError::prefetch(Tool::Nix, url, &output.stderr).into());
+            }
+
+            Ok(Some(serde_json::from_slice::<NixPrefetchResult>(&output.stdout)?.hash))
+        })
+    }
+
+    /// The fetcher nurl picks for a URL, with the source prefetched at `rev` (or the latest revision)
+    pub fn nurl(url: &str, rev: Option<&str>) -> Result<Option<Fetcher>> {
+        let output = run(command(Tool::Nurl).arg("--json").arg(url).args(rev), Tool::Nurl)?;
+
+        if !output.status.success() {
+            return Err(UpdateError::prefetch(Tool::Nurl, url, &output.stderr).into());
+        }
+
+        if let Some(last_line) = String::from_utf8_lossy(&output.stdout).trim_end().lines().last()
+            && !last_line.is_empty()
+        {
+            return Ok(Some(serde_json::from_str(last_line)?));
         }
 
         Ok(None)
     }
+
+    /// Hash of a crate as `fetchCrate` computes it, from the unpacked crates.io download
+    pub fn prefetch_fetchcrate(pname: &str, version: &str) -> Result<Option<String>> {
+        let native = Self::prefetch_unpacked_hash(&format!("https://crates.io/api/v1/crates/{pname}/{version}/download"));
+
+        if matches!(native, Ok(Some(_))) || !installed(Tool::Nurl) {
+            return native;
+        }
+
+        let crate_url = format!("https://crates.io/crates/{pname}");
+        let output = run(command(Tool::Nurl).args(["--json", "--fetcher", "fetchCrate", &crate_url, version]), Tool::Nurl)?;
+
+        if !output.status.success() {
+            return Err(UpdateError::prefetch(Tool::Nurl, &crate_url, &output.stderr).into());
+        }
+
+        match String::from_utf8_lossy(&output.stdout).trim_end().lines().last() {
+            Some(last_line) if !last_line.is_empty() => {
+                let result: NurlResult = serde_json::from_str(last_line)?;
+                Ok(Some(result.args.hash))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// npmDepsHash for a package-lock.json, computed locally by nixpkgs' `prefetch-npm-deps`.
+    ///
+    /// `None` when the tool isn't installed or fails, so callers can fall back to building.
+    pub fn prefetch_npm_deps(package_lock: &Path) -> Option<String> {
+        let output = match run(command(Tool::PrefetchNpmDeps).arg(package_lock), Tool::PrefetchNpmDeps) {
+            Ok(output) => output,
+            Err(e) => {
+                debug!("prefetch-npm-deps unavailable: {e}");
+                return None;
+            }
+        };
+
+        if !output.status.success() {
+            warn!("prefetch-npm-deps failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            return None;
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .rfind(|line| line.starts_with("sha256-"))
+            .map(String::from)
+    }
+}
+
+/// `owner/repo` for a GitHub repository URL (not a file or archive within one)
+fn github_repository(url: &str) -> Option<String> {
+    let path = url.strip_prefix("https://github.com/")?.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+
+    (path.split('/').count() == 2).then(|| path.to_string())
+}
+
+/// Look `key` up in `prefetched`, or run `prefetch` and remember what it found. Entries for sources that aren't
+/// `pinned` by a revision expire after the cache's TTL. Failures aren't cached.
+fn cached<T: Serialize + DeserializeOwned>(prefetched: Option<&Prefetched>, key: &str, pinned: bool, prefetch: impl FnOnce() -> Result<Option<T>>) -> Result<Option<T>> {
+    if let Some(value) = prefetched
+        .and_then(|prefetched| prefetched.cache.get(key).filter(|entry| pinned || entry.is_fresh(prefetched.ttl, Utc::now())))
+        .and_then(|entry| serde_json::from_slice(&entry.body).ok())
+    {
+        debug!("{key}: using the cached hash");
+        return Ok(Some(value));
+    }
+
+    let value = prefetch()?;
+
+    if let (Some(prefetched), Some(value)) = (prefetched, &value)
+        && let Err(e) = prefetched.cache.put(key, None, &serde_json::to_vec(value)?)
+    {
+        warn!("Could not cache {key}: {e}");
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::{Prefetched, cached, github_repository};
+    use crate::cache::ResponseCache;
+
+    #[test]
+    fn caches_successful_prefetches() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefetched = Prefetched {
+            cache: ResponseCache::new(dir.path().to_path_buf()),
+            ttl: TimeDelta::hours(1),
+        };
+
+        let first = cached(Some(&prefetched), "prefetch-file https://example.com/a.tar.gz", false, || Ok(Some("sha256-a".to_string()))).unwrap();
+        let second = cached(
+            Some(&prefetched),
+            "prefetch-file https://example.com/a.tar.gz",
+            false,
+            || -> rootcause::Result<Option<String>> { panic!("should have been cached") },
+        )
+        .unwrap();
+
+        assert_eq!(first.as_deref(), Some("sha256-a"));
+        assert_eq!(second, first);
+
+        // Failures are retried next time
+        assert_eq!(
+            cached::<String>(Some(&prefetched), "prefetch-file https://example.com/b.tar.gz", false, || Ok(None)).unwrap(),
+            None
+        );
+        assert!(prefetched.cache.get("prefetch-file https://example.com/b.tar.gz").is_none());
+    }
+
+    #[test]
+    fn expires_prefetches_not_pinned_by_a_revision() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefetched = Prefetched {
+            cache: ResponseCache::new(dir.path().to_path_buf()),
+            ttl: TimeDelta::zero(),
+        };
+
+        cached(Some(&prefetched), "prefetch-file https://example.com/latest.tar.gz", false, || {
+            Ok(Some("sha256-old".to_string()))
+        })
+        .unwrap();
+        cached(Some(&prefetched), "fetchhg https://example.com/repo abc", true, || Ok(Some("sha256-abc".to_string()))).unwrap();
+
+        let unpinned = cached(Some(&prefetched), "prefetch-file https://example.com/latest.tar.gz", false, || {
+            Ok(Some("sha256-new".to_string()))
+        })
+        .unwrap();
+        let pinned = cached(Some(&prefetched), "fetchhg https://example.com/repo abc", true, || -> rootcause::Result<Option<String>> {
+            panic!("should have been cached")
+        })
+        .unwrap();
+
+        assert_eq!(unpinned.as_deref(), Some("sha256-new"));
+        assert_eq!(pinned.as_deref(), Some("sha256-abc"));
+    }
+
+    #[test]
+    fn recognizes_repository_urls() {
+        assert_eq!(github_repository("https://github.com/astral-sh/ruff").as_deref(), Some("astral-sh/ruff"));
+        assert_eq!(github_repository("https://github.com/astral-sh/ruff.git/").as_deref(), Some("astral-sh/ruff"));
+        assert_eq!(github_repository("https://github.com/o/r/archive/refs/tags/v1.tar.gz"), None);
+    }
 }
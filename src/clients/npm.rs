@@ -1,32 +1,67 @@
-use reqwest::blocking::Client;
-use rootcause::{Result, bail};
+use rootcause::Result;
+use serde::Deserialize;
+
+use crate::clients::http::Http;
+use crate::error::UpdateError;
+use crate::provenance::{Source, digest};
+
+/// The release an npm dist-tag points at
+#[derive(Debug, Deserialize)]
+pub struct NpmRelease {
+    pub version: String,
+
+    /// The commit the release was published from, when npm recorded it
+    #[serde(rename = "gitHead")]
+    pub git_head: Option<String>,
+
+    #[serde(skip)]
+    pub source: Option<Source>,
+}
 
 pub struct NpmClient {
-    client: Client,
+    client: Http,
 }
 
 impl NpmClient {
     pub fn new() -> Result<Self> {
-        Ok(Self {
-            client: Client::builder()
-                .timeout(std::time::Duration::from_secs(30))
-                .user_agent(format!("nix-updater/{}", env!("CARGO_PKG_VERSION")))
-                .build()?,
-        })
+        Ok(Self { client: Http::shared()? })
+    }
+
+    /// The release `tag` (e.g. `latest`, `next`) points at, or `None` if the package or tag doesn't exist
+    pub fn dist_tag(&self, name: &str, tag: &str) -> Result<Option<NpmRelease>> {
+        // Scoped packages keep their `@`, but the slash is escaped
+        let url = format!("https://registry.npmjs.org/{}/{tag}", name.replace('/', "%2f"));
+        let response = self.client.fetch(&url)?;
+
+        if response.status.as_u16() == 404 {
+            return Ok(None);
+        }
+
+        if !response.status.is_success() {
+            return Err(UpdateError::status("npm", &url, response.status).into());
+        }
+
+        let mut release: NpmRelease = serde_json::from_slice(&response.body)?;
+
+        release.source = Some(Source {
+            digest: Some(digest(&response.body)),
+            url,
+        });
+
+        Ok(Some(release))
     }
 
     pub fn download_package_lock(&self, url: &str) -> Result<Option<String>> {
-        match self.client.get(url).send() {
-            Ok(response) => {
-                if response.status().is_success() {
-                    Ok(Some(response.text()?))
-                } else if response.status().as_u16() == 404 {
-                    Ok(None)
-                } else {
-                    bail!("Failed to download package-lock.json: status {}", response.status())
-                }
-            }
-            Err(e) => bail!("Failed to download package-lock.json: {e}"),
+        let response = self.client.fetch(url)?;
+
+        if response.status.as_u16() == 404 {
+            return Ok(None);
+        }
+
+        if !response.status.is_success() {
+            return Err(UpdateError::status("GitHub", url, response.status).into());
         }
+
+        Ok(Some(String::from_utf8(response.body)?))
     }
 }
@@ -1,52 +1,83 @@
-use reqwest::blocking::Client;
-use rootcause::{Result, bail};
+use chrono::{DateTime, Utc};
+use rootcause::Result;
 use serde::Deserialize;
 
+use crate::clients::http::Http;
+use crate::error::UpdateError;
+use crate::provenance::{Source, digest};
+
 #[derive(Debug, Deserialize)]
 pub struct PyPiProjectResponse {
     pub info: PyPiProjectInfo,
     pub releases: std::collections::HashMap<String, Vec<PyPiReleaseFile>>,
+
+    /// Files of the latest release
+    #[serde(default)]
+    pub urls: Vec<PyPiReleaseFile>,
+
+    #[serde(skip)]
+    pub source: Option<Source>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PyPiProjectInfo {
     pub version: String,
+    pub home_page: Option<String>,
+    pub project_urls: Option<std::collections::HashMap<String, String>>,
+}
+
+impl PyPiProjectInfo {
+    /// Every URL the project declares about itself (`home_page` and `project_urls`).
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        self.home_page
+            .iter()
+            .chain(self.project_urls.iter().flat_map(std::collections::HashMap::values))
+            .map(String::as_str)
+            .filter(|url| !url.is_empty())
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PyPiReleaseFile {
     pub filename: String,
     pub url: String,
+
+    /// `sdist` or `bdist_wheel`
+    #[serde(default)]
+    pub packagetype: String,
+    pub upload_time_iso_8601: Option<DateTime<Utc>>,
 }
 
 pub struct PyPiClient {
-    client: Client,
+    client: Http,
 }
 
 impl PyPiClient {
     pub fn new() -> Result<Self> {
-        Ok(Self {
-            client: Client::builder()
-                .timeout(std::time::Duration::from_secs(30))
-                .user_agent(format!("nix-updater/{}", env!("CARGO_PKG_VERSION")))
-                .build()?,
-        })
+        Ok(Self { client: Http::shared()? })
     }
 
     pub fn project(&self, name: &str) -> Result<Option<PyPiProjectResponse>> {
         let url = format!("https://pypi.org/pypi/{name}/json");
 
-        match self.client.get(&url).send() {
-            Ok(response) => {
-                if response.status().is_success() {
-                    Ok(Some(response.json()?))
-                } else if response.status().as_u16() == 404 {
-                    Ok(None)
-                } else {
-                    bail!("PyPI API returned status: {}", response.status())
-                }
-            }
-            Err(e) => bail!("Failed to fetch PyPI data: {e}"),
+        let response = self.client.fetch(&url)?;
+
+        if response.status.as_u16() == 404 {
+            return Ok(None);
+        }
+
+        if !response.status.is_success() {
+            return Err(UpdateError::status("PyPI", &url, response.status).into());
         }
+
+        let bytes = response.body;
+        let mut data: PyPiProjectResponse = serde_json::from_slice(&bytes)?;
+
+        data.source = Some(Source {
+            url,
+            digest: Some(digest(&bytes)),
+        });
+
+        Ok(Some(data))
     }
 }
@@ -0,0 +1,175 @@
+use colored::Colorize;
+use itertools::Itertools;
+use rootcause::{Result, bail};
+
+use nix_package_updater::clients::GitHubClient;
+use nix_package_updater::clients::github::{Comparison, Release};
+use nix_package_updater::package::Package;
+use nix_package_updater::updater::{normalize_version, short_hash, version_is_greater};
+
+/// What changed upstream between the revision a package is pinned to and the newest one an update would move it to
+pub struct UpstreamDiff {
+    pub base: String,
+    pub head: String,
+    /// `None` when GitHub can't compare the two, e.g. a tag that was deleted
+    pub comparison: Option<Comparison>,
+    /// Releases newer than the pinned revision, newest first
+    pub releases: Vec<Release>,
+}
+
+/// A full commit hash, as opposed to a tag or branch name
+fn is_commit(rev: &str) -> bool {
+    rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The tag of the `current` release and the releases newer than it, newest first, given releases newest first
+fn release_range(releases: Vec<Release>, current: &str, version_of: impl Fn(&Release) -> String) -> (Option<String>, Vec<Release>) {
+    let base = releases.iter().find(|release| version_of(release) == current).map(|release| release.tag.clone());
+    let newer = releases.into_iter().filter(|release| version_is_greater(&version_of(release), current)).collect();
+
+    (base, newer)
+}
+
+/// Compare a GitHub-hosted package's pinned revision with the latest upstream: the branch head for packages pinned to a
+/// commit, the latest release otherwise. `None` when it's already there.
+pub fn upstream_diff(client: &GitHubClient, package: &Package) -> Result<Option<UpstreamDiff>> {
+    if package.homepage.host() != Some("github.com") {
+        bail!("{}: diff needs a GitHub-hosted upstream, not {}", package.name, package.homepage);
+    }
+
+    let releases = client.releases(&package.homepage, package.settings.tag_prefix.as_deref())?;
+    let rev = package.ast().get_in(&["src"], "rev");
+
+    if let Some(rev) = rev.clone().filter(|rev| is_commit(rev)) {
+        let Some(head) = client.latest_commit(&package.homepage, package.settings.branch.as_deref())? else {
+            bail!("{}: couldn't find the latest commit", package.name);
+        };
+
+        if head == rev {
+            return Ok(None);
+        }
+
+        let pinned_at = client.commit_date(&package.homepage, &rev)?;
+        let releases = releases
+            .into_iter()
+            .filter(|release| pinned_at.is_some_and(|pinned_at| release.published_at.is_some_and(|published_at| published_at > pinned_at)))
+            .collect();
+
+        return Ok(Some(UpstreamDiff {
+            comparison: client.compare(&package.homepage, &rev, &head)?,
+            base: rev,
+            head,
+            releases,
+        }));
+    }
+
+    let Some(head) = releases.first().map(|release| release.tag.clone()) else {
+        bail!("{}: no releases on GitHub to compare with", package.name);
+    };
+
+    let (base, newer) = release_range(releases, &package.version, |release| {
+        normalize_version(&package.name, package.settings.strip_tag_prefix(&release.tag))
+    });
+
+    if newer.is_empty() {
+        return Ok(None);
+    }
+
+    // Not among the recent releases: the tag `src` is pinned to, as in `rev = "v${version}"`
+    let base = base.or_else(|| {
+        rev.map(|rev| rev.replace("${version}", &package.version).replace("${finalAttrs.version}", &package.version))
+            .filter(|rev| !rev.contains("${"))
+    });
+
+    let Some(base) = base else {
+        bail!("{}: couldn't find the tag of version {}", package.name, package.version);
+    };
+
+    Ok(Some(UpstreamDiff {
+        comparison: client.compare(&package.homepage, &base, &head)?,
+        base,
+        head,
+        releases: newer,
+    }))
+}
+
+/// Show each package's upstream changes: commit count and shortlog (the newest `limit` commits), then the release
+/// notes of the releases it would move past
+pub fn print_diffs(packages: &[Package], limit: usize) -> Result<()> {
+    let client = GitHubClient::shared()?;
+
+    for package in packages.iter().sorted_by(|a, b| a.name.cmp(&b.name)) {
+        let diff = match upstream_diff(&client, package) {
+            Ok(Some(diff)) => diff,
+            Ok(None) => {
+                println!("{}: {}", package.name().cyan().bold(), "up to date".green());
+                continue;
+            }
+            Err(e) => {
+                println!("{}: {}", package.name().cyan().bold(), e.to_string().red());
+                continue;
+            }
+        };
+
+        let shorten = |rev: &str| if is_commit(rev) { short_hash(rev) } else { rev.to_string() };
+
+        println!("\n{} {} → {}", package.name().cyan().bold(), shorten(&diff.base), shorten(&diff.head).yellow());
+
+        match &diff.comparison {
+            Some(comparison) => {
+                println!("{} {}", format!("{} commits", comparison.total_commits).bold(), comparison.html_url.dimmed());
+
+                for commit in comparison.commits.iter().rev().take(limit) {
+                    let author = commit.commit.author.as_ref().map(|author| format!("({})", author.name)).unwrap_or_default();
+
+                    println!("  {} {} {}", short_hash(&commit.sha).dimmed(), commit.title(), author.dimmed());
+                }
+
+                if comparison.total_commits > limit.min(comparison.commits.len()) {
+                    println!("  {}", format!("… {} more", comparison.total_commits - limit.min(comparison.commits.len())).dimmed());
+                }
+            }
+            None => println!("{}", "GitHub couldn't compare these revisions".dimmed()),
+        }
+
+        for release in &diff.releases {
+            let published = release.published_at.map(|date| format!(" ({})", date.format("%Y-%m-%d"))).unwrap_or_default();
+
+            println!("\n  {}{}", release.tag.bold(), published.dimmed());
+
+            for line in release.notes.as_deref().unwrap_or("No release notes").lines() {
+                println!("    {}", line.trim_end());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Release, release_range};
+
+    fn release(tag: &str) -> Release {
+        Release {
+            tag: tag.to_string(),
+            published_at: None,
+            assets: Vec::new(),
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn finds_pinned_and_newer_releases() {
+        let releases = vec![release("v1.3.0"), release("v1.2.1"), release("v1.2.0"), release("v1.1.0")];
+        let (base, newer) = release_range(releases, "1.2.0", |release| release.tag.trim_start_matches('v').to_string());
+
+        assert_eq!(base.as_deref(), Some("v1.2.0"));
+        assert_eq!(newer.iter().map(|release| release.tag.as_str()).collect::<Vec<_>>(), ["v1.3.0", "v1.2.1"]);
+
+        let (base, newer) = release_range(vec![release("v1.3.0")], "1.0.0", |release| release.tag.trim_start_matches('v').to_string());
+
+        assert_eq!(base, None);
+        assert_eq!(newer.len(), 1);
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use colored::Colorize;
+use rootcause::{Result, bail};
+use strum::IntoEnumIterator;
+
+use nix_package_updater::clients::GitHubClient;
+use nix_package_updater::tools::{Tool, locate};
+
+/// Report whether the tools, configuration and credentials a run needs are in place. Fails if a required tool is missing.
+pub fn print_doctor(config_path: &Path) -> Result<()> {
+    println!("{}", "Tools".bright_white().bold());
+
+    let mut missing = Vec::new();
+
+    for tool in Tool::iter() {
+        match locate(tool) {
+            Some(path) => println!("  {} {:<18} {}", "✓".green(), tool.to_string(), path.display().to_string().dimmed()),
+            None if tool.required() => {
+                missing.push(tool);
+                println!("  {} {:<18} {} (required)", "✗".red(), tool.to_string(), tool.purpose());
+            }
+            None => println!("  {} {:<18} {} (optional, not found)", "-".yellow(), tool.to_string(), tool.purpose()),
+        }
+    }
+
+    println!("\n{}", "Configuration".bright_white().bold());
+
+    if config_path.is_file() {
+        println!("  {} {}", "✓".green(), config_path.display());
+    } else {
+        println!("  {} no {} (using defaults)", "-".yellow(), config_path.display());
+    }
+
+    match GitHubClient::token() {
+        Some((_, source)) => println!("  {} GitHub token from {source}", "✓".green()),
+        None => println!(
+            "  {} No GitHub token (GITHUB_TOKEN, GH_TOKEN, github_token or `gh auth login`): GitHub allows 60 unauthenticated API requests per hour",
+            "-".yellow()
+        ),
+    }
+
+    println!("\n{}", "Packages".bright_white().bold());
+
+    let roots = ["packages/", "nix/packages/"].into_iter().filter(|root| Path::new(root).is_dir()).collect::<Vec<_>>();
+
+    if roots.is_empty() {
+        println!(
+            "  {} no packages/ or nix/packages/ here (use --discover flake to find packages through the flake)",
+            "-".yellow()
+        );
+    } else {
+        for root in roots {
+            println!("  {} {root}", "✓".green());
+        }
+    }
+
+    if !missing.is_empty() {
+        bail!("Missing required tools: {}", missing.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+    }
+
+    Ok(())
+}
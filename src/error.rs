@@ -0,0 +1,211 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local, Utc};
+use reqwest::StatusCode;
+use rootcause::Report;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::nix::fod::FodError;
+use crate::package::Package;
+use crate::tools::Tool;
+
+/// Most of a failing command's stderr kept in an error, from the end where the reason usually is
+const STDERR_EXCERPT: usize = 800;
+
+/// Why a package failed, with enough context for automation to tell a rate limit or flaky network from an upstream
+/// that genuinely broke. Serialized with its variant as `type`, e.g. `{"type": "rate_limited", ...}`.
+#[derive(Clone, Debug, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UpdateError {
+    /// A forge or registry API won't answer until its quota resets; nothing is wrong with the package
+    #[error("{service} API rate limit exceeded{}", resets_at(*.reset))]
+    RateLimited { service: String, reset: Option<DateTime<Utc>> },
+
+    /// A request failed outright (connection, timeout) or returned an error status
+    #[error("{url}: {message}")]
+    Http { url: String, status: Option<u16>, message: String },
+
+    /// The upstream doesn't have what the package follows: a release, asset, tag, branch, commit or registry entry
+    #[error("{message}")]
+    Upstream { source_kind: String, url: String, message: String },
+
+    /// Fetching a source to hash it failed
+    #[error("{tool} couldn't fetch {url}{}", stderr_suffix(.stderr))]
+    Prefetch { tool: String, url: String, stderr: String },
+
+    /// An external tool the update needs isn't installed
+    #[error("{tool} is not installed: {hint}")]
+    MissingTool { tool: String, hint: String },
+
+    /// The package's build or checks failed or timed out
+    #[error("{message}")]
+    Build { message: String, log: Option<PathBuf> },
+
+    /// The edited file no longer evaluates, so the edits were reverted
+    #[error("{message}")]
+    Evaluation { message: String },
+
+    #[error("{message}")]
+    Other { message: String },
+}
+
+fn resets_at(reset: Option<DateTime<Utc>>) -> String {
+    reset
+        .map(|reset| format!(" (resets at {})", reset.with_timezone(&Local).format("%H:%M")))
+        .unwrap_or_default()
+}
+
+fn stderr_suffix(stderr: &str) -> String {
+    if stderr.is_empty() { String::new() } else { format!(": {stderr}") }
+}
+
+/// The tail of a command's stderr, trimmed to [`STDERR_EXCERPT`] bytes on a character boundary
+pub fn stderr_excerpt(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim();
+    let start = stderr.len().saturating_sub(STDERR_EXCERPT);
+    let start = (start..stderr.len()).find(|&index| stderr.is_char_boundary(index)).unwrap_or(stderr.len());
+
+    stderr[start..].to_string()
+}
+
+impl UpdateError {
+    /// The package's upstream lacks what it follows
+    pub fn upstream(package: &Package, message: impl Into<String>) -> Self {
+        Self::Upstream {
+            source_kind: package.kind.to_string(),
+            url: package.homepage.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// An error status from `service` at `url`: its rate limit when that's 429 Too Many Requests
+    pub fn status(service: &str, url: &str, status: StatusCode) -> Self {
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Self::RateLimited {
+                service: service.to_string(),
+                reset: None,
+            };
+        }
+
+        Self::Http {
+            url: url.to_string(),
+            status: Some(status.as_u16()),
+            message: format!("{service} returned {status}"),
+        }
+    }
+
+    /// `tool` exited unsuccessfully fetching `url`
+    pub fn prefetch(tool: Tool, url: &str, stderr: &[u8]) -> Self {
+        Self::Prefetch {
+            tool: tool.to_string(),
+            url: url.to_string(),
+            stderr: stderr_excerpt(stderr),
+        }
+    }
+
+    /// `tool` ran without complaint fetching `url`, but printed no hash
+    pub fn no_hash(tool: Tool, url: &str) -> Self {
+        Self::Prefetch {
+            tool: tool.to_string(),
+            url: url.to_string(),
+            stderr: "no hash in its output".to_string(),
+        }
+    }
+
+    /// The error behind a failed step: the first [`UpdateError`], fixed-output hash or `reqwest` failure found walking
+    /// the report and its causes, or else the report's message
+    pub fn from_report(report: &Report) -> Self {
+        for cause in report.iter_reports() {
+            if let Some(error) = cause.downcast_current_context::<Self>() {
+                return error.clone();
+            }
+
+            if let Some(error) = cause.downcast_current_context::<FodError>() {
+                return error.into();
+            }
+
+            if let Some(error) = cause.downcast_current_context::<reqwest::Error>() {
+                return Self::Http {
+                    url: error.url().map(ToString::to_string).unwrap_or_default(),
+                    status: error.status().map(|status| status.as_u16()),
+                    message: error.to_string(),
+                };
+            }
+        }
+
+        Self::Other { message: report.to_string() }
+    }
+
+    /// Whether trying again later may well work: rate limits, connection failures and server errors, as opposed to
+    /// failures that need someone to look at the package or its upstream
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::RateLimited { .. } => true,
+            Self::Http { status, .. } => status.is_none_or(|status| status == 429 || status >= 500),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rootcause::{Report, report};
+
+    use super::{UpdateError, stderr_excerpt};
+    use crate::nix::fod::FodError;
+
+    #[test]
+    fn serializes_with_type_tag() {
+        let error = UpdateError::Prefetch {
+            tool: "nurl".to_string(),
+            url: "https://example.org/tool".to_string(),
+            stderr: "error: unable to download".to_string(),
+        };
+
+        assert_eq!(error.to_string(), "nurl couldn't fetch https://example.org/tool: error: unable to download");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "type": "prefetch",
+                "tool": "nurl",
+                "url": "https://example.org/tool",
+                "stderr": "error: unable to download",
+            })
+        );
+        assert!(!error.is_transient());
+
+        let rate_limited = UpdateError::RateLimited {
+            service: "GitHub".to_string(),
+            reset: None,
+        };
+
+        assert_eq!(rate_limited.to_string(), "GitHub API rate limit exceeded");
+        assert!(rate_limited.is_transient());
+    }
+
+    #[test]
+    fn keeps_the_end_of_stderr() {
+        let stderr = format!("{}error: hash mismatch", "é".repeat(1000));
+        let excerpt = stderr_excerpt(stderr.as_bytes());
+
+        assert!(excerpt.len() <= 800);
+        assert!(excerpt.ends_with("error: hash mismatch"));
+    }
+
+    #[test]
+    fn finds_the_typed_cause_behind_added_context() {
+        let rate_limited = UpdateError::RateLimited {
+            service: "GitHub".to_string(),
+            reset: None,
+        };
+        let wrapped: Report = report!(rate_limited.clone()).context("Checking ripgrep").into_dynamic();
+
+        assert_eq!(UpdateError::from_report(&wrapped), rate_limited);
+
+        let fod: Report = report!(FodError::NoMismatch { hash: "vendorHash".to_string() }).context("Updating tool").into_dynamic();
+
+        assert!(matches!(UpdateError::from_report(&fod), UpdateError::Build { message, .. } if message.contains("did not report a hash mismatch")));
+    }
+}
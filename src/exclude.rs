@@ -0,0 +1,190 @@
+use std::fs;
+use std::path::Path;
+
+use regex_automata::meta::Regex;
+use rootcause::{Result, report};
+
+/// Patterns read from this file at the repository root are excluded like `--exclude` ones, one per line
+pub const IGNORE_FILE: &str = ".nix-updater-ignore";
+
+/// A glob, or a regex written between slashes
+#[derive(Debug)]
+enum Pattern {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        match pattern.strip_prefix('/').and_then(|rest| rest.strip_suffix('/')) {
+            Some(regex) if !regex.is_empty() => Ok(Self::Regex(Regex::new(regex).map_err(|e| report!("Invalid exclude pattern '{pattern}': {e}"))?)),
+            _ => Ok(Self::Glob(pattern.to_string())),
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob_matches(glob, text),
+            Self::Regex(regex) => regex.is_match(text),
+        }
+    }
+
+    /// Whether the pattern picks out `path`, relative to the repository root: all of it, anything under a directory
+    /// pattern ending in `/`, or the file name for globs without a `/`
+    fn matches_path(&self, path: &str) -> bool {
+        match self {
+            Self::Glob(glob) if glob.ends_with('/') => glob_matches(&format!("{}**", glob.trim_start_matches('/')), path),
+            Self::Glob(glob) if !glob.contains('/') => path.rsplit('/').next().is_some_and(|name| self.matches(name)),
+            Self::Glob(glob) => glob_matches(glob.trim_start_matches('/'), path),
+            Self::Regex(_) => self.matches(path),
+        }
+    }
+}
+
+/// What a run leaves out: `--exclude` patterns and the lines of [`IGNORE_FILE`].
+///
+/// Patterns are globs (`*` and `?` within a path segment, `**` across them) or regexes between slashes
+/// (`/^python3?-/`), checked against package names and against package files' paths relative to the repository root.
+/// A plain name still excludes just that package.
+#[derive(Debug, Default)]
+pub struct Exclusions {
+    patterns: Vec<Pattern>,
+}
+
+impl Exclusions {
+    /// The given patterns plus those in `ignore_file`, if it exists. Blank lines and `#` comments are skipped.
+    pub fn load(patterns: &[String], ignore_file: &Path) -> Result<Self> {
+        let ignored = match fs::read_to_string(ignore_file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(report!("Failed to read {}: {e}", ignore_file.display())),
+        };
+
+        Self::parse(
+            patterns
+                .iter()
+                .map(String::as_str)
+                .chain(ignored.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'))),
+        )
+    }
+
+    pub fn parse<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        Ok(Self {
+            patterns: patterns.into_iter().map(Pattern::parse).collect::<Result<_>>()?,
+        })
+    }
+
+    /// Whether a package (or pin) of this name is excluded
+    pub fn excludes_name(&self, name: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(name))
+    }
+
+    /// Whether every package in this file is excluded
+    pub fn excludes_file(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+        let path = path.trim_start_matches("./");
+
+        self.patterns.iter().any(|pattern| pattern.matches_path(path))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Char(char),
+    /// `?`
+    One,
+    /// `*`
+    Star,
+    /// `**`, and the `/` after it
+    Any,
+}
+
+/// Whether `text` matches the glob: `*` and `?` don't cross `/`, `**` does. The one glob matcher for exclude patterns
+/// and release asset patterns alike; it tracks every position the glob could be at in a single pass over `text`, so a
+/// pattern full of stars stays linear in the text.
+pub fn glob_matches(glob: &str, text: &str) -> bool {
+    let mut tokens = Vec::new();
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '*' if chars.next_if_eq(&'*').is_some() => {
+                chars.next_if_eq(&'/');
+                Token::Any
+            }
+            '*' => Token::Star,
+            '?' => Token::One,
+            c => Token::Char(c),
+        });
+    }
+
+    // Stars may match nothing, so whoever reaches one also reaches what follows it
+    let close = |states: &mut Vec<bool>| {
+        for index in 0..tokens.len() {
+            if states[index] && matches!(tokens[index], Token::Star | Token::Any) {
+                states[index + 1] = true;
+            }
+        }
+    };
+
+    let mut states = vec![false; tokens.len() + 1];
+    states[0] = true;
+    close(&mut states);
+
+    for c in text.chars() {
+        let mut next = vec![false; tokens.len() + 1];
+
+        for (index, token) in tokens.iter().enumerate().filter(|(index, _)| states[*index]) {
+            match token {
+                Token::Char(expected) if *expected == c => next[index + 1] = true,
+                Token::One if c != '/' => next[index + 1] = true,
+                Token::Star if c != '/' => next[index] = true,
+                Token::Any => next[index] = true,
+                _ => {}
+            }
+        }
+
+        close(&mut next);
+        states = next;
+
+        if !states.contains(&true) {
+            return false;
+        }
+    }
+
+    states[tokens.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Exclusions, glob_matches};
+
+    #[test]
+    fn matches_names_and_paths() {
+        let exclusions = Exclusions::parse(["ripgrep", "python3*-env", "/^go-.*-bin$/", "packages/generated/", "vendor/**/*.nix", "default.nix"]).unwrap();
+
+        assert!(exclusions.excludes_name("ripgrep"));
+        assert!(!exclusions.excludes_name("ripgrep-all"));
+        assert!(exclusions.excludes_name("python312-env"));
+        assert!(exclusions.excludes_name("go-task-bin"));
+        assert!(!exclusions.excludes_name("go-task"));
+
+        assert!(exclusions.excludes_file(Path::new("./packages/generated/node/foo.nix")));
+        assert!(exclusions.excludes_file(Path::new("vendor/a/b/foo.nix")));
+        assert!(exclusions.excludes_file(Path::new("vendor/foo.nix")));
+        assert!(exclusions.excludes_file(Path::new("packages/tools/default.nix")));
+        assert!(!exclusions.excludes_file(Path::new("packages/tools/ripgrep.nix")));
+
+        assert!(Exclusions::parse(["/(/"]).is_err());
+    }
+
+    #[test]
+    fn many_stars_stay_fast() {
+        let text = "a".repeat(200);
+
+        assert!(!glob_matches(&format!("{}b", "**a".repeat(30)), &text));
+        assert!(glob_matches(&"*a".repeat(30), &text));
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+
+use colored::Colorize;
+use itertools::Itertools;
+
+use nix_package_updater::package::Package;
+
+/// Lines Nix prints around every build that say nothing about why it failed
+const NOISE: &[&str] = &[
+    "stdout:",
+    "stderr:",
+    "building '/nix/store/",
+    "copying path '",
+    "these ",
+    "warning: Git tree",
+    "For full logs, run",
+    "last ",
+];
+
+/// Words that mark a line as explaining a failure
+const MARKERS: &[&str] = &[
+    "error",
+    "failed",
+    "failure",
+    "fatal",
+    "panicked",
+    "undefined reference",
+    "cannot find",
+    "not found",
+    "no such file",
+];
+
+/// The lines of a build log that say why it failed: the last `lines` lines mentioning an error, or its last `lines` lines
+/// when none do. Builder output quoted by Nix (`> ...`) is unquoted.
+pub fn excerpt(log: &str, lines: usize) -> Vec<String> {
+    let relevant = log
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            line.strip_prefix("> ").or_else(|| line.strip_prefix('>')).unwrap_or(line).trim_end()
+        })
+        .filter(|line| !line.is_empty() && !NOISE.iter().any(|noise| line.starts_with(noise)))
+        .dedup()
+        .collect_vec();
+
+    let errors = relevant
+        .iter()
+        .filter(|line| {
+            let lower = line.to_lowercase();
+            MARKERS.iter().any(|marker| lower.contains(marker))
+        })
+        .collect_vec();
+
+    let chosen = if errors.is_empty() { relevant.iter().collect_vec() } else { errors };
+
+    chosen[chosen.len().saturating_sub(lines)..].iter().map(|line| (**line).to_string()).collect()
+}
+
+/// After the summary, show each failed build's log excerpt and where the full log is
+pub fn print_failures(packages: &[Package], lines: usize) {
+    let failed = packages.iter().filter_map(|package| Some((package, package.result.failed_log.as_ref()?))).collect_vec();
+
+    if lines == 0 || failed.is_empty() {
+        return;
+    }
+
+    println!("\n{}", "Failures".bright_white().bold());
+
+    for (package, log_file) in failed {
+        println!("\n{} {}", package.name().red().bold(), format!("({})", log_file.display()).dimmed());
+
+        match fs::read_to_string(log_file) {
+            Ok(log) => {
+                for line in excerpt(&log, lines) {
+                    println!("  {line}");
+                }
+            }
+            Err(e) => println!("  {}", format!("Couldn't read the log: {e}").dimmed()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::excerpt;
+
+    #[test]
+    fn excerpts_errors() {
+        let log = "stdout:\n\nstderr:\nbuilding '/nix/store/abc-foo.drv'...\n\
+                   error: builder for '/nix/store/abc-foo.drv' failed with exit code 101;\n       \
+                   last 25 log lines:\n       > Compiling foo v1.0\n       > error[E0425]: cannot find value `x`\n       \
+                   > error: could not compile `foo`\n       For full logs, run 'nix log /nix/store/abc-foo.drv'.\n";
+
+        assert_eq!(excerpt(log, 2), ["error[E0425]: cannot find value `x`", "error: could not compile `foo`"]);
+        assert_eq!(excerpt("stdout:\nstderr:\nbuild timed out\n", 5), ["build timed out"]);
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use rootcause::Result;
+use rusqlite::{Connection, params};
+use tracing::warn;
+
+use nix_package_updater::package::{Package, UpdateStatus};
+
+/// A past update attempt
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub package: String,
+    pub kind: String,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    pub old_rev: Option<String>,
+    pub new_rev: Option<String>,
+    pub status: String,
+    pub message: Option<String>,
+    pub log_path: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// SQLite database of every update and failure, for answering "when was this last bumped?" after the fact
+pub struct History {
+    conn: Connection,
+    /// Where build logs are kept next to the database, as `build-results` only holds the latest run's
+    logs: PathBuf,
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(path)?;
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS updates (
+                id INTEGER PRIMARY KEY,
+                package TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                old_version TEXT,
+                new_version TEXT,
+                old_rev TEXT,
+                new_rev TEXT,
+                status TEXT NOT NULL,
+                message TEXT,
+                log_path TEXT,
+                recorded_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS updates_package ON updates (package, recorded_at);",
+        )?;
+
+        let logs = path.parent().unwrap_or(Path::new(".")).join("logs");
+
+        Ok(Self { conn, logs })
+    }
+
+    /// Record the outcome for every package that was updated or failed. Up-to-date packages are not recorded.
+    pub fn record(&self, packages: &[Package], build_path: &Path) -> Result<()> {
+        let now = Utc::now();
+
+        for package in packages {
+            let result = &package.result;
+
+            let status = if result.status.contains(&UpdateStatus::Failed) {
+                UpdateStatus::Failed
+            } else if result.status.contains(&UpdateStatus::Built) {
+                UpdateStatus::Built
+            } else if result.status.contains(&UpdateStatus::Updated) {
+                UpdateStatus::Updated
+            } else {
+                continue;
+            };
+
+            // The failed build's or check's log, else the build's
+            let log = result.failed_log.clone().unwrap_or_else(|| build_path.join(format!("{}.log", package.name)));
+
+            // Not keeping the log is no reason to lose the entry
+            let log_path = self
+                .keep_log(&log, &package.name, now)
+                .inspect_err(|e| warn!(package = %package.name, "Build log not kept: {e}"))
+                .ok()
+                .flatten();
+
+            self.conn.execute(
+                "INSERT INTO updates (package, kind, old_version, new_version, old_rev, new_rev, status, message, log_path, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    package.name,
+                    package.kind.to_string(),
+                    result.old_version,
+                    result.new_version,
+                    result.old_git_commit,
+                    result.new_git_commit,
+                    status.to_string(),
+                    result.message,
+                    log_path.map(|path| path.display().to_string()),
+                    now,
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy the run's `log` of `package`, when there is one, next to the database, where the next run won't overwrite
+    /// it, returning the copy's absolute path
+    fn keep_log(&self, log: &Path, package: &str, now: DateTime<Utc>) -> Result<Option<PathBuf>> {
+        if !log.is_file() {
+            return Ok(None);
+        }
+
+        let name = log.file_name().map_or_else(|| format!("{package}.log"), |name| name.to_string_lossy().into_owned());
+        let kept = self.logs.join(format!("{}-{name}", now.format("%Y%m%dT%H%M%S")));
+
+        fs::create_dir_all(&self.logs)?;
+        fs::copy(log, &kept)?;
+
+        Ok(Some(std::path::absolute(kept)?))
+    }
+
+    /// The most recent entries, newest first, optionally limited to packages whose name contains `package`
+    pub fn query(&self, package: Option<&str>, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let mut statement = self.conn.prepare(
+            "SELECT package, kind, old_version, new_version, old_rev, new_rev, status, message, log_path, recorded_at
+             FROM updates
+             WHERE ?1 IS NULL OR instr(package, ?1) > 0
+             ORDER BY recorded_at DESC, id DESC
+             LIMIT ?2",
+        )?;
+
+        let entries = statement
+            .query_map(params![package, i64::try_from(limit).unwrap_or(i64::MAX)], |row| {
+                Ok(HistoryEntry {
+                    package: row.get(0)?,
+                    kind: row.get(1)?,
+                    old_version: row.get(2)?,
+                    new_version: row.get(3)?,
+                    old_rev: row.get(4)?,
+                    new_rev: row.get(5)?,
+                    status: row.get(6)?,
+                    message: row.get(7)?,
+                    log_path: row.get(8)?,
+                    recorded_at: row.get(9)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+}
+
+impl HistoryEntry {
+    /// Summary of what changed, e.g. "1.2.0 → 1.3.0"
+    pub fn change(&self) -> String {
+        match (&self.old_version, &self.new_version, &self.old_rev, &self.new_rev) {
+            (Some(old), Some(new), _, _) => format!("{old} → {new}"),
+            (_, _, Some(old), Some(new)) => format!("{} → {}", nix_package_updater::updater::short_hash(old), nix_package_updater::updater::short_hash(new)),
+            _ => String::new(),
+        }
+    }
+}
+
+pub fn print_history(entries: &[HistoryEntry]) {
+    if entries.is_empty() {
+        println!("{}", "No update history recorded".yellow());
+        return;
+    }
+
+    println!(
+        "{:<20} {:<30} {:<8} {:<8} Details",
+        "Date".bright_white().bold(),
+        "Package".bright_white().bold(),
+        "Source".bright_white().bold(),
+        "Status".bright_white().bold(),
+    );
+
+    println!("{}", "-".repeat(80));
+
+    for entry in entries {
+        let status = match entry.status.as_str() {
+            "Failed" => entry.status.red(),
+            "Built" => entry.status.green(),
+            _ => entry.status.yellow(),
+        };
+
+        let details = [Some(entry.change()), entry.message.clone(), entry.log_path.clone()]
+            .into_iter()
+            .flatten()
+            .filter(|detail| !detail.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        println!(
+            "{:<20} {:<30} {:<8} {:<8} {details}",
+            entry.recorded_at.format("%Y-%m-%d %H:%M"),
+            entry.package.cyan(),
+            entry.kind.magenta(),
+            status,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use nix_package_updater::exclude::Exclusions;
+    use nix_package_updater::package::{Package, UpdateStatus};
+
+    use super::History;
+
+    #[test]
+    fn keeps_build_logs_past_the_next_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_path = dir.path().join("build-results");
+        let nix = dir.path().join("tool.nix");
+
+        fs::create_dir_all(&build_path).unwrap();
+        fs::write(&nix, r#"{ pname = "tool"; version = "1.0"; meta = { homepage = "https://github.com/example/tool"; }; }"#).unwrap();
+        fs::write(build_path.join("tool.log"), "first run").unwrap();
+
+        let mut package = Package::from_file(&nix, &[], &Exclusions::default()).packages.remove(0);
+        package.result.status.insert(UpdateStatus::Built);
+
+        let history = History::open(&dir.path().join("data").join("history.db")).unwrap();
+        history.record(&[package], &build_path).unwrap();
+
+        // The next run's build overwrites the log in build-results
+        fs::write(build_path.join("tool.log"), "second run").unwrap();
+
+        let entries = history.query(Some("tool"), 1).unwrap();
+        let log = entries[0].log_path.as_deref().map(Path::new).unwrap();
+
+        assert!(log.is_absolute());
+        assert!(log.starts_with(dir.path().join("data").join("logs")));
+        assert_eq!(fs::read_to_string(log).unwrap(), "first run");
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+use rootcause::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::messages::MessagesConfig;
+use crate::notify::Summary;
+use crate::package::Package;
+
+/// Shell commands run around every package's update, with the package described in `NIX_UPDATER_*` variables.
+///
+/// The updater doesn't commit anything itself; hooks do, with the `[messages]` templates. `NIX_UPDATER_VCS` says whether
+/// the repository is a Jujutsu (`jj`, also when colocated with git) or plain `git` one.
+///
+/// ```toml
+/// [hooks]
+/// pre_update = "./scripts/login.sh"
+/// post_update = "nixfmt \"$NIX_UPDATER_FILE\""
+/// notify = "notify-send 'Nix packages' \"$NIX_UPDATER_SUMMARY\""
+/// # or, for a commit per update in a jj working copy:
+/// # post_update = "jj commit -m \"$NIX_UPDATER_COMMIT_MESSAGE\" \"$NIX_UPDATER_FILE\""
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Runs before a package is checked; if it fails, the package is failed without checking it
+    pub pre_update: Option<String>,
+
+    /// Runs after an update has been written, with `NIX_UPDATER_OLD_VERSION`/`NIX_UPDATER_NEW_VERSION` and the
+    /// `[messages] commit` message in `NIX_UPDATER_COMMIT_MESSAGE` set too
+    pub post_update: Option<String>,
+
+    /// Runs once after a run that updated or failed packages, with a line per package in `NIX_UPDATER_SUMMARY` and the
+    /// `[messages]` pull request in `NIX_UPDATER_PR_TITLE`/`NIX_UPDATER_PR_BODY`
+    pub notify: Option<String>,
+}
+
+/// Run `command` with `sh -c`, describing `package` in the environment
+pub fn shell(command: &str, package: &Package) -> Result<Output> {
+    Ok(shell_command(command, package).output()?)
+}
+
+/// The version control system of the repository in the current directory: `jj` when there's a `.jj`, else `git` when
+/// there's a `.git`
+pub fn vcs() -> Option<&'static str> {
+    [("jj", ".jj"), ("git", ".git")].into_iter().find(|(_, dir)| Path::new(dir).exists()).map(|(name, _)| name)
+}
+
+fn shell_command(command: &str, package: &Package) -> Command {
+    let mut shell = Command::new("sh");
+
+    shell
+        .arg("-c")
+        .arg(command)
+        .env("NIX_UPDATER_PACKAGE", &package.name)
+        .env("NIX_UPDATER_FILE", &package.path)
+        .env("NIX_UPDATER_VERSION", &package.version)
+        .env("NIX_UPDATER_HOMEPAGE", package.homepage.to_string());
+
+    if let Some(vcs) = vcs() {
+        shell.env("NIX_UPDATER_VCS", vcs);
+    }
+
+    if let Some(old_version) = &package.result.old_version {
+        shell.env("NIX_UPDATER_OLD_VERSION", old_version);
+    }
+
+    if let Some(new_version) = &package.result.new_version {
+        shell.env("NIX_UPDATER_NEW_VERSION", new_version);
+    }
+
+    shell
+}
+
+/// Run a hook with `env` set on top of the package's variables, failing with its stderr if it exits unsuccessfully
+pub fn run_hook(name: &str, command: &str, package: &Package, env: &[(&str, String)]) -> Result<()> {
+    let output = shell_command(command, package).envs(env.iter().map(|(key, value)| (key, value))).output()?;
+
+    if !output.status.success() {
+        bail!("{name} hook failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(())
+}
+
+/// Run the `notify` hook with what a run updated and what failed, if anything did
+pub fn notify(command: &str, packages: &[Package], messages: &MessagesConfig) -> Result<()> {
+    let summary = Summary::new(packages);
+
+    if summary.is_empty() {
+        return Ok(());
+    }
+
+    let mut shell = Command::new("sh");
+
+    shell
+        .arg("-c")
+        .arg(command)
+        .env("NIX_UPDATER_SUMMARY", summary.lines().join("\n"))
+        .env("NIX_UPDATER_PR_TITLE", messages.pr_title(packages))
+        .env("NIX_UPDATER_PR_BODY", messages.pr_body(packages));
+
+    if let Some(vcs) = vcs() {
+        shell.env("NIX_UPDATER_VCS", vcs);
+    }
+
+    let output = shell.output()?;
+
+    if !output.status.success() {
+        bail!("notify hook failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(())
+}
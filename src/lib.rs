@@ -0,0 +1,136 @@
+//! The update logic behind `nix-package-updater`, for automation that wants to drive it directly rather than through the
+//! CLI: find the packages a repository defines, check them against their upstreams with the [`Updaters`], land the
+//! edits to their `.nix` files, and build them.
+//!
+//! ```no_run
+//! use nix_package_updater::{Discovery, Exclusions, Reporter, UpdateError, UpdateOptions, UpdateReport, Updaters, discover};
+//!
+//! # fn main() -> rootcause::Result<()> {
+//! let mut packages = discover(Discovery::Files, &[], &Exclusions::default())?.packages;
+//! let updaters = Updaters::new(&UpdateOptions::default())?;
+//! let reporter = Reporter::detect(packages.len());
+//!
+//! for package in &mut packages {
+//!     let pb = reporter.add();
+//!
+//!     if let Err(e) = updaters.update(package, &pb, None) {
+//!         package.result.fail(UpdateError::from_report(&e));
+//!     }
+//!
+//!     // Writes the edits of an update that succeeded and still evaluates, and discards the rest
+//!     let _ = package.land();
+//! }
+//!
+//! let reports = packages.iter().map(UpdateReport::from).collect::<Vec<_>>();
+//! println!("{}", serde_json::to_string_pretty(&reports)?);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod clients;
+pub mod exclude;
+pub mod nix;
+pub mod package;
+pub mod progress;
+pub mod provenance;
+pub mod settings;
+pub mod tools;
+pub mod transaction;
+pub mod updater;
+
+// What the `nix-package-updater` binaries build their runs with (backups for `revert`, hooks, notifications, commit
+// messages, `--record`/`--replay`), rather than part of the library's API
+#[doc(hidden)]
+pub mod backup;
+#[doc(hidden)]
+pub mod hooks;
+#[doc(hidden)]
+pub mod messages;
+#[doc(hidden)]
+pub mod notify;
+#[doc(hidden)]
+pub mod recording;
+
+mod cache;
+mod error;
+mod report;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use rootcause::Result;
+use serde::{Deserialize, Serialize};
+
+pub use crate::error::UpdateError;
+pub use crate::exclude::Exclusions;
+pub use crate::package::{Discovered, Package, PackageKind, Skipped, UpdateStatus};
+pub use crate::progress::{Progress, Reporter};
+pub use crate::report::UpdateReport;
+pub use crate::settings::PackageSettings;
+pub use crate::updater::{UpdateOptions, Updater, Updaters};
+use crate::nix::flake::package_files;
+
+/// Where packages are looked for
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Discovery {
+    /// Every `.nix` file under `packages/` and `nix/packages/`
+    #[default]
+    Files,
+    /// The files defining `packages.<system>`, including ones wired up via `callPackage`
+    Flake,
+}
+
+/// The packages defined in the repository in the current directory, limited to `names` unless it's empty and leaving out
+/// what `exclusions` match, along with the files skipped because they couldn't be read as packages. Their settings are
+/// the defaults; `config.toml` is the CLI's business.
+pub fn discover(discovery: Discovery, names: &[String], exclusions: &Exclusions) -> Result<Discovered> {
+    Ok(match discovery {
+        Discovery::Files => ["packages/", "nix/packages/"]
+            .iter()
+            .map(|&path| Package::discover(Path::new(path), names, exclusions))
+            .collect(),
+        Discovery::Flake => {
+            let mut exported = BTreeMap::<PathBuf, Vec<String>>::new();
+
+            for (attr, path) in package_files()? {
+                exported.entry(path).or_default().push(attr);
+            }
+
+            exported
+                .into_iter()
+                .map(|(path, attrs)| {
+                    let mut found = Package::from_file(&path, names, exclusions);
+                    let single = found.packages.len() == 1;
+
+                    for package in &mut found.packages {
+                        package.attr = exported_as(&package.name, &attrs, single);
+                    }
+
+                    found
+                })
+                .collect()
+        }
+    })
+}
+
+/// Which of the attributes exporting a file's packages is this one: the attribute named after its pname, or in a
+/// file defining a single package, the first of them
+fn exported_as(pname: &str, attrs: &[String], single: bool) -> Option<String> {
+    attrs.iter().find(|attr| *attr == pname).or_else(|| attrs.first().filter(|_| single)).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::exported_as;
+
+    #[test]
+    fn matches_packages_to_the_attributes_exporting_them() {
+        let attrs = ["rg".to_string(), "ripgrep".to_string()];
+
+        assert_eq!(exported_as("ripgrep", &attrs, false).as_deref(), Some("ripgrep"));
+        assert_eq!(exported_as("ripgrep-all", &attrs[..1], true).as_deref(), Some("rg"));
+        assert_eq!(exported_as("ripgrep-all", &attrs, false), None);
+    }
+}
@@ -1,26 +1,39 @@
 #![allow(clippy::module_name_repetitions, clippy::missing_errors_doc, clippy::missing_panics_doc, clippy::struct_excessive_bools)]
 
-mod clients;
-mod nix;
-mod package;
-mod updater;
+mod audit;
+mod branch;
+mod diff;
+mod doctor;
+mod failures;
+mod history;
+mod metrics;
+mod outdated;
+mod pins;
+mod prefetch;
+mod schedule;
+mod serve;
+mod state;
+mod systemd;
+mod table;
+mod watch;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
-use std::time::Duration;
-use std::{fs, io};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{fs, io, thread};
 
-use clap::{CommandFactory, Parser};
+use chrono::{DateTime, Utc};
+use clap::{Args, CommandFactory, Parser, Subcommand};
 use clap_complete::{Shell, generate};
 use colored::Colorize;
 use etcetera::base_strategy::{BaseStrategy, choose_base_strategy};
 use figment::Figment;
 use figment::providers::{Env, Format, Serialized, Toml};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use rayon::prelude::*;
 use rootcause::hooks::Hooks;
-use rootcause::{Result, report};
+use rootcause::{Report, Result, bail, report};
 use rootcause_backtrace::BacktraceCollector;
 use rootcause_tracing::{RootcauseLayer, SpanCollector};
 use serde::{Deserialize, Serialize};
@@ -30,15 +43,46 @@ use tracing_subscriber::EnvFilter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
-use crate::nix::builder::build_package;
-use crate::package::{Package, PackageKind, UpdateStatus};
-use crate::updater::Updater;
-use crate::updater::cargo::Cargo;
-use crate::updater::git::GitRepository;
-use crate::updater::github::GitHubRelease;
-use crate::updater::go::GoUpdater;
-use crate::updater::npm::NpmUpdater;
-use crate::updater::pypi::PyPiUpdater;
+use nix_package_updater::backup::{RunBackup, print_restored, revert};
+use nix_package_updater::clients::GitHubClient;
+use nix_package_updater::clients::gitlab::GitLabClient;
+use nix_package_updater::clients::http::{Http, HttpConfig};
+use nix_package_updater::clients::nix::Nix;
+use nix_package_updater::exclude::{Exclusions, IGNORE_FILE};
+use nix_package_updater::hooks::{HooksConfig, notify, run_hook};
+use nix_package_updater::messages::MessagesConfig;
+use nix_package_updater::nix::binary_cache::BinaryCacheConfig;
+use nix_package_updater::nix::builder::{BuildConfig, BuildOptions, BuildSlots, build_dependents, build_package};
+use nix_package_updater::nix::closure;
+use nix_package_updater::nix::dependents::Dependents;
+use nix_package_updater::nix::inputs::check_inputs;
+use nix_package_updater::nix::lock::FlakeInputs;
+use nix_package_updater::nix::sri::migrate_hashes;
+use nix_package_updater::nix::verify::verify_hashes;
+use nix_package_updater::notify::{Notifier, send_notifications};
+use nix_package_updater::package::{Package, PackageKind, UpdateResult, UpdateStatus};
+use nix_package_updater::progress::{Progress, Reporter, color_enabled};
+use nix_package_updater::recording::Recording;
+use nix_package_updater::settings::{Group, PackageSettings};
+use nix_package_updater::tools::Tools;
+use nix_package_updater::transaction::Transaction;
+use nix_package_updater::updater::{UpdateOptions, Updaters};
+use nix_package_updater::{Discovered, Discovery, Skipped, UpdateError, discover, provenance};
+
+use crate::audit::{find_package_file, print_audit};
+use crate::branch::UpdateBranch;
+use crate::diff::print_diffs;
+use crate::doctor::print_doctor;
+use crate::failures::print_failures;
+use crate::history::{History, print_history};
+use crate::metrics::write_metrics;
+use crate::outdated::{check_outdated, print_outdated};
+use crate::pins::{PinFile, print_pins, update_pins};
+use crate::prefetch::{Target, print_prefetch};
+use crate::serve::Dashboard;
+use crate::state::RunState;
+use crate::systemd::{UnitOptions, generate_systemd};
+use crate::table::{Cell, Column, Table};
 
 #[derive(Parser, Clone, Debug, Serialize, Deserialize)]
 #[command(
@@ -66,32 +110,78 @@ Examples:
     nix-package-updater --type pypi
 
     # Build only, no updates
-    nix-package-updater --build-only
+    nix-package-updater build
+
+    # Report available updates without changing anything
+    nix-package-updater check
+
+    # Report build inputs missing from the pinned nixpkgs
+    nix-package-updater check --inputs
+
+    # List the packages that would be processed
+    nix-package-updater list
+
+    # Check that the external tools are installed
+    nix-package-updater doctor
 
     # Force update even if up to date
     nix-package-updater --force
 
+    # Also build the packages depending on what was updated
+    nix-package-updater --dependents
+
+    # Verify updated packages build reproducibly before caching them
+    nix-package-updater --check-reproducibility
+
+    # Keep running, checking for updates every 6 hours
+    nix-package-updater --watch --interval 6h
+
     # Push successful builds to cachix
     nix-package-updater --cache
 
+    # Show recent updates and failures
+    nix-package-updater history
+
+    # Hash a source for a hand-written derivation
+    nix-package-updater prefetch github:owner/repo@v1.2.3
+
+    # Write a systemd service and timer for scheduled updates
+    nix-package-updater generate systemd --output ~/.config/systemd/user -- update --cache
+
     # Generate shell completions
     nix-package-updater completions bash"#
 )]
 struct Config {
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+
+    /// Packages to update (all when none are given). Command-line only; `packages` in config.toml holds per-package settings
+    #[serde(skip)]
     packages: Vec<String>,
 
+    /// Also process the packages of this `[groups.<name>]` from config.toml, repeatable
     #[arg(long, global = true)]
-    exclude: Vec<String>,
+    group: Vec<String>,
 
-    /// Skip updating packages, only build
+    /// Leave out packages matching this pattern, repeatable: a name, a glob (`python3*`, `packages/generated/`) or a
+    /// `/regex/`, matched against package names and file paths. Patterns in `.nix-updater-ignore` are left out too.
     #[arg(long, global = true)]
+    exclude: Vec<String>,
+
+    /// How packages are found: walk `packages/` and `nix/packages/`, or evaluate the flake's `packages` output
+    #[arg(long, global = true, value_enum, default_value = "files")]
+    discover: Discovery,
+
+    /// Skip updating packages, only build. Set by `build`; the flag is kept for old invocations.
+    #[arg(long, global = true, hide = true, conflicts_with_all = ["force", "since", "check_inputs", "migrate_cargo_vendor"])]
     build_only: bool,
 
     /// Force update even if packages are up to date
     #[arg(short, long, global = true)]
     force: bool,
 
-    /// Push successful builds to cachix
+    /// Push successful builds to the binary cache (cachix unless `[binary_cache]` says otherwise)
     #[arg(short, long, global = true, default_value = "true")]
     cache: bool,
 
@@ -99,16 +189,366 @@ struct Config {
     #[arg(short, long, global = true)]
     verbose: bool,
 
-    /// Dry run - show what would be updated without making changes
+    /// Summary table columns, comma-separated (default: package,source,updated,built,systems,checked,cached,details,
+    /// plus reproducible with --check-reproducibility)
+    #[arg(long, global = true, value_enum, value_delimiter = ',')]
+    columns: Option<Vec<Column>>,
+
+    /// Plain output without colors or hyperlinks; also the default when NO_COLOR is set or stdout isn't a terminal
     #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Show what would be updated without making changes or building. Set by `check`; the flag is kept for old invocations.
+    #[arg(long, global = true, hide = true)]
     dry_run: bool,
 
-    /// Generate shell completions
+    /// Re-process packages even if a recent run already handled them
+    #[arg(long, global = true)]
+    no_resume: bool,
+
+    /// Skip packages successfully processed within this many hours
+    #[arg(long, global = true, default_value = "12")]
+    resume_hours: u64,
+
+    /// Maximum number of packages checked concurrently (defaults to the number of CPUs)
+    #[arg(short, long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Maximum number of `nix build` invocations running at once (defaults to no limit beyond --jobs)
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    build_jobs: Option<u16>,
+
+    /// Build each package's `checks.<system>.<name>` flake output after it builds, before pushing to cachix
+    #[arg(long, global = true)]
+    check: bool,
+
+    /// Rebuild each built package with `nix build --rebuild` and report those whose outputs differ, pushing only
+    /// reproducible outputs to the binary cache
+    #[arg(long, global = true)]
+    check_reproducibility: bool,
+
+    /// Stop starting new checks and builds after the first package fails
+    #[arg(long, global = true, conflicts_with = "max_failures")]
+    fail_fast: bool,
+
+    /// Stop starting new checks and builds once this many packages have failed, e.g. when credentials have expired
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    max_failures: Option<u16>,
+
+    /// Extra argument passed to every `nix build`, repeatable (e.g. --nix-arg=--max-jobs --nix-arg=4)
+    #[arg(long = "nix-arg", global = true, allow_hyphen_values = true)]
+    #[serde(skip)]
+    nix_args: Vec<String>,
+
+    /// Rewrite Rust packages still vendored with fetchCargoTarball (`cargoSha256`, or `cargoHash` without `useFetchCargoVendor`) to fetchCargoVendor
+    #[arg(long, global = true)]
+    migrate_cargo_vendor: bool,
+
+    /// Report build inputs that no longer exist (or were renamed) in the pinned nixpkgs, without updating anything.
+    /// Set by `check --inputs`; the flag is kept for old invocations.
+    #[arg(long, global = true, hide = true)]
+    check_inputs: bool,
+
+    /// Fetch each package's current sources again and report those whose recorded hash no longer matches, without
+    /// updating anything. Set by `check --verify`.
+    #[arg(long, global = true, hide = true, conflicts_with_all = ["build_only", "check_inputs", "watch"])]
+    verify: bool,
+
+    /// Also update the sources pinned in `npins/sources.json` or niv's `nix/sources.json`
+    #[arg(long, global = true)]
+    pins: bool,
+
+    /// After a package updates, also build the flake packages that depend on it and report whether they still build
+    #[arg(long, global = true)]
+    dependents: bool,
+
+    /// Refresh the flake.lock inputs updated packages refer to (or the configured `flake_inputs`) with `nix flake update`
+    #[arg(long, global = true)]
+    update_inputs: bool,
+
+    /// Keep running, checking for updates again every --interval
+    #[arg(long, global = true, conflicts_with_all = ["build_only", "check_inputs"])]
+    watch: bool,
+
+    /// How long --watch waits between rounds (e.g. 6h, 30m), give or take a tenth
+    #[arg(long, global = true, default_value = "6h")]
+    interval: String,
+
+    /// With --watch, serve `GET /status`, `GET /report.json` and `POST /trigger` (an on-demand round) as JSON on this
+    /// address, e.g. 127.0.0.1:8080, for a dashboard
+    #[arg(long, global = true, value_name = "ADDR", requires = "watch")]
+    listen: Option<String>,
+
+    /// Write Prometheus metrics for node_exporter's textfile collector here after each run (e.g.
+    /// /var/lib/node_exporter/textfile/nix-package-updater.prom)
     #[arg(long, global = true)]
-    completions: Option<String>,
+    metrics_file: Option<PathBuf>,
+
+    /// Save every HTTP request, GitHub API call and external command (prefetches, git, nurl, hash-mismatch builds) and
+    /// its outcome as JSON files in this directory, to replay the run later with --replay
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer every HTTP request, GitHub API call and external command from a --record directory instead of making
+    /// it, failing those it doesn't have, to reproduce a run's update decisions offline. Implies --dry-run.
+    #[arg(long, global = true, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    /// Lines of each failed build's log shown in the failure summary after a run (0 leaves it out)
+    #[arg(long, global = true, default_value = "20")]
+    failure_lines: usize,
+
+    /// Only check packages whose upstream published something within this window (e.g. 7d, 12h)
+    #[arg(long, global = true)]
+    since: Option<String>,
+
+    /// Make the run's edits on this git branch (created from the current commit if needed, `{date}` standing for
+    /// today's date) and commit them there, then switch back
+    #[arg(long, global = true)]
+    branch: Option<String>,
+
+    /// With --branch, work in a temporary worktree so the current checkout isn't touched at all
+    #[arg(long, global = true, requires = "branch")]
+    worktree: bool,
+
+    /// With --branch, push the branch to `origin` and open a GitLab merge request for it (or find the open one) into
+    /// the branch the run started on
+    #[arg(long, global = true, requires = "branch")]
+    merge_request: bool,
+
+    /// Kind-wide settings, `[defaults.<kind>]` in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    defaults: BTreeMap<String, PackageSettings>,
+
+    /// Per-package settings, `[packages.<name>]` in config.toml
+    #[arg(skip)]
+    #[serde(default, rename = "packages")]
+    package_settings: BTreeMap<String, PackageSettings>,
+
+    /// Named sets of packages for `--group`, `[groups.<name>]` in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    groups: BTreeMap<String, Group>,
+
+    /// Flake inputs `--update-inputs` refreshes once per run, instead of those updated packages refer to, configured
+    /// in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    flake_inputs: Vec<String>,
+
+    /// GitHub API token, configured in config.toml, used when neither GITHUB_TOKEN nor GH_TOKEN is set
+    #[arg(skip)]
+    #[serde(default)]
+    github_token: Option<String>,
+
+    /// GitLab API token for `--merge-request`, configured in config.toml, used instead of GITLAB_TOKEN
+    #[arg(skip)]
+    #[serde(default)]
+    gitlab_token: Option<String>,
+
+    /// Secret the provenance log's entries are signed with (HMAC-SHA256), configured in config.toml. Without one the
+    /// log is only chained, which shows entries were removed or reordered but not that one was rewritten.
+    #[arg(skip)]
+    #[serde(default)]
+    provenance_key: Option<String>,
+
+    /// HTTP timeouts, retries and proxy, configured in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    http: HttpConfig,
+
+    /// Paths to external tools, configured in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    tools: Tools,
+
+    /// Systems to build for, configured in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    build: BuildConfig,
+
+    /// Commands run before and after each package's update, configured in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    hooks: HooksConfig,
+
+    /// Commit message and pull request templates handed to the hooks, configured in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    messages: MessagesConfig,
+
+    /// Webhooks (generic JSON, Slack, Matrix) told what a run updated and failed, configured in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    notifications: Vec<Notifier>,
+
+    /// Where `--cache` pushes builds (cachix, or any store via `nix copy`), configured in config.toml
+    #[arg(skip)]
+    #[serde(default)]
+    binary_cache: BinaryCacheConfig,
+}
+
+/// The packages a run works on
+#[derive(Args, Clone, Debug)]
+struct Selection {
+    /// Packages to process (all when none are given)
+    packages: Vec<String>,
 }
 
-fn init_tracing(verbose: bool) {
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Check for updates, apply them and build what changed (what a bare invocation does)
+    Update {
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// Build packages without checking for updates
+    Build {
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// Report which packages have updates available, without changing or building anything
+    Check {
+        /// Instead, report build inputs that no longer exist (or were renamed) in the pinned nixpkgs
+        #[arg(long)]
+        inputs: bool,
+
+        /// Instead, fetch each package's current sources (and platform assets) again and report those whose recorded
+        /// hash no longer matches upstream, e.g. retagged releases or replaced assets
+        #[arg(long, conflicts_with = "inputs")]
+        verify: bool,
+
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// List the packages a run would process
+    List {
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// List each package's current and latest upstream version, from registry and forge metadata alone: nothing is
+    /// prefetched, changed or built
+    Outdated {
+        /// Print a JSON array instead of a table
+        #[arg(long)]
+        json: bool,
+
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// Show what changed upstream between the packages' pinned revision and the latest one: commit count, shortlog and
+    /// release notes, from GitHub
+    Diff {
+        /// Maximum number of commits to list per package
+        #[arg(short = 'n', long, default_value = "50")]
+        limit: usize,
+
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// Rewrite legacy `sha256 = "<base32>"` attributes in package files to SRI `hash = "sha256-..."`, reporting `md5`
+    /// hashes and anything else that has to be migrated by hand. With `--dry-run`, print the changes instead.
+    MigrateHashes {
+        #[command(flatten)]
+        selection: Selection,
+    },
+
+    /// Show previously recorded updates and failures
+    History {
+        /// Only show packages whose name contains this
+        package: Option<String>,
+
+        /// Maximum number of entries to show
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
+
+    /// Put back the files the last run changed, or only those of one package as they were before the last run that
+    /// changed it; each backup is used once, so reverting again goes back another run
+    Revert {
+        /// Package name (pname)
+        package: Option<String>,
+    },
+
+    /// Check that every entry of the provenance log chains on from the one before and, with `provenance_key`
+    /// configured, is signed with it
+    VerifyProvenance {
+        /// The log to check
+        #[arg(default_value = "build-results/provenance.jsonl")]
+        path: PathBuf,
+    },
+
+    /// Show which attributes of a package file the updater can read and rewrite
+    Audit {
+        /// Package name (pname) or path to its .nix file
+        package: String,
+    },
+
+    /// Print SRI hashes and a fetcher expression for a URL or `github:owner/repo[@rev]`
+    Prefetch { target: String },
+
+    /// Generate shell completions
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Check that the external tools and configuration a run needs are in place
+    Doctor,
+
+    /// Generate files for running the updater as a service
+    Generate {
+        #[command(subcommand)]
+        target: Generate,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Generate {
+    /// A systemd service and timer running scheduled updates from the current directory, with the current PATH
+    Systemd {
+        /// When the timer fires, as a systemd `OnCalendar=` expression
+        #[arg(long, default_value = "daily")]
+        on_calendar: String,
+
+        /// File the service reads secrets such as GITHUB_TOKEN from
+        #[arg(long)]
+        environment_file: Option<PathBuf>,
+
+        /// Write `nix-package-updater.{service,timer}` into this directory instead of printing them
+        #[arg(long)]
+        output: Option<PathBuf>,
+
+        /// Arguments for the scheduled run, after `--` (e.g. `-- update --cache`)
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+}
+
+impl Command {
+    /// The packages selected by a command that runs over packages, `None` for the standalone ones
+    fn selection(&self) -> Option<&Selection> {
+        match self {
+            Self::Update { selection }
+            | Self::Build { selection }
+            | Self::Check { selection, .. }
+            | Self::List { selection }
+            | Self::Outdated { selection, .. }
+            | Self::Diff { selection, .. }
+            | Self::MigrateHashes { selection } => Some(selection),
+            Self::History { .. } | Self::Revert { .. } | Self::VerifyProvenance { .. } | Self::Audit { .. } | Self::Prefetch { .. } | Self::Completions { .. } | Self::Doctor | Self::Generate { .. } => None,
+        }
+    }
+}
+
+fn init_tracing(verbose: bool, color: bool) {
     let indicatif_layer = IndicatifLayer::new();
 
     let filter = if verbose {
@@ -124,6 +564,7 @@ fn init_tracing(verbose: bool) {
             tracing_subscriber::fmt::layer()
                 .without_time()
                 .with_target(false)
+                .with_ansi(color)
                 .with_writer(indicatif_layer.get_stderr_writer()),
         )
         .with(indicatif_layer)
@@ -135,141 +576,1078 @@ fn init_tracing(verbose: bool) {
         .install();
 }
 
-fn discover_packages(config: &Config) -> Vec<Package> {
-    ["packages/", "nix/packages/"]
+impl Config {
+    fn update_options(&self) -> UpdateOptions {
+        UpdateOptions {
+            force: self.force,
+            migrate_cargo_vendor: self.migrate_cargo_vendor,
+        }
+    }
+
+    /// How many packages may fail before the run stops starting new work
+    fn failure_limit(&self) -> Option<usize> {
+        if self.fail_fast { Some(1) } else { self.max_failures.map(usize::from) }
+    }
+
+    /// Whether packages a recent run already handled are left out. Every `--watch` round checks everything again, and
+    /// packages asked for by name, with `--force`, or to be built are wanted whatever happened to them last time.
+    fn resumes(&self) -> bool {
+        !self.no_resume && !self.dry_run && !self.watch && !self.verify && !self.force && !self.build_only && self.packages.is_empty()
+    }
+
+    /// Kind defaults for the package, overridden by its own `[packages.<name>]` section
+    fn settings_for(&self, package: &Package) -> PackageSettings {
+        let defaults = self.defaults.get(&package.kind.to_string().to_lowercase()).cloned().unwrap_or_default();
+
+        match self.package_settings.get(&package.name) {
+            Some(overrides) => defaults.merge(overrides),
+            None => defaults,
+        }
+    }
+}
+
+fn discover_packages(config: &Config, exclusions: &Exclusions) -> Result<Discovered> {
+    let mut discovered = discover(config.discover, &config.packages, exclusions)?;
+
+    for package in &mut discovered.packages {
+        // A forced kind also decides which kind defaults apply
+        if let Some(kind) = config.package_settings.get(&package.name).and_then(|settings| settings.kind) {
+            package.kind = kind;
+        }
+
+        package.settings = config.settings_for(package);
+    }
+
+    Ok(discovered)
+}
+
+/// Check every package for updates first, so what will change is known early, then build shortest-expected-first
+fn process_packages(
+    packages: &mut [Package],
+    config: &Config,
+    updaters: Option<&Updaters>,
+    flake_inputs: Option<&FlakeInputs>,
+    build_path: &Path,
+    state: &Mutex<RunState>,
+    since: Option<DateTime<Utc>>,
+) {
+    let reporter = Reporter::detect(packages.len());
+
+    let stopped = |package: &mut Package, pb: &Progress| skip_past_failure_limit(&mut package.result, config.failure_limit(), &reporter, pb);
+
+    // A package stopped while checking is done with, even when `--force` or `--build-only` would build it
+    let needs_build = |package: &Package| {
+        !config.dry_run && !package.result.stopped && (package.result.status.contains(&UpdateStatus::Updated) || config.force || config.build_only)
+    };
+
+    if let Some(updaters) = updaters {
+        // Packages sharing a file are checked one after another, each on top of the edits its predecessors landed
+        let files = packages.chunk_by_mut(|a, b| a.path == b.path).collect_vec();
+
+        files.into_par_iter().for_each(|siblings| {
+            for (index, package) in siblings.iter_mut().enumerate() {
+                let pb = reporter.add();
+
+                if stopped(package, &pb) {
+                    continue;
+                }
+
+                if index > 0
+                    && let Err(e) = package.reload()
+                {
+                    package.result.failed(format!("Read error: {e}"));
+                } else {
+                    check_package(package, config, updaters, flake_inputs, &pb, since);
+                }
+
+                if !needs_build(package) {
+                    record_state(package, config, state, &pb);
+                }
+
+                pb.finish_and_clear();
+            }
+        });
+    }
+
+    let mut builds = packages.iter_mut().filter(|package| needs_build(package)).collect_vec();
+
+    if let Ok(state) = state.lock() {
+        schedule::shortest_first(&mut builds, |package| state.expected_build_time(package));
+    }
+
+    let build_slots = BuildSlots::new(config.build_jobs.map_or(usize::MAX, usize::from));
+
+    // Check once whether pushing can work at all, rather than failing every package on it
+    let cache_unavailable = if config.cache && !builds.is_empty() { config.binary_cache.unavailable() } else { None };
+
+    if let Some(reason) = &cache_unavailable {
+        warn!("Not pushing builds: {reason}");
+    }
+
+    let graph = dependency_graph(&builds, config);
+    let names = builds.iter().map(|package| package.flake_attr().to_string()).collect_vec();
+    let building = names.iter().cloned().collect::<BTreeSet<_>>();
+
+    schedule::run_in_order(builds, &build_dependencies(&names, graph.as_ref()), |package, failed| {
+        let pb = reporter.add();
+
+        if stopped(package, &pb) {
+            return false;
+        }
+
+        if !failed.is_empty() {
+            package.result.fail(UpdateError::Build {
+                message: format!("Not built: {} failed to build", failed.iter().map(|&index| &names[index]).join(", ")),
+                log: None,
+            });
+            record_state(package, config, state, &pb);
+            pb.finish_and_clear();
+            return false;
+        }
+
+        pb.set_message(format!("{}: Waiting for a build slot ...", package.name()));
+
+        let _slot = build_slots.acquire();
+        let counted = pb.building();
+        let started = Instant::now();
+
+        if let Err(e) = build_package(
+            package,
+            &pb,
+            build_path,
+            BuildOptions {
+                push: (config.cache && cache_unavailable.is_none() && package.settings.push()).then_some(&config.binary_cache),
+                verbose: config.verbose,
+                check: config.check,
+                reproducible: config.check_reproducibility,
+                systems: &config.build.systems,
+            },
+        ) {
+            pb.suspend(|| error!(package = %package.name, "Build failed: {e}"));
+            package.result.fail(build_error(&e));
+        }
+
+        // Dependents being built in their own right already build against the update
+        if config.dependents
+            && let Some(graph) = &graph
+            && package.result.status.contains(&UpdateStatus::Built)
+            && package.result.status.contains(&UpdateStatus::Updated)
+        {
+            let pending = graph.of(package.flake_attr()).filter(|dependent| !building.contains(*dependent)).collect_vec();
+
+            if let Err(e) = build_dependents(package, &pending, &pb, build_path, config.verbose) {
+                pb.suspend(|| warn!(package = %package.name, "Building dependents failed: {e}"));
+                package.result.message(format!("Dependents not built: {e}"));
+            }
+        }
+
+        package.result.build_duration = Some(started.elapsed());
+        drop(counted);
+
+        if package.result.status.contains(&UpdateStatus::Built) {
+            record_closure(package, state, build_path);
+        }
+
+        if let Some(reason) = &cache_unavailable
+            && package.result.status.contains(&UpdateStatus::Built)
+        {
+            package.result.message(format!("Not cached: {reason}"));
+        }
+
+        record_state(package, config, state, &pb);
+
+        pb.finish_and_clear();
+
+        package.result.status.contains(&UpdateStatus::Built)
+    });
+
+    reporter.finish();
+}
+
+/// Measure the built package's closure against the previous build's, without failing the package when that fails
+fn record_closure(package: &mut Package, state: &Mutex<RunState>, build_path: &Path) {
+    let previous = state.lock().ok().and_then(|state| state.previous_closure(package));
+
+    match closure::measure(package, previous.as_ref().map(|(path, size)| (path.as_str(), *size)), build_path) {
+        Ok(closure) => package.result.closure = Some(closure),
+        Err(e) => warn!(package = %package.name, "Closure size not measured: {e}"),
+    }
+}
+
+/// For each build, the other builds it depends on. Dependencies build first, so their dependents build against them
+/// rather than racing them.
+fn build_dependencies(names: &[String], graph: Option<&Dependents>) -> Vec<Vec<usize>> {
+    names
         .iter()
-        .flat_map(|&path| Package::discover(Path::new(path), &config.packages, &config.exclude))
+        .map(|name| {
+            let depends_on = |dependency: &String| graph.is_some_and(|graph| graph.of(dependency).any(|dependent| dependent == name));
+
+            names.iter().positions(depends_on).collect_vec()
+        })
         .collect_vec()
 }
 
-fn process_packages(packages: &mut [Package], config: &Config, build_path: &Path) {
-    let multi = MultiProgress::new();
+/// Past the failure `limit`, packages not yet started are skipped and left for the next run. Each is counted as done
+/// once, however many phases it's stopped in.
+fn skip_past_failure_limit(result: &mut UpdateResult, limit: Option<usize>, reporter: &Reporter, pb: &Progress) -> bool {
+    if result.stopped {
+        pb.finish_and_clear();
+        return true;
+    }
+
+    let Some(limit) = limit.filter(|limit| reporter.failed() >= *limit) else {
+        return false;
+    };
+
+    result.stopped = true;
+    result.message(format!("Skipped: run stopped after {limit} failed package(s)"));
+    pb.done(false);
+    pb.finish_and_clear();
 
-    let style = ProgressStyle::with_template("{spinner:.cyan.bold} {msg}")
-        .expect("Couldn't set spinner style")
-        .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ");
+    true
+}
 
-    packages.par_iter_mut().for_each(|package| {
-        let pb = multi.add(ProgressBar::new_spinner());
-        pb.enable_steady_tick(Duration::from_millis(50));
-        pb.set_style(style.clone());
+/// The flake's dependency graph, when there are several builds to order or, with `--dependents`, an update whose
+/// consumers need building
+fn dependency_graph(builds: &[&mut Package], config: &Config) -> Option<Dependents> {
+    let wanted = builds.len() > 1 || (config.dependents && builds.iter().any(|package| package.result.status.contains(&UpdateStatus::Updated)));
 
-        if !config.build_only {
-            pb.set_message(format!("{}: Checking for version updates ...", package.name()));
+    if !wanted {
+        return None;
+    }
 
-            let update_result = match package.kind {
-                PackageKind::PyPi => PyPiUpdater::new(config).and_then(|u| u.update(package, Some(&pb))),
-                PackageKind::GitHub => GitHubRelease::new(config).and_then(|u| u.update(package, Some(&pb))),
-                PackageKind::Cargo => Cargo::new(config).and_then(|u| u.update(package, Some(&pb))),
-                PackageKind::Npm => NpmUpdater::new(config).and_then(|u| u.update(package, Some(&pb))),
-                PackageKind::Go => GoUpdater::new(config).and_then(|u| u.update(package, Some(&pb))),
-                PackageKind::Git => GitRepository::new(config).and_then(|u| u.update(package, Some(&pb))),
-            };
+    Dependents::evaluate()
+        .inspect_err(|e| warn!("Building without regard to dependencies between packages: {e}"))
+        .ok()
+}
+
+/// Why a build failed: the error it was raised with, or else a build error carrying the report's message
+fn build_error(e: &Report) -> UpdateError {
+    match UpdateError::from_report(e) {
+        UpdateError::Other { .. } => UpdateError::Build {
+            message: format!("Build error: {e}"),
+            log: None,
+        },
+        error => error,
+    }
+}
+
+/// Look for an update and land its edits, or roll them back if any step failed (or this is a dry run). The
+/// configured hooks run before the check and after the edits land; `--update-inputs` refreshes of the flake inputs an
+/// updated package refers to land with its edits.
+fn check_package(package: &mut Package, config: &Config, updaters: &Updaters, flake_inputs: Option<&FlakeInputs>, pb: &Progress, since: Option<DateTime<Utc>>) {
+    pb.set_message(format!("{}: Checking for version updates ...", package.name()));
+
+    let checked = match &config.hooks.pre_update {
+        Some(hook) => run_hook("pre_update", hook, package, &[]),
+        None => Ok(()),
+    }
+    .and_then(|()| updaters.update(package, pb, since));
 
-            if let Err(e) = update_result {
+    if let Err(e) = checked {
+        match UpdateError::from_report(&e) {
+            // Not the package's fault; leave it for the next run rather than reporting a failure
+            error @ UpdateError::RateLimited { .. } => {
+                package.result.skip(error);
+            }
+            UpdateError::Other { .. } => {
                 pb.suspend(|| error!(package = %package.name, "Update failed: {e}"));
                 package.result.failed(format!("Update error: {e}"));
             }
+            error => {
+                pb.suspend(|| error!(package = %package.name, "Update failed: {e}"));
+                package.result.fail(error);
+            }
         }
+    }
 
-        if (package.result.status.contains(&UpdateStatus::Updated) || config.force || config.build_only)
-            && let Err(e) = build_package(package, &pb, build_path, config.cache)
-        {
-            pb.suspend(|| error!(package = %package.name, "Build failed: {e}"));
-            package.result.failed(format!("Build error: {e}"));
+    // Only land the edits once the whole update, including hash recomputation, succeeded. A rate limit can cut it
+    // short after e.g. the version was staged but before the hash was, so its edits go too.
+    if package.result.status.contains(&UpdateStatus::Failed) || package.result.rate_limited() || config.dry_run {
+        package.transaction.rollback();
+        return;
+    }
+
+    // Configured `flake_inputs` are refreshed once up front instead. The lock lands along with the package's edits.
+    if let Some(flake_inputs) = flake_inputs
+        && config.flake_inputs.is_empty()
+        && package.result.status.contains(&UpdateStatus::Updated)
+    {
+        pb.set_message(format!("{}: Updating flake inputs ...", package.name()));
+
+        match flake_inputs.update_related(package) {
+            Ok(changes) => package.result.changes.extend(changes.iter().map(ToString::to_string)),
+            Err(e) => {
+                pb.suspend(|| warn!(package = %package.name, "{e}"));
+                package.result.message(format!("Flake inputs not updated: {e}"));
+            }
         }
+    }
 
-        pb.finish_and_clear();
-    });
+    if let Err(e) = package.land() {
+        pb.suspend(|| error!(package = %package.name, "Landing changes failed, reverted: {e}"));
+        return;
+    }
+
+    if !package.result.status.contains(&UpdateStatus::Updated) {
+        return;
+    }
+
+    if let Some(hook) = &config.hooks.post_update
+        && let Err(e) = run_hook("post_update", hook, package, &[("NIX_UPDATER_COMMIT_MESSAGE", config.messages.commit(package))])
+    {
+        pb.suspend(|| warn!(package = %package.name, "{e}"));
+        package.result.message(e.to_string());
+    }
+}
+
+/// Record a finished package, in the run state and the overall progress
+fn record_state(package: &Package, config: &Config, state: &Mutex<RunState>, pb: &Progress) {
+    // A build that didn't succeed leaves its log behind without necessarily failing the package
+    pb.done(package.result.status.contains(&UpdateStatus::Failed) || package.result.failed_log.is_some());
+
+    // Skipped for a rate limit, it wasn't processed and is left for the next run
+    let rate_limited = package.result.rate_limited();
+
+    if !config.dry_run
+        && !rate_limited
+        && let Ok(mut state) = state.lock()
+    {
+        state.record(package);
+
+        if let Err(e) = state.save() {
+            pb.suspend(|| warn!("Failed to save run state: {e}"));
+        }
+    }
 }
 
-fn print_results(packages: &[Package]) {
-    println!(
-        "{:<30} {:<8} {:<8} {:<8} {:<8} Details",
-        "Package".bright_white().bold(),
-        "Source".bright_white().bold(),
-        "Updated".bright_white().bold(),
-        "Built".bright_white().bold(),
-        "Cached".bright_white().bold()
-    );
+/// Look up the GitHub repositories packages come from in a few batched queries, rather than package by package
+fn prefetch_github(packages: &[Package]) {
+    let homepages = packages.iter().filter(|package| package.kind != PackageKind::PyPi).map(|package| &package.homepage);
 
-    println!("{}", "-".repeat(74));
+    if let Err(e) = GitHubClient::shared().and_then(|client| client.prefetch(homepages)) {
+        warn!("Could not prefetch GitHub repositories, looking them up one by one: {e}");
+    }
+}
+
+/// Print the remaining GitHub API quota before a run that will spend it
+fn report_github_quota(packages: &[Package]) {
+    if packages.iter().all(|package| package.kind == PackageKind::PyPi) {
+        return;
+    }
+
+    match GitHubClient::shared().and_then(|client| client.rate_limit()) {
+        Ok(rate) => {
+            let reset = DateTime::from_timestamp(i64::try_from(rate.reset).unwrap_or_default(), 0)
+                .map(|reset| reset.with_timezone(&chrono::Local).format("%H:%M").to_string())
+                .unwrap_or_default();
+
+            let message = format!("GitHub API: {}/{} requests remaining (resets at {reset})", rate.remaining, rate.limit);
+
+            // Most GitHub-backed updates take two or three requests
+            if rate.remaining < packages.len() * 3 {
+                println!("{}", message.yellow());
+            } else {
+                println!("{}", message.dimmed());
+            }
+        }
+        Err(e) => warn!("Could not check GitHub rate limit: {e}"),
+    }
+}
 
-    packages
+/// Advisory check of every package's build inputs against the pinned nixpkgs
+fn print_input_issues(packages: &[Package]) {
+    let reports = packages
+        .par_iter()
+        .map(|package| (package, check_inputs(&package.ast().build_inputs())))
+        .collect::<Vec<_>>();
+
+    let mut clean = true;
+
+    for (package, report) in reports.into_iter().sorted_by(|(a, _), (b, _)| a.name.cmp(&b.name)) {
+        match report {
+            Ok(issues) => {
+                for issue in issues {
+                    clean = false;
+                    println!("{}: {} — {}", package.name(), issue.name.yellow(), issue.problem);
+                }
+            }
+            Err(e) => {
+                clean = false;
+                println!("{}: {}", package.name(), format!("Could not check inputs: {e}").red());
+            }
+        }
+    }
+
+    if clean {
+        println!("{}", "All build inputs resolve in the pinned nixpkgs.".green());
+    }
+}
+
+/// Report the packages whose recorded source hashes no longer match what upstream serves, failing when there are any or
+/// some couldn't be checked
+fn print_hash_drift(packages: &[Package]) -> Result<()> {
+    let reports = packages.par_iter().map(|package| (package, verify_hashes(package))).collect::<Vec<_>>();
+
+    let mut flagged = 0;
+
+    for (package, report) in reports.into_iter().sorted_by(|(a, _), (b, _)| a.name.cmp(&b.name)) {
+        match report {
+            Ok(drifts) => {
+                flagged += usize::from(!drifts.is_empty());
+
+                for drift in drifts {
+                    println!(
+                        "{} ({}): {} — recorded {}, upstream now {}",
+                        package.name(),
+                        drift.system,
+                        drift.url.yellow(),
+                        drift.recorded,
+                        drift.actual.red()
+                    );
+                }
+            }
+            Err(e) => {
+                flagged += 1;
+                println!("{}: {}", package.name(), format!("Could not verify hashes: {e}").red());
+            }
+        }
+    }
+
+    if flagged > 0 {
+        bail!("{flagged} package(s) have drifted or unverifiable hashes");
+    }
+
+    println!("{}", "All recorded hashes still match upstream.".green());
+
+    Ok(())
+}
+
+/// List the files discovery couldn't read packages from, so they don't go unnoticed in a long run's log
+fn print_skipped(skipped: &[Skipped]) {
+    if skipped.is_empty() {
+        return;
+    }
+
+    println!("\n{}", "Skipped".bright_white().bold());
+
+    for skipped in skipped {
+        println!("  {}", skipped.to_string().yellow());
+    }
+}
+
+fn print_results(packages: &[Package], columns: &[Column], systems: &[String]) {
+    let headers = columns
+        .iter()
+        .flat_map(|column| match column {
+            Column::Package => vec![("Package".to_string(), Some(30))],
+            // One extra Built column per configured system
+            Column::Systems => systems.iter().map(|system| (system.clone(), None)).collect(),
+            Column::Reproducible => vec![("Repro".to_string(), None)],
+            column => vec![(format!("{column:?}"), None)],
+        })
+        .collect();
+
+    let mut table = Table::new(headers);
+    let now = Utc::now();
+
+    for package in packages
         .iter()
-        .filter(|package| !package.is_up_to_date())
+        .filter(|package| !package.is_up_to_date() && !package.is_dormant())
         .sorted_by(|a, b| a.name.cmp(&b.name))
-        .for_each(|package| {
-            let mut details = Vec::new();
+    {
+        let result = &package.result;
+
+        let cells = columns.iter().flat_map(|column| match column {
+            Column::Package => vec![Cell::from(package.name.as_str().cyan()).link(package.homepage.to_string())],
+            Column::Source => vec![Cell::from(package.kind.to_string().magenta())],
+            Column::Updated => vec![Cell::from(result.status(UpdateStatus::Updated))],
+            Column::Built => vec![Cell::from(result.status(UpdateStatus::Built))],
+            Column::Systems => systems.iter().map(|system| Cell::from(result.system_status(system))).collect(),
+            Column::Checked => vec![Cell::from(result.status(UpdateStatus::Checked))],
+            Column::Reproducible => vec![Cell::from(result.status(UpdateStatus::Reproducible))],
+            Column::Cached => vec![Cell::from(result.status(UpdateStatus::Cached))],
+            Column::Details => vec![Cell::new(details(package, now).join("\n"))],
+        });
+
+        table.row(cells.collect());
+    }
+
+    print!("{}", table.render(terminal_width()));
+}
+
+/// What to say about a package in the summary, a part per line
+fn details(package: &Package, now: DateTime<Utc>) -> Vec<String> {
+    let result = &package.result;
+    let mut details = Vec::new();
+
+    if !result.changes.is_empty() {
+        details.push(result.changes.join(", "));
+    }
+
+    if let Some(msg) = &result.message {
+        details.push(msg.clone());
+    }
+
+    if let Some(closure) = &result.closure {
+        details.push(closure.summary());
+    }
 
-            if !package.result.changes.is_empty() {
-                details.push(package.result.changes.join(", "));
+    if let Some(dependents) = result.dependent_status() {
+        details.push(format!("Dependents: {dependents}"));
+    }
+
+    if result.status.contains(&UpdateStatus::Updated)
+        && let Some(age) = result.release_age(now)
+    {
+        details.push(age);
+    }
+
+    details
+}
+
+/// Width of the terminal stdout is attached to, for wrapping tables; `None` when it isn't one
+fn terminal_width() -> Option<usize> {
+    console::Term::stdout().size_checked().map(|(_, columns)| usize::from(columns))
+}
+
+/// Remove the per-package build logs, and the build directory itself once nothing else (e.g. the provenance log) is left in it
+fn remove_build_logs(build_path: &Path, packages: &[Package]) {
+    for package in packages {
+        let logs = ["log", "check.log", "rebuild.log", "closure.log"]
+            .map(String::from)
+            .into_iter()
+            .chain(package.result.systems.keys().map(|system| format!("{system}.log")))
+            .chain(package.result.dependents.keys().map(|dependent| format!("dependent.{dependent}.log")));
+
+        for log_file in logs.map(|suffix| build_path.join(format!("{}.{suffix}", package.name))) {
+            if let Err(e) = fs::remove_file(&log_file)
+                && e.kind() != io::ErrorKind::NotFound
+            {
+                warn!("Failed to remove build log {}: {e}", log_file.display());
             }
+        }
+    }
+
+    let _ = fs::remove_dir(build_path);
+}
 
-            if let Some(msg) = &package.result.message {
-                details.push(msg.clone());
+/// Leave out packages a recent run already handled
+fn skip_recently_processed(packages: &mut Vec<Package>, state: &RunState, resume_hours: u64) {
+    let now = Utc::now();
+    let discovered = packages.len();
+
+    packages.retain(|package| !state.recently_processed(package, resume_hours, now));
+
+    let skipped = discovered - packages.len();
+
+    if skipped > 0 {
+        println!(
+            "{}",
+            format!("Skipping {skipped} packages processed in the last {resume_hours}h (use --no-resume to re-run them)").yellow()
+        );
+    }
+}
+
+/// Run a standalone subcommand, one that doesn't process packages
+fn run_command(command: Command, data_dir: &Path, config_path: &Path, provenance_key: Option<&str>) -> Result<()> {
+    match command {
+        Command::History { package, limit } => {
+            let history = History::open(&data_dir.join("history.db"))?;
+
+            print_history(&history.query(package.as_deref(), limit)?);
+        }
+        Command::Revert { package } => print_restored(&revert(&data_dir.join("backups"), package.as_deref())?),
+        Command::VerifyProvenance { path } => {
+            let entries = provenance::verify(&path, provenance_key)?;
+            let checked = if provenance_key.is_some() { "chained and signed" } else { "chained" };
+
+            println!("{}", format!("{}: {entries} entries, all {checked}", path.display()).green());
+
+            if provenance_key.is_none() {
+                println!("{}", "No provenance_key configured, so signatures weren't checked".yellow());
             }
+        }
+        Command::Audit { package } => {
+            let (path, scope) = find_package_file(&package)?;
+            print_audit(&path, scope)?;
+        }
+        Command::Prefetch { target } => print_prefetch(&Target::parse(&target)?)?,
+        Command::Completions { shell } => {
+            let mut cmd = Config::command();
+            let name = &cmd.get_name().to_string();
 
-            println!(
-                "{} {:<8} {:<8} {:<8} {:<8} {}",
-                format_args!("{}{}", package.name(), " ".repeat(30 - package.display_width())),
-                package.kind.to_string().magenta(),
-                package.result.status(UpdateStatus::Updated),
-                package.result.status(UpdateStatus::Built),
-                package.result.status(UpdateStatus::Cached),
-                details.join("\n")
-            );
-        });
+            info!("Generating completion file for {shell}...");
+
+            generate(shell, &mut cmd, name, &mut io::stdout());
+        }
+        Command::Doctor => print_doctor(config_path)?,
+        Command::Generate {
+            target: Generate::Systemd {
+                on_calendar,
+                environment_file,
+                output,
+                args,
+            },
+        } => generate_systemd(&UnitOptions::current(on_calendar, args, environment_file)?, output.as_deref())?,
+        // Processed by the run in `main`
+        Command::Update { .. }
+        | Command::Build { .. }
+        | Command::Check { .. }
+        | Command::List { .. }
+        | Command::Outdated { .. }
+        | Command::Diff { .. }
+        | Command::MigrateHashes { .. } => {}
+    }
+
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let strategy = choose_base_strategy().expect("Unable to find base strategy");
-    let path = strategy.config_dir().join("nix-updater").join("config.toml");
+/// Print the packages discovery found, without checking them
+fn print_packages(packages: &[Package]) {
+    let mut table = Table::new(vec![
+        ("Package".to_string(), Some(30)),
+        ("Source".to_string(), None),
+        ("Version".to_string(), None),
+        ("Path".to_string(), None),
+    ]);
+
+    for package in packages.iter().sorted_by(|a, b| a.name.cmp(&b.name)) {
+        table.row(vec![
+            Cell::from(package.name.as_str().cyan()).link(package.homepage.to_string()),
+            Cell::from(package.kind.to_string().magenta()),
+            Cell::new(&package.version),
+            Cell::new(package.path.display().to_string()),
+        ]);
+    }
+
+    print!("{}", table.render(terminal_width()));
+}
+
+/// Rewrite legacy `sha256` attributes to SRI `hash` in the selected packages' files, failing when some need migrating
+/// by hand. A dry run prints the lines it would change instead. Files are written through a [`Transaction`] backed up
+/// under `backups`, so `revert` can undo the migration.
+fn migrate_legacy_hashes(packages: &[Package], dry_run: bool, backups: &Path) -> Result<()> {
+    let backup = (!dry_run).then(|| RunBackup::start(backups));
+    let mut converted = 0;
+    let mut problems = 0;
+
+    for package in packages.iter().unique_by(|package| &package.path).sorted_by_key(|package| &package.path) {
+        let path = &package.path;
+        let original = fs::read_to_string(path)?;
+        let migration = migrate_hashes(&original, |hash| Nix::convert_hash(hash, "sri"));
+
+        if migration.converted > 0 {
+            converted += migration.converted;
+
+            if let Some(backup) = &backup {
+                let mut transaction = Transaction::default();
+                transaction.backup = Some(backup.package(&package.name, path));
 
-    let config: Config = Figment::new()
-        .merge(Serialized::defaults(Config::parse()))
+                transaction.stage(path, migration.content);
+                transaction.commit(|| Ok(()))?;
+
+                println!("{}: {}", path.display(), format!("converted {} hash(es) to SRI", migration.converted).green());
+            } else {
+                println!("{}: {}", path.display(), format!("would convert {} hash(es) to SRI", migration.converted).yellow());
+
+                for (number, (old, new)) in original.lines().zip(migration.content.lines()).enumerate().filter(|(_, (old, new))| old != new) {
+                    println!("  {}: {}", number + 1, format!("- {}", old.trim()).red());
+                    println!("  {}: {}", number + 1, format!("+ {}", new.trim()).green());
+                }
+            }
+        }
+
+        for problem in migration.problems {
+            problems += 1;
+            println!("{}:{}: {}", path.display(), problem.line, problem.message.red());
+        }
+    }
+
+    if problems > 0 {
+        bail!("{problems} hash attribute(s) need migrating by hand");
+    }
+
+    if converted == 0 {
+        println!("{}", "No legacy sha256 hashes found.".green());
+    }
+
+    Ok(())
+}
+
+/// Command-line arguments merged with config.toml and `NIX_UPDATER_*` variables, with the subcommand and
+/// `--nix-arg`s, which only come from the command line
+fn load_config(path: &Path) -> Result<(Config, Option<Command>, Vec<String>)> {
+    let cli = Config::parse();
+    let command = cli.command.clone();
+    let nix_args = cli.nix_args.clone();
+
+    // Packages can be named after a subcommand, or directly for a bare `update`
+    let selected = command
+        .as_ref()
+        .and_then(Command::selection)
+        .map_or_else(|| cli.packages.clone(), |selection| selection.packages.clone());
+
+    let mut config: Config = Figment::new()
+        .merge(Serialized::defaults(cli))
         .merge(Toml::file(path))
         .merge(Env::prefixed("NIX_UPDATER_").split("_"))
         .extract()?;
 
-    init_tracing(config.verbose);
+    config.packages = selected;
 
-    if let Some(shell) = config.completions {
-        let mut cmd = Config::command();
-        let name = &cmd.get_name().to_string();
+    for name in &config.group {
+        let Some(group) = config.groups.get(name) else {
+            bail!("No group '{name}' in config.toml (configured: {})", config.groups.keys().join(", "));
+        };
 
-        info!("Generating completion file for {shell}...");
+        // An empty selection means every package, which is not what an empty group asks for
+        if group.packages.is_empty() {
+            bail!("Group '{name}' has no packages");
+        }
 
-        let shell_type = Shell::from_str(&shell).map_err(|_| report!("Invalid shell: {shell}. Valid shells: bash, zsh, fish, elvish, powershell"))?;
+        config.packages.extend(group.packages.iter().cloned());
+    }
 
-        generate(shell_type, &mut cmd, name, &mut io::stdout());
+    match &command {
+        Some(Command::Build { .. }) => config.build_only = true,
+        Some(Command::Check { inputs: true, .. }) => config.check_inputs = true,
+        Some(Command::Check { verify: true, .. }) => config.verify = true,
+        Some(Command::Check { .. }) => config.dry_run = true,
+        _ => {}
+    }
 
-        return Ok(());
+    Ok((config, command, nix_args))
+}
+
+fn main() -> Result<()> {
+    let strategy = choose_base_strategy().expect("Unable to find base strategy");
+    let path = strategy.config_dir().join("nix-updater").join("config.toml");
+
+    let (mut config, command, nix_args) = load_config(&path)?;
+
+    let color = color_enabled(config.no_color);
+
+    colored::control::set_override(color);
+    init_tracing(config.verbose, color);
+
+    config.tools.build_args.extend(nix_args);
+    config.tools.clone().install();
+
+    config.binary_cache.validate()?;
+    config.messages.validate()?;
+
+    // A recording has to see every request and prefetch go out, and a replay mustn't be answered from the caches
+    if let Some(recording) = config.record.clone().map(Recording::Record).or_else(|| config.replay.clone().map(Recording::Replay)) {
+        recording.install();
+        config.http.cache_ttl = 0;
+    }
+
+    // A replay reproduces a run's decisions; acting on them would build and push against a world that isn't there
+    if config.replay.is_some() {
+        config.dry_run = true;
+    }
+
+    Http::configure(&config.http, Some(strategy.cache_dir().join("nix-updater").join("http")))?;
+    GitHubClient::configure(config.github_token.clone());
+
+    if config.http.cache_ttl > 0 {
+        Nix::cache_prefetches(strategy.cache_dir().join("nix-updater").join("prefetch"), Duration::from_secs(config.http.cache_ttl));
+    }
+
+    let data_dir = strategy.data_dir().join("nix-updater");
+
+    if let Some(command) = command.clone().filter(|command| command.selection().is_none()) {
+        return run_command(command, &data_dir, &path, config.provenance_key.as_deref());
+    }
+
+    rayon::ThreadPoolBuilder::new().num_threads(config.jobs.map_or(0, usize::from)).build_global()?;
+
+    // A listing, diff or migration has nothing to watch for
+    if !config.watch
+        || matches!(
+            command,
+            Some(Command::List { .. } | Command::Outdated { .. } | Command::Diff { .. } | Command::MigrateHashes { .. })
+        )
+    {
+        return run(&config, command.as_ref(), &data_dir);
+    }
+
+    let interval = humantime::parse_duration(&config.interval).map_err(|e| report!("Invalid --interval duration '{}': {e}", config.interval))?;
+    let dashboard = config.listen.as_deref().map(Dashboard::serve).transpose()?;
+
+    loop {
+        if let Some(dashboard) = dashboard {
+            dashboard.started();
+        }
+
+        let result = run(&config, command.as_ref(), &data_dir);
+
+        if let Err(e) = &result {
+            error!("Run failed: {e}");
+        }
+
+        let wait = watch::next_round(interval, watch::github_quota_reset());
+        let next = Utc::now() + wait;
+
+        println!("{}", format!("Next check at {}", next.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")).dimmed());
+
+        match dashboard {
+            Some(dashboard) => {
+                dashboard.finished(result.err().map(|e| e.to_string()), next);
+
+                if dashboard.wait(wait) {
+                    println!("{}", "Checking now, as requested through /trigger".dimmed());
+                }
+            }
+            None => thread::sleep(wait),
+        }
+    }
+}
+
+/// Write the `--metrics-file`, if configured, and hand the results to a `--listen` endpoint, for a run that got as far
+/// as checking packages
+fn record_metrics(config: &Config, packages: &[Package], started: Instant) {
+    serve::publish(packages);
+
+    if let Some(path) = &config.metrics_file
+        && let Err(e) = write_metrics(path, packages, started.elapsed())
+    {
+        warn!("Failed to write metrics to {}: {e}", path.display());
+    }
+}
+
+/// Fail when a package named on the command line matches neither a package nor a pin
+fn ensure_selection_matches(names: &[String], packages: &[Package], pin_names: &[String]) -> Result<()> {
+    let unmatched = names
+        .iter()
+        .filter(|name| !packages.iter().any(|package| package.name.contains(name.as_str())) && !pin_names.iter().any(|pin| pin.contains(name.as_str())))
+        .collect_vec();
+
+    if !unmatched.is_empty() {
+        bail!(
+            "No package matches {} (or it isn't supported on this platform)",
+            unmatched.iter().map(|name| format!("'{name}'")).join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Commands that only report on (or migrate) the selected packages, `None` for update and build runs
+fn run_report(config: &Config, command: Option<&Command>, packages: &[Package], data_dir: &Path) -> Option<Result<()>> {
+    match command? {
+        Command::List { .. } => {
+            print_packages(packages);
+            Some(Ok(()))
+        }
+        Command::Outdated { json, .. } => {
+            prefetch_github(packages);
+            Some(Updaters::new(&config.update_options()).and_then(|updaters| print_outdated(&check_outdated(packages, &updaters), *json)))
+        }
+        Command::Diff { .. } if config.packages.is_empty() => Some(Err(report!("Name the packages to diff"))),
+        Command::Diff { limit, .. } => Some(print_diffs(packages, *limit)),
+        Command::MigrateHashes { .. } => Some(migrate_legacy_hashes(packages, config.dry_run, &data_dir.join("backups"))),
+        _ => None,
+    }
+}
+
+/// The `--branch` an update run edits on, `None` without one or for runs that don't edit package files
+fn update_branch(config: &Config, command: Option<&Command>) -> Result<Option<UpdateBranch>> {
+    let edits = !config.dry_run && !config.build_only && !config.check_inputs && !config.verify && matches!(command, None | Some(Command::Update { .. }));
+
+    match &config.branch {
+        Some(name) if edits => Ok(Some(UpdateBranch::start(name, config.worktree)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Commit what the run changed to its `--branch`, naming the branch to review, and with `--merge-request` push it and
+/// open a merge request
+fn commit_to_branch(branch: &UpdateBranch, config: &Config, packages: &[Package]) {
+    let (title, body) = (config.messages.pr_title(packages), config.messages.pr_body(packages));
+
+    match branch.commit(&title, &body) {
+        Ok(true) => println!("Committed the updates to branch {}", branch.name.green()),
+        Ok(false) => println!("{}", format!("Nothing to commit on branch {}", branch.name).dimmed()),
+        Err(e) => {
+            warn!("Failed to commit to branch {}: {e}", branch.name);
+            return;
+        }
+    }
+
+    if !config.merge_request || !branch.has_commits().unwrap_or(false) {
+        return;
+    }
+
+    let Some(base) = &branch.base else {
+        warn!("No merge request for {}: the run didn't start on a branch to merge into", branch.name);
+        return;
+    };
+
+    let opened = branch
+        .push()
+        .and_then(|remote| GitLabClient::for_remote(&remote, config.gitlab_token.as_deref()))
+        .and_then(|client| client.merge_request(&branch.name, base, &title, &body));
+
+    match opened {
+        Ok(url) => println!("Merge request: {}", url.cyan()),
+        Err(e) => warn!("Failed to open a merge request for {}: {e}", branch.name),
     }
+}
+
+/// Check, update and build the selected packages once
+fn run(config: &Config, command: Option<&Command>, data_dir: &Path) -> Result<()> {
+    let started = Instant::now();
+    let since = config
+        .since
+        .as_deref()
+        .map(|window| humantime::parse_duration(window).map_err(|e| report!("Invalid --since duration '{window}': {e}")))
+        .transpose()?
+        .map(|window| Utc::now() - window);
 
-    let mut packages = discover_packages(&config);
+    // Moves into the branch's worktree, if it has one, so everything below works there until it's dropped
+    let branch = update_branch(config, command)?;
+
+    let exclusions = Exclusions::load(&config.exclude, Path::new(IGNORE_FILE))?;
+    let Discovered { mut packages, skipped } = discover_packages(config, &exclusions)?;
+    let mut pin_files = if config.pins { PinFile::discover()? } else { Vec::new() };
+    let pin_names = pin_files.iter().flat_map(PinFile::names).collect_vec();
+
+    ensure_selection_matches(&config.packages, &packages, &pin_names)?;
+
+    if let Some(result) = run_report(config, command, &packages, data_dir) {
+        return result;
+    }
+
+    // Originals of the files this run changes, pins included, for `revert`
+    let backup = (!config.dry_run && !config.build_only && !config.check_inputs && !config.verify).then(|| RunBackup::start(&data_dir.join("backups")));
+
+    // Pins have nothing to build, so they are settled before the packages
+    if !pin_files.is_empty() && !config.build_only && !config.check_inputs && !config.verify {
+        print_pins(&update_pins(&mut pin_files, &config.packages, &exclusions, config.force, config.dry_run, backup.as_ref())?);
+    }
+
+    let state = RunState::load(&data_dir.join("state.json"));
+
+    if config.resumes() {
+        skip_recently_processed(&mut packages, &state, config.resume_hours);
+    }
 
     if packages.is_empty() {
         println!("{}", "No packages found to process".yellow());
+        record_metrics(config, &packages, started);
         return Ok(());
     }
 
+    if config.check_inputs {
+        print_input_issues(&packages);
+        return Ok(());
+    }
+
+    if config.verify {
+        return print_hash_drift(&packages);
+    }
+
     let build_path = PathBuf::from("build-results");
 
-    process_packages(&mut packages, &config, &build_path);
+    if let Some(backup) = &backup {
+        backup.cover(&mut packages);
+    }
 
-    if packages.iter().all(|p| p.result.status.contains(&UpdateStatus::UpToDate)) {
+    let updaters = if config.build_only {
+        None
+    } else {
+        report_github_quota(&packages);
+        prefetch_github(&packages);
+        Some(Updaters::new(&config.update_options())?)
+    };
+
+    let flake_inputs = (config.update_inputs && updaters.is_some() && !config.dry_run && Path::new("flake.lock").is_file()).then(|| FlakeInputs::new(PathBuf::from("flake.lock")));
+
+    if let Some(flake_inputs) = &flake_inputs
+        && !config.flake_inputs.is_empty()
+    {
+        for change in flake_inputs.update(&config.flake_inputs)? {
+            println!("Flake input {change}");
+        }
+    }
+
+    process_packages(&mut packages, config, updaters.as_ref(), flake_inputs.as_ref(), &build_path, &Mutex::new(state), since);
+
+    if let Some(branch) = &branch {
+        commit_to_branch(branch, config, &packages);
+    }
+
+    if packages.iter().all(|p| p.is_up_to_date() || p.is_dormant()) {
         println!("{}", "No packages needed updating.".yellow());
+        print_skipped(&skipped);
+        record_metrics(config, &packages, started);
         return Ok(());
     }
 
-    print_results(&packages);
+    let columns = config.columns.clone().unwrap_or_else(|| Column::defaults(config.check_reproducibility));
+
+    print_results(&packages, &columns, &config.build.systems);
+    print_failures(&packages, config.failure_lines);
+    print_skipped(&skipped);
+
+    // A check only says what would change, which is nothing to tell anyone about
+    if !config.dry_run {
+        if let Some(hook) = &config.hooks.notify
+            && let Err(e) = notify(hook, &packages, &config.messages)
+        {
+            warn!("{e}");
+        }
+
+        send_notifications(&config.notifications, &packages);
+    }
+
+    if !config.dry_run {
+        if let Err(e) = provenance::record(&build_path.join("provenance.jsonl"), &packages, config.provenance_key.as_deref()) {
+            warn!("Failed to record provenance: {e}");
+        }
+
+        if let Err(e) = History::open(&data_dir.join("history.db")).and_then(|history| history.record(&packages, &build_path)) {
+            warn!("Failed to record update history: {e}");
+        }
+    }
 
-    if packages.iter().all(|p| p.result.status.contains(&UpdateStatus::Built))
-        && let Err(e) = fs::remove_dir_all(&build_path)
+    if packages
+        .iter()
+        .all(|p| p.result.status.contains(&UpdateStatus::Built) && p.result.dependents.values().all(|built| *built))
     {
-        warn!("Failed to remove build directory: {e}");
+        remove_build_logs(&build_path, &packages);
     }
 
+    record_metrics(config, &packages, started);
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use nix_package_updater::package::UpdateResult;
+    use nix_package_updater::progress::Reporter;
+
+    use super::skip_past_failure_limit;
+
+    #[test]
+    fn counts_stopped_packages_once() {
+        let reporter = Reporter::detect(3);
+        let mut result = UpdateResult::default();
+
+        // Under the limit nothing is stopped
+        assert!(!skip_past_failure_limit(&mut result, Some(1), &reporter, &reporter.add()));
+
+        reporter.add().done(true);
+
+        // Stopped while checking, then again where it would be built
+        assert!(skip_past_failure_limit(&mut result, Some(1), &reporter, &reporter.add()));
+        assert!(skip_past_failure_limit(&mut result, Some(1), &reporter, &reporter.add()));
+
+        assert!(result.stopped);
+        assert_eq!(reporter.done(), 2);
+        assert_eq!(reporter.failed(), 1);
+    }
+}
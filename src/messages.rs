@@ -0,0 +1,176 @@
+use itertools::Itertools;
+use rootcause::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::package::{Package, UpdateStatus};
+
+/// What a package template can refer to
+const PACKAGE_PLACEHOLDERS: &[&str] = &["name", "old_version", "new_version", "changelog_url", "homepage", "file"];
+
+/// What the pull request title can also refer to, describing the whole run
+const RUN_PLACEHOLDERS: &[&str] = &["count", "names"];
+
+/// Templates for the commit message and pull request an update goes in, handed to the hooks that make them:
+/// `NIX_UPDATER_COMMIT_MESSAGE` to `post_update`, `NIX_UPDATER_PR_TITLE` and `NIX_UPDATER_PR_BODY` to `notify`.
+/// `{placeholder}`s are replaced with the package's (or the run's) values.
+///
+/// ```toml
+/// [messages]
+/// commit = "chore(deps): bump {name} from {old_version} to {new_version}"
+/// pr_title = "chore(deps): update {names}"
+/// pr_line = "- [{name}]({changelog_url}): {old_version} → {new_version}"
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessagesConfig {
+    /// One commit per updated package
+    pub commit: String,
+
+    /// The pull request title for everything a run updated, which can also use `{count}` and `{names}`
+    pub pr_title: String,
+
+    /// A line of the pull request body per updated package
+    pub pr_line: String,
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        Self {
+            commit: "{name}: {old_version} -> {new_version}".to_string(),
+            pr_title: "Update {names}".to_string(),
+            pr_line: "- {name}: {old_version} -> {new_version}".to_string(),
+        }
+    }
+}
+
+impl MessagesConfig {
+    /// Fail on placeholders a template can't fill, before a run renders them into commits
+    pub fn validate(&self) -> Result<()> {
+        let run_placeholders = [PACKAGE_PLACEHOLDERS, RUN_PLACEHOLDERS].concat();
+
+        for (name, template, known) in [
+            ("commit", &self.commit, PACKAGE_PLACEHOLDERS),
+            ("pr_title", &self.pr_title, &run_placeholders[..]),
+            ("pr_line", &self.pr_line, PACKAGE_PLACEHOLDERS),
+        ] {
+            if let Some(unknown) = placeholders(template).find(|placeholder| !known.contains(placeholder)) {
+                bail!("Unknown placeholder {{{unknown}}} in [messages] {name} (available: {})", known.join(", "));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The commit message for an updated package
+    pub fn commit(&self, package: &Package) -> String {
+        render(&self.commit, &package_values(package))
+    }
+
+    /// The pull request title for the packages a run updated. With a single package, `{name}` and friends are that
+    /// package's.
+    pub fn pr_title(&self, packages: &[Package]) -> String {
+        let updated = updated(packages).collect_vec();
+
+        let mut values = match updated[..] {
+            [package] => package_values(package),
+            _ => Vec::new(),
+        };
+
+        values.push(("count", updated.len().to_string()));
+        values.push(("names", updated.iter().map(|package| package.name.as_str()).join(", ")));
+
+        render(&self.pr_title, &values)
+    }
+
+    /// The pull request body: a line per package a run updated
+    pub fn pr_body(&self, packages: &[Package]) -> String {
+        updated(packages).map(|package| render(&self.pr_line, &package_values(package))).join("\n")
+    }
+}
+
+fn updated(packages: &[Package]) -> impl Iterator<Item = &Package> {
+    packages
+        .iter()
+        .filter(|package| package.result.status.contains(&UpdateStatus::Updated) && !package.result.status.contains(&UpdateStatus::Failed))
+}
+
+fn package_values(package: &Package) -> Vec<(&'static str, String)> {
+    let result = &package.result;
+    let old = result.old_version.clone().or_else(|| result.old_git_commit.clone()).unwrap_or_default();
+    let new = result.new_version.clone().or_else(|| result.new_git_commit.clone()).unwrap_or_default();
+
+    vec![
+        ("name", package.name.clone()),
+        ("changelog_url", changelog_url(package, result.new_version.as_deref().unwrap_or(&package.version))),
+        ("old_version", old),
+        ("new_version", new),
+        ("homepage", package.homepage.to_string()),
+        ("file", package.path.display().to_string()),
+    ]
+}
+
+/// `meta.changelog` for `version` when the package sets one, else the releases page of a GitHub homepage
+fn changelog_url(package: &Package, version: &str) -> String {
+    if let Some(changelog) = package.ast().get_in(&["meta"], "changelog") {
+        return changelog.replace("${version}", version).replace("${finalAttrs.version}", version);
+    }
+
+    if package.homepage.host() == Some("github.com") {
+        return format!("https://github.com/{}/releases", package.homepage.path().trim_start_matches('/').trim_end_matches(".git"));
+    }
+
+    String::new()
+}
+
+/// The names between braces in a template
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|part| part.split_once('}').map(|(name, _)| name))
+}
+
+fn render(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |rendered, (name, value)| rendered.replace(&format!("{{{name}}}"), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MessagesConfig, placeholders, render};
+
+    #[test]
+    fn renders_templates() {
+        let values = [
+            ("name", "ripgrep".to_string()),
+            ("old_version", "14.0.0".to_string()),
+            ("new_version", "14.1.1".to_string()),
+        ];
+
+        assert_eq!(
+            render("chore(deps): bump {name} from {old_version} to {new_version}", &values),
+            "chore(deps): bump ripgrep from 14.0.0 to 14.1.1"
+        );
+        assert_eq!(
+            placeholders("{name}: {old_version} -> {new_version}").collect::<Vec<_>>(),
+            ["name", "old_version", "new_version"]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        assert!(MessagesConfig::default().validate().is_ok());
+
+        let messages = MessagesConfig {
+            commit: "{name} {count}".to_string(),
+            ..MessagesConfig::default()
+        };
+
+        assert!(messages.validate().is_err());
+
+        let messages = MessagesConfig {
+            pr_title: "deps: {count} updates ({names})".to_string(),
+            ..MessagesConfig::default()
+        };
+
+        assert!(messages.validate().is_ok());
+    }
+}
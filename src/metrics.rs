@@ -0,0 +1,70 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rootcause::Result;
+
+use nix_package_updater::package::{Package, UpdateStatus};
+
+/// Render a run's metrics, all gauges, in the Prometheus text exposition format
+fn render(packages: &[Package], duration: Duration, finished: DateTime<Utc>) -> String {
+    let count = |status: UpdateStatus| packages.iter().filter(|package| package.result.status.contains(&status)).count();
+
+    let metrics = [
+        ("packages_total", "Packages processed by the last run", packages.len().to_string()),
+        ("packages_updated", "Packages the last run updated", count(UpdateStatus::Updated).to_string()),
+        (
+            "packages_failed",
+            "Packages that failed to update or build in the last run",
+            count(UpdateStatus::Failed).to_string(),
+        ),
+        ("run_duration_seconds", "How long the last run took", format!("{:.3}", duration.as_secs_f64())),
+        (
+            "last_run_timestamp_seconds",
+            "When the last run finished, as a Unix timestamp",
+            finished.timestamp().to_string(),
+        ),
+    ];
+
+    let mut text = String::new();
+
+    for (name, help, value) in metrics {
+        let _ = writeln!(text, "# HELP nix_package_updater_{name} {help}");
+        let _ = writeln!(text, "# TYPE nix_package_updater_{name} gauge");
+        let _ = writeln!(text, "nix_package_updater_{name} {value}");
+    }
+
+    text
+}
+
+/// Write the metrics file for node_exporter's textfile collector. It's written next to `path` and renamed into place,
+/// so the collector never reads half a file.
+pub fn write_metrics(path: &Path, packages: &[Package], duration: Duration) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temporary = path.with_extension("prom.tmp");
+
+    fs::write(&temporary, render(packages, duration, Utc::now()))?;
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_textfile_metrics() {
+        let finished = DateTime::from_timestamp(1_760_000_000, 0).unwrap();
+        let text = render(&[], Duration::from_millis(1500), finished);
+
+        assert!(text.contains("# TYPE nix_package_updater_packages_total gauge\nnix_package_updater_packages_total 0\n"));
+        assert!(text.contains("nix_package_updater_run_duration_seconds 1.500\n"));
+        assert!(text.contains("nix_package_updater_last_run_timestamp_seconds 1760000000\n"));
+    }
+}
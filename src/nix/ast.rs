@@ -1,13 +1,10 @@
-use std::collections::HashMap;
-use std::fs;
-use std::process::Command;
+use std::collections::{BTreeMap, HashMap};
 
-use indicatif::ProgressBar;
+use itertools::Itertools;
 use rnix::{Parse, Root, SyntaxKind, SyntaxNode};
 use rootcause::{Result, bail};
-use tracing::info;
 
-use crate::package::Package;
+use crate::clients::nix::{FetchFlags, FetchKind};
 
 #[derive(Debug)]
 pub struct PlatformBlock {
@@ -15,21 +12,161 @@ pub struct PlatformBlock {
     pub attributes: std::collections::HashMap<String, String>,
 }
 
+/// How an attribute's value is written in the file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::Display)]
+pub enum Origin {
+    /// A plain string, e.g. `version = "1.2.3";`
+    Literal,
+    /// A string with `${...}`, e.g. `rev = "v${version}";`
+    Interpolated,
+    /// Bound in a `let` block
+    LetBound,
+    /// Brought in with `inherit`
+    Inherited,
+    /// Refers to another binding, e.g. `repo = pname;`
+    Reference,
+    /// Any other expression
+    Expression,
+}
+
+/// An attribute's value, for reading and writing more than strings
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Bool(bool),
+    List(Vec<Value>),
+    AttrSet(BTreeMap<String, Value>),
+    /// Anything else, as written (e.g. `./Cargo.lock`, `lib.fakeHash`)
+    Expression(String),
+}
+
+impl Value {
+    /// Nix source for the value; lists and attrsets span several lines, indented one level past `indent`
+    pub fn to_nix(&self, indent: &str) -> String {
+        let inner = format!("{indent}  ");
+
+        match self {
+            Self::String(value) => format!("\"{value}\""),
+            Self::Bool(value) => value.to_string(),
+            Self::List(items) if items.is_empty() => "[ ]".to_string(),
+            Self::List(items) => format!("[\n{}{indent}]", items.iter().map(|item| format!("{inner}{}\n", item.to_nix(&inner))).join("")),
+            Self::AttrSet(attrs) if attrs.is_empty() => "{ }".to_string(),
+            Self::AttrSet(attrs) => format!(
+                "{{\n{}{indent}}}",
+                attrs.iter().map(|(name, value)| format!("{inner}{} = {};\n", quote_name(name), value.to_nix(&inner))).join("")
+            ),
+            Self::Expression(text) => text.clone(),
+        }
+    }
+}
+
+/// An attribute name, quoted unless it is a plain identifier (e.g. `"serde-1.0.0"`)
+fn quote_name(name: &str) -> String {
+    let plain = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''));
+
+    if plain { name.to_string() } else { format!("\"{name}\"") }
+}
+
 /// Extract string value from a Nix string node
 fn extract_string_value(node: &SyntaxNode) -> String {
     node.text().to_string().replace('"', "")
 }
 
+/// A piece of a Nix string
+enum Segment {
+    Literal(String),
+    /// `${...}`, with the identifier inside and the string it's bound to, when it is that simple
+    Interpolation { source: String, name: Option<String>, value: Option<String> },
+}
+
+/// What [`Ast::set`] should do with a string
+enum Rewrite {
+    /// Holds some other value
+    NoMatch,
+    /// Matches, but its interpolations can't be kept; left as is
+    Skip,
+    /// Replace this node's text
+    Edit(SyntaxNode, String),
+}
+
+/// For a string with a single interpolation of an identifier: the text before it, the identifier, and the text after it
+fn lone_interpolation(segments: &[Segment]) -> Option<(String, &str, String)> {
+    let [Segment::Interpolation { name: Some(name), .. }] = segments.iter().filter(|segment| matches!(segment, Segment::Interpolation { .. })).collect::<Vec<_>>()[..]
+    else {
+        return None;
+    };
+
+    let position = segments.iter().position(|segment| matches!(segment, Segment::Interpolation { .. }))?;
+    let literal = |segments: &[Segment]| {
+        segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Literal(text) => Some(text.as_str()),
+                Segment::Interpolation { .. } => None,
+            })
+            .collect::<String>()
+    };
+
+    Some((literal(&segments[..position]), name, literal(&segments[position + 1..])))
+}
+
 /// AST Updater that maintains the parse tree and applies updates
 pub struct Ast {
     content: String,
-    ast: Parse<Root>,
+    parsed: Parse<Root>,
+    scope: Option<usize>,
 }
 
 impl Ast {
     pub fn from_ast(ast: Parse<Root>) -> Self {
         let content = ast.tree().to_string();
-        Self { content, ast }
+        Self { content, parsed: ast, scope: None }
+    }
+
+    /// Confine lookups and edits to the `scope`-th package in the file (see [`Ast::package_count`]).
+    ///
+    /// `let` bindings enclosing that package stay visible, since its attributes may refer to them.
+    #[must_use]
+    pub fn scoped(mut self, scope: Option<usize>) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Number of packages defined in the file, i.e. outermost attrsets with their own `pname`.
+    ///
+    /// Attrsets nested in a package (e.g. `fetchPypi { pname = ...; }`) belong to it and aren't counted.
+    pub fn package_count(&self) -> usize {
+        self.package_sets().count()
+    }
+
+    fn package_sets(&self) -> impl Iterator<Item = SyntaxNode> {
+        let defines_pname = |node: &SyntaxNode| {
+            node.kind() == SyntaxKind::NODE_ATTR_SET
+                && node.children().any(|child| {
+                    child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE && child.first_child().is_some_and(|path| path.text() == "pname")
+                })
+        };
+
+        self.parsed
+            .syntax()
+            .descendants()
+            .filter(move |node| defines_pname(node) && !node.ancestors().skip(1).any(|ancestor| defines_pname(&ancestor)))
+    }
+
+    /// The node lookups and edits are confined to: the scoped package's attrset, or the whole file
+    pub fn node(&self) -> SyntaxNode {
+        self.scope.and_then(|scope| self.package_sets().nth(scope)).unwrap_or_else(|| self.parsed.syntax())
+    }
+
+    /// `let` blocks enclosing the scoped package, innermost first
+    fn enclosing_lets(&self) -> impl Iterator<Item = SyntaxNode> {
+        self.node().ancestors().skip(1).filter(|node| node.kind() == SyntaxKind::NODE_LET_IN)
+    }
+
+    /// Every node in scope: the package's own nodes, then the bindings of the `let` blocks around it
+    fn nodes(&self) -> impl Iterator<Item = SyntaxNode> {
+        self.node().descendants().chain(self.enclosing_lets().flat_map(|node| node.children()))
     }
 
     /// Check if content contains a specific function call
@@ -50,7 +187,7 @@ impl Ast {
     ///
     /// Such packages have no upstream to track, so there is nothing to update.
     pub fn has_local_src(&self) -> bool {
-        for child in self.ast.syntax().descendants() {
+        for child in self.node().descendants() {
             if child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE
                 && let Some(key) = child.first_child()
                 && key.kind() == SyntaxKind::NODE_ATTRPATH
@@ -69,11 +206,170 @@ impl Ast {
 
     /// Set an attribute value using precise AST-guided replacement
     pub fn set(&mut self, attr_name: &str, old_value: &str, new_value: &str) -> Result<()> {
+        if self.replace(self.nodes().collect(), attr_name, old_value, new_value) {
+            return Ok(());
+        }
+
+        bail!("Attribute '{attr_name}' with value '{old_value}' not found")
+    }
+
+    /// Like [`Ast::set`], but only within the block at `path`, e.g. `["src"]` for the `src = fetchFromGitHub { ... }` call.
+    ///
+    /// Files with several fetchers often repeat attribute names, or even values; matching anywhere would edit the wrong one.
+    /// Falls back to [`Ast::set`] when there is no such block, or the attribute lives outside it (e.g. in a `let`).
+    pub fn set_in(&mut self, path: &[&str], attr_name: &str, old_value: &str, new_value: &str) -> Result<()> {
+        if let Some(block) = self.block(path)
+            && self.replace(block.descendants().collect(), attr_name, old_value, new_value)
+        {
+            return Ok(());
+        }
+
+        self.set(attr_name, old_value, new_value)
+    }
+
+    /// Like [`Ast::get`], but preferring the attribute inside the block at `path`, see [`Ast::set_in`]
+    pub fn get_in(&self, path: &[&str], attr_name: &str) -> Option<String> {
+        self.block(path)
+            .and_then(|block| {
+                block.descendants().find_map(|node| {
+                    if node.kind() == SyntaxKind::NODE_ATTRPATH_VALUE
+                        && node.first_child().is_some_and(|key| key.text() == attr_name)
+                        && let Some(value) = node.last_child()
+                        && value.kind() == SyntaxKind::NODE_STRING
+                    {
+                        Some(self.value(&value))
+                    } else {
+                        None
+                    }
+                })
+            })
+            .or_else(|| self.get(attr_name))
+    }
+
+    /// The value of the attribute at `path`, each name looked up within the previous one's value.
+    /// Quoted names (e.g. `"x86_64-linux"`) match without their quotes.
+    fn block(&self, path: &[&str]) -> Option<SyntaxNode> {
+        path.iter().try_fold(self.node(), |node, name| {
+            node.descendants()
+                .find(|child| {
+                    child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE
+                        && child.first_child().is_some_and(|key| key.text().to_string().trim_matches('"') == *name)
+                })
+                .and_then(|child| child.last_child())
+        })
+    }
+
+    /// The value of `attr_name` within the block at `path` (the whole scope when empty), whatever kind of value it is
+    pub fn get_value(&self, path: &[&str], attr_name: &str) -> Option<Value> {
+        Some(self.to_value(&self.attribute(path, attr_name)?.last_child()?))
+    }
+
+    /// Replace the value of `attr_name` within the block at `path`, writing it as Nix in place of whatever was there
+    pub fn set_value(&mut self, path: &[&str], attr_name: &str, value: &Value) -> Result<()> {
+        let Some(attribute) = self.attribute(path, attr_name) else {
+            bail!("Attribute '{attr_name}' not found");
+        };
+
+        let Some(old) = attribute.last_child() else {
+            bail!("Attribute '{attr_name}' has no value");
+        };
+
+        // Nested lines line up with the attribute's own indentation
+        let indent = self.indent(&attribute);
+
+        self.splice(&old, &value.to_nix(&indent));
+
+        Ok(())
+    }
+
+    /// Add `attr_name = value;` on its own line right after the attribute `after`
+    pub fn insert_after(&mut self, after: &str, attr_name: &str, value: &Value) -> Result<()> {
+        let Some(attribute) = self.attribute(&[], after) else {
+            bail!("Attribute '{after}' not found");
+        };
+
+        let indent = self.indent(&attribute);
+        let end = usize::from(attribute.text_range().end());
+
+        self.content.insert_str(end, &format!("\n{indent}{} = {};", quote_name(attr_name), value.to_nix(&indent)));
+        self.parsed = rnix::Root::parse(&self.content);
+
+        Ok(())
+    }
+
+    /// Rename the attribute `from` to `to`, keeping its value
+    pub fn rename_attribute(&mut self, from: &str, to: &str) -> Result<()> {
+        let Some(key) = self.attribute(&[], from).and_then(|attribute| attribute.first_child()) else {
+            bail!("Attribute '{from}' not found");
+        };
+
+        self.splice(&key, &quote_name(to));
+
+        Ok(())
+    }
+
+    /// Call `to` where the package calls `from`, e.g. `rustPlatform.fetchCargoTarball` to `rustPlatform.fetchCargoVendor`
+    pub fn rename_call(&mut self, from: &str, to: &str) -> Result<()> {
+        let Some(function) = self
+            .node()
+            .descendants()
+            .filter(|node| node.kind() == SyntaxKind::NODE_APPLY)
+            .filter_map(|node| node.first_child())
+            .find(|function| function.text().to_string().ends_with(from))
+        else {
+            bail!("No call to '{from}' found");
+        };
+
+        let text = function.text().to_string();
+        self.splice(&function, &format!("{}{to}", &text[..text.len() - from.len()]));
+
+        Ok(())
+    }
+
+    /// Leading whitespace of the line a node starts on, or nothing if other code precedes it there
+    fn indent(&self, node: &SyntaxNode) -> String {
+        let start = usize::from(node.text_range().start());
+        let line = &self.content[self.content[..start].rfind('\n').map_or(0, |newline| newline + 1)..start];
+
+        if line.trim().is_empty() { line.to_string() } else { String::new() }
+    }
+
+    /// Replace a node's text, re-parsing to keep the tree in sync
+    fn splice(&mut self, node: &SyntaxNode, text: &str) {
+        let range = node.text_range();
+        self.content.replace_range(usize::from(range.start())..usize::from(range.end()), text);
+        self.parsed = rnix::Root::parse(&self.content);
+    }
+
+    /// The first `attr_name = ...;` within the block at `path`
+    fn attribute(&self, path: &[&str], attr_name: &str) -> Option<SyntaxNode> {
+        self.block(path)?.descendants().find(|node| {
+            node.kind() == SyntaxKind::NODE_ATTRPATH_VALUE && node.first_child().is_some_and(|key| key.text().to_string().trim_matches('"') == attr_name)
+        })
+    }
+
+    fn to_value(&self, node: &SyntaxNode) -> Value {
+        match node.kind() {
+            SyntaxKind::NODE_STRING => Value::String(self.value(node)),
+            SyntaxKind::NODE_IDENT if node.text() == "true" || node.text() == "false" => Value::Bool(node.text() == "true"),
+            SyntaxKind::NODE_LIST => Value::List(node.children().map(|item| self.to_value(&item)).collect()),
+            SyntaxKind::NODE_ATTR_SET if !node.children_with_tokens().any(|child| child.kind() == SyntaxKind::TOKEN_REC) => Value::AttrSet(
+                node.children()
+                    .filter(|child| child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+                    .filter_map(|child| Some((child.first_child()?.text().to_string().trim_matches('"').to_string(), self.to_value(&child.last_child()?))))
+                    .collect(),
+            ),
+            _ => Value::Expression(node.text().to_string()),
+        }
+    }
+
+    /// Replace the first string value of `attr_name` equal to `old_value` among `nodes`, returning whether one matched
+    fn replace(&mut self, nodes: Vec<SyntaxNode>, attr_name: &str, old_value: &str, new_value: &str) -> bool {
         // Find the exact location of the attribute in the AST
-        for child in self.ast.syntax().descendants() {
+        for child in nodes {
             if child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE {
                 let mut found_attr = false;
-                let mut string_node: Option<SyntaxNode> = None;
+                let mut rewrite = Rewrite::NoMatch;
 
                 for attr_child in child.children() {
                     match attr_child.kind() {
@@ -84,41 +380,150 @@ impl Ast {
                                 found_attr = true;
                             }
                         }
-                        SyntaxKind::NODE_STRING
-                            if found_attr && extract_string_value(&attr_child) == old_value =>
-                        {
-                            // Skip updating strings with interpolation: (${...})
-                            let content = attr_child.text().to_string();
-
-                            if content.contains("${") && content.contains('}') {
-                                return Ok(());
+                        // `version = v;` edits the string `v` is bound to
+                        SyntaxKind::NODE_IDENT if found_attr => {
+                            if let Some(binding) = self.binding(&attr_child.text().to_string()) {
+                                rewrite = self.rewrite(&binding, old_value, new_value);
                             }
-
-                            string_node = Some(attr_child);
+                            break;
+                        }
+                        SyntaxKind::NODE_STRING if found_attr => {
+                            rewrite = self.rewrite(&attr_child, old_value, new_value);
                             break;
                         }
                         _ => {}
                     }
                 }
 
-                if let Some(node) = string_node {
-                    // Get the exact text range and replace it
-                    let range = node.text_range();
-                    let start = usize::from(range.start());
-                    let end = usize::from(range.end());
+                match rewrite {
+                    Rewrite::NoMatch => {}
+                    Rewrite::Skip => return true,
+                    Rewrite::Edit(node, new_string) => {
+                        // Get the exact text range and replace it
+                        let range = node.text_range();
+                        let start = usize::from(range.start());
+                        let end = usize::from(range.end());
 
-                    // Sigh. rnix doesn't use the rowan cursor API.
-                    let new_string = format!("\"{new_value}\"");
-                    self.content.replace_range(start..end, &new_string);
+                        // Sigh. rnix doesn't use the rowan cursor API.
+                        self.content.replace_range(start..end, &new_string);
 
-                    // Re-parse to keep AST in sync
-                    self.ast = rnix::Root::parse(&self.content);
-                    return Ok(());
+                        // Re-parse to keep AST in sync
+                        self.parsed = rnix::Root::parse(&self.content);
+                        return true;
+                    }
                 }
             }
         }
 
-        bail!("Attribute '{attr_name}' with value '{old_value}' not found")
+        false
+    }
+
+    /// How to make `string` read `new_value`, if it currently reads `old_value`.
+    ///
+    /// Interpolations are kept: when a single one sits between unchanged literal text (`"1.2.${patch}"` to `"1.2.4"`)
+    /// the binding it refers to is edited, otherwise only the literal segments around the interpolated values are. If those
+    /// values no longer appear at all, the string is replaced by a plain literal; unresolvable interpolations are left alone.
+    fn rewrite(&self, string: &SyntaxNode, old_value: &str, new_value: &str) -> Rewrite {
+        let segments = self.segments(string);
+
+        if !segments.iter().any(|segment| matches!(segment, Segment::Interpolation { .. })) {
+            return if extract_string_value(string) == old_value {
+                Rewrite::Edit(string.clone(), format!("\"{new_value}\""))
+            } else {
+                Rewrite::NoMatch
+            };
+        }
+
+        if extract_string_value(string) != old_value && self.evaluate(string).as_deref() != Some(old_value) {
+            return Rewrite::NoMatch;
+        }
+
+        // One interpolation with unchanged text around it: the value it refers to is what changed
+        if let Some((before, name, after)) = lone_interpolation(&segments)
+            && let Some(middle) = new_value.strip_prefix(before.as_str()).and_then(|rest| rest.strip_suffix(after.as_str()))
+            && let Some(binding) = self.binding(name)
+            && !self.segments(&binding).iter().any(|segment| matches!(segment, Segment::Interpolation { .. }))
+            && !middle.is_empty()
+        {
+            return Rewrite::Edit(binding, format!("\"{middle}\""));
+        }
+
+        // Otherwise keep every interpolation where its value still appears, and rewrite the text between them
+        let mut rest = new_value;
+        let mut text = String::from("\"");
+
+        for segment in &segments {
+            match segment {
+                Segment::Literal(_) => {}
+                Segment::Interpolation { value: Some(value), source, .. } => {
+                    // The new value no longer contains it, so it can't be kept
+                    let Some(position) = rest.find(value.as_str()) else {
+                        return Rewrite::Edit(string.clone(), format!("\"{new_value}\""));
+                    };
+
+                    text.push_str(&rest[..position]);
+                    text.push_str(source);
+                    rest = &rest[position + value.len()..];
+                }
+                Segment::Interpolation { value: None, .. } => return Rewrite::Skip,
+            }
+        }
+
+        text.push_str(rest);
+        text.push('"');
+
+        Rewrite::Edit(string.clone(), text)
+    }
+
+    /// A string's literal text and interpolations, in order
+    fn segments(&self, string: &SyntaxNode) -> Vec<Segment> {
+        self.segments_at(string, 0)
+    }
+
+    /// `depth` counts nested lookups, so self-referencing bindings (invalid Nix, but still parseable) can't recurse forever
+    fn segments_at(&self, string: &SyntaxNode, depth: usize) -> Vec<Segment> {
+        string
+            .children_with_tokens()
+            .filter_map(|element| match element.kind() {
+                SyntaxKind::TOKEN_STRING_CONTENT => Some(Segment::Literal(element.to_string())),
+                SyntaxKind::NODE_INTERPOL => {
+                    let node = element.into_node()?;
+                    let name = node.children().find(|child| child.kind() == SyntaxKind::NODE_IDENT).map(|ident| ident.text().to_string());
+                    let value = name
+                        .as_deref()
+                        .filter(|_| depth < 8)
+                        .and_then(|name| self.binding(name))
+                        .and_then(|binding| self.evaluate_at(&binding, depth + 1));
+
+                    Some(Segment::Interpolation {
+                        source: node.text().to_string(),
+                        name,
+                        value,
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A string's value with its interpolations filled in, when each is a plain identifier bound to a string
+    fn evaluate(&self, string: &SyntaxNode) -> Option<String> {
+        self.evaluate_at(string, 0)
+    }
+
+    fn evaluate_at(&self, string: &SyntaxNode, depth: usize) -> Option<String> {
+        self.segments_at(string, depth)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => Some(text),
+                Segment::Interpolation { value, .. } => value,
+            })
+            .collect()
+    }
+
+    /// A string's value, evaluated where possible, or its raw text (`${...}` included) otherwise
+    fn value(&self, string: &SyntaxNode) -> String {
+        self.evaluate(string).unwrap_or_else(|| extract_string_value(string))
     }
 
     /// Get the current content
@@ -139,7 +544,7 @@ impl Ast {
 
     /// Helper to get attribute values in Nix AST
     fn get_internal(&self, attr_name: &str) -> Option<String> {
-        for child in self.ast.syntax().descendants() {
+        for child in self.node().descendants() {
             if child.kind() == SyntaxKind::NODE_ATTR_SET {
                 for attr_child in child.children() {
                     if attr_child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE {
@@ -156,11 +561,12 @@ impl Ast {
                                     }
                                 }
                                 SyntaxKind::NODE_STRING => {
-                                    value = Some(extract_string_value(&kv_child));
+                                    value = Some(self.value(&kv_child));
                                 }
                                 SyntaxKind::NODE_IDENT => {
-                                    // Handle identifier references like `repo = pname;`
-                                    value = Some(kv_child.text().to_string());
+                                    // Handle identifier references like `repo = pname;`, by the bound string when there is one
+                                    let name = kv_child.text().to_string();
+                                    value = Some(self.binding(&name).map_or(name, |binding| self.value(&binding)));
                                 }
                                 _ => {}
                             }
@@ -177,9 +583,42 @@ impl Ast {
         None
     }
 
+    /// The string an identifier is bound to, by a `let` or in a `rec` attrset, following references like `version = v;`
+    fn binding(&self, name: &str) -> Option<SyntaxNode> {
+        let mut name = name.to_string();
+
+        // Bounded, so `a = b; b = a;` can't loop forever
+        for _ in 0..8 {
+            let value = self.nodes().find_map(|node| {
+                let parent = node.parent()?;
+                let binds = parent.kind() == SyntaxKind::NODE_LET_IN
+                    || (parent.kind() == SyntaxKind::NODE_ATTR_SET && parent.children_with_tokens().any(|child| child.kind() == SyntaxKind::TOKEN_REC));
+
+                (node.kind() == SyntaxKind::NODE_ATTRPATH_VALUE && binds && node.first_child()?.text() == name.as_str()).then(|| node.last_child())?
+            })?;
+
+            match value.kind() {
+                SyntaxKind::NODE_STRING => return Some(value),
+                SyntaxKind::NODE_IDENT => name = value.text().to_string(),
+                _ => return None,
+            }
+        }
+
+        None
+    }
+
+    /// Whether the attribute `name` refers to a string binding (e.g. `version = v;`), which [`Ast::set`] edits in its place
+    pub fn resolves(&self, name: &str) -> bool {
+        self.nodes().any(|node| {
+            node.kind() == SyntaxKind::NODE_ATTRPATH_VALUE
+                && node.first_child().is_some_and(|path| path.text() == name)
+                && node.last_child().is_some_and(|value| value.kind() == SyntaxKind::NODE_IDENT && self.binding(&value.text().to_string()).is_some())
+        })
+    }
+
     /// Get a value from let binding or inherit statement
     fn get_from_let_or_inherit(&self, binding_name: &str) -> Option<String> {
-        for child in self.ast.syntax().descendants() {
+        for child in self.enclosing_lets().chain(self.node().descendants()) {
             // Check for let bindings
             if child.kind() == SyntaxKind::NODE_LET_IN {
                 for let_child in child.children() {
@@ -190,7 +629,7 @@ impl Ast {
                         // Get the value after the = sign
                         for value_child in let_child.children() {
                             if value_child.kind() == SyntaxKind::NODE_STRING {
-                                return Some(extract_string_value(&value_child));
+                                return Some(self.value(&value_child));
                             }
                         }
                     }
@@ -216,7 +655,7 @@ impl Ast {
     pub fn platforms(&self) -> Vec<PlatformBlock> {
         let mut blocks = Vec::new();
 
-        for child in self.ast.syntax().descendants() {
+        for child in self.node().descendants() {
             if child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE
                 && let Some(attr_path) = child.first_child()
             {
@@ -281,10 +720,136 @@ impl Ast {
         blocks
     }
 
+    /// How the attribute or binding `name` is written, if it exists at all
+    pub fn origin(&self, name: &str) -> Option<Origin> {
+        for node in self.nodes() {
+            match node.kind() {
+                SyntaxKind::NODE_ATTRPATH_VALUE if node.first_child().is_some_and(|path| path.text() == name) => {
+                    let value = node.last_child()?;
+
+                    return Some(match value.kind() {
+                        _ if node.parent().is_some_and(|parent| parent.kind() == SyntaxKind::NODE_LET_IN) => Origin::LetBound,
+                        SyntaxKind::NODE_STRING if value.children().any(|child| child.kind() == SyntaxKind::NODE_INTERPOL) => Origin::Interpolated,
+                        SyntaxKind::NODE_STRING => Origin::Literal,
+                        SyntaxKind::NODE_IDENT | SyntaxKind::NODE_SELECT => Origin::Reference,
+                        _ => Origin::Expression,
+                    });
+                }
+                SyntaxKind::NODE_INHERIT if node.children().any(|child| child.kind() == SyntaxKind::NODE_IDENT && child.text() == name) => {
+                    return Some(Origin::Inherited);
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Package names listed directly in `buildInputs` and `propagatedBuildInputs`, e.g. `openssl` or `python3Packages.requests`.
+    /// Conditional entries (`lib.optionals ...`) and other expressions are ignored.
+    pub fn build_inputs(&self) -> Vec<String> {
+        let mut inputs = Vec::new();
+
+        for child in self.node().descendants() {
+            if child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE
+                && let Some(attr_path) = child.first_child()
+                && (attr_path.text() == "buildInputs" || attr_path.text() == "propagatedBuildInputs")
+                && let Some(list) = child.last_child().and_then(|value| value.descendants().find(|node| node.kind() == SyntaxKind::NODE_LIST))
+            {
+                for item in list.children() {
+                    if matches!(item.kind(), SyntaxKind::NODE_IDENT | SyntaxKind::NODE_SELECT) {
+                        let name = item.text().to_string();
+                        let name = name.strip_prefix("pkgs.").unwrap_or(&name).to_string();
+
+                        if !inputs.contains(&name) {
+                            inputs.push(name);
+                        }
+                    }
+                }
+            }
+        }
+
+        inputs
+    }
+
+    /// The `src` fetcher's kind, and the flags (`fetchSubmodules`, `leaveDotGit`, `deepClone`) that change its hash
+    pub fn fetch_flags(&self) -> FetchFlags {
+        let enabled = |name: &str| self.get_value(&[], name) == Some(Value::Bool(true));
+        let calls = |fetcher: &str| self.block(&["src"]).is_some_and(|src| Self::contains_function_call(&src, fetcher));
+
+        let kind = if calls("fetchgit") {
+            FetchKind::Checkout
+        } else if calls("fetchFromGitHub") {
+            FetchKind::Git
+        } else if calls("fetchFrom") {
+            FetchKind::Forge
+        } else if calls("fetchzip") || calls("fetchCrate") {
+            FetchKind::Archive
+        } else if calls("fetchurl") || calls("fetchPypi") {
+            FetchKind::File
+        } else {
+            FetchKind::Git
+        };
+
+        FetchFlags {
+            kind,
+            submodules: enabled("fetchSubmodules"),
+            leave_dot_git: enabled("leaveDotGit"),
+            deep_clone: enabled("deepClone"),
+        }
+    }
+
+    /// Whether the source is pinned to a release tag derived from `version`, e.g. `rev = "v${version}";` or
+    /// `tag = "v${version}";`. Such sources move with `version` and have no commit to write.
+    pub fn pins_tag(&self) -> bool {
+        self.block(&["src"]).is_some_and(|src| {
+            src.descendants().any(|node| {
+                node.kind() == SyntaxKind::NODE_ATTRPATH_VALUE
+                    && node.first_child().is_some_and(|key| key.text() == "rev" || key.text() == "tag")
+                    && node.last_child().is_some_and(|value| {
+                        value.kind() == SyntaxKind::NODE_STRING
+                            && self
+                                .segments(&value)
+                                .iter()
+                                .any(|segment| matches!(segment, Segment::Interpolation { name: Some(name), .. } if name == "version"))
+                    })
+            })
+        })
+    }
+
+    /// Where `src` is fetched from, for packages without a `meta.homepage`: the repository a forge fetcher names with
+    /// `owner` and `repo` (on its `githubBase` or `domain` when set), the `url` of a `fetchgit`, or a GitHub archive
+    /// or release download's repository. A download from anywhere else names no repository to follow.
+    pub fn source_url(&self) -> Option<String> {
+        let src = self.block(&["src"])?;
+
+        for (fetcher, host_attr, default_host) in [
+            ("fetchFromGitHub", "githubBase", Some("github.com")),
+            ("fetchFromGitLab", "domain", Some("gitlab.com")),
+            ("fetchFromGitea", "domain", None),
+            ("fetchFromForgejo", "domain", None),
+            ("fetchFromCodeberg", "domain", Some("codeberg.org")),
+        ] {
+            if Self::contains_function_call(&src, fetcher) {
+                let host = self.get_in(&["src"], host_attr).or_else(|| default_host.map(String::from))?;
+
+                return Some(format!("https://{host}/{}/{}", self.get_in(&["src"], "owner")?, self.get_in(&["src"], "repo")?));
+            }
+        }
+
+        let url = self.get_in(&["src"], "url")?;
+
+        match url.strip_prefix("https://github.com/").map(|path| path.split('/').take(2).collect_vec()) {
+            Some(parts) if parts.len() == 2 => Some(format!("https://github.com/{}", parts.join("/").trim_end_matches(".git"))),
+            _ if Self::contains_function_call(&src, "fetchgit") || url.starts_with("git://") => Some(url),
+            _ => None,
+        }
+    }
+
     /// Extract the `platforms` attribute from the `meta` block as raw text.
     /// Returns the trailing segment (e.g. "linux", "darwin", "unix", "all") or None if absent.
     pub fn meta_platforms(&self) -> Option<String> {
-        for child in self.ast.syntax().descendants() {
+        for child in self.node().descendants() {
             if child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE
                 && let Some(attr_path) = child.first_child()
                 && attr_path.text() == "platforms"
@@ -309,7 +874,7 @@ impl Ast {
         if let Some(old_rev) = old_rev
             && !new_rev.is_empty()
         {
-            self.set("rev", old_rev, new_rev)?;
+            self.set_in(&["src"], "rev", old_rev, new_rev)?;
 
             // Update version if it contains the old rev
             if let Some(current_version) = self.get("version")
@@ -321,52 +886,10 @@ impl Ast {
         }
 
         // Update hash
-        let old_hash_value = if let Some(h) = old_hash { h.to_string() } else { self.get("hash").unwrap_or_default() };
+        let old_hash_value = if let Some(h) = old_hash { h.to_string() } else { self.get_in(&["src"], "hash").unwrap_or_default() };
 
         if !old_hash_value.is_empty() && !new_hash.is_empty() {
-            self.set("hash", &old_hash_value, new_hash)?;
-        }
-
-        Ok(())
-    }
-
-    /// Clear a vendor hash (cargoHash, vendorHash, npmDepsHash) to force recalculation
-    pub fn clear_vendor_hash(&mut self, hash_type: &str) -> Result<()> {
-        let attr_name = format!("{hash_type}Hash");
-        if let Some(old_hash) = self.get(&attr_name) {
-            self.set(&attr_name, &old_hash, "")?;
-        }
-        Ok(())
-    }
-
-    /// Update vendor hash by building the package and extracting the hash from error output
-    pub fn update_vendor(&mut self, package: &Package, hash_type: &str, pb: Option<&ProgressBar>) -> Result<()> {
-        //
-        if let Some(pb) = pb {
-            pb.set_message(format!("{}: Building to get new {hash_type}Hash...", package.name()));
-        } else {
-            info!(package = %package.name, hash_type, "Building to get new hash");
-        }
-
-        // Write out the current content so "nix build" can work with the latest changes
-        fs::write(&package.path, self.content())?;
-
-        let output = Command::new("nix").args(["build", &format!(".#{}", package.name), "--no-link"]).output()?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-
-            if let Some(new_hash) = stderr.lines().find_map(|l| Some(l.trim().split_once("got:")?.1.trim().to_string())) {
-                let attr_name = format!("{hash_type}Hash");
-
-                if let Some(old_hash) = self.get(&attr_name) {
-                    self.set(&attr_name, &old_hash, &new_hash)?;
-                    return Ok(());
-                }
-
-                // Handle case where hash is empty or doesn't exist
-                self.set(&attr_name, "", &new_hash)?;
-            }
+            self.set_in(&["src"], "hash", &old_hash_value, new_hash)?;
         }
 
         Ok(())
@@ -375,7 +898,7 @@ impl Ast {
 
 #[cfg(test)]
 mod tests {
-    use super::Ast;
+    use super::{Ast, FetchKind, Origin, Value};
 
     #[test]
     fn platforms_extracts_packages_blocks() {
@@ -407,6 +930,100 @@ mod tests {
         assert_eq!(platforms[1].attributes.get("hash").map(String::as_str), Some("sha256-old-linux"));
     }
 
+    #[test]
+    fn build_inputs_lists_plain_package_references() {
+        let ast = Ast::from_ast(rnix::Root::parse(
+            r"
+{
+  buildInputs = with pkgs; [
+    openssl
+    pkgs.zlib
+    python3Packages.requests
+  ] ++ lib.optionals stdenv.isDarwin [ libiconv ];
+  propagatedBuildInputs = [ openssl curl ];
+}
+",
+        ));
+
+        assert_eq!(ast.build_inputs(), ["openssl", "zlib", "python3Packages.requests", "curl"]);
+    }
+
+    #[test]
+    fn detects_fetch_flags() {
+        let submodules = Ast::from_ast(rnix::Root::parse(r#"{ src = fetchFromGitHub { owner = "o"; repo = "r"; fetchSubmodules = true; }; }"#)).fetch_flags();
+        let dot_git = Ast::from_ast(rnix::Root::parse(r#"{ src = fetchgit { url = "u"; leaveDotGit = true; deepClone = false; }; }"#)).fetch_flags();
+
+        assert!(submodules.submodules && !submodules.leave_dot_git && !submodules.deep_clone);
+        assert!(!dot_git.submodules && dot_git.leave_dot_git && !dot_git.deep_clone);
+        assert!(submodules.needs_clone() && dot_git.needs_clone());
+    }
+
+    #[test]
+    fn detects_tag_pinned_sources() {
+        let parse = |nix: &str| Ast::from_ast(rnix::Root::parse(nix));
+
+        assert!(parse(r#"{ version = "1.0"; src = fetchFromGitHub { owner = "o"; repo = "r"; rev = "v${version}"; }; }"#).pins_tag());
+        assert!(parse(r#"{ version = "1.0"; src = fetchFromGitHub { owner = "o"; repo = "r"; tag = "release-${version}"; }; }"#).pins_tag());
+        assert!(!parse(r#"{ version = "1.0"; src = fetchFromGitHub { owner = "o"; repo = "r"; rev = "0123abcd"; }; }"#).pins_tag());
+        assert!(!parse(r#"{ commit = "0123abcd"; src = fetchFromGitHub { owner = "o"; repo = "r"; rev = "${commit}"; }; }"#).pins_tag());
+    }
+
+    #[test]
+    fn derives_source_url_from_fetcher() {
+        let source = |nix: &str| Ast::from_ast(rnix::Root::parse(nix)).source_url();
+
+        assert_eq!(
+            source(r#"rec { pname = "tool"; src = fetchFromGitHub { owner = "example"; repo = pname; rev = "v1"; }; }"#),
+            Some("https://github.com/example/tool".to_string())
+        );
+        assert_eq!(
+            source(r#"{ src = fetchFromGitLab { owner = "group"; repo = "tool"; rev = "v1"; }; }"#),
+            Some("https://gitlab.com/group/tool".to_string())
+        );
+        assert_eq!(
+            source(r#"{ src = fetchgit { url = "https://git.example.org/tool.git"; rev = "abc"; }; }"#),
+            Some("https://git.example.org/tool.git".to_string())
+        );
+        assert_eq!(
+            source(r#"{ version = "1.2"; src = fetchurl { url = "https://github.com/example/tool/archive/v${version}.tar.gz"; }; }"#),
+            Some("https://github.com/example/tool".to_string())
+        );
+        assert_eq!(
+            source(r#"{ src = fetchFromGitLab { domain = "gitlab.gnome.org"; owner = "GNOME"; repo = "tool"; rev = "v1"; }; }"#),
+            Some("https://gitlab.gnome.org/GNOME/tool".to_string())
+        );
+        assert_eq!(
+            source(r#"{ src = fetchFromGitHub { githubBase = "github.example.com"; owner = "example"; repo = "tool"; rev = "v1"; }; }"#),
+            Some("https://github.example.com/example/tool".to_string())
+        );
+        assert_eq!(source(r#"{ src = fetchurl { url = "https://example.org/tool-1.2.tar.gz"; }; }"#), None);
+        assert_eq!(source("{ src = ./.; }"), None);
+    }
+
+    #[test]
+    fn origin_classifies_how_values_are_written() {
+        let ast = Ast::from_ast(rnix::Root::parse(
+            r#"
+let
+  version = "1.2.3";
+in
+{
+  pname = "tool";
+  inherit version;
+  rev = "v${version}";
+  repo = pname;
+  hash = "sha256-abc";
+}
+"#,
+        ));
+
+        assert_eq!(ast.origin("version"), Some(Origin::LetBound));
+        assert_eq!(ast.origin("pname"), Some(Origin::Literal));
+        assert_eq!(ast.origin("rev"), Some(Origin::Interpolated));
+        assert_eq!(ast.origin("repo"), Some(Origin::Reference));
+        assert_eq!(ast.origin("cargoHash"), None);
+    }
+
     #[test]
     fn detects_local_src() {
         let ast = Ast::from_ast(rnix::Root::parse(
@@ -439,4 +1056,222 @@ mod tests {
 
         assert!(!ast.has_local_src());
     }
+
+    #[test]
+    fn tells_fetcher_kinds_apart() {
+        let kind = |src: &str| Ast::from_ast(rnix::Root::parse(&format!("{{ pname = \"example\"; src = {src}; }}"))).fetch_flags().kind;
+
+        assert_eq!(kind("fetchurl { url = \"https://example.com/example.tar.gz\"; }"), FetchKind::File);
+        assert_eq!(kind("fetchzip { url = \"https://example.com/example.tar.gz\"; }"), FetchKind::Archive);
+        assert_eq!(kind("fetchFromGitHub { owner = \"o\"; repo = \"r\"; }"), FetchKind::Git);
+        assert_eq!(kind("fetchFromGitLab { owner = \"o\"; repo = \"r\"; }"), FetchKind::Forge);
+        assert_eq!(kind("fetchgit { url = \"https://example.com/r\"; }"), FetchKind::Checkout);
+
+        // Hashed as the checkout it is, not as GitHub's archive of the repository
+        assert_eq!(kind("fetchgit { url = \"https://github.com/o/r\"; }"), FetchKind::Checkout);
+    }
+
+    #[test]
+    fn scopes_lookups_and_edits_to_one_package() {
+        let source = r#"
+let
+  version = "1.0.0";
+in
+{
+  foo = buildPythonPackage {
+    pname = "foo";
+    inherit version;
+    src = fetchPypi {
+      pname = "foo_lib";
+      hash = "sha256-foo";
+    };
+  };
+  bar = buildPythonPackage {
+    pname = "bar";
+    version = "2.0.0";
+    hash = "sha256-bar";
+  };
+}
+"#;
+        let ast = Ast::from_ast(rnix::Root::parse(source));
+        assert_eq!(ast.package_count(), 2);
+
+        let mut bar = Ast::from_ast(rnix::Root::parse(source)).scoped(Some(1));
+        assert_eq!(bar.get("pname").as_deref(), Some("bar"));
+        assert_eq!(bar.get("hash").as_deref(), Some("sha256-bar"));
+
+        bar.set("hash", "sha256-bar", "sha256-new").unwrap();
+        assert!(bar.content().contains("sha256-foo"));
+        assert!(bar.content().contains("sha256-new"));
+
+        // Enclosing let bindings are shared, and stay reachable from the scope
+        let mut foo = Ast::from_ast(rnix::Root::parse(source)).scoped(Some(0));
+        assert_eq!(foo.get("version").as_deref(), Some("1.0.0"));
+
+        foo.set("version", "1.0.0", "1.1.0").unwrap();
+        assert!(foo.content().contains(r#"version = "1.1.0";"#));
+        assert!(foo.content().contains(r#"version = "2.0.0";"#));
+    }
+
+    #[test]
+    fn set_in_edits_only_the_src_fetcher() {
+        let mut ast = Ast::from_ast(rnix::Root::parse(
+            r#"
+{
+  pname = "example";
+  version = "1.0.0";
+  assets = fetchurl {
+    url = "https://example.com/assets.tar.gz";
+    hash = "sha256-same";
+  };
+  src = fetchFromGitHub {
+    owner = "example";
+    repo = "example";
+    rev = "v1.0.0";
+    hash = "sha256-same";
+  };
+}
+"#,
+        ));
+
+        assert_eq!(ast.get_in(&["src"], "rev").as_deref(), Some("v1.0.0"));
+
+        ast.set_in(&["src"], "hash", "sha256-same", "sha256-new").unwrap();
+
+        let content = ast.content();
+        assert!(content.find("sha256-same").unwrap() < content.find("sha256-new").unwrap());
+        assert_eq!(content.matches("sha256-same").count(), 1);
+    }
+
+    #[test]
+    fn follows_references_to_let_and_rec_bindings() {
+        let mut ast = Ast::from_ast(rnix::Root::parse(
+            r#"
+let
+  v = "1.2.3";
+in
+stdenv.mkDerivation rec {
+  pname = "example";
+  version = v;
+  tag = "v1.2.3";
+  rev = tag;
+}
+"#,
+        ));
+
+        assert_eq!(ast.get("version").as_deref(), Some("1.2.3"));
+        assert_eq!(ast.get("rev").as_deref(), Some("v1.2.3"));
+
+        ast.set("version", "1.2.3", "2.0.0").unwrap();
+        ast.set("rev", "v1.2.3", "v2.0.0").unwrap();
+
+        assert!(ast.content().contains(r#"v = "2.0.0";"#));
+        assert!(ast.content().contains(r#"tag = "v2.0.0";"#));
+        assert!(ast.content().contains("version = v;"));
+        assert!(ast.resolves("version"));
+    }
+
+    #[test]
+    fn updates_interpolated_strings() {
+        let mut ast = Ast::from_ast(rnix::Root::parse(
+            r#"
+let
+  patch = "3";
+in
+stdenv.mkDerivation rec {
+  pname = "example";
+  version = "1.2.${patch}";
+  rev = "v${version}";
+  url = "https://example.com/${pname}-${version}.tar.gz";
+  name = "${unknown}-1";
+}
+"#,
+        ));
+
+        assert_eq!(ast.get("version").as_deref(), Some("1.2.3"));
+        assert_eq!(ast.get("url").as_deref(), Some("https://example.com/example-1.2.3.tar.gz"));
+
+        // A lone interpolation between unchanged text updates the binding it refers to
+        ast.set("version", "1.2.3", "1.2.4").unwrap();
+        assert!(ast.content().contains(r#"patch = "4";"#));
+        assert!(ast.content().contains(r#"version = "1.2.${patch}";"#));
+
+        // Otherwise only the literal text around the interpolated values changes
+        ast.set("rev", "v1.2.4", "release-1.2.4").unwrap();
+        assert!(ast.content().contains(r#"rev = "release-${version}";"#));
+
+        ast.set("version", "1.2.4", "2.0.0").unwrap();
+        assert!(ast.content().contains(r#"version = "2.0.0";"#));
+        assert!(ast.content().contains(r#"rev = "release-${version}";"#));
+
+        // Interpolations that can't be resolved are left alone
+        ast.set("name", "${unknown}-1", "other-1").unwrap();
+        assert!(ast.content().contains(r#"name = "${unknown}-1";"#));
+    }
+
+    #[test]
+    fn reads_and_writes_non_string_values() {
+        let mut ast = Ast::from_ast(rnix::Root::parse(
+            r#"
+{
+  pname = "example";
+  src = fetchgit {
+    fetchSubmodules = true;
+    sparseCheckout = [ "src" ];
+  };
+  cargoLock = {
+    lockFile = ./Cargo.lock;
+    outputHashes = {
+      "foo-0.1.0" = "sha256-old";
+    };
+  };
+}
+"#,
+        ));
+
+        assert_eq!(ast.get_value(&["src"], "fetchSubmodules"), Some(Value::Bool(true)));
+        assert_eq!(ast.get_value(&[], "sparseCheckout"), Some(Value::List(vec![Value::String("src".to_string())])));
+        assert_eq!(ast.get_value(&["cargoLock"], "lockFile"), Some(Value::Expression("./Cargo.lock".to_string())));
+
+        let Some(Value::AttrSet(mut hashes)) = ast.get_value(&["cargoLock"], "outputHashes") else {
+            panic!("outputHashes should be an attrset");
+        };
+
+        hashes.insert("bar-0.2.0".to_string(), Value::String("sha256-new".to_string()));
+        ast.set_value(&["cargoLock"], "outputHashes", &Value::AttrSet(hashes)).unwrap();
+        ast.set_value(&["src"], "fetchSubmodules", &Value::Bool(false)).unwrap();
+
+        assert!(ast.content().contains(
+            r#"    outputHashes = {
+      "bar-0.2.0" = "sha256-new";
+      "foo-0.1.0" = "sha256-old";
+    };"#
+        ));
+        assert!(ast.content().contains("fetchSubmodules = false;"));
+    }
+
+    #[test]
+    fn inserts_and_renames() {
+        let mut ast = Ast::from_ast(rnix::Root::parse(
+            r#"
+rustPlatform.buildRustPackage {
+  pname = "example";
+  cargoSha256 = "sha256-old";
+  cargoDeps = rustPlatform.fetchCargoTarball {
+    hash = "sha256-old";
+  };
+}
+"#,
+        ));
+
+        ast.rename_attribute("cargoSha256", "cargoHash").unwrap();
+        ast.insert_after("cargoHash", "useFetchCargoVendor", &Value::Bool(true)).unwrap();
+        ast.rename_call("fetchCargoTarball", "fetchCargoVendor").unwrap();
+
+        assert!(ast.content().contains(
+            r#"  cargoHash = "sha256-old";
+  useFetchCargoVendor = true;
+  cargoDeps = rustPlatform.fetchCargoVendor {"#
+        ));
+    }
 }
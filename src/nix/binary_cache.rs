@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+
+use etcetera::base_strategy::{BaseStrategy, choose_base_strategy};
+use rootcause::{Result, bail};
+use serde::{Deserialize, Serialize};
+use whoami::username;
+
+use crate::package::{Package, UpdateStatus};
+use crate::progress::Progress;
+use crate::tools::{Tool, command, run};
+
+/// Where successful builds are pushed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    #[default]
+    Cachix,
+    /// `nix copy --to <store>`, for any store URI Nix understands
+    NixCopy,
+}
+
+/// `[binary_cache]` settings
+///
+/// ```toml
+/// [binary_cache]
+/// backend = "nix-copy"
+/// store = "s3://my-cache?region=eu-west-1"
+/// sign_key = "/etc/nix/cache-priv-key.pem"
+///
+/// # or, for cachix
+/// name = "my-cache"
+/// compression_method = "zstd"
+/// compression_level = 3
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BinaryCacheConfig {
+    pub backend: Backend,
+
+    /// Destination store for `nix-copy`, e.g. `s3://bucket`, `ssh://host` or `file:///srv/cache`
+    pub store: Option<String>,
+
+    /// Secret key file; when set, output paths are signed with `nix store sign` before they're pushed
+    pub sign_key: Option<PathBuf>,
+
+    /// Cachix cache to push to; defaults to the current user name
+    pub name: Option<String>,
+
+    /// Cachix compression method (`xz` or `zstd`)
+    pub compression_method: String,
+
+    /// Cachix compression level
+    pub compression_level: u8,
+}
+
+impl Default for BinaryCacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            store: None,
+            sign_key: None,
+            name: None,
+            compression_method: "xz".to_string(),
+            compression_level: 6,
+        }
+    }
+}
+
+impl BinaryCacheConfig {
+    /// Catch an incomplete configuration before any builds run
+    pub fn validate(&self) -> Result<()> {
+        if self.backend == Backend::NixCopy && self.store.is_none() {
+            bail!("binary_cache.backend = \"nix-copy\" needs binary_cache.store set to a store URI");
+        }
+
+        if let Some(key) = &self.sign_key
+            && !key.is_file()
+        {
+            bail!("binary_cache.sign_key {} does not exist", key.display());
+        }
+
+        Ok(())
+    }
+
+    /// Why pushing can't work in this environment, checked once before any builds
+    pub fn unavailable(&self) -> Option<String> {
+        if self.backend != Backend::Cachix {
+            return None;
+        }
+
+        if run(command(Tool::Cachix).arg("--version"), Tool::Cachix).is_err() {
+            return Some("cachix is not installed".to_string());
+        }
+
+        let has_config = choose_base_strategy().is_ok_and(|strategy| strategy.config_dir().join("cachix").join("cachix.dhall").is_file());
+
+        if std::env::var_os("CACHIX_AUTH_TOKEN").is_none() && !has_config {
+            return Some("no cachix auth token (set CACHIX_AUTH_TOKEN or run `cachix authtoken`)".to_string());
+        }
+
+        None
+    }
+
+    /// Push a built package's output paths to the configured cache
+    pub fn push(&self, package: &mut Package, pb: &Progress, systems: &[String]) -> Result<()> {
+        let paths = output_paths(package, systems)?;
+
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(key) = &self.sign_key {
+            pb.set_message(format!("{}: Signing ...", package.name()));
+
+            let output = run(command(Tool::Nix).args(["store", "sign", "--recursive", "--key-file"]).arg(key).args(&paths), Tool::Nix)?;
+
+            if !output.status.success() {
+                bail!("Signing with {} failed: {}", key.display(), String::from_utf8_lossy(&output.stderr).trim());
+            }
+        }
+
+        match self.backend {
+            Backend::NixCopy => {
+                let Some(store) = &self.store else {
+                    bail!("No binary_cache.store configured for nix-copy");
+                };
+
+                pb.set_message(format!("{}: Copying to {store} ...", package.name()));
+
+                let output = run(command(Tool::Nix).args(["copy", "--to", store]).args(&paths), Tool::Nix)?;
+
+                if !output.status.success() {
+                    bail!("nix copy to {store} failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+                }
+            }
+            Backend::Cachix => {
+                pb.set_message(format!("{}: Pushing to cachix ...", package.name()));
+
+                let name = match &self.name {
+                    Some(name) => name.clone(),
+                    None => username()?,
+                };
+
+                let level = self.compression_level.to_string();
+
+                let output = run(
+                    command(Tool::Cachix)
+                        .args(["push", "--compression-method", &self.compression_method, "--compression-level", &level, &name])
+                        .args(&paths),
+                    Tool::Cachix,
+                )?;
+
+                if !output.status.success() {
+                    bail!("cachix push to {name} failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+                }
+            }
+        }
+
+        package.result.status.insert(UpdateStatus::Cached);
+
+        Ok(())
+    }
+}
+
+/// Store paths of the package's flake output, for each of `systems` (or just the current system)
+fn output_paths(package: &Package, systems: &[String]) -> Result<Vec<String>> {
+    let systems = if systems.is_empty() { vec![None] } else { systems.iter().map(Some).collect() };
+    let mut paths = Vec::new();
+
+    for system in systems {
+        let mut path_info = command(Tool::Nix);
+
+        path_info.args(["path-info", &format!(".#{}", package.flake_attr())]);
+
+        if let Some(system) = system {
+            path_info.args(["--system", system]);
+        }
+
+        let output = run(&mut path_info, Tool::Nix)?;
+
+        if output.status.success() {
+            paths.extend(String::from_utf8_lossy(&output.stdout).lines().filter(|path| !path.is_empty()).map(String::from));
+        }
+    }
+
+    Ok(paths)
+}
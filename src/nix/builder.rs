@@ -1,56 +1,389 @@
-use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
-use std::process::Command;
+use std::process::{Output, Stdio};
+use std::sync::{Condvar, Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+use std::{fs, thread};
 
-use indicatif::ProgressBar;
-use rootcause::Result;
-use whoami::username;
+use rootcause::{Result, bail};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use wait_timeout::ChildExt;
 
+use crate::error::UpdateError;
+use crate::nix::binary_cache::BinaryCacheConfig;
 use crate::package::{Package, UpdateStatus};
+use crate::progress::Progress;
+use crate::tools::{Tool, command, nix_build, run};
 
-pub fn build_package(package: &mut Package, pb: &ProgressBar, build_path: &Path, cache: bool) -> Result<()> {
-    fs::create_dir_all(build_path)?;
+/// What to do around a package's `nix build`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BuildOptions<'a> {
+    /// Cache to push the result to once it built (and passed its checks)
+    pub push: Option<&'a BinaryCacheConfig>,
 
-    let log_file = build_path.join(format!("{}.log", package.name));
+    /// Stream build logs to the progress line
+    pub verbose: bool,
 
-    pb.set_message(format!("{}: Building ...", package.name()));
+    /// Build the package's `checks.<system>.<name>` flake output after it built
+    pub check: bool,
 
-    let output = Command::new("nix").args(["build", &format!(".#{}", package.name), "--no-link"]).output()?;
+    /// Rebuild the package with `--rebuild` after it built, pushing it only when the outputs match
+    pub reproducible: bool,
 
-    let log_content = format!("stdout:\n{}\nstderr:\n{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    /// Systems to build for, from `[build]`; empty means just the current system
+    pub systems: &'a [String],
+}
 
-    fs::write(&log_file, log_content)?;
+/// Caps how many `nix build` invocations run at once, independently of how many packages are being checked
+pub struct BuildSlots {
+    available: Mutex<usize>,
+    freed: Condvar,
+}
 
-    if output.status.success() {
-        package.result.status.insert(UpdateStatus::Built);
+/// A claimed build slot, handed back when dropped
+pub struct BuildSlot<'a>(&'a BuildSlots);
+
+impl BuildSlots {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            available: Mutex::new(limit.max(1)),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Block until a slot is free
+    pub fn acquire(&self) -> BuildSlot<'_> {
+        let available = self.available.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut available = self.freed.wait_while(available, |available| *available == 0).unwrap_or_else(PoisonError::into_inner);
+
+        *available -= 1;
+
+        BuildSlot(self)
+    }
+}
+
+impl Drop for BuildSlot<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// `[build]` settings
+///
+/// ```toml
+/// [build]
+/// systems = ["x86_64-linux", "aarch64-darwin"]
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BuildConfig {
+    /// Build updated packages for each of these systems (locally or on remote builders) instead of just the current one
+    pub systems: Vec<String>,
+}
+
+/// Build a package, writing its output to `build_path/<name>.log` (`<name>.<system>.log` per configured system)
+pub fn build_package(package: &mut Package, pb: &Progress, build_path: &Path, options: BuildOptions<'_>) -> Result<()> {
+    fs::create_dir_all(build_path)?;
+
+    let built = if options.systems.is_empty() {
+        build_for_system(package, pb, build_path, None, options.verbose)?
+    } else {
+        let mut built = true;
 
-        if cache {
-            push_to_cachix(package, pb)?;
+        for system in options.systems {
+            let built_system = build_for_system(package, pb, build_path, Some(system), options.verbose)?;
+
+            package.result.systems.insert(system.clone(), built_system);
+            built &= built_system;
         }
+
+        built
+    };
+
+    if !built {
+        return Ok(());
+    }
+
+    package.result.status.insert(UpdateStatus::Built);
+
+    if options.check && !run_checks(package, pb, build_path, options.verbose)? {
+        return Ok(());
+    }
+
+    if options.reproducible && !check_reproducibility(package, pb, build_path, options.verbose)? {
+        return Ok(());
+    }
+
+    // The build itself succeeded; a failed push is reported but doesn't fail the package
+    if let Some(cache) = options.push
+        && let Err(e) = cache.push(package, pb, options.systems)
+    {
+        package.result.message(format!("Not cached: {e}"));
     }
 
     Ok(())
 }
 
-pub fn push_to_cachix(package: &mut Package, pb: &ProgressBar) -> Result<()> {
-    pb.set_message(format!("{}: Pushing to cachix ...", package.name()));
+/// Build the flake packages that depend on an updated package, recording whether each still builds. A broken
+/// dependent is reported on the updated package without failing it; its output goes to
+/// `build_path/<name>.dependent.<dependent>.log`.
+pub fn build_dependents(package: &mut Package, dependents: &[&str], pb: &Progress, build_path: &Path, verbose: bool) -> Result<()> {
+    let timeout = package.settings.build_timeout();
 
-    let output = Command::new("nix").args(["path-info", &format!(".#{}", package.name)]).output()?;
+    for dependent in dependents {
+        pb.set_message(format!("{}: Building dependent {dependent} ...", package.name()));
 
-    if output.status.success() {
-        let user = username()?;
-        let paths = String::from_utf8_lossy(&output.stdout);
+        let target = format!(".#{dependent}");
+        let log_file = build_path.join(format!("{}.dependent.{dependent}.log", package.name));
+
+        let Some(output) = run_build(&[&target], timeout, verbose.then_some((package.name.as_str(), pb)))? else {
+            fs::write(&log_file, "build timed out\n")?;
+            package.result.dependents.insert((*dependent).to_string(), false);
+            continue;
+        };
+
+        fs::write(
+            &log_file,
+            format!("stdout:\n{}\nstderr:\n{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr)),
+        )?;
+
+        package.result.dependents.insert((*dependent).to_string(), output.status.success());
+    }
+
+    Ok(())
+}
+
+/// Build for one system (the current one when `None`), retrying and timing out per the package settings
+fn build_for_system(package: &mut Package, pb: &Progress, build_path: &Path, system: Option<&str>, verbose: bool) -> Result<bool> {
+    let (log_file, label) = match system {
+        Some(system) => (build_path.join(format!("{}.{system}.log", package.name)), format!(" for {system}")),
+        None => (build_path.join(format!("{}.log", package.name)), String::new()),
+    };
 
-        for path in paths.lines() {
-            if !path.is_empty() {
-                Command::new("cachix")
-                    .args(["push", "--compression-method", "xz", "--compression-level", "6", &user, path])
-                    .output()?;
+    let target = format!(".#{}", package.flake_attr());
+    let args = match system {
+        Some(system) => vec![target.as_str(), "--system", system],
+        None => vec![target.as_str()],
+    };
 
-                package.result.status.insert(UpdateStatus::Cached);
+    let timeout = package.settings.build_timeout();
+    let attempts = package.settings.build_retries() + 1;
+
+    for attempt in 1..=attempts {
+        if attempt == 1 {
+            pb.set_message(format!("{}: Building{label} ...", package.name()));
+        } else {
+            pb.set_message(format!("{}: Building{label} (attempt {attempt}/{attempts}) ...", package.name()));
+        }
+
+        let Some(output) = run_build(&args, timeout, verbose.then_some((package.name.as_str(), pb)))? else {
+            fs::write(&log_file, "build timed out\n")?;
+            debug!(package = %package.name, attempt, system, "Build timed out");
+
+            if attempt == attempts {
+                let timeout = timeout.unwrap_or_default();
+                package.result.fail(UpdateError::Build {
+                    message: format!("Build{label} timed out after {}", humantime::format_duration(timeout)),
+                    log: None,
+                });
             }
+
+            continue;
+        };
+
+        let log_content = format!("stdout:\n{}\nstderr:\n{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+
+        fs::write(&log_file, log_content)?;
+
+        if output.status.success() {
+            return Ok(true);
         }
     }
 
-    Ok(())
+    // A failed build leaves the package's status alone, but automation still learns why it didn't build
+    package.result.error.get_or_insert_with(|| UpdateError::Build {
+        message: format!("Build{label} failed"),
+        log: Some(log_file.clone()),
+    });
+    package.result.failed_log = Some(log_file);
+
+    Ok(false)
+}
+
+/// Build the package's flake check, if it has one, writing its output to `build_path/<name>.check.log`.
+///
+/// Returns whether the package may go on to be cached: true when the checks passed or none are defined.
+fn run_checks(package: &mut Package, pb: &Progress, build_path: &Path, verbose: bool) -> Result<bool> {
+    pb.set_message(format!("{}: Running checks ...", package.name()));
+
+    let target = format!(".#checks.{}.{}", current_system()?, package.flake_attr());
+    let timeout = package.settings.build_timeout();
+
+    let Some(output) = run_build(&[&target], timeout, verbose.then_some((package.name.as_str(), pb)))? else {
+        package.result.fail(UpdateError::Build {
+            message: "Checks timed out".to_string(),
+            log: None,
+        });
+        return Ok(false);
+    };
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let log_file = build_path.join(format!("{}.check.log", package.name));
+
+    fs::write(&log_file, stderr.as_bytes())?;
+
+    if output.status.success() {
+        package.result.status.insert(UpdateStatus::Checked);
+        Ok(true)
+    } else if stderr.contains("does not provide attribute") {
+        debug!(package = %package.name, "No flake checks defined");
+        Ok(true)
+    } else {
+        package.result.fail(UpdateError::Build {
+            message: "Checks failed".to_string(),
+            log: Some(log_file.clone()),
+        });
+        package.result.failed_log = Some(log_file);
+        Ok(false)
+    }
+}
+
+/// Build the package a second time with `nix build --rebuild`, which fails when the outputs differ, writing its output to
+/// `build_path/<name>.rebuild.log`.
+///
+/// Returns whether the package may go on to be cached: only outputs verified to be reproducible are.
+fn check_reproducibility(package: &mut Package, pb: &Progress, build_path: &Path, verbose: bool) -> Result<bool> {
+    pb.set_message(format!("{}: Rebuilding to check reproducibility ...", package.name()));
+
+    let target = format!(".#{}", package.flake_attr());
+
+    let Some(output) = run_build(&[&target, "--rebuild"], package.settings.build_timeout(), verbose.then_some((package.name.as_str(), pb)))? else {
+        package.result.message("Reproducibility not checked: rebuild timed out");
+        return Ok(false);
+    };
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    fs::write(build_path.join(format!("{}.rebuild.log", package.name)), stderr.as_bytes())?;
+
+    if output.status.success() {
+        package.result.status.insert(UpdateStatus::Reproducible);
+        Ok(true)
+    } else if stderr.contains("may not be deterministic") {
+        package.result.status.insert(UpdateStatus::NotReproducible);
+        package
+            .result
+            .message(format!("Not reproducible: rebuild produced different output for {}", differing_outputs(&stderr).join(", ")));
+        Ok(false)
+    } else {
+        package.result.message("Reproducibility not checked: rebuild failed");
+        Ok(false)
+    }
+}
+
+/// The outputs `nix build --rebuild` found to differ, from its `output '<path>' differs from '<path>.check'` errors
+fn differing_outputs(stderr: &str) -> Vec<&str> {
+    stderr
+        .lines()
+        .filter_map(|line| line.split_once("output '")?.1.split_once("' differs from").map(|(path, _)| path))
+        .collect()
+}
+
+/// The Nix system double (e.g. `x86_64-linux`) that flake outputs are selected for
+pub fn current_system() -> Result<&'static str> {
+    static SYSTEM: OnceLock<String> = OnceLock::new();
+
+    if let Some(system) = SYSTEM.get() {
+        return Ok(system);
+    }
+
+    let output = run(command(Tool::Nix).args(["eval", "--impure", "--raw", "--expr", "builtins.currentSystem"]), Tool::Nix)?;
+
+    if !output.status.success() {
+        bail!("Failed to determine the current system: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(SYSTEM.get_or_init(|| String::from_utf8_lossy(&output.stdout).trim().to_string()))
+}
+
+/// Run `nix build` for a flake output (plus any extra arguments), killing it once `timeout` passes. `None` means it timed out.
+///
+/// With `stream`, build log lines are shown on the package's progress line as they arrive.
+fn run_build(target: &[&str], timeout: Option<Duration>, stream: Option<(&str, &Progress)>) -> Result<Option<Output>> {
+    let mut args = target.to_vec();
+
+    args.push("--no-link");
+
+    if stream.is_some() {
+        args.push("--print-build-logs");
+    }
+
+    let mut child = nix_build(&args).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    let stdout_pipe = child.stdout.take();
+    let stderr_pipe = child.stderr.take();
+
+    thread::scope(|scope| {
+        // Drain both pipes while waiting, so a chatty build can't block on a full pipe
+        let stdout = scope.spawn(|| {
+            let mut buffer = Vec::new();
+
+            if let Some(mut pipe) = stdout_pipe {
+                let _ = pipe.read_to_end(&mut buffer);
+            }
+
+            buffer
+        });
+
+        let stderr = scope.spawn(|| {
+            let mut buffer = Vec::new();
+
+            for line in stderr_pipe.into_iter().flat_map(|pipe| BufReader::new(pipe).split(b'\n')).map_while(io::Result::ok) {
+                if let Some((name, pb)) = stream {
+                    let text = String::from_utf8_lossy(&line);
+
+                    if !text.trim().is_empty() {
+                        pb.set_message(format!("{name}: {}", text.trim_end()));
+                    }
+                }
+
+                buffer.extend_from_slice(&line);
+                buffer.push(b'\n');
+            }
+
+            buffer
+        });
+
+        let status = match timeout {
+            Some(timeout) => child.wait_timeout(timeout)?,
+            None => Some(child.wait()?),
+        };
+
+        let Some(status) = status else {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        };
+
+        Ok(Some(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::differing_outputs;
+
+    #[test]
+    fn lists_outputs_that_differ_on_rebuild() {
+        let stderr = "building '/nix/store/abc-hello-2.12.drv'...\n\
+            error: derivation '/nix/store/abc-hello-2.12.drv' may not be deterministic: output '/nix/store/def-hello-2.12' differs from '/nix/store/def-hello-2.12.check'";
+
+        assert_eq!(differing_outputs(stderr), ["/nix/store/def-hello-2.12"]);
+    }
 }
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use rootcause::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::package::Package;
+use crate::tools::{Tool, command, run};
+
+/// A built output's closure, and how it compares to the previous build's
+#[derive(Clone, Debug, Serialize)]
+pub struct Closure {
+    pub path: String,
+    pub size: u64,
+    pub previous_size: Option<u64>,
+}
+
+impl Closure {
+    /// e.g. `Closure 120.4 MiB (+2.1 MiB)`
+    pub fn summary(&self) -> String {
+        match self.previous_size {
+            Some(previous) if previous != self.size => {
+                let (sign, delta) = if self.size > previous {
+                    ('+', self.size - previous)
+                } else {
+                    ('-', previous - self.size)
+                };
+
+                format!("Closure {} ({sign}{})", format_size(self.size), format_size(delta))
+            }
+            _ => format!("Closure {}", format_size(self.size)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PathInfo {
+    #[serde(default)]
+    path: Option<String>,
+    closure_size: Option<u64>,
+}
+
+/// `nix path-info --json` prints a list of paths before Nix 2.19 and an object keyed by path since
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PathInfos {
+    Keyed(BTreeMap<String, PathInfo>),
+    Listed(Vec<PathInfo>),
+}
+
+/// Measure the closure of the package's (current system) output with `nix path-info -S`, writing what changed since
+/// `previous` (the last build's output, when it's still in the store) to `build_path/<name>.closure.log`
+pub fn measure(package: &Package, previous: Option<(&str, u64)>, build_path: &Path) -> Result<Closure> {
+    let output = run(
+        command(Tool::Nix).args(["path-info", "--json", "--closure-size", &format!(".#{}", package.flake_attr())]),
+        Tool::Nix,
+    )?;
+
+    if !output.status.success() {
+        bail!("nix path-info failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let Some((path, size)) = parse_path_info(&output.stdout)? else {
+        bail!("nix path-info reported no closure size");
+    };
+
+    if let Some((previous_path, _)) = previous
+        && previous_path != path
+    {
+        let diff = run(command(Tool::Nix).args(["store", "diff-closures", previous_path, &path]), Tool::Nix)?;
+
+        // The previous output may have been garbage collected since, leaving just the size to compare
+        if diff.status.success() {
+            fs::write(build_path.join(format!("{}.closure.log", package.name)), &diff.stdout)?;
+        }
+    }
+
+    Ok(Closure {
+        path,
+        size,
+        previous_size: previous.map(|(_, size)| size),
+    })
+}
+
+fn parse_path_info(json: &[u8]) -> Result<Option<(String, u64)>> {
+    let infos = match serde_json::from_slice(json)? {
+        PathInfos::Keyed(infos) => infos.into_iter().map(|(path, info)| (Some(path), info.closure_size)).collect::<Vec<_>>(),
+        PathInfos::Listed(infos) => infos.into_iter().map(|info| (info.path, info.closure_size)).collect(),
+    };
+
+    Ok(infos.into_iter().find_map(|(path, size)| Some((path?, size?))))
+}
+
+/// Bytes in binary units, e.g. `120.4 MiB`
+#[allow(clippy::cast_precision_loss)]
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 { format!("{bytes} B") } else { format!("{size:.1} {}", UNITS[unit]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_closure_sizes() {
+        let keyed = br#"{"/nix/store/abc-foo-1.0":{"closureSize":1048576,"narSize":4096}}"#;
+        let listed = br#"[{"path":"/nix/store/abc-foo-1.0","closureSize":1048576,"narSize":4096}]"#;
+
+        for json in [&keyed[..], &listed[..]] {
+            assert_eq!(parse_path_info(json).unwrap(), Some(("/nix/store/abc-foo-1.0".to_string(), 1_048_576)));
+        }
+
+        let grown = Closure {
+            path: String::new(),
+            size: 126_248_550,
+            previous_size: Some(124_046_131),
+        };
+
+        assert_eq!(grown.summary(), "Closure 120.4 MiB (+2.1 MiB)");
+        assert_eq!(format_size(512), "512 B");
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rootcause::{Result, bail};
+use serde::Deserialize;
+
+use crate::tools::{Tool, command, run};
+
+/// Each `packages.<system>` attribute mapped to its derivation, leaving out those that fail to evaluate
+const EXPR: &str = "let flake = builtins.getFlake (toString ./.); \
+     packages = flake.packages.${builtins.currentSystem} or { }; \
+     drvPath = drv: let result = builtins.tryEval drv.drvPath; in if result.success then result.value else null; \
+     in builtins.mapAttrs (_: drvPath) packages";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Derivation {
+    #[serde(default)]
+    input_drvs: BTreeMap<String, serde_json::Value>,
+}
+
+/// Which of the flake's packages depend on which, for building the consumers of an updated package
+#[derive(Debug, Default)]
+pub struct Dependents {
+    /// Each package mapped to the packages built from it, directly or through other packages of the flake
+    dependents: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Dependents {
+    /// Evaluate the flake's packages for the current system and read their derivations' inputs with `nix derivation show`
+    pub fn evaluate() -> Result<Self> {
+        let output = run(command(Tool::Nix).args(["eval", "--impure", "--json", "--expr", EXPR]), Tool::Nix)?;
+
+        if !output.status.success() {
+            bail!("Failed to evaluate flake packages: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        let packages: BTreeMap<String, Option<String>> = serde_json::from_slice(&output.stdout)?;
+        let packages: BTreeMap<String, String> = packages.into_iter().filter_map(|(name, drv)| Some((name, drv?))).collect();
+
+        if packages.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let output = run(command(Tool::Nix).args(["derivation", "show"]).args(packages.values()), Tool::Nix)?;
+
+        if !output.status.success() {
+            bail!("Failed to read package derivations: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        let derivations: BTreeMap<String, Derivation> = serde_json::from_slice(&output.stdout)?;
+
+        Ok(Self::from_derivations(&packages, &derivations))
+    }
+
+    fn from_derivations(packages: &BTreeMap<String, String>, derivations: &BTreeMap<String, Derivation>) -> Self {
+        // Newer Nix versions key derivations by store path name rather than full path
+        let by_drv: BTreeMap<&str, &str> = packages.iter().map(|(name, drv)| (store_name(drv), name.as_str())).collect();
+
+        let mut direct: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+        for (drv, derivation) in derivations {
+            let Some(&dependent) = by_drv.get(store_name(drv)) else {
+                continue;
+            };
+
+            for input in derivation.input_drvs.keys().filter_map(|input| by_drv.get(store_name(input))) {
+                if *input != dependent {
+                    direct.entry((*input).to_string()).or_default().insert(dependent.to_string());
+                }
+            }
+        }
+
+        let dependents = direct
+            .keys()
+            .map(|name| {
+                let mut seen = BTreeSet::new();
+                let mut pending = vec![name];
+
+                while let Some(next) = pending.pop() {
+                    for dependent in direct.get(next).into_iter().flatten() {
+                        if dependent != name && seen.insert(dependent.clone()) {
+                            pending.push(dependent);
+                        }
+                    }
+                }
+
+                (name.clone(), seen)
+            })
+            .collect();
+
+        Self { dependents }
+    }
+
+    /// The packages that have to be rebuilt when `package` changes
+    pub fn of(&self, package: &str) -> impl Iterator<Item = &str> {
+        self.dependents.get(package).into_iter().flatten().map(String::as_str)
+    }
+}
+
+/// `/nix/store/<hash>-<name>.drv` without the store directory
+fn store_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_dependencies_between_packages() {
+        let packages: BTreeMap<String, String> = [
+            ("libfoo", "/nix/store/aaa-libfoo-1.0.drv"),
+            ("foo-cli", "/nix/store/bbb-foo-cli-1.0.drv"),
+            ("foo-gui", "/nix/store/ccc-foo-gui-1.0.drv"),
+            ("unrelated", "/nix/store/ddd-unrelated-1.0.drv"),
+        ]
+        .into_iter()
+        .map(|(name, drv)| (name.to_string(), drv.to_string()))
+        .collect();
+
+        let derivations: BTreeMap<String, Derivation> = serde_json::from_str(
+            r#"{
+  "/nix/store/aaa-libfoo-1.0.drv": { "inputDrvs": { "/nix/store/eee-stdenv.drv": {} } },
+  "/nix/store/bbb-foo-cli-1.0.drv": { "inputDrvs": { "/nix/store/aaa-libfoo-1.0.drv": {}, "/nix/store/eee-stdenv.drv": {} } },
+  "ccc-foo-gui-1.0.drv": { "inputDrvs": { "bbb-foo-cli-1.0.drv": {} } },
+  "/nix/store/ddd-unrelated-1.0.drv": {}
+}"#,
+        )
+        .unwrap();
+
+        let dependents = Dependents::from_derivations(&packages, &derivations);
+
+        assert_eq!(dependents.of("libfoo").collect::<Vec<_>>(), ["foo-cli", "foo-gui"]);
+        assert_eq!(dependents.of("foo-cli").collect::<Vec<_>>(), ["foo-gui"]);
+        assert_eq!(dependents.of("foo-gui").count(), 0);
+        assert_eq!(dependents.of("unrelated").count(), 0);
+    }
+}
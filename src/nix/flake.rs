@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use rootcause::{Result, bail};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::tools::{Tool, command, run};
+
+/// Each `packages.<system>` attribute mapped to the file defining it.
+///
+/// `meta.position` is what nixpkgs' `mkDerivation` records; `unsafeGetAttrPos` on `pname` covers derivations
+/// built without it. Paths come back in the store copy of the flake, so `root` is returned to strip them.
+const EXPR: &str = "let flake = builtins.getFlake (toString ./.); \
+     packages = flake.packages.${builtins.currentSystem} or { }; \
+     position = drv: let meta = builtins.tryEval (drv.meta.position or null); \
+       pos = builtins.unsafeGetAttrPos \"pname\" drv; \
+       in if meta.success && meta.value != null then meta.value else if pos != null then pos.file else null; \
+     in { root = toString flake.outPath; packages = builtins.mapAttrs (_: position) packages; }";
+
+#[derive(Debug, Deserialize)]
+struct Positions {
+    root: String,
+    packages: BTreeMap<String, Option<String>>,
+}
+
+/// The flake's packages for the current system, each attribute with its source file relative to the repository root.
+/// A file shows up once per attribute exporting a package from it.
+///
+/// Finds packages the directory walk can't: those wired up through `callPackage` in a `default.nix`, or generated
+/// attrsets whose files live outside `packages/`.
+pub fn package_files() -> Result<Vec<(String, PathBuf)>> {
+    let output = run(command(Tool::Nix).args(["eval", "--impure", "--json", "--expr", EXPR]), Tool::Nix)?;
+
+    if !output.status.success() {
+        bail!("Failed to evaluate flake packages: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let positions: Positions = serde_json::from_slice(&output.stdout)?;
+
+    Ok(positions
+        .packages
+        .into_iter()
+        .filter_map(|(name, position)| {
+            let Some(position) = position else {
+                warn!(package = %name, "Skipping: no source position");
+                return None;
+            };
+
+            let file = strip_line(&position);
+
+            let Some(relative) = file.strip_prefix(&positions.root).map(|path| path.trim_start_matches('/')) else {
+                // Re-exported from nixpkgs or another input, nothing to update here
+                debug!(package = %name, file, "Skipping: defined outside this flake");
+                return None;
+            };
+
+            Some((name, PathBuf::from(relative)))
+        })
+        .collect())
+}
+
+/// Check that an edited package still evaluates as the flake attribute `name`: its `version`, and its `src` down to the
+/// store path, which is where a hash written into the wrong attribute shows up. Unless the attribute is one the flake
+/// was found to `export`, a missing one means the package isn't exported, which can't be checked and passes.
+pub fn evaluate(name: &str, exported: bool) -> Result<()> {
+    let target = format!(".#{name}");
+
+    let output = run(
+        command(Tool::Nix).args(["eval", "--json", &target, "--apply", "p: { inherit (p) version; src = p.src.outPath or null; }"]),
+        Tool::Nix,
+    )?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if !exported && stderr.contains("does not provide attribute") {
+        debug!(package = %name, "Not exported by the flake, edits not evaluated");
+        return Ok(());
+    }
+
+    bail!(
+        "{name} no longer evaluates: {}",
+        stderr.lines().map(str::trim).rfind(|line| line.starts_with("error:")).unwrap_or_else(|| stderr.trim())
+    )
+}
+
+/// `meta.position` is `<file>:<line>`
+fn strip_line(position: &str) -> &str {
+    match position.rsplit_once(':') {
+        Some((file, line)) if !line.is_empty() && line.bytes().all(|b| b.is_ascii_digit()) => file,
+        _ => position,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_line_from_positions() {
+        assert_eq!(strip_line("/nix/store/abc-source/packages/foo.nix:12"), "/nix/store/abc-source/packages/foo.nix");
+        assert_eq!(strip_line("/nix/store/abc-source/packages/foo.nix"), "/nix/store/abc-source/packages/foo.nix");
+    }
+}
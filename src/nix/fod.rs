@@ -0,0 +1,166 @@
+use std::fmt;
+
+use itertools::Itertools;
+use rootcause::Result;
+use thiserror::Error;
+use tracing::info;
+
+use crate::error::UpdateError;
+use crate::nix::ast::{Ast, Value};
+use crate::package::Package;
+use crate::progress::Progress;
+use crate::tools::{Tool, nix_build, run};
+
+/// A fixed-output derivation hash a package declares, such as `vendorHash`, and the derivation it pins
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedOutput {
+    /// Block holding the hash, empty for the package's own attributes (`["cargoDeps"]` for `cargoDeps = ... { hash = ...; }`)
+    pub path: &'static [&'static str],
+    pub attr: &'static str,
+    /// Package attribute evaluating to the fixed-output derivation, so it can be built without the rest of the package
+    pub derivation: &'static str,
+}
+
+impl FixedOutput {
+    pub const CARGO: Self = Self::new(&[], "cargoHash", "cargoDeps");
+    pub const CARGO_SHA256: Self = Self::new(&[], "cargoSha256", "cargoDeps");
+    pub const CARGO_DEPS: Self = Self::new(&["cargoDeps"], "hash", "cargoDeps");
+    pub const GO: Self = Self::new(&[], "vendorHash", "goModules");
+    pub const NPM: Self = Self::new(&[], "npmDepsHash", "npmDeps");
+    pub const MAVEN: Self = Self::new(&[], "mvnHash", "fetchedMavenDeps");
+
+    /// Every hash the resolver knows, for packages whose kind doesn't say which to expect
+    pub const ALL: [Self; 6] = [Self::CARGO, Self::CARGO_SHA256, Self::CARGO_DEPS, Self::GO, Self::NPM, Self::MAVEN];
+
+    const fn new(path: &'static [&'static str], attr: &'static str, derivation: &'static str) -> Self {
+        Self { path, attr, derivation }
+    }
+
+    /// Whether the package declares this hash. `null` (e.g. `vendorHash = null;` for dependencies vendored in the
+    /// source) means there is nothing to fetch, so nothing to resolve.
+    pub fn declared(self, ast: &Ast) -> bool {
+        ast.get_value(self.path, self.attr).is_some_and(|value| value != Value::Expression("null".to_string()))
+    }
+
+    /// Recompute the hash: clear it, build the fixed-output derivation, and record the hash Nix reports it actually has
+    pub fn resolve(self, ast: &mut Ast, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
+        if let Some(pb) = pb {
+            pb.set_message(format!("{}: Building to get new {self}...", package.name()));
+        } else {
+            info!(package = %package.name, hash = %self, "Building to get new hash");
+        }
+
+        if let Some(old_hash) = ast.get_in(self.path, self.attr) {
+            ast.set_in(self.path, self.attr, &old_hash, "")?;
+        }
+
+        // Write out the current content so "nix build" can work with the latest changes
+        package.transaction.write_through(&package.path, ast.content())?;
+
+        let mut output = run(&mut nix_build(&[&format!(".#{}.{}", package.flake_attr(), self.derivation), "--no-link"]), Tool::Nix)?;
+
+        // Builders that don't expose the derivation under this name still reach it when building the whole package
+        if !output.status.success() && String::from_utf8_lossy(&output.stderr).contains("does not provide attribute") {
+            output = run(&mut nix_build(&[&format!(".#{}", package.flake_attr()), "--no-link"]), Tool::Nix)?;
+        }
+
+        if output.status.success() {
+            return Err(FodError::NoMismatch { hash: self.to_string() }.into());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        let Some(mismatch) = HashMismatch::parse(&stderr) else {
+            return Err(FodError::BuildFailed {
+                hash: self.to_string(),
+                excerpt: stderr
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .rev()
+                    .take(3)
+                    .collect_vec()
+                    .into_iter()
+                    .rev()
+                    .join("\n"),
+            }
+            .into());
+        };
+
+        ast.set_in(self.path, self.attr, "", &mismatch.got)?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for FixedOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.iter().chain([&self.attr]).join("."))
+    }
+}
+
+/// Why a fixed-output hash couldn't be resolved
+#[derive(Debug, Error)]
+pub enum FodError {
+    /// The build failed without reporting a hash mismatch, e.g. a fetch or evaluation error
+    #[error("Building to find {hash} failed without a hash mismatch:\n{excerpt}")]
+    BuildFailed { hash: String, excerpt: String },
+    /// The build succeeded even with the hash cleared, so Nix never reported the real one
+    #[error("Building with {hash} cleared did not report a hash mismatch")]
+    NoMismatch { hash: String },
+}
+
+impl From<&FodError> for UpdateError {
+    fn from(error: &FodError) -> Self {
+        Self::Build {
+            message: error.to_string(),
+            log: None,
+        }
+    }
+}
+
+/// Nix's "hash mismatch in fixed-output derivation" report
+#[derive(Debug, PartialEq, Eq)]
+pub struct HashMismatch {
+    pub specified: String,
+    pub got: String,
+}
+
+impl HashMismatch {
+    pub fn parse(stderr: &str) -> Option<Self> {
+        let field = |name: &str| stderr.lines().find_map(|line| Some(line.trim().strip_prefix(name)?.trim().to_string()));
+
+        Some(Self {
+            specified: field("specified:").unwrap_or_default(),
+            got: field("got:")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FixedOutput, HashMismatch};
+
+    #[test]
+    fn parses_hash_mismatch() {
+        let stderr = "error: hash mismatch in fixed-output derivation '/nix/store/abc-example-vendor.drv':
+         specified: sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=
+            got:    sha256-Zm9vYmFy
+error: 1 dependencies of derivation '/nix/store/def-example.drv' failed to build";
+
+        assert_eq!(
+            HashMismatch::parse(stderr),
+            Some(HashMismatch {
+                specified: "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string(),
+                got: "sha256-Zm9vYmFy".to_string(),
+            })
+        );
+        assert_eq!(HashMismatch::parse("error: builder failed"), None);
+    }
+
+    #[test]
+    fn displays_hash_location() {
+        assert_eq!(FixedOutput::CARGO_DEPS.to_string(), "cargoDeps.hash");
+        assert_eq!(FixedOutput::GO.to_string(), "vendorHash");
+    }
+}
@@ -0,0 +1,66 @@
+use rootcause::{Result, bail};
+
+use crate::tools::{Tool, command, run};
+
+/// A build input that doesn't resolve in the pinned nixpkgs
+#[derive(Debug)]
+pub struct InputIssue {
+    pub name: String,
+    pub problem: String,
+}
+
+/// The flake's pinned nixpkgs for the current system
+const PKGS: &str = "(builtins.getFlake (toString ./.)).inputs.nixpkgs.legacyPackages.${builtins.currentSystem}";
+
+pub fn nix_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace("${", "\\${"))
+}
+
+/// Check input names against the flake's pinned nixpkgs, returning those that are missing, removed, or renamed.
+///
+/// Existence is checked for all names in a single evaluation; only the failures are evaluated again individually
+/// to pick up nixpkgs' alias messages (e.g. "'foo' has been renamed to 'bar'").
+pub fn check_inputs(names: &[String]) -> Result<Vec<InputIssue>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let list = names.iter().map(|name| nix_string(name)).collect::<Vec<_>>().join(" ");
+
+    let expr = format!(
+        "let pkgs = {PKGS}; lib = pkgs.lib; \
+         resolves = name: let r = builtins.tryEval (lib.attrByPath (lib.splitString \".\" name) null pkgs); in r.success && r.value != null; \
+         in builtins.filter (name: !(resolves name)) [ {list} ]"
+    );
+
+    let output = run(command(Tool::Nix).args(["eval", "--impure", "--json", "--expr", &expr]), Tool::Nix)?;
+
+    if !output.status.success() {
+        bail!("Failed to evaluate nixpkgs: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let missing: Vec<String> = serde_json::from_slice(&output.stdout)?;
+
+    Ok(missing
+        .into_iter()
+        .map(|name| {
+            let problem = explain(&name);
+            InputIssue { name, problem }
+        })
+        .collect())
+}
+
+/// Evaluate a single input to surface nixpkgs' own removal/rename message
+fn explain(name: &str) -> String {
+    let expr = format!("({PKGS}).{name}.name");
+
+    let Ok(output) = run(command(Tool::Nix).args(["eval", "--impure", "--raw", "--expr", &expr]), Tool::Nix) else {
+        return "not found in nixpkgs".to_string();
+    };
+
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(str::trim)
+        .find(|line| line.contains("renamed") || line.contains("removed") || line.contains("deprecated"))
+        .map_or_else(|| "not found in nixpkgs".to_string(), |line| line.trim_start_matches("error:").trim().to_string())
+}
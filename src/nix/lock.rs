@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+use itertools::Itertools;
+use rootcause::{Result, bail};
+use serde::Deserialize;
+
+use crate::package::Package;
+use crate::tools::{Tool, command, run};
+use crate::transaction::Transaction;
+use crate::updater::short_hash;
+
+/// The parts of `flake.lock` that say what each input is locked to
+#[derive(Debug, Deserialize)]
+struct FlakeLock {
+    nodes: BTreeMap<String, Node>,
+    root: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Node {
+    /// Input names mapped to their node, or to a `follows` path
+    inputs: BTreeMap<String, serde_json::Value>,
+    locked: Option<Locked>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Locked {
+    rev: Option<String>,
+    nar_hash: Option<String>,
+}
+
+impl FlakeLock {
+    /// What each of the flake's own inputs is locked to: its revision, or the NAR hash of inputs without one
+    fn root_inputs(&self) -> BTreeMap<String, String> {
+        let Some(root) = self.nodes.get(&self.root) else {
+            return BTreeMap::new();
+        };
+
+        root.inputs
+            .iter()
+            .filter_map(|(name, node)| {
+                let locked = self.nodes.get(node.as_str()?)?.locked.as_ref()?;
+
+                Some((name.clone(), locked.rev.clone().or_else(|| locked.nar_hash.clone())?))
+            })
+            .collect()
+    }
+}
+
+/// An input `nix flake update` moved
+#[derive(Debug)]
+pub struct InputChange {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for InputChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} → {}", self.name, short_hash(&self.old), short_hash(&self.new))
+    }
+}
+
+/// Refreshes the flake's inputs for `--update-inputs`, each at most once per run. Calls are serialised, since every
+/// `nix flake update` rewrites the whole lock file.
+pub struct FlakeInputs {
+    path: PathBuf,
+    updated: Mutex<BTreeSet<String>>,
+}
+
+impl FlakeInputs {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, updated: Mutex::default() }
+    }
+
+    fn locked(&self) -> Result<BTreeMap<String, String>> {
+        let lock: FlakeLock = serde_json::from_str(&fs::read_to_string(&self.path)?)?;
+
+        Ok(lock.root_inputs())
+    }
+
+    /// The inputs `package` relates to: those its `inputs` setting names, and those its file refers to as `inputs.<name>`
+    pub fn related(&self, package: &Package) -> Result<Vec<String>> {
+        let ast = package.ast();
+        let configured = package.settings.inputs.clone().unwrap_or_default();
+
+        Ok(self
+            .locked()?
+            .into_keys()
+            .filter(|name| configured.contains(name) || refers_to(ast.content(), name))
+            .collect())
+    }
+
+    /// Refresh the `inputs` not refreshed yet this run, returning those that moved
+    pub fn update(&self, inputs: &[String]) -> Result<Vec<InputChange>> {
+        self.refresh(inputs, None)
+    }
+
+    /// Refresh the inputs `package` relates to as part of its update: the new `flake.lock` is written through its
+    /// transaction, so it lands or is rolled back with the package's other edits
+    pub fn update_related(&self, package: &mut Package) -> Result<Vec<InputChange>> {
+        let related = self.related(package)?;
+
+        self.refresh(&related, Some(&mut package.transaction))
+    }
+
+    fn refresh(&self, inputs: &[String], transaction: Option<&mut Transaction>) -> Result<Vec<InputChange>> {
+        let mut updated = self.updated.lock().unwrap_or_else(PoisonError::into_inner);
+        let pending = inputs.iter().filter(|input| !updated.contains(*input)).unique().collect_vec();
+
+        if pending.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let original = fs::read_to_string(&self.path)?;
+        let before = self.locked()?;
+        let output = run(command(Tool::Nix).args(["flake", "update"]).args(&pending), Tool::Nix)?;
+
+        if !output.status.success() {
+            bail!("nix flake update failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        updated.extend(pending.iter().map(|input| (*input).clone()));
+
+        let after = self.locked()?;
+
+        // `nix flake update` writes the lock itself; put the original back for the transaction to remember
+        if let Some(transaction) = transaction {
+            let refreshed = fs::read_to_string(&self.path)?;
+
+            fs::write(&self.path, &original)?;
+            transaction.write_through(&self.path, &refreshed)?;
+        }
+
+        Ok(pending
+            .into_iter()
+            .filter_map(|name| {
+                let (old, new) = (before.get(name)?, after.get(name)?);
+
+                (old != new).then(|| InputChange {
+                    name: name.clone(),
+                    old: old.clone(),
+                    new: new.clone(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Whether Nix code refers to the flake input `name` as `inputs.<name>`
+fn refers_to(content: &str, name: &str) -> bool {
+    let reference = format!("inputs.{name}");
+
+    content.match_indices(&reference).any(|(start, _)| {
+        let before = content[..start].chars().next_back();
+        let after = content[start + reference.len()..].chars().next();
+        let identifier = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\'');
+
+        !before.is_some_and(identifier) && !after.is_some_and(identifier)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlakeLock, refers_to};
+
+    #[test]
+    fn reads_root_input_revisions() {
+        let lock: FlakeLock = serde_json::from_str(
+            r#"{
+  "nodes": {
+    "crane": { "locked": { "owner": "ipetkov", "repo": "crane", "rev": "abc123", "type": "github" } },
+    "flake-utils": { "inputs": { "systems": "systems" }, "locked": { "narHash": "sha256-xyz", "type": "tarball" } },
+    "nixpkgs": { "locked": { "rev": "def456", "type": "github" } },
+    "root": { "inputs": { "crane": "crane", "flake-utils": "flake-utils", "nixpkgs": "nixpkgs", "pinned": ["crane", "nixpkgs"] } },
+    "systems": { "locked": { "rev": "0000", "type": "github" } }
+  },
+  "root": "root",
+  "version": 7
+}"#,
+        )
+        .unwrap();
+
+        let inputs = lock.root_inputs();
+
+        assert_eq!(inputs.get("crane").map(String::as_str), Some("abc123"));
+        assert_eq!(inputs.get("flake-utils").map(String::as_str), Some("sha256-xyz"));
+        assert_eq!(inputs.get("nixpkgs").map(String::as_str), Some("def456"));
+        assert!(!inputs.contains_key("systems") && !inputs.contains_key("pinned"));
+    }
+
+    #[test]
+    fn finds_input_references() {
+        assert!(refers_to("{ craneLib = inputs.crane.mkLib pkgs; }", "crane"));
+        assert!(refers_to("src = inputs.crane;", "crane"));
+        assert!(!refers_to("{ craneLib = inputs.crane-lib.mkLib pkgs; }", "crane"));
+        assert!(!refers_to("{ x = inputs.cranelift; }", "crane"));
+        assert!(refers_to("{ x = self.inputs.crane.lib; }", "crane"));
+        assert!(!refers_to("{ x = myinputs.crane; }", "crane"));
+    }
+}
@@ -1,2 +1,11 @@
 pub mod ast;
+pub mod binary_cache;
 pub mod builder;
+pub mod closure;
+pub mod dependents;
+pub mod flake;
+pub mod fod;
+pub mod inputs;
+pub mod lock;
+pub mod sri;
+pub mod verify;
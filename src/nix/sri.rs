@@ -0,0 +1,191 @@
+use rnix::{SyntaxKind, SyntaxNode};
+use rootcause::Result;
+
+/// A legacy hash attribute that couldn't be rewritten
+#[derive(Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub line: usize,
+    pub message: String,
+}
+
+/// A file's content with its `sha256 = "..."` attributes rewritten to `hash = "sha256-..."`
+#[derive(Debug, Default)]
+pub struct Migration {
+    pub content: String,
+    pub converted: usize,
+    pub problems: Vec<Problem>,
+}
+
+/// Rewrite every `sha256 = "<base16/base32>"` attribute in `content` to SRI `hash = "sha256-..."`, with `convert` turning
+/// a hash into its SRI form (see `Nix::convert_hash`).
+///
+/// `md5` hashes can't be converted, nixpkgs no longer accepts them, and are reported instead. So are hashes given as
+/// expressions, those next to an existing `hash`, and files naming `sha256` elsewhere (`inherit (p) sha256;`,
+/// `p.sha256`), where renaming the attribute would break the reference; such files are left unchanged.
+pub fn migrate_hashes(content: &str, convert: impl Fn(&str) -> Result<String>) -> Migration {
+    let root = rnix::Root::parse(content).syntax();
+    let line = |node: &SyntaxNode| content[..usize::from(node.text_range().start())].matches('\n').count() + 1;
+
+    let mut migration = Migration {
+        content: content.to_string(),
+        ..Migration::default()
+    };
+
+    if let Some(reference) = root.descendants().find(names_sha256) {
+        migration.problems.push(Problem {
+            line: line(&reference),
+            message: format!("`{}` refers to sha256 by name; migrate this file by hand", reference.text()),
+        });
+
+        return migration;
+    }
+
+    let mut edits = Vec::new();
+
+    for attribute in root.descendants().filter(|node| node.kind() == SyntaxKind::NODE_ATTRPATH_VALUE) {
+        let (Some(key), Some(value)) = (attribute.first_child(), attribute.last_child()) else {
+            continue;
+        };
+
+        let key_name = key.text().to_string();
+
+        if key_name == "md5" {
+            migration.problems.push(Problem {
+                line: line(&attribute),
+                message: "md5 hashes can't be converted to SRI; replace it with a `hash`".to_string(),
+            });
+            continue;
+        }
+
+        if key_name != "sha256" {
+            continue;
+        }
+
+        if has_sibling(&attribute, "hash") {
+            migration.problems.push(Problem {
+                line: line(&attribute),
+                message: "sha256 next to an existing hash; remove one of them".to_string(),
+            });
+            continue;
+        }
+
+        if value.kind() != SyntaxKind::NODE_STRING || value.children().any(|child| child.kind() == SyntaxKind::NODE_INTERPOL) {
+            migration.problems.push(Problem {
+                line: line(&attribute),
+                message: format!("sha256 = {} isn't a literal hash", value.text()),
+            });
+            continue;
+        }
+
+        let hash = value.text().to_string().trim_matches('"').to_string();
+
+        let sri = if hash.is_empty() || hash.starts_with("sha256-") {
+            hash
+        } else {
+            match convert(&hash) {
+                Ok(sri) if sri.starts_with("sha256-") => sri,
+                Ok(_) | Err(_) => {
+                    migration.problems.push(Problem {
+                        line: line(&attribute),
+                        message: format!("\"{hash}\" isn't a valid SHA-256 hash"),
+                    });
+                    continue;
+                }
+            }
+        };
+
+        edits.push((key.text_range(), "hash".to_string()));
+        edits.push((value.text_range(), format!("\"{sri}\"")));
+        migration.converted += 1;
+    }
+
+    // From the end of the file, so earlier ranges stay put
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start()));
+
+    for (range, text) in edits {
+        migration.content.replace_range(usize::from(range.start())..usize::from(range.end()), &text);
+    }
+
+    migration
+}
+
+/// `inherit (p) sha256;` or `p.sha256`, which would no longer resolve once `p`'s `sha256` is renamed
+fn names_sha256(node: &SyntaxNode) -> bool {
+    match node.kind() {
+        SyntaxKind::NODE_INHERIT => node.children().any(|child| child.kind() == SyntaxKind::NODE_IDENT && child.text() == "sha256"),
+        SyntaxKind::NODE_SELECT => node.last_child().and_then(|path| path.last_child()).is_some_and(|name| name.text() == "sha256"),
+        _ => false,
+    }
+}
+
+/// Whether the attrset holding `attribute` also sets `name`
+fn has_sibling(attribute: &SyntaxNode, name: &str) -> bool {
+    attribute.parent().is_some_and(|set| {
+        set.children()
+            .filter(|child| child.kind() == SyntaxKind::NODE_ATTRPATH_VALUE)
+            .any(|child| child.first_child().is_some_and(|key| key.text() == name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rootcause::{Result, bail};
+
+    use super::{Problem, migrate_hashes};
+
+    fn convert(hash: &str) -> Result<String> {
+        match hash {
+            "0v2l6asdqmkfbj2kf0ap0y8hjabqwy4mpxb2vx1j4lfw9lgj2bxx" => Ok("sha256-Zm9vYmFyZm9vYmFyZm9vYmFyZm9vYmFyZm9vYmFyZm8=".to_string()),
+            _ => bail!("invalid hash"),
+        }
+    }
+
+    #[test]
+    fn converts_sha256_to_sri_hash() {
+        let migration = migrate_hashes(
+            r#"{
+  src = fetchurl {
+    url = "https://example.com/foo.tar.gz";
+    sha256 = "0v2l6asdqmkfbj2kf0ap0y8hjabqwy4mpxb2vx1j4lfw9lgj2bxx";
+  };
+  cargoSha256 = "0v2l6asdqmkfbj2kf0ap0y8hjabqwy4mpxb2vx1j4lfw9lgj2bxx";
+  patch = fetchpatch { url = "https://example.com/fix.patch"; sha256 = ""; };
+}"#,
+            convert,
+        );
+
+        assert_eq!(migration.converted, 2);
+        assert_eq!(migration.problems, []);
+        assert_eq!(
+            migration.content,
+            r#"{
+  src = fetchurl {
+    url = "https://example.com/foo.tar.gz";
+    hash = "sha256-Zm9vYmFyZm9vYmFyZm9vYmFyZm9vYmFyZm9vYmFyZm8=";
+  };
+  cargoSha256 = "0v2l6asdqmkfbj2kf0ap0y8hjabqwy4mpxb2vx1j4lfw9lgj2bxx";
+  patch = fetchpatch { url = "https://example.com/fix.patch"; hash = ""; };
+}"#
+        );
+    }
+
+    #[test]
+    fn reports_what_it_cannot_convert() {
+        let migration = migrate_hashes("{\n  md5 = \"d41d8cd98f00b204e9800998ecf8427e\";\n  sha256 = lib.fakeSha256;\n}", convert);
+
+        assert_eq!(migration.converted, 0);
+        assert_eq!(migration.problems.iter().map(|problem| problem.line).collect::<Vec<_>>(), [2, 3]);
+
+        let inherited = "{ src = fetchurl { inherit (dist) url sha256; }; dist.sha256 = \"abc\"; }";
+        let migration = migrate_hashes(inherited, convert);
+
+        assert_eq!(migration.content, inherited);
+        assert_eq!(
+            migration.problems,
+            [Problem {
+                line: 1,
+                message: "`inherit (dist) url sha256;` refers to sha256 by name; migrate this file by hand".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use itertools::Itertools;
+use rootcause::{Result, bail};
+use serde::Deserialize;
+
+use crate::clients::nix::Nix;
+use crate::nix::builder::current_system;
+use crate::nix::fod::HashMismatch;
+use crate::nix::inputs::nix_string;
+use crate::package::Package;
+use crate::tools::{Tool, command, nix_build, run};
+
+/// A source whose upstream no longer matches the hash recorded for it
+#[derive(Debug, PartialEq, Eq)]
+pub struct Drift {
+    pub system: String,
+    pub url: String,
+    pub recorded: String,
+    pub actual: String,
+}
+
+/// A package's `src` as evaluated for one system
+#[derive(Debug, Deserialize)]
+struct Source {
+    system: String,
+    urls: Vec<String>,
+    hash: String,
+    algo: String,
+    mode: String,
+}
+
+impl Source {
+    /// The recorded hash in SRI form, `None` when it isn't a SHA-256 and can't be compared to a prefetch
+    fn sri(&self) -> Result<Option<String>> {
+        if self.hash.starts_with("sha256-") {
+            return Ok(Some(self.hash.clone()));
+        }
+
+        if self.algo != "sha256" {
+            return Ok(None);
+        }
+
+        Ok(Some(Nix::convert_hash(&self.hash, "sri")?))
+    }
+}
+
+/// Fetch each of the package's sources again, without changing its version, and report those whose recorded hash no
+/// longer matches what upstream serves: retagged releases and replaced assets.
+///
+/// Flat sources (`fetchurl`) are checked for every system a platform block names, `mirror://` URLs resolved through the
+/// flake's nixpkgs; unpacked ones (`fetchzip`, git), and flat ones whose mirror can't be resolved, are rebuilt for the
+/// current system only.
+pub fn verify_hashes(package: &Package) -> Result<Vec<Drift>> {
+    let current = current_system()?;
+    let mut drifts = Vec::new();
+
+    for source in sources(package)? {
+        let url = source.urls.first().cloned().unwrap_or_default();
+
+        if source.mode == "flat" && !url.is_empty() && !url.starts_with("mirror://") {
+            let Some(recorded) = source.sri()? else {
+                continue;
+            };
+
+            // A resolved mirror lists every mirror's URL, any of which may be down
+            let Some(actual) = source.urls.iter().find_map(|url| Nix::refetch_hash(url).ok().flatten()) else {
+                bail!("Failed to fetch {url}");
+            };
+
+            if actual != recorded {
+                drifts.push(Drift {
+                    system: source.system,
+                    url,
+                    recorded,
+                    actual,
+                });
+            }
+        } else if source.system == current
+            && let Some(mismatch) = refetch_unpacked(package.flake_attr())?
+        {
+            drifts.push(Drift {
+                system: source.system,
+                url,
+                recorded: mismatch.specified,
+                actual: mismatch.got,
+            });
+        } else if source.system != current && url.starts_with("mirror://") {
+            bail!("Can't check {url} for {}: its mirror doesn't resolve", source.system);
+        }
+    }
+
+    Ok(drifts)
+}
+
+/// Evaluate the package's `src` for the current system and each system its platform blocks name, dropping sources shared
+/// between systems
+fn sources(package: &Package) -> Result<Vec<Source>> {
+    let systems = package
+        .ast()
+        .platforms()
+        .into_iter()
+        .map(|block| block.platform_name)
+        .filter(|name| name.ends_with("-linux") || name.ends_with("-darwin"))
+        .map(|name| nix_string(&name))
+        .join(" ");
+
+    let expr = format!(
+        "let flake = builtins.getFlake (toString ./.); \
+         mirrors = let nixpkgs = flake.inputs.nixpkgs or null; in \
+           if nixpkgs == null then {{ }} else import (nixpkgs + \"/pkgs/build-support/fetchurl/mirrors.nix\"); \
+         resolve = url: let m = builtins.match \"mirror://([^/]+)/(.*)\" url; in \
+           if m == null then [ url ] else map (base: base + builtins.elemAt m 1) (mirrors.${{builtins.elemAt m 0}} or [ url ]); \
+         describe = system: let src = flake.packages.${{system}}.${{{name}}}.src or null; in \
+           if src == null || !(src ? outputHash) then [ ] else [ {{ \
+             inherit system; \
+             urls = builtins.concatMap resolve (src.urls or (if src ? url then [ src.url ] else [ ])); \
+             hash = src.outputHash; \
+             algo = if src.outputHashAlgo or null == null then \"\" else src.outputHashAlgo; \
+             mode = src.outputHashMode or \"flat\"; \
+           }} ]; \
+         in builtins.concatMap describe [ builtins.currentSystem {systems} ]",
+        name = nix_string(package.flake_attr()),
+    );
+
+    let output = run(command(Tool::Nix).args(["eval", "--impure", "--json", "--expr", &expr]), Tool::Nix)?;
+
+    if !output.status.success() {
+        bail!("Failed to evaluate {}.src: {}", package.name, String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(unique(serde_json::from_slice(&output.stdout)?))
+}
+
+/// The sources in order, without those whose URLs and hash an earlier system already has
+fn unique(sources: Vec<Source>) -> Vec<Source> {
+    sources.into_iter().unique_by(|source| (source.urls.clone(), source.hash.clone())).collect()
+}
+
+/// Fetch an unpacked source again for the current system. `--rebuild` checks a source that's already in the store; one
+/// that isn't is fetched without substituters, which would otherwise hand back a cached copy by its hash.
+fn refetch_unpacked(name: &str) -> Result<Option<HashMismatch>> {
+    let target = format!(".#{name}.src");
+
+    let mut output = run(&mut nix_build(&[&target, "--rebuild", "--no-link"]), Tool::Nix)?;
+    let mut stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+    if !output.status.success() && stderr.contains("so checking is not possible") {
+        output = run(&mut nix_build(&[&target, "--no-link", "--option", "substitute", "false"]), Tool::Nix)?;
+        stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    }
+
+    if output.status.success() {
+        return Ok(None);
+    }
+
+    match HashMismatch::parse(&stderr) {
+        Some(mismatch) => Ok(Some(mismatch)),
+        None => bail!(
+            "Failed to fetch {name}.src: {}",
+            stderr.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or_default()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Source, unique};
+
+    fn source(system: &str, url: &str, hash: &str) -> Source {
+        Source {
+            system: system.to_string(),
+            urls: vec![url.to_string()],
+            hash: hash.to_string(),
+            algo: String::new(),
+            mode: "flat".to_string(),
+        }
+    }
+
+    #[test]
+    fn keeps_one_source_per_download() {
+        let sources = unique(vec![
+            source("x86_64-linux", "https://example.com/foo-linux.tar.gz", "sha256-AAAA"),
+            source("x86_64-linux", "https://example.com/foo-linux.tar.gz", "sha256-AAAA"),
+            source("aarch64-darwin", "https://example.com/foo-darwin.tar.gz", "sha256-BBBB"),
+        ]);
+
+        assert_eq!(sources.iter().map(|source| source.system.as_str()).collect::<Vec<_>>(), ["x86_64-linux", "aarch64-darwin"]);
+    }
+}
@@ -0,0 +1,224 @@
+use std::env;
+
+use chrono::Utc;
+use reqwest::{Method, Url};
+use rootcause::{Result, report};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+
+use crate::clients::http::Http;
+use crate::error::UpdateError;
+use crate::package::{Package, UpdateStatus};
+
+/// Where a run's summary is sent, `[[notifications]]` in config.toml
+///
+/// ```toml
+/// [[notifications]]
+/// kind = "webhook"
+/// url = "https://example.com/hooks/nix-updater"
+///
+/// [[notifications]]
+/// kind = "slack"
+/// url = "https://hooks.slack.com/services/T000/B000/XXXX"
+///
+/// [[notifications]]
+/// kind = "matrix"
+/// homeserver = "https://matrix.example.org"
+/// room = "!abcdef:example.org"
+/// # or MATRIX_ACCESS_TOKEN in the environment
+/// access_token = "syt_..."
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Notifier {
+    /// POSTs the [`Summary`] as JSON
+    Webhook { url: String },
+    /// POSTs the summary text to a Slack-compatible incoming webhook
+    Slack { url: String },
+    /// Sends the summary text to a Matrix room as a notice
+    Matrix { homeserver: String, room: String, access_token: Option<String> },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Updated {
+    pub package: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failed {
+    pub package: String,
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<UpdateError>,
+}
+
+/// What a run updated, built and failed
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub updated: Vec<Updated>,
+    pub built: Vec<String>,
+    pub failed: Vec<Failed>,
+}
+
+impl Summary {
+    pub fn new(packages: &[Package]) -> Self {
+        let mut summary = Self::default();
+
+        for package in packages {
+            let result = &package.result;
+
+            if result.status.contains(&UpdateStatus::Failed) {
+                summary.failed.push(Failed {
+                    package: package.name.clone(),
+                    message: result.message.clone(),
+                    error: result.error.clone(),
+                });
+                continue;
+            }
+
+            if result.status.contains(&UpdateStatus::Updated) {
+                summary.updated.push(Updated {
+                    package: package.name.clone(),
+                    old: result.old_version.clone().or_else(|| result.old_git_commit.clone()),
+                    new: result.new_version.clone().or_else(|| result.new_git_commit.clone()),
+                });
+            }
+
+            if result.status.contains(&UpdateStatus::Built) {
+                summary.built.push(package.name.clone());
+            }
+        }
+
+        summary
+    }
+
+    /// Whether there's anything worth telling anyone: builds alone (e.g. `build` runs) aren't
+    pub fn is_empty(&self) -> bool {
+        self.updated.is_empty() && self.failed.is_empty()
+    }
+
+    /// A line per updated or failed package
+    pub fn lines(&self) -> Vec<String> {
+        let updated = self
+            .updated
+            .iter()
+            .map(|updated| format!("{} {} → {}", updated.package, updated.old.as_deref().unwrap_or("?"), updated.new.as_deref().unwrap_or("?")));
+
+        let failed = self
+            .failed
+            .iter()
+            .map(|failed| format!("{} failed: {}", failed.package, failed.message.as_deref().unwrap_or("unknown error")));
+
+        updated.chain(failed).collect()
+    }
+
+    /// A heading with the counts, then a line per package
+    pub fn text(&self) -> String {
+        let heading = format!(
+            "nix-package-updater: {} updated, {} built, {} failed",
+            self.updated.len(),
+            self.built.len(),
+            self.failed.len()
+        );
+
+        std::iter::once(heading)
+            .chain(self.lines().into_iter().map(|line| format!("• {line}")))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Notifier {
+    fn send(&self, http: &Http, summary: &Summary) -> Result<()> {
+        match self {
+            Self::Webhook { url } => http.send_json(&Method::POST, url, Some(&serde_json::to_value(summary)?), None)?,
+            Self::Slack { url } => http.send_json(&Method::POST, url, Some(&json!({ "text": summary.text() })), None)?,
+            Self::Matrix { homeserver, room, access_token } => {
+                let token = access_token
+                    .clone()
+                    .or_else(|| env::var("MATRIX_ACCESS_TOKEN").ok())
+                    .ok_or_else(|| report!("No Matrix access token: set access_token or MATRIX_ACCESS_TOKEN"))?;
+
+                let url = matrix_send_url(homeserver, room)?;
+
+                http.send_json(&Method::PUT, url.as_str(), Some(&json!({ "msgtype": "m.notice", "body": summary.text() })), Some(&token))?
+            }
+        };
+
+        Ok(())
+    }
+}
+
+/// `PUT /_matrix/client/v3/rooms/{room}/send/m.room.message/{txnId}`, with a transaction id unique to this run
+fn matrix_send_url(homeserver: &str, room: &str) -> Result<Url> {
+    let mut url = Url::parse(homeserver)?;
+    let transaction = format!("nix-updater-{}-{}", Utc::now().timestamp_millis(), fastrand::u32(..));
+
+    url.path_segments_mut()
+        .map_err(|()| report!("Invalid Matrix homeserver URL: {homeserver}"))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3", "rooms", room, "send", "m.room.message", &transaction]);
+
+    Ok(url)
+}
+
+/// Send the run's summary to every configured notifier, if it updated or failed anything. A notifier failing is
+/// only warned about.
+pub fn send_notifications(notifiers: &[Notifier], packages: &[Package]) {
+    let summary = Summary::new(packages);
+
+    if notifiers.is_empty() || summary.is_empty() {
+        return;
+    }
+
+    let Ok(http) = Http::shared().inspect_err(|e| warn!("Notifications not sent: {e}")) else {
+        return;
+    };
+
+    for notifier in notifiers {
+        if let Err(e) = notifier.send(&http, &summary) {
+            warn!("Notification not sent: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_summaries() {
+        let summary = Summary {
+            updated: vec![Updated {
+                package: "ripgrep".to_string(),
+                old: Some("14.1.0".to_string()),
+                new: Some("14.1.1".to_string()),
+            }],
+            built: vec!["ripgrep".to_string()],
+            failed: vec![Failed {
+                package: "fd".to_string(),
+                message: Some("Build error: boom".to_string()),
+                error: None,
+            }],
+        };
+
+        assert_eq!(
+            summary.text(),
+            "nix-package-updater: 1 updated, 1 built, 1 failed\n• ripgrep 14.1.0 → 14.1.1\n• fd failed: Build error: boom"
+        );
+        assert!(Summary::default().is_empty());
+    }
+
+    #[test]
+    fn builds_matrix_urls() {
+        let url = matrix_send_url("https://matrix.example.org/", "!abc:example.org").unwrap();
+
+        assert!(
+            url.as_str()
+                .starts_with("https://matrix.example.org/_matrix/client/v3/rooms/!abc:example.org/send/m.room.message/nix-updater-")
+        );
+    }
+}
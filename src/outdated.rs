@@ -0,0 +1,94 @@
+use colored::Colorize;
+use itertools::Itertools;
+use rayon::prelude::*;
+use rootcause::Result;
+use serde::Serialize;
+
+use nix_package_updater::UpdateError;
+use nix_package_updater::package::Package;
+use nix_package_updater::updater::Updaters;
+
+use crate::table::{Cell, Table};
+use crate::terminal_width;
+
+/// One package's line in `outdated`: what it's at and what its upstream has. `latest` is `None` when its updater can't
+/// tell from metadata (custom commands, nix-update) or the lookup failed.
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub package: String,
+    pub kind: String,
+    pub current: String,
+    pub latest: Option<String>,
+    pub outdated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<UpdateError>,
+}
+
+/// Look up every package's newest upstream version in parallel, sorted by package name
+pub fn check_outdated(packages: &[Package], updaters: &Updaters) -> Vec<Comparison> {
+    packages
+        .par_iter()
+        .map(|package| {
+            let (current, latest, error) = match updaters.latest(package) {
+                Ok(Some(latest)) => (latest.current.clone(), Some(latest), None),
+                Ok(None) => (package.version.clone(), None, None),
+                Err(e) => (package.version.clone(), None, Some(UpdateError::from_report(&e))),
+            };
+
+            Comparison {
+                package: package.name.clone(),
+                kind: package.kind.to_string(),
+                current,
+                outdated: latest.as_ref().is_some_and(|latest| latest.outdated),
+                latest: latest.map(|latest| latest.latest),
+                error,
+            }
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .sorted_by(|a, b| a.package.cmp(&b.package))
+        .collect()
+}
+
+/// Print the packages' current and latest versions as a table, or as a JSON array for scripts
+pub fn print_outdated(rows: &[Comparison], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(rows)?);
+        return Ok(());
+    }
+
+    let mut table = Table::new(vec![
+        ("Package".to_string(), Some(30)),
+        ("Source".to_string(), None),
+        ("Current".to_string(), None),
+        ("Latest".to_string(), None),
+    ]);
+
+    for row in rows {
+        let latest = match (&row.latest, &row.error) {
+            (_, Some(error)) => Cell::from(error.to_string().red()),
+            (Some(latest), None) if row.outdated => Cell::from(latest.as_str().yellow()),
+            (Some(latest), None) => Cell::from(latest.as_str().dimmed()),
+            (None, None) => Cell::from("unknown".dimmed()),
+        };
+
+        table.row(vec![
+            Cell::from(row.package.as_str().cyan()),
+            Cell::from(row.kind.as_str().magenta()),
+            Cell::new(&row.current),
+            latest,
+        ]);
+    }
+
+    print!("{}", table.render(terminal_width()));
+
+    let outdated = rows.iter().filter(|row| row.outdated).count();
+
+    if outdated == 0 {
+        println!("{}", "All packages are up to date.".green());
+    } else {
+        println!("{}", format!("{outdated} of {} packages have updates available.", rows.len()).yellow());
+    }
+
+    Ok(())
+}
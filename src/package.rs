@@ -1,19 +1,33 @@
-use std::collections::HashSet;
-use std::fs;
+use std::collections::{BTreeMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fmt, fs};
 
+use chrono::{DateTime, Utc};
 use colored::{ColoredString, Colorize};
 use git_url_parse::GitUrl;
 use rnix::{Parse, Root};
 use rootcause::Result;
-use strum::Display;
-use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+use tracing::{debug, info, warn};
 use walkdir::WalkDir;
 
+use crate::error::UpdateError;
+use crate::exclude::Exclusions;
 use crate::nix::ast::Ast;
+use crate::nix::closure::Closure;
+use crate::nix::flake::evaluate;
+use crate::provenance::{Artifact, Provenance, Source};
+use crate::settings::PackageSettings;
+use crate::transaction::Transaction;
 use crate::updater::short_hash;
 
-#[derive(Clone, Copy, Display, PartialEq, Eq)]
+/// Which updater handles a package. Detected from the fetchers and builders a file calls, unless forced with `kind`
+/// in its `[packages.<name>]` settings or a `# nix-updater: kind=<kind>` comment.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq, Eq, Serialize, Deserialize)]
+#[strum(ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
 pub enum PackageKind {
     PyPi,
     GitHub,
@@ -21,107 +35,204 @@ pub enum PackageKind {
     Npm,
     Go,
     Git,
+    Mercurial,
 }
 
 pub struct Package {
     pub name: String,
+    /// The `packages.<system>` attribute the flake exports it under, when discovered through the flake
+    pub attr: Option<String>,
     pub path: PathBuf,
     pub kind: PackageKind,
     pub homepage: GitUrl,
     pub ast: Parse<Root>,
+    /// Which package in a file defining several, see [`Ast::scoped`]
+    pub scope: Option<usize>,
 
     pub version: String,
     pub nix_hash: String,
 
     pub result: UpdateResult,
+
+    pub transaction: Transaction,
+
+    pub settings: PackageSettings,
 }
 
-impl Package {
-    pub fn discover(root: &Path, include: &[String], exclude: &[String]) -> Vec<Package> {
-        let mut packages = Vec::new();
+/// A `.nix` file, or one package in it, that looked like a package but couldn't be read as one
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Skipped {
+    pub path: PathBuf,
+    /// The package's `pname`, when it got that far
+    pub name: Option<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} ({name}): {}", self.path.display(), self.reason),
+            None => write!(f, "{}: {}", self.path.display(), self.reason),
+        }
+    }
+}
 
-        for entry in WalkDir::new(root)
+/// What discovery found: the packages, and what it had to skip
+#[derive(Default)]
+pub struct Discovered {
+    pub packages: Vec<Package>,
+    pub skipped: Vec<Skipped>,
+}
+
+impl FromIterator<Discovered> for Discovered {
+    fn from_iter<I: IntoIterator<Item = Discovered>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::default(), |mut all, found| {
+            all.packages.extend(found.packages);
+            all.skipped.extend(found.skipped);
+            all
+        })
+    }
+}
+
+impl Package {
+    pub fn discover(root: &Path, include: &[String], exclude: &Exclusions) -> Discovered {
+        WalkDir::new(root)
             .into_iter()
             .filter_map(std::result::Result::ok)
             .filter(|e| e.path().extension().is_some_and(|ext| ext == "nix") && e.file_type().is_file())
-        {
-            let path = entry.path();
+            .map(|entry| Self::from_file(entry.path(), include, exclude))
+            .collect()
+    }
 
-            let Ok(content) = fs::read_to_string(path) else {
-                warn!(path = %path.display(), "Could not read file");
-                continue;
-            };
+    /// Parse a .nix file into the packages it defines, leaving out those the updater can't handle or that are filtered out.
+    /// Packages missing what an update needs are skipped with a warning rather than failing discovery, and returned
+    /// as [`Skipped`] for the run's summary.
+    ///
+    /// A file with several packages (e.g. an attrset of derivations) yields one `Package` per package, each scoped to its own attrset.
+    pub fn from_file(path: &Path, include: &[String], exclude: &Exclusions) -> Discovered {
+        if exclude.excludes_file(path) {
+            debug!(path = %path.display(), "Skipping: excluded");
+            return Discovered::default();
+        }
 
-            let ast = rnix::Root::parse(&content);
-            let root_syntax = ast.syntax();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!(path = %path.display(), "Could not read file: {e}");
+
+                return Discovered {
+                    packages: Vec::new(),
+                    skipped: vec![Skipped {
+                        path: path.to_path_buf(),
+                        name: None,
+                        reason: format!("could not read file: {e}"),
+                    }],
+                };
+            }
+        };
 
-            let updater = Ast::from_ast(ast.clone());
+        let ast = rnix::Root::parse(&content);
 
-            let Some(pname) = updater.get("pname") else {
-                continue;
-            };
+        let scopes = match Ast::from_ast(ast.clone()).package_count() {
+            0 | 1 => vec![None],
+            count => (0..count).map(Some).collect(),
+        };
 
-            // Apply package filter if specified
-            if !include.is_empty() && !include.iter().any(|pkg| pname.contains(pkg)) {
-                continue;
-            }
+        let mut discovered = Discovered::default();
 
-            // Skip excluded packages
-            if exclude.iter().any(|e| e == &pname) {
-                continue;
+        for result in scopes.into_iter().filter_map(|scope| Self::parse(path, &ast, scope, include, exclude)) {
+            match result {
+                Ok(package) => discovered.packages.push(package),
+                Err(skipped) => {
+                    warn!(path = %skipped.path.display(), package = skipped.name.as_deref().unwrap_or_default(), "Skipping: {}", skipped.reason);
+                    discovered.skipped.push(skipped);
+                }
             }
+        }
 
-            // Skip packages not supported on the current platform
-            if !Self::supported_on_current_platform(&updater) {
-                info!(package = %pname, "Skipping: not supported on current platform");
-                continue;
-            }
+        discovered
+    }
 
-            // Skip purely local packages (src = ./.) — there is no upstream to track
-            if updater.has_local_src() {
-                info!(package = %pname, "Skipping: local source");
-                continue;
-            }
+    /// The package at `scope`, `None` when there's none or it's filtered out, or why it can't be updated
+    fn parse(path: &Path, ast: &Parse<Root>, scope: Option<usize>, include: &[String], exclude: &Exclusions) -> Option<std::result::Result<Package, Skipped>> {
+        let updater = Ast::from_ast(ast.clone()).scoped(scope);
+        let root_syntax = updater.node();
+        let content = root_syntax.text().to_string();
 
-            // Determine package type by checking content
-            let package_type = Self::detect_package_kind(&root_syntax, &content);
+        let pname = updater.get("pname")?;
 
-            let Some(homepage_str) = updater.get("homepage") else {
-                warn!(package = %pname, "Skipping: missing 'homepage' attribute");
-                continue;
-            };
+        // Apply package filter if specified
+        if !include.is_empty() && !include.iter().any(|pkg| pname.contains(pkg)) {
+            return None;
+        }
 
-            let Ok(homepage) = GitUrl::parse(&homepage_str) else {
-                warn!(package = %pname, url = %homepage_str, "Skipping: invalid homepage URL");
-                continue;
-            };
+        // Skip excluded packages
+        if exclude.excludes_name(&pname) {
+            return None;
+        }
 
-            // Optional for fetchGit
-            let nix_hash = updater.get("hash").unwrap_or_default();
+        // Skip packages not supported on the current platform
+        if !Self::supported_on_current_platform(&updater) {
+            info!(package = %pname, "Skipping: not supported on current platform");
+            return None;
+        }
 
-            let Some(version) = updater.get("version") else {
-                warn!(package = %pname, "Skipping: missing 'version' attribute");
-                continue;
-            };
+        // Skip purely local packages (src = ./.) — there is no upstream to track
+        if updater.has_local_src() {
+            info!(package = %pname, "Skipping: local source");
+            return None;
+        }
 
-            packages.push(Self {
-                name: pname,
+        let skipped = |reason: String| {
+            Some(Err(Skipped {
                 path: path.to_path_buf(),
-                kind: package_type,
-                homepage,
-                nix_hash,
-                version,
-                ast: ast.clone(),
-                result: UpdateResult::default(),
-            });
-        }
+                name: Some(pname.clone()),
+                reason,
+            }))
+        };
+
+        // Determine package type by checking content
+        let package_type = Self::detect_package_kind(&root_syntax, &content);
+
+        // Without a homepage, the repository the source is fetched from is the upstream to follow
+        let Some(homepage_str) = updater.get("homepage").or_else(|| updater.source_url()) else {
+            return skipped("no 'homepage' attribute, nor a fetcher URL to follow instead".to_string());
+        };
+
+        let Ok(homepage) = GitUrl::parse(&homepage_str) else {
+            return skipped(format!("invalid homepage URL '{homepage_str}'"));
+        };
+
+        // Optional for fetchGit
+        let nix_hash = updater.get_in(&["src"], "hash").unwrap_or_default();
+
+        let Some(version) = updater.get("version") else {
+            return skipped("missing 'version' attribute".to_string());
+        };
 
-        packages
+        Some(Ok(Self {
+            name: pname,
+            attr: None,
+            path: path.to_path_buf(),
+            kind: package_type,
+            homepage,
+            nix_hash,
+            version,
+            ast: ast.clone(),
+            scope,
+            result: UpdateResult::default(),
+            transaction: Transaction::default(),
+            settings: PackageSettings::default(),
+        }))
     }
 
-    fn detect_package_kind(root: &rnix::SyntaxNode, content: &str) -> PackageKind {
-        if Ast::contains_function_call(root, "fetchPypi") {
+    pub fn detect_package_kind(root: &rnix::SyntaxNode, content: &str) -> PackageKind {
+        if let Some(kind) = annotated_kind(content) {
+            kind
+        } else if Ast::contains_function_call(root, "fetchPypi") {
             PackageKind::PyPi
+        } else if Ast::contains_function_call(root, "fetchhg") {
+            PackageKind::Mercurial
         } else if Ast::contains_function_call(root, "rustPlatform.buildRustPackage") {
             PackageKind::Cargo
         } else if Ast::contains_function_call(root, "buildNpmPackage") {
@@ -135,7 +246,7 @@ impl Package {
         }
     }
 
-    fn supported_on_current_platform(ast: &Ast) -> bool {
+    pub fn supported_on_current_platform(ast: &Ast) -> bool {
         let Some(platform) = ast.meta_platforms() else {
             return true;
         };
@@ -148,34 +259,91 @@ impl Package {
         }
     }
 
-    /// Format the package name with hyperlink if homepage is available
+    /// The package name, linked to its homepage (OSC 8) when output is colored, plain otherwise
     pub fn name(&self) -> String {
-        format!("\x1B]8;;{}\x1B\\{}\x1B]8;;\x1B\\", &self.homepage.to_string(), &self.name).cyan().to_string()
+        if !colored::control::SHOULD_COLORIZE.should_colorize() {
+            return self.name.clone();
+        }
+
+        format!("\x1B]8;;{}\x1B\\{}\x1B]8;;\x1B\\", self.homepage, self.name).cyan().to_string()
     }
 
-    /// Get the visual display width of the package name (excluding escape sequences)
-    pub fn display_width(&self) -> usize {
-        self.name.len()
+    /// The flake attribute to build and evaluate: the one it was discovered under, otherwise the pname
+    pub fn flake_attr(&self) -> &str {
+        self.attr.as_deref().unwrap_or(&self.name)
     }
 
     pub fn ast(&self) -> Ast {
-        Ast::from_ast(self.ast.clone())
+        Ast::from_ast(self.ast.clone()).scoped(self.scope)
     }
 
-    pub fn write(&self, ast: &Ast) -> Result<()> {
-        Ok(std::fs::write(&self.path, ast.content())?)
+    /// Re-read the file, picking up edits landed by another package defined in it
+    pub fn reload(&mut self) -> Result<()> {
+        self.ast = rnix::Root::parse(&fs::read_to_string(&self.path)?);
+
+        Ok(())
+    }
+
+    /// Stage the edited file content; it is written to disk when the package's transaction commits
+    pub fn stage(&mut self, ast: &Ast) {
+        self.transaction.stage(&self.path, ast.content());
+    }
+
+    /// Land the edits of a finished update: roll them back if it failed or was skipped for a rate limit, otherwise
+    /// commit them, checking that an updated package still evaluates. Edits that break evaluation are reverted and the
+    /// package fails with an [`UpdateError::Evaluation`], which is also returned.
+    pub fn land(&mut self) -> Result<()> {
+        if self.result.status.contains(&UpdateStatus::Failed) || self.result.rate_limited() {
+            self.transaction.rollback();
+            return Ok(());
+        }
+
+        // A bad edit would break evaluating the flake for every package after it, so it's reverted right away
+        let (attr, exported) = (self.flake_attr().to_string(), self.attr.is_some());
+        let updated = self.result.status.contains(&UpdateStatus::Updated);
+
+        if let Err(e) = self.transaction.commit(|| if updated { evaluate(&attr, exported) } else { Ok(()) }) {
+            let error = UpdateError::Evaluation {
+                message: format!("Edits reverted: {e}"),
+            };
+
+            self.result.fail(error.clone());
+            return Err(error.into());
+        }
+
+        Ok(())
     }
 
     pub fn is_up_to_date(&self) -> bool {
         self.result.status.contains(&UpdateStatus::UpToDate)
     }
+
+    /// Whether the package was left unchecked for lack of upstream activity
+    pub fn is_dormant(&self) -> bool {
+        self.result.status.contains(&UpdateStatus::Dormant)
+    }
 }
 
-#[derive(Clone, Copy, Debug, Default, Display, Hash, PartialEq, Eq)]
+/// The kind forced by a `# nix-updater: kind=<kind>` comment, if any
+fn annotated_kind(content: &str) -> Option<PackageKind> {
+    content
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("nix-updater:"))
+        .flat_map(str::split_whitespace)
+        .find_map(|setting| setting.strip_prefix("kind=")?.parse().ok())
+}
+
+#[derive(Clone, Copy, Debug, Default, Display, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub enum UpdateStatus {
     Built,
     Cached,
+    Checked,
+    /// Not checked: upstream has been quiet since `--since`
+    Dormant,
     Failed,
+    /// Rebuilding it gave different output
+    NotReproducible,
+    Reproducible,
     Updated,
     UpToDate,
     #[default]
@@ -195,36 +363,139 @@ pub struct UpdateResult {
     pub new_git_commit: Option<String>,
 
     pub changes: Vec<String>,
+
+    /// When upstream published the new version, if the forge or registry says
+    pub released: Option<DateTime<Utc>>,
+
+    /// Wall-clock time of this run's build, including checks and pushing
+    pub build_duration: Option<Duration>,
+
+    /// Whether the build succeeded, per system in `[build] systems`
+    pub systems: BTreeMap<String, bool>,
+
+    /// Whether each flake package depending on this one still built against the update, with `--dependents`
+    pub dependents: BTreeMap<String, bool>,
+
+    /// The built output's closure size, compared with the previous build's
+    pub closure: Option<Closure>,
+
+    /// Log of the build or check that failed, excerpted in the failure summary
+    pub failed_log: Option<PathBuf>,
+
+    /// Why the package failed, or was skipped for a rate limit
+    pub error: Option<UpdateError>,
+
+    /// Skipped once the run reached its failure limit, and left for the next run
+    pub stopped: bool,
+
+    pub provenance: Provenance,
 }
 
 impl UpdateResult {
     pub fn status(&self, check: UpdateStatus) -> ColoredString {
         match check {
             _ if self.status.contains(&UpdateStatus::Failed) => "✗".red(),
-            UpdateStatus::Built | UpdateStatus::Updated | UpdateStatus::Cached if self.status.contains(&check) => "✓".green(),
+            UpdateStatus::Reproducible if self.status.contains(&UpdateStatus::NotReproducible) => "✗".red(),
+            UpdateStatus::Built | UpdateStatus::Updated | UpdateStatus::Checked | UpdateStatus::Reproducible | UpdateStatus::Cached if self.status.contains(&check) => "✓".green(),
             _ => "-".yellow(),
         }
     }
 
+    /// Build status for one of the configured systems
+    pub fn system_status(&self, system: &str) -> ColoredString {
+        match self.systems.get(system) {
+            Some(true) => "✓".green(),
+            Some(false) => "✗".red(),
+            None => "-".yellow(),
+        }
+    }
+
+    /// Which dependents still built against the update, e.g. `foo-cli ✓, foo-gui ✗`
+    pub fn dependent_status(&self) -> Option<String> {
+        (!self.dependents.is_empty()).then(|| {
+            self.dependents
+                .iter()
+                .map(|(name, built)| format!("{name} {}", if *built { "✓" } else { "✗" }))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+    }
+
     pub fn failed(&mut self, message: impl Into<String>) -> &mut Self {
+        self.fail(UpdateError::Other { message: message.into() })
+    }
+
+    /// Mark the package failed because of `error`, which is also its message
+    pub fn fail(&mut self, error: UpdateError) -> &mut Self {
         self.status.clear();
         self.status.insert(UpdateStatus::Failed);
 
-        self.message = Some(message.into());
+        self.message = Some(error.to_string());
+        self.error = Some(error);
         self
     }
 
+    /// Leave the package for the next run because of a rate limit, which is not its fault and so not a failure
+    pub fn skip(&mut self, error: UpdateError) -> &mut Self {
+        self.message = Some(format!("Skipped: {error}"));
+        self.error = Some(error);
+        self
+    }
+
+    /// Whether the package was skipped for a rate limit, leaving whatever the update had staged unfinished
+    pub fn rate_limited(&self) -> bool {
+        matches!(self.error, Some(UpdateError::RateLimited { .. }))
+    }
+
     pub fn message(&mut self, message: impl Into<String>) -> &mut Self {
         self.message = Some(message.into());
         self
     }
 
+    /// Record the registry or API response an update was based on
+    pub fn source(&mut self, source: Source) -> &mut Self {
+        self.provenance.sources.push(source);
+        self
+    }
+
+    /// Record an artifact that was fetched and hashed for an update
+    pub fn artifact(&mut self, url: impl Into<String>, rev: Option<&str>, hash: impl Into<String>) -> &mut Self {
+        self.provenance.artifacts.push(Artifact {
+            url: url.into(),
+            rev: rev.map(String::from),
+            hash: hash.into(),
+        });
+        self
+    }
+
+    pub fn released(&mut self, at: Option<DateTime<Utc>>) -> &mut Self {
+        self.released = at;
+        self
+    }
+
+    /// "released N days ago" for the new version, when its publish time is known
+    pub fn release_age(&self, now: DateTime<Utc>) -> Option<String> {
+        let days = (now - self.released?).num_days();
+
+        Some(match days {
+            ..=0 => "released today".to_string(),
+            1 => "released 1 day ago".to_string(),
+            _ => format!("released {days} days ago"),
+        })
+    }
+
     pub fn up_to_date(&mut self) -> &mut Self {
         self.status.insert(UpdateStatus::UpToDate);
         self.message = Some("Up to date".to_string());
         self
     }
 
+    pub fn dormant(&mut self, last_activity: DateTime<Utc>) -> &mut Self {
+        self.status.insert(UpdateStatus::Dormant);
+        self.message = Some(format!("No upstream activity since {}", last_activity.format("%Y-%m-%d")));
+        self
+    }
+
     pub fn git_commit(&mut self, old: Option<&str>, new: Option<&str>) -> &mut Self {
         //
         if let (Some(o), Some(n)) = (old, new)
@@ -241,6 +512,19 @@ impl UpdateResult {
         self
     }
 
+    /// A new source hash for the same version and revision, e.g. a re-tagged release
+    pub fn hash(&mut self, old: Option<&str>, new: Option<&str>) -> &mut Self {
+        if let (Some(o), Some(n)) = (old, new)
+            && o != n
+        {
+            self.status.insert(UpdateStatus::Updated);
+
+            self.changes.push(format!("hash {} → {}", short_hash(o), short_hash(n)));
+        }
+
+        self
+    }
+
     pub fn version(&mut self, old: Option<&str>, new: Option<&str>) -> &mut Self {
         //
         if let (Some(o), Some(n)) = (old, new)
@@ -258,3 +542,134 @@ impl UpdateResult {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{TimeDelta, Utc};
+
+    use super::{Package, PackageKind, UpdateResult, UpdateStatus, annotated_kind};
+    use crate::error::UpdateError;
+    use crate::exclude::Exclusions;
+
+    #[test]
+    fn release_age_counts_whole_days() {
+        let now = Utc::now();
+        let mut result = UpdateResult::default();
+
+        assert_eq!(result.release_age(now), None);
+
+        result.released(Some(now - TimeDelta::hours(5)));
+        assert_eq!(result.release_age(now).as_deref(), Some("released today"));
+
+        result.released(Some(now - TimeDelta::hours(30)));
+        assert_eq!(result.release_age(now).as_deref(), Some("released 1 day ago"));
+
+        result.released(Some(now - TimeDelta::days(12)));
+        assert_eq!(result.release_age(now).as_deref(), Some("released 12 days ago"));
+    }
+
+    #[test]
+    fn records_a_hash_only_change_without_commits() {
+        let mut result = UpdateResult::default();
+
+        result.hash(Some("sha256-AAAAAAAAAAAA="), Some("sha256-BBBBBBBBBBBB="));
+
+        assert!(result.status.contains(&UpdateStatus::Updated));
+        assert_eq!(result.changes, ["hash AAAAAAAA → BBBBBBBB"]);
+        assert_eq!(result.old_git_commit, None);
+    }
+
+    #[test]
+    fn rolls_back_updates_skipped_for_a_rate_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tool.nix");
+        let content = r#"{ pname = "tool"; version = "1.0"; src = fetchFromGitHub { owner = "example"; repo = "tool"; rev = "v1.0"; hash = "sha256-AAAAAAAAAAAA="; }; }"#;
+
+        fs::write(&path, content).unwrap();
+
+        let mut package = Package::from_file(&path, &[], &Exclusions::default()).packages.remove(0);
+
+        // The version is bumped, but the limit is hit before the hash is recomputed
+        package.transaction.write_through(&path, &content.replace("1.0", "2.0")).unwrap();
+        package.transaction.stage(&path, content.replace("1.0", "2.0"));
+        package.result.version(Some("1.0"), Some("2.0"));
+        package.result.skip(UpdateError::RateLimited {
+            service: "GitHub".to_string(),
+            reset: None,
+        });
+
+        assert!(package.result.rate_limited());
+        package.land().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn reads_kind_annotation() {
+        assert_eq!(annotated_kind("# nix-updater: kind=github\n{ pname = \"tool\"; }"), Some(PackageKind::GitHub));
+        assert_eq!(annotated_kind("{\n  # nix-updater: kind=Cargo\n}"), Some(PackageKind::Cargo));
+        assert_eq!(annotated_kind("# nix-updater: kind=nonsense"), None);
+        assert_eq!(annotated_kind("# built with kind=github"), None);
+    }
+
+    #[test]
+    fn detects_mercurial_sources() {
+        let content = r#"{ pname = "tool"; src = fetchhg { url = "https://hg.example.org/tool"; rev = "abc"; }; }"#;
+
+        assert_eq!(Package::detect_package_kind(&rnix::Root::parse(content).syntax(), content), PackageKind::Mercurial);
+    }
+
+    #[test]
+    fn skips_packages_missing_what_updates_need() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let files = [
+            (
+                "good.nix",
+                r#"{ pname = "good"; version = "1.0"; meta = { homepage = "https://github.com/example/good"; }; }"#,
+            ),
+            (
+                "unversioned.nix",
+                r#"{ pname = "unversioned"; meta = { homepage = "https://github.com/example/unversioned"; }; }"#,
+            ),
+            ("odd.nix", r#"{ pname = "odd"; version = "1.0"; meta = { homepage = "not a url at all"; }; }"#),
+            (
+                "fetched.nix",
+                r#"{ pname = "fetched"; version = "1.0"; src = fetchFromGitHub { owner = "example"; repo = "fetched"; }; }"#,
+            ),
+        ];
+
+        for (name, content) in files {
+            fs::write(dir.join(name), content).unwrap();
+        }
+
+        let discovered = Package::discover(dir, &[], &Exclusions::default());
+        let mut skipped = discovered.skipped.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        skipped.sort();
+
+        let mut found = discovered
+            .packages
+            .iter()
+            .map(|package| (package.name.as_str(), package.homepage.to_string()))
+            .collect::<Vec<_>>();
+
+        found.sort();
+
+        assert_eq!(
+            found,
+            [
+                ("fetched", "https://github.com/example/fetched".to_string()),
+                ("good", "https://github.com/example/good".to_string())
+            ]
+        );
+        assert_eq!(
+            skipped,
+            [
+                format!("{} (odd): invalid homepage URL 'not a url at all'", dir.join("odd.nix").display()),
+                format!("{} (unversioned): missing 'version' attribute", dir.join("unversioned.nix").display()),
+            ]
+        );
+    }
+}
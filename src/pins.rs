@@ -0,0 +1,411 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use git_url_parse::GitUrl;
+use itertools::Itertools;
+use rayon::prelude::*;
+use rootcause::{Result, report};
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
+use serde_json::{Map, Value};
+
+use nix_package_updater::backup::RunBackup;
+use nix_package_updater::clients::GitHubClient;
+use nix_package_updater::clients::nix::{FetchFlags, Nix};
+use nix_package_updater::exclude::Exclusions;
+use nix_package_updater::transaction::Transaction;
+use nix_package_updater::updater::short_hash;
+
+/// Where npins and niv keep their pins
+const PIN_FILES: [&str; 2] = ["npins/sources.json", "nix/sources.json"];
+
+/// A `sources.json` written by npins (pins under `"pins"`) or niv (pins at the top level)
+pub struct PinFile {
+    pub path: PathBuf,
+    json: Value,
+    /// Written back with the file's own indentation, so a diff shows only the changed values
+    indent: usize,
+}
+
+/// How a pin follows its upstream
+#[derive(Debug, PartialEq, Eq)]
+enum Tracking {
+    /// The head of `branch`
+    Branch { url: String, branch: String },
+    /// The newest GitHub release whose tag has `prefix` (npins' `GitRelease`)
+    Release { url: String, prefix: Option<String> },
+}
+
+/// The attributes an update rewrites, named differently by npins and niv
+struct Keys {
+    rev: &'static str,
+    hash: &'static str,
+}
+
+const NPINS: Keys = Keys { rev: "revision", hash: "hash" };
+const NIV: Keys = Keys { rev: "rev", hash: "sha256" };
+
+/// What checking a pin came to
+pub enum PinStatus {
+    UpToDate,
+    /// From and to which revision, or release for release pins
+    Updated(String, String),
+    /// Marked `"frozen": true` with `npins freeze`, so left where it is
+    Frozen,
+    Unsupported(String),
+    Failed(String),
+}
+
+pub struct PinOutcome {
+    pub name: String,
+    pub path: PathBuf,
+    pub status: PinStatus,
+}
+
+impl PinFile {
+    /// The pin files of the repository in the current directory
+    pub fn discover() -> Result<Vec<Self>> {
+        PIN_FILES.iter().map(Path::new).filter(|path| path.is_file()).map(Self::read).collect()
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let json = serde_json::from_str(&content).map_err(|e| report!("{}: {e}", path.display()))?;
+        let indent = content.lines().nth(1).map_or(2, |line| line.len() - line.trim_start().len());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            json,
+            indent,
+        })
+    }
+
+    fn is_npins(&self) -> bool {
+        self.json.get("pins").is_some_and(Value::is_object)
+    }
+
+    fn pins(&self) -> Option<&Map<String, Value>> {
+        if self.is_npins() { self.json.get("pins") } else { Some(&self.json) }.and_then(Value::as_object)
+    }
+
+    fn pin_mut(&mut self, name: &str) -> Option<&mut Map<String, Value>> {
+        let pins = if self.is_npins() { self.json.get_mut("pins") } else { Some(&mut self.json) };
+
+        pins.and_then(|pins| pins.get_mut(name)).and_then(Value::as_object_mut)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.pins().map(|pins| pins.keys().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Write the pins back through a [`Transaction`], backed up for `revert` under the tool's name
+    fn write(&self, backup: Option<&RunBackup>) -> Result<()> {
+        let indent = " ".repeat(self.indent);
+        let mut content = Vec::new();
+
+        self.json
+            .serialize(&mut serde_json::Serializer::with_formatter(&mut content, PrettyFormatter::with_indent(indent.as_bytes())))?;
+        content.push(b'\n');
+
+        let mut transaction = Transaction::default();
+        transaction.backup = backup.map(|backup| backup.package(if self.is_npins() { "npins" } else { "niv" }, &self.path));
+
+        transaction.stage(&self.path, String::from_utf8(content)?);
+        transaction.commit(|| Ok(()))
+    }
+}
+
+/// Check the pins selected like packages (`include` by substring, `exclude` by pattern), writing updates back unless
+/// this is a dry run, with the originals saved to `backup`
+pub fn update_pins(files: &mut [PinFile], include: &[String], exclude: &Exclusions, force: bool, dry_run: bool, backup: Option<&RunBackup>) -> Result<Vec<PinOutcome>> {
+    let client = GitHubClient::shared()?;
+    let mut outcomes = Vec::new();
+
+    for file in files {
+        let npins = file.is_npins();
+
+        let checked = file
+            .pins()
+            .into_iter()
+            .flatten()
+            .filter(|(name, _)| (include.is_empty() || include.iter().any(|pin| name.contains(pin.as_str()))) && !exclude.excludes_name(name))
+            .collect_vec()
+            .into_par_iter()
+            .map(|(name, pin)| {
+                let checked = match pin.as_object() {
+                    Some(pin) => check_pin(pin, npins, &client, force),
+                    None => Ok((PinStatus::Unsupported("not an object".to_string()), Vec::new())),
+                };
+
+                (name.clone(), checked)
+            })
+            .collect::<Vec<_>>();
+
+        let mut changed = false;
+
+        for (name, checked) in checked {
+            let status = match checked {
+                Ok((status, edits)) => {
+                    if let Some(pin) = file.pin_mut(&name) {
+                        for (key, value) in edits {
+                            pin.insert(key.to_string(), Value::String(value));
+                            changed = true;
+                        }
+                    }
+
+                    status
+                }
+                Err(e) => PinStatus::Failed(e.to_string()),
+            };
+
+            outcomes.push(PinOutcome {
+                name,
+                path: file.path.clone(),
+                status,
+            });
+        }
+
+        if changed && !dry_run {
+            file.write(backup)?;
+        }
+    }
+
+    Ok(outcomes)
+}
+
+/// How a pin follows its upstream, or why it can't be updated
+fn tracking(pin: &Map<String, Value>, npins: bool) -> std::result::Result<Tracking, String> {
+    let field = |name: &str| pin.get(name).and_then(Value::as_str).map(String::from);
+
+    if !npins {
+        let branch = field("branch").ok_or_else(|| "no branch to follow".to_string())?;
+
+        return match (field("type").as_deref(), field("owner"), field("repo")) {
+            (Some("git"), _, Some(url)) => Ok(Tracking::Branch { url, branch }),
+            (_, Some(owner), Some(repo)) => Ok(Tracking::Branch {
+                url: format!("https://github.com/{owner}/{repo}"),
+                branch,
+            }),
+            _ => Err("not a GitHub or git source".to_string()),
+        };
+    }
+
+    let repository = pin.get("repository").and_then(Value::as_object);
+    let repository_field = |name: &str| repository.and_then(|repository| repository.get(name)).and_then(Value::as_str);
+
+    let url = match repository_field("type") {
+        Some("GitHub") => repository_field("owner")
+            .zip(repository_field("repo"))
+            .map(|(owner, repo)| format!("https://github.com/{owner}/{repo}")),
+        Some("GitLab") => repository_field("repo_path").map(|path| format!("{}/{path}", repository_field("server").unwrap_or("https://gitlab.com").trim_end_matches('/'))),
+        Some("Git") => repository_field("url").map(String::from),
+        _ => None,
+    };
+
+    match (field("type").unwrap_or_default().as_str(), url) {
+        ("Git", Some(url)) => Ok(Tracking::Branch {
+            url,
+            branch: field("branch").ok_or_else(|| "no branch to follow".to_string())?,
+        }),
+        ("GitRelease", Some(url)) if url.starts_with("https://github.com/") => Ok(Tracking::Release {
+            url,
+            prefix: field("release_prefix"),
+        }),
+        (kind, _) => Err(format!("{kind} pins from this repository aren't supported")),
+    }
+}
+
+/// Find a pin's latest revision and, if it moved, the attributes to rewrite
+fn check_pin(pin: &Map<String, Value>, npins: bool, client: &GitHubClient, force: bool) -> Result<(PinStatus, Vec<(&'static str, String)>)> {
+    let keys = if npins { &NPINS } else { &NIV };
+    let field = |name: &str| pin.get(name).and_then(Value::as_str);
+
+    if npins && pin.get("frozen").and_then(Value::as_bool).unwrap_or_default() {
+        return Ok((PinStatus::Frozen, Vec::new()));
+    }
+
+    let tracking = match tracking(pin, npins) {
+        Ok(tracking) => tracking,
+        Err(reason) => return Ok((PinStatus::Unsupported(reason), Vec::new())),
+    };
+
+    let (url, new_rev, tag) = match tracking {
+        Tracking::Branch { url, branch } => {
+            let head = match GitUrl::parse(&url) {
+                Ok(repository) if repository.host() == Some("github.com") => client.latest_commit(&repository, Some(&branch))?,
+                _ => Nix::remote_ref(&url, &format!("refs/heads/{branch}"))?,
+            };
+
+            let Some(head) = head else {
+                return Ok((PinStatus::Failed(format!("Branch {branch} not found")), Vec::new()));
+            };
+
+            (url, head, None)
+        }
+        Tracking::Release { url, prefix } => {
+            let repository = GitUrl::parse(&url).map_err(|e| report!("{url}: {e}"))?;
+
+            let Some(release) = client.latest_release(&repository, prefix.as_deref())? else {
+                return Ok((PinStatus::Failed("No releases found".to_string()), Vec::new()));
+            };
+
+            // Annotated tags point at a tag object, which `^{}` peels to its commit
+            let rev = match Nix::remote_ref(&url, &format!("refs/tags/{}^{{}}", release.tag))? {
+                Some(rev) => Some(rev),
+                None => Nix::remote_ref(&url, &format!("refs/tags/{}", release.tag))?,
+            };
+
+            let Some(rev) = rev else {
+                return Ok((PinStatus::Failed(format!("Tag {} not found", release.tag)), Vec::new()));
+            };
+
+            (url, rev, Some(release.tag))
+        }
+    };
+
+    let old_rev = field(keys.rev).unwrap_or_default();
+
+    if new_rev == old_rev && !force {
+        return Ok((PinStatus::UpToDate, Vec::new()));
+    }
+
+    let mut edits = vec![(keys.rev, new_rev.clone())];
+
+    // Archive URLs carry the revision, or the tag for releases
+    if let Some(old_url) = field("url") {
+        let mut new_url = if old_rev.is_empty() { old_url.to_string() } else { old_url.replace(old_rev, &new_rev) };
+
+        if let (Some(old_tag), Some(new_tag)) = (field("version"), &tag) {
+            new_url = new_url.replace(old_tag, new_tag);
+        }
+
+        edits.push(("url", new_url));
+    }
+
+    if let Some(tag) = &tag {
+        edits.push(("version", tag.clone()));
+    }
+
+    if let Some(old_hash) = field(keys.hash) {
+        let flags = FetchFlags {
+            submodules: pin.get("submodules").and_then(Value::as_bool).unwrap_or_default(),
+            ..FetchFlags::default()
+        };
+
+        let Some((hash, _)) = Nix::hash_and_rev(&url, Some(&new_rev), flags)? else {
+            return Ok((PinStatus::Failed(format!("Failed to hash {url} at {new_rev}")), Vec::new()));
+        };
+
+        // niv and older npins keep nix32 hashes
+        let hash = if old_hash.starts_with("sha256-") { hash } else { Nix::convert_hash(&hash, "nix32")? };
+
+        edits.push((keys.hash, hash));
+    }
+
+    let status = match (field("version"), tag) {
+        (Some(old_tag), Some(new_tag)) => PinStatus::Updated(old_tag.to_string(), new_tag),
+        _ => PinStatus::Updated(short_hash(old_rev), short_hash(&new_rev)),
+    };
+
+    Ok((status, edits))
+}
+
+/// Print the pins that changed or couldn't be checked
+pub fn print_pins(outcomes: &[PinOutcome]) {
+    for outcome in outcomes.iter().sorted_by(|a, b| a.name.cmp(&b.name)) {
+        let name = format!("{} ({})", outcome.name.cyan(), outcome.path.display());
+
+        match &outcome.status {
+            PinStatus::UpToDate => {}
+            PinStatus::Updated(old, new) => println!("{name}: {old} → {}", new.green()),
+            PinStatus::Frozen => println!("{name}: {}", "frozen, not updated".yellow()),
+            PinStatus::Unsupported(reason) => println!("{name}: {}", format!("skipped, {reason}").yellow()),
+            PinStatus::Failed(e) => println!("{name}: {}", e.red()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use nix_package_updater::backup::{RunBackup, revert};
+    use serde_json::{Map, Value, json};
+
+    use super::{PinFile, Tracking, tracking};
+
+    fn pin(value: &Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn reads_how_pins_are_tracked() {
+        let npins_git = pin(&json!({
+            "type": "Git",
+            "repository": { "type": "GitHub", "owner": "nix-community", "repo": "home-manager" },
+            "branch": "master",
+            "revision": "abc",
+            "url": "https://github.com/nix-community/home-manager/archive/abc.tar.gz",
+            "hash": "sha256-old"
+        }));
+        let npins_release = pin(&json!({
+            "type": "GitRelease",
+            "repository": { "type": "GitHub", "owner": "o", "repo": "r" },
+            "release_prefix": "cli-",
+            "version": "cli-1.0.0"
+        }));
+        let niv = pin(&json!({ "branch": "main", "owner": "o", "repo": "r", "rev": "abc", "sha256": "0abc", "type": "tarball" }));
+        let niv_git = pin(&json!({ "branch": "main", "repo": "https://git.example.org/r.git", "rev": "abc", "type": "git" }));
+
+        assert_eq!(
+            tracking(&npins_git, true),
+            Ok(Tracking::Branch {
+                url: "https://github.com/nix-community/home-manager".to_string(),
+                branch: "master".to_string()
+            })
+        );
+        assert_eq!(
+            tracking(&npins_release, true),
+            Ok(Tracking::Release {
+                url: "https://github.com/o/r".to_string(),
+                prefix: Some("cli-".to_string())
+            })
+        );
+        assert_eq!(
+            tracking(&niv, false),
+            Ok(Tracking::Branch {
+                url: "https://github.com/o/r".to_string(),
+                branch: "main".to_string()
+            })
+        );
+        assert_eq!(
+            tracking(&niv_git, false),
+            Ok(Tracking::Branch {
+                url: "https://git.example.org/r.git".to_string(),
+                branch: "main".to_string()
+            })
+        );
+        assert!(tracking(&pin(&json!({ "type": "Channel", "name": "nixos-unstable" })), true).is_err());
+    }
+
+    #[test]
+    fn writes_pins_back_revertably() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let path = root.join("sources.json");
+        let original = "{\n    \"pins\": {\n        \"tool\": {\n            \"revision\": \"abc\"\n        }\n    },\n    \"version\": 5\n}\n";
+
+        fs::write(&path, original).unwrap();
+
+        let mut file = PinFile::read(&path).unwrap();
+        file.pin_mut("tool").unwrap().insert("revision".to_string(), json!("def"));
+        file.write(Some(&RunBackup::start(&root.join("runs")))).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), original.replace("abc", "def"));
+
+        revert(&root.join("runs"), Some("npins")).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+    }
+}
@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use colored::Colorize;
+use rootcause::{Result, bail};
+
+use nix_package_updater::clients::nix::Nix;
+
+/// What `prefetch` was asked to hash
+#[derive(Debug, PartialEq, Eq)]
+pub enum Target {
+    /// A git repository, optionally at a specific revision
+    Repository { url: String, rev: Option<String> },
+    /// A single file or archive
+    File { url: String },
+}
+
+impl Target {
+    /// Parse `github:owner/repo[@rev]`, a git URL (`.git`, `git+`), or a plain URL
+    pub fn parse(target: &str) -> Result<Self> {
+        if let Some(path) = target.strip_prefix("github:") {
+            let (repo, rev) = match path.split_once('@') {
+                Some((repo, rev)) => (repo, Some(rev.to_string())),
+                None => (path, None),
+            };
+
+            if repo.split('/').filter(|part| !part.is_empty()).count() != 2 {
+                bail!("Expected github:owner/repo[@rev], got '{target}'");
+            }
+
+            return Ok(Self::Repository {
+                url: format!("https://github.com/{repo}"),
+                rev,
+            });
+        }
+
+        if let Some(url) = target.strip_prefix("git+") {
+            return Ok(Self::Repository { url: url.to_string(), rev: None });
+        }
+
+        if Path::new(target).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("git")) {
+            return Ok(Self::Repository {
+                url: target.to_string(),
+                rev: None,
+            });
+        }
+
+        if !target.contains("://") {
+            bail!("Expected a URL or github:owner/repo[@rev], got '{target}'");
+        }
+
+        Ok(Self::File { url: target.to_string() })
+    }
+}
+
+/// Print SRI hashes and a fetcher expression for a URL or repository
+pub fn print_prefetch(target: &Target) -> Result<()> {
+    match target {
+        Target::Repository { url, rev } => {
+            let Some(fetcher) = Nix::nurl(url, rev.as_deref())? else {
+                bail!("nurl could not prefetch {url}");
+            };
+
+            println!("{:<10} {}", "unpacked".bright_white().bold(), fetcher.hash().unwrap_or_default());
+
+            if let Some(rev) = fetcher.rev() {
+                println!("{:<10} {rev}", "rev".bright_white().bold());
+            }
+
+            println!("\n{}", fetcher.expression());
+        }
+        Target::File { url } => {
+            let Some(flat) = Nix::prefetch_hash(url)? else {
+                bail!("Could not download {url}");
+            };
+
+            // Not every file is an archive that can be unpacked
+            let unpacked = Nix::prefetch_unpacked_hash(url).ok().flatten();
+
+            println!("{:<10} {flat}", "flat".bright_white().bold());
+
+            if let Some(unpacked) = &unpacked {
+                println!("{:<10} {unpacked}", "unpacked".bright_white().bold());
+            }
+
+            println!("\nfetchurl {{\n  url = \"{url}\";\n  hash = \"{flat}\";\n}}");
+
+            if let Some(unpacked) = unpacked {
+                println!("\nfetchzip {{\n  url = \"{url}\";\n  hash = \"{unpacked}\";\n}}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Target;
+    use nix_package_updater::clients::nix::Fetcher;
+
+    #[test]
+    fn fetcher_expression_puts_hash_last() {
+        let fetcher: Fetcher =
+            serde_json::from_str(r#"{"fetcher":"fetchFromGitHub","args":{"hash":"sha256-abc","owner":"o","repo":"r","rev":"v1","fetchSubmodules":true}}"#).unwrap();
+
+        assert_eq!(
+            fetcher.expression(),
+            "fetchFromGitHub {\n  fetchSubmodules = true;\n  owner = \"o\";\n  repo = \"r\";\n  rev = \"v1\";\n  hash = \"sha256-abc\";\n}"
+        );
+    }
+
+    #[test]
+    fn parses_github_shorthand_with_and_without_rev() {
+        assert_eq!(
+            Target::parse("github:owner/repo@v1.2.3").unwrap(),
+            Target::Repository {
+                url: "https://github.com/owner/repo".to_string(),
+                rev: Some("v1.2.3".to_string()),
+            }
+        );
+
+        assert_eq!(
+            Target::parse("github:owner/repo").unwrap(),
+            Target::Repository {
+                url: "https://github.com/owner/repo".to_string(),
+                rev: None,
+            }
+        );
+
+        assert!(Target::parse("github:owner").is_err());
+    }
+
+    #[test]
+    fn parses_plain_urls_as_files() {
+        assert_eq!(
+            Target::parse("https://example.com/tool-1.0.tar.gz").unwrap(),
+            Target::File {
+                url: "https://example.com/tool-1.0.tar.gz".to_string(),
+            }
+        );
+
+        assert!(Target::parse("not a url").is_err());
+    }
+}
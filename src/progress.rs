@@ -0,0 +1,247 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+/// Whether stdout is a terminal that can redraw spinners
+pub fn interactive() -> bool {
+    io::stdout().is_terminal() && env::var("TERM").map_or(true, |term| term != "dumb")
+}
+
+/// Whether output may be colored (and package names hyperlinked): not with `--no-color` or `NO_COLOR`, nor when
+/// stdout isn't a terminal unless `CLICOLOR_FORCE` asks for it
+pub fn color_enabled(no_color: bool) -> bool {
+    let set = |name: &str| env::var_os(name).is_some_and(|value| !value.is_empty() && value != "0");
+
+    !no_color && !set("NO_COLOR") && (set("CLICOLOR_FORCE") || interactive())
+}
+
+/// How per-package progress is reported
+#[derive(Clone)]
+enum Mode {
+    /// Animated spinners, one per package above an overall bar, for interactive terminals
+    Spinners {
+        multi: MultiProgress,
+        style: ProgressStyle,
+        overall: ProgressBar,
+    },
+    /// Timestamped lines on stdout, for CI logs and redirected output where spinners can't redraw
+    Lines,
+}
+
+/// Counts across the whole run, for the overall progress line
+struct Overall {
+    total: usize,
+    started: Instant,
+    done: AtomicUsize,
+    building: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl Overall {
+    fn message(&self) -> String {
+        format!(
+            "{}/{} packages, {} building, {} failed",
+            self.done.load(Ordering::Relaxed),
+            self.total,
+            self.building.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed)
+        )
+    }
+
+    /// Time left at the average pace of the packages done so far
+    fn eta(&self) -> Option<Duration> {
+        let done = u32::try_from(self.done.load(Ordering::Relaxed)).ok().filter(|done| *done > 0)?;
+        let remaining = u32::try_from(self.total).ok()?.checked_sub(done).filter(|remaining| *remaining > 0)?;
+
+        Some(Duration::from_secs((self.started.elapsed() / done * remaining).as_secs()))
+    }
+}
+
+/// Reports per-package progress, and how far the run as a whole has got
+#[derive(Clone)]
+pub struct Reporter {
+    mode: Mode,
+    overall: Arc<Overall>,
+}
+
+impl Reporter {
+    /// Pick spinners when stdout is a terminal, plain lines otherwise, for a run over `total` packages
+    pub fn detect(total: usize) -> Self {
+        let overall = Arc::new(Overall {
+            total,
+            started: Instant::now(),
+            done: AtomicUsize::new(0),
+            building: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+        });
+
+        if !interactive() {
+            return Self { mode: Mode::Lines, overall };
+        }
+
+        let (template, overall_template) = if colored::control::SHOULD_COLORIZE.should_colorize() {
+            ("{spinner:.cyan.bold} {msg}", "{bar:30.cyan/blue} {msg} (ETA {eta})")
+        } else {
+            ("{spinner} {msg}", "{bar:30} {msg} (ETA {eta})")
+        };
+
+        let style = ProgressStyle::with_template(template).expect("Couldn't set spinner style").tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ");
+
+        let multi = MultiProgress::new();
+        let bar = multi.add(ProgressBar::new(total as u64));
+
+        bar.set_style(
+            ProgressStyle::with_template(overall_template)
+                .expect("Couldn't set progress bar style")
+                .progress_chars("━╸─"),
+        );
+        bar.set_message(overall.message());
+        bar.enable_steady_tick(Duration::from_millis(500));
+
+        Self {
+            mode: Mode::Spinners { multi, style, overall: bar },
+            overall,
+        }
+    }
+
+    /// Start reporting progress for one package
+    pub fn add(&self) -> Progress {
+        let bar = match &self.mode {
+            Mode::Spinners { multi, style, overall } => {
+                // Spinners stack above the overall bar, which stays at the bottom
+                let pb = multi.insert_before(overall, ProgressBar::new_spinner());
+                pb.enable_steady_tick(Duration::from_millis(50));
+                pb.set_style(style.clone());
+
+                Some(pb)
+            }
+            Mode::Lines => None,
+        };
+
+        Progress { bar, reporter: self.clone() }
+    }
+
+    fn update(&self) {
+        match &self.mode {
+            Mode::Spinners { overall, .. } => {
+                overall.set_position(self.overall.done.load(Ordering::Relaxed) as u64);
+                overall.set_message(self.overall.message());
+            }
+            Mode::Lines => {
+                let eta = self.overall.eta().map(|eta| format!(", ETA {}", humantime::format_duration(eta))).unwrap_or_default();
+
+                println!("{} {}{eta}", Local::now().format("%H:%M:%S"), self.overall.message());
+            }
+        }
+    }
+
+    /// Packages finished so far, failed or not
+    pub fn done(&self) -> usize {
+        self.overall.done.load(Ordering::Relaxed)
+    }
+
+    /// Packages that have failed so far
+    pub fn failed(&self) -> usize {
+        self.overall.failed.load(Ordering::Relaxed)
+    }
+
+    /// Clear the overall bar once the run is done
+    pub fn finish(&self) {
+        if let Mode::Spinners { overall, .. } = &self.mode {
+            overall.finish_and_clear();
+        }
+    }
+}
+
+/// Counts a package as building until dropped
+pub struct Building(Arc<Overall>);
+
+impl Drop for Building {
+    fn drop(&mut self) {
+        self.0.building.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Progress handle for a single package: a spinner, or a line logger when not attached to a terminal
+pub struct Progress {
+    bar: Option<ProgressBar>,
+    reporter: Reporter,
+}
+
+impl Progress {
+    pub fn set_message(&self, message: impl Into<String>) {
+        let message = message.into();
+
+        match &self.bar {
+            Some(pb) => pb.set_message(message),
+            // println! holds the stdout lock per line, so concurrent packages never interleave mid-line
+            None => println!("{} {message}", Local::now().format("%H:%M:%S")),
+        }
+    }
+
+    /// Run `f` with the spinner hidden so its output isn't overdrawn
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        match &self.bar {
+            Some(pb) => pb.suspend(f),
+            None => f(),
+        }
+    }
+
+    /// Count the package as building, until the returned guard is dropped
+    pub fn building(&self) -> Building {
+        let overall = &self.reporter.overall;
+
+        overall.building.fetch_add(1, Ordering::Relaxed);
+        self.reporter.update();
+
+        Building(overall.clone())
+    }
+
+    /// Count the package as done, towards the overall progress and ETA
+    pub fn done(&self, failed: bool) {
+        let overall = &self.reporter.overall;
+
+        overall.done.fetch_add(1, Ordering::Relaxed);
+
+        if failed {
+            overall.failed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.reporter.update();
+    }
+
+    pub fn finish_and_clear(&self) {
+        if let Some(pb) = &self.bar {
+            pb.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_from_completed_packages() {
+        let overall = Overall {
+            total: 4,
+            started: Instant::now().checked_sub(Duration::from_secs(60)).unwrap(),
+            done: AtomicUsize::new(0),
+            building: AtomicUsize::new(1),
+            failed: AtomicUsize::new(0),
+        };
+
+        assert_eq!(overall.eta(), None);
+
+        overall.done.store(1, Ordering::Relaxed);
+        overall.failed.store(1, Ordering::Relaxed);
+
+        assert_eq!(overall.message(), "1/4 packages, 1 building, 1 failed");
+        assert!(overall.eta().is_some_and(|eta| (Duration::from_secs(180)..Duration::from_secs(185)).contains(&eta)));
+    }
+}
@@ -0,0 +1,235 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rootcause::{Result, bail};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::package::{Package, UpdateStatus};
+
+/// A registry or forge API response an update decision was based on
+#[derive(Clone, Debug, Serialize)]
+pub struct Source {
+    pub url: String,
+    pub digest: Option<String>,
+}
+
+/// An artifact that was fetched and hashed while applying an update
+#[derive(Clone, Debug, Serialize)]
+pub struct Artifact {
+    pub url: String,
+    pub rev: Option<String>,
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Provenance {
+    pub sources: Vec<Source>,
+    pub artifacts: Vec<Artifact>,
+}
+
+#[derive(Default, Serialize)]
+struct Entry<'a> {
+    timestamp: String,
+    package: &'a str,
+    kind: String,
+    path: String,
+    old_version: Option<&'a str>,
+    new_version: Option<&'a str>,
+    old_rev: Option<&'a str>,
+    new_rev: Option<&'a str>,
+    released: Option<DateTime<Utc>>,
+    sources: &'a [Source],
+    artifacts: &'a [Artifact],
+    closure_size: Option<u64>,
+    previous_closure_size: Option<u64>,
+    /// Digest of the previous line, chaining entries so that removing or reordering them is detectable
+    previous: Option<String>,
+    /// HMAC-SHA256 of the entry without this field, under the configured `provenance_key`, so that an edited entry
+    /// can't simply have every later `previous` recomputed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+/// The entry as a JSON line, signed with `key` when there is one
+fn signed_line(mut entry: Entry<'_>, key: Option<&str>) -> Result<String> {
+    entry.signature = None;
+
+    let unsigned = serde_json::to_string(&entry)?;
+
+    let Some(key) = key else {
+        return Ok(unsigned);
+    };
+
+    entry.signature = Some(sign(key, &unsigned)?);
+
+    Ok(serde_json::to_string(&entry)?)
+}
+
+/// HMAC-SHA256 of `line` under `key`, in `hmac-sha256:<hex>` form
+fn sign(key: &str, line: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())?;
+    mac.update(line.as_bytes());
+
+    Ok(format!("hmac-sha256:{:x}", mac.finalize().into_bytes()))
+}
+
+/// Walk the provenance log at `path`, checking each entry chains on from the one before and, with `key`, carries a
+/// valid signature. Returns how many entries there are, failing at the first one that doesn't check out.
+pub fn verify(path: &Path, key: Option<&str>) -> Result<usize> {
+    let content = fs::read_to_string(path)?;
+    let mut previous = None;
+
+    for (index, line) in content.lines().enumerate() {
+        let number = index + 1;
+        let mut entry: Value = serde_json::from_str(line).map_err(|e| rootcause::report!("Line {number}: {e}"))?;
+
+        if entry.get("previous").and_then(Value::as_str) != previous.as_deref() {
+            bail!("Line {number}: doesn't follow on from the line before, which was changed, removed or reordered");
+        }
+
+        if let Some(key) = key {
+            let signature = entry.as_object_mut().and_then(|entry| entry.remove("signature"));
+
+            let Some(signature) = signature.as_ref().and_then(Value::as_str).map(String::from) else {
+                bail!("Line {number}: not signed");
+            };
+
+            if sign(key, &serde_json::to_string(&entry)?)? != signature {
+                bail!("Line {number}: the signature doesn't match, so the entry was changed or signed with another key");
+            }
+        }
+
+        previous = Some(digest(line.as_bytes()));
+    }
+
+    Ok(content.lines().count())
+}
+
+/// SHA-256 digest of a byte slice, in `sha256:<hex>` form
+pub fn digest(bytes: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(bytes))
+}
+
+/// Append a provenance entry for every updated package to the JSON lines log at `path`, signed with `key` when there
+/// is one.
+pub fn record(path: &Path, packages: &[Package], key: Option<&str>) -> Result<()> {
+    let updated = packages.iter().filter(|p| p.result.status.contains(&UpdateStatus::Updated)).collect::<Vec<_>>();
+
+    if updated.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut previous = fs::read_to_string(path).ok().and_then(|content| content.lines().last().map(|line| digest(line.as_bytes())));
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    for package in updated {
+        let result = &package.result;
+
+        let line = signed_line(
+            Entry {
+                timestamp: Utc::now().to_rfc3339(),
+                package: &package.name,
+                kind: package.kind.to_string(),
+                path: package.path.display().to_string(),
+                old_version: result.old_version.as_deref(),
+                new_version: result.new_version.as_deref(),
+                old_rev: result.old_git_commit.as_deref(),
+                new_rev: result.new_git_commit.as_deref(),
+                released: result.released,
+                sources: &result.provenance.sources,
+                artifacts: &result.provenance.artifacts,
+                closure_size: result.closure.as_ref().map(|closure| closure.size),
+                previous_closure_size: result.closure.as_ref().and_then(|closure| closure.previous_size),
+                previous: previous.take(),
+                signature: None,
+            },
+            key,
+        )?;
+
+        writeln!(file, "{line}")?;
+
+        previous = Some(digest(line.as_bytes()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Entry, digest, signed_line, verify};
+
+    /// A log of `count` chained entries, signed with `key` when given
+    fn log(count: usize, key: Option<&str>) -> String {
+        let mut lines = String::new();
+        let mut previous = None;
+
+        for index in 0..count {
+            let version = format!("1.{index}");
+            let line = signed_line(
+                Entry {
+                    package: "tool",
+                    new_version: Some(&version),
+                    previous: previous.take(),
+                    ..Entry::default()
+                },
+                key,
+            )
+            .unwrap();
+
+            previous = Some(digest(line.as_bytes()));
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+
+        lines
+    }
+
+    #[test]
+    fn verifies_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("provenance.jsonl");
+        let content = log(3, None);
+
+        fs::write(&path, &content).unwrap();
+        assert_eq!(verify(&path, None).unwrap(), 3);
+
+        // Dropping an entry breaks the chain at the one after it
+        let lines = content.lines().collect::<Vec<_>>();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(verify(&path, None).unwrap_err().to_string().contains("Line 2"));
+
+        // Without a key there's nothing to tell a rewritten chain from the real one
+        assert!(verify(&path, Some("secret")).is_err());
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("provenance.jsonl");
+        let content = log(2, Some("secret"));
+
+        fs::write(&path, &content).unwrap();
+        assert_eq!(verify(&path, Some("secret")).unwrap(), 2);
+        assert!(verify(&path, Some("other")).is_err());
+
+        // A rewritten log, chained again but without the key to sign it, doesn't pass
+        let forged = log(2, Some("forger"));
+        fs::write(&path, forged.replace("1.1", "6.6")).unwrap();
+        assert!(verify(&path, Some("secret")).is_err());
+
+        fs::write(&path, content.replacen("1.0", "6.6", 1)).unwrap();
+        assert!(verify(&path, Some("secret")).unwrap_err().to_string().contains("Line 1"));
+    }
+}
@@ -0,0 +1,182 @@
+//! Recording and replaying the HTTP requests, GitHub API calls and external commands an update run makes, so an odd
+//! update decision can be reproduced offline from the recording, e.g. one attached to a bug report.
+//!
+//! Each interaction is a JSON file in the recording directory named after the SHA-256 of its key (`GET <url>`,
+//! `github latest_release <repo> ...`, or the tool and its arguments), holding the key and the response or error.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rootcause::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::error::UpdateError;
+
+static MODE: OnceLock<Recording> = OnceLock::new();
+
+/// What `--record` or `--replay` asked for
+#[derive(Clone, Debug)]
+pub enum Recording {
+    /// Make every interaction for real and save it under this directory, replacing an earlier recording of it
+    Record(PathBuf),
+
+    /// Answer every interaction from this directory; those it doesn't have fail instead of going out
+    Replay(PathBuf),
+}
+
+/// One recorded interaction
+#[derive(Serialize, Deserialize)]
+struct Interaction<T> {
+    key: String,
+    #[serde(flatten)]
+    outcome: Outcome<T>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome<T> {
+    Response(T),
+    Error(UpdateError),
+}
+
+/// Bytes in a recording: text when they're UTF-8, so recordings stay readable, otherwise base64
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Body {
+    Text(String),
+    Base64(String),
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Self::Text(text),
+            Err(e) => Self::Base64(STANDARD.encode(e.as_bytes())),
+        }
+    }
+}
+
+impl From<Body> for Vec<u8> {
+    fn from(body: Body) -> Self {
+        match body {
+            Body::Text(text) => text.into_bytes(),
+            Body::Base64(encoded) => STANDARD.decode(encoded).unwrap_or_default(),
+        }
+    }
+}
+
+impl Recording {
+    /// Record or replay every interaction from now on. Only the first call has any effect.
+    pub fn install(self) {
+        let _ = MODE.set(self);
+    }
+
+    /// Whether interactions are answered from a recording rather than made
+    pub fn replaying() -> bool {
+        matches!(MODE.get(), Some(Self::Replay(_)))
+    }
+
+    fn path(dir: &Path, key: &str) -> PathBuf {
+        dir.join(format!("{:x}.json", Sha256::digest(key.as_bytes())))
+    }
+}
+
+/// The outcome of the interaction `key` names: `live`'s when not replaying (saved when recording), otherwise the
+/// recorded one. Errors are kept as the [`UpdateError`] they amount to.
+pub fn recorded<T>(key: &str, live: impl FnOnce() -> Result<T>) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    match MODE.get() {
+        None => live(),
+        Some(Recording::Replay(dir)) => replay(dir, key),
+        Some(Recording::Record(dir)) => {
+            let result = live();
+
+            let outcome = match &result {
+                Ok(response) => Outcome::Response(response),
+                Err(e) => Outcome::Error(UpdateError::from_report(e)),
+            };
+
+            if let Err(e) = save(dir, key, outcome) {
+                warn!(key, "Failed to record interaction: {e}");
+            }
+
+            result
+        }
+    }
+}
+
+fn replay<T: DeserializeOwned>(dir: &Path, key: &str) -> Result<T> {
+    let path = Recording::path(dir, key);
+
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Err(UpdateError::Other {
+            message: format!("Nothing recorded in {} for `{key}`", dir.display()),
+        }
+        .into());
+    };
+
+    debug!(key, path = %path.display(), "Replaying recorded interaction");
+
+    match serde_json::from_str::<Interaction<T>>(&content)?.outcome {
+        Outcome::Response(response) => Ok(response),
+        Outcome::Error(error) => Err(error.into()),
+    }
+}
+
+fn save<T: Serialize>(dir: &Path, key: &str, outcome: Outcome<&T>) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let interaction = Interaction { key: key.to_string(), outcome };
+
+    fs::write(Recording::path(dir, key), serde_json::to_string_pretty(&interaction)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_binary_bodies_as_base64() {
+        let text = Body::from(b"{\"version\": \"1.0\"}".to_vec());
+        let binary = Body::from(vec![0x1f, 0x8b, 0xff]);
+
+        assert!(matches!(&text, Body::Text(text) if text.contains("1.0")));
+        assert!(matches!(&binary, Body::Base64(_)));
+        assert_eq!(Vec::from(binary), vec![0x1f, 0x8b, 0xff]);
+    }
+
+    #[test]
+    fn replays_what_was_saved() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        save(dir, "GET https://pypi.org/pypi/demo/json", Outcome::Response(&vec!["1.0".to_string()])).unwrap();
+        save(
+            dir,
+            "GET https://crates.io/api/v1/crates/gone",
+            Outcome::<&()>::Error(UpdateError::Http {
+                url: "https://crates.io/api/v1/crates/gone".to_string(),
+                status: Some(404),
+                message: "crates.io returned 404 Not Found".to_string(),
+            }),
+        )
+        .unwrap();
+
+        let versions: Vec<String> = replay(dir, "GET https://pypi.org/pypi/demo/json").unwrap();
+        let error = replay::<()>(dir, "GET https://crates.io/api/v1/crates/gone").unwrap_err();
+
+        assert_eq!(versions, ["1.0"]);
+        assert!(matches!(UpdateError::from_report(&error), UpdateError::Http { status: Some(404), .. }));
+        assert!(replay::<()>(dir, "GET https://example.com").is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::error::UpdateError;
+use crate::nix::closure::Closure;
+use crate::package::{Package, UpdateStatus};
+use crate::provenance::Provenance;
+
+/// What happened to a package during a run, detached from its parsed file so it can be kept, compared or serialized
+#[derive(Clone, Debug, Serialize)]
+pub struct UpdateReport {
+    pub name: String,
+    pub kind: String,
+    pub file: String,
+    pub status: Vec<UpdateStatus>,
+    pub message: Option<String>,
+    /// Why the package failed or was skipped, tagged by kind
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<UpdateError>,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    pub old_git_commit: Option<String>,
+    pub new_git_commit: Option<String>,
+    pub changes: Vec<String>,
+    pub released: Option<DateTime<Utc>>,
+    pub build_seconds: Option<u64>,
+    /// Whether the build succeeded, per system
+    pub systems: BTreeMap<String, bool>,
+    /// Whether each dependent still built against the update
+    pub dependents: BTreeMap<String, bool>,
+    /// The built output's closure size, compared with the previous build's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closure: Option<Closure>,
+    pub provenance: Provenance,
+}
+
+impl UpdateReport {
+    pub fn failed(&self) -> bool {
+        self.status.contains(&UpdateStatus::Failed)
+    }
+
+    pub fn updated(&self) -> bool {
+        self.status.contains(&UpdateStatus::Updated) && !self.failed()
+    }
+}
+
+impl From<&Package> for UpdateReport {
+    fn from(package: &Package) -> Self {
+        let result = &package.result;
+
+        Self {
+            name: package.name.clone(),
+            kind: package.kind.to_string(),
+            file: package.path.display().to_string(),
+            status: result.status.iter().copied().sorted().collect(),
+            message: result.message.clone(),
+            error: result.error.clone(),
+            old_version: result.old_version.clone(),
+            new_version: result.new_version.clone(),
+            old_git_commit: result.old_git_commit.clone(),
+            new_git_commit: result.new_git_commit.clone(),
+            changes: result.changes.clone(),
+            released: result.released,
+            build_seconds: result.build_duration.map(|duration| duration.as_secs()),
+            systems: result.systems.clone(),
+            dependents: result.dependents.clone(),
+            closure: result.closure.clone(),
+            provenance: result.provenance.clone(),
+        }
+    }
+}
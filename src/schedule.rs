@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+/// Order work shortest-expected-first; items with no recorded duration go last, as they may well be the big ones
+pub fn shortest_first<T>(items: &mut [T], expected: impl Fn(&T) -> Option<Duration>) {
+    items.sort_by_cached_key(|item| expected(item).map_or((1, Duration::ZERO), |duration| (0, duration)));
+}
+
+/// Put every item after the items it depends on, otherwise keeping their order. `dependencies` lists each item's
+/// dependencies as indexes into the items; any left in a cycle keep their original order at the end.
+fn dependencies_first(dependencies: &[Vec<usize>]) -> Vec<usize> {
+    let mut order = Vec::with_capacity(dependencies.len());
+    let mut placed = vec![false; dependencies.len()];
+
+    while order.len() < dependencies.len() {
+        let ready = (0..dependencies.len()).find(|&index| !placed[index] && dependencies[index].iter().all(|&dependency| placed[dependency] || dependency == index));
+
+        let Some(index) = ready else {
+            order.extend((0..dependencies.len()).filter(|&index| !placed[index]));
+            break;
+        };
+
+        placed[index] = true;
+        order.push(index);
+    }
+
+    order
+}
+
+struct Queue<T> {
+    pending: VecDeque<(usize, T)>,
+    /// Whether each item succeeded, once it finished
+    finished: Vec<Option<bool>>,
+    running: usize,
+}
+
+/// Marks an item finished when dropped, so a panic in it still frees its slot (as a failure) instead of leaving the
+/// other workers waiting for it forever
+struct Finish<'a, T> {
+    queue: &'a Mutex<Queue<T>>,
+    changed: &'a Condvar,
+    index: usize,
+    succeeded: bool,
+}
+
+impl<T> Drop for Finish<'_, T> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+
+        queue.finished[self.index] = Some(self.succeeded);
+        queue.running -= 1;
+
+        self.changed.notify_all();
+    }
+}
+
+/// Run `f` over `items` on the rayon pool, starting them in order, but each only once the items it depends on
+/// (indexes into `items`, see [`dependencies_first`]) have finished.
+///
+/// Unlike `par_iter`, which splits the input into chunks up front, each worker takes the next ready item from a shared
+/// queue, so an ordering established by [`shortest_first`] is respected. `f` is given the dependencies that failed,
+/// and returns whether the item succeeded.
+pub fn run_in_order<T: Send>(items: Vec<T>, dependencies: &[Vec<usize>], f: impl Fn(T, &[usize]) -> bool + Sync) {
+    let mut items = items.into_iter().map(Some).collect::<Vec<_>>();
+    let dependencies = (0..items.len()).map(|index| dependencies.get(index).cloned().unwrap_or_default()).collect::<Vec<_>>();
+
+    let queue = Mutex::new(Queue {
+        pending: dependencies_first(&dependencies)
+            .into_iter()
+            .filter_map(|index| Some((index, items[index].take()?)))
+            .collect(),
+        finished: vec![None; items.len()],
+        running: 0,
+    });
+    let changed = Condvar::new();
+
+    let workers = rayon::current_num_threads().min(items.len());
+
+    // The lock is released as soon as an item is taken, not held while it runs
+    let next = || {
+        let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+
+        loop {
+            let ready = queue
+                .pending
+                .iter()
+                .position(|(index, _)| dependencies[*index].iter().all(|&dependency| dependency == *index || queue.finished[dependency].is_some()));
+
+            let position = match ready {
+                Some(position) => position,
+                // Only a cycle leaves nothing ready with nothing running; break it rather than wait forever
+                None if queue.running == 0 && !queue.pending.is_empty() => 0,
+                None if queue.pending.is_empty() => return None,
+                None => {
+                    queue = changed.wait(queue).unwrap_or_else(PoisonError::into_inner);
+                    continue;
+                }
+            };
+
+            let (index, item) = queue.pending.remove(position)?;
+            let failed = dependencies[index]
+                .iter()
+                .copied()
+                .filter(|&dependency| queue.finished[dependency] == Some(false))
+                .collect::<Vec<_>>();
+
+            queue.running += 1;
+
+            return Some((index, item, failed));
+        }
+    };
+
+    rayon::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|_| {
+                while let Some((index, item, failed)) = next() {
+                    let mut finish = Finish {
+                        queue: &queue,
+                        changed: &changed,
+                        index,
+                        succeeded: false,
+                    };
+
+                    finish.succeeded = f(item, &failed);
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use std::sync::{Mutex, PoisonError};
+
+    use super::{dependencies_first, run_in_order, shortest_first};
+
+    #[test]
+    fn shortest_first_puts_unknown_durations_last() {
+        let mut builds = [("big", Some(600)), ("new", None), ("small", Some(20)), ("medium", Some(90))];
+
+        shortest_first(&mut builds, |(_, seconds)| seconds.map(Duration::from_secs));
+
+        assert_eq!(builds.map(|(name, _)| name), ["small", "medium", "big", "new"]);
+    }
+
+    #[test]
+    fn dependencies_come_first() {
+        // 0 depends on 2, 2 on 1; 3 and 4 depend on each other
+        let dependencies = [vec![2], vec![], vec![1], vec![4], vec![3]];
+
+        assert_eq!(dependencies_first(&dependencies), [1, 2, 0, 3, 4]);
+    }
+
+    #[test]
+    fn reports_failed_dependencies() {
+        let started = Mutex::new(Vec::new());
+        let blocked = Mutex::new(Vec::new());
+
+        run_in_order(vec!["app", "lib", "tool"], &[vec![1], vec![], vec![]], |name, failed| {
+            started.lock().unwrap().push(name);
+
+            if !failed.is_empty() {
+                blocked.lock().unwrap().push((name, failed.to_vec()));
+            }
+
+            name != "lib"
+        });
+
+        let started = started.into_inner().unwrap();
+
+        assert!(started.iter().position(|name| *name == "lib") < started.iter().position(|name| *name == "app"));
+        assert_eq!(blocked.into_inner().unwrap(), [("app", vec![1])]);
+    }
+
+    #[test]
+    fn a_panicking_item_does_not_leave_the_others_waiting() {
+        let started = Mutex::new(Vec::new());
+
+        // Returning at all is the point: the panicking item's slot is freed, so no worker waits on it forever
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            run_in_order(vec!["app", "lib"], &[vec![1], vec![]], |name, _| {
+                started.lock().unwrap().push(name);
+                assert_ne!(name, "lib", "lib's build panicked");
+                true
+            });
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(started.into_inner().unwrap_or_else(PoisonError::into_inner).first(), Some(&"lib"));
+    }
+}
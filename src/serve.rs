@@ -0,0 +1,245 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rootcause::Result;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use nix_package_updater::{Package, UpdateReport};
+
+static DASHBOARD: OnceLock<Dashboard> = OnceLock::new();
+
+/// How long a client gets to send its request, or take the response, before the next one is served instead
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the `--listen` endpoint serves about the `--watch` rounds, and the on-demand runs it asks for
+pub struct Dashboard {
+    rounds: Mutex<Rounds>,
+    trigger: Sender<()>,
+    triggered: Mutex<Receiver<()>>,
+}
+
+#[derive(Default)]
+struct Rounds {
+    running: bool,
+    started: Option<DateTime<Utc>>,
+    finished: Option<DateTime<Utc>>,
+    next_check: Option<DateTime<Utc>>,
+    error: Option<String>,
+    reports: Vec<UpdateReport>,
+}
+
+/// `GET /status`: how the rounds are going and how the last one's packages fared
+#[derive(Debug, Serialize)]
+struct Status {
+    running: bool,
+    last_started: Option<DateTime<Utc>>,
+    last_finished: Option<DateTime<Utc>>,
+    next_check: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    packages: usize,
+    updated: usize,
+    failed: usize,
+}
+
+impl Dashboard {
+    fn new() -> Self {
+        let (trigger, triggered) = channel();
+
+        Self {
+            rounds: Mutex::new(Rounds::default()),
+            trigger,
+            triggered: Mutex::new(triggered),
+        }
+    }
+
+    /// Serve `/status`, `/report.json` and `POST /trigger` on `addr`, a request at a time, from a background thread
+    pub fn serve(addr: &str) -> Result<&'static Self> {
+        let listener = TcpListener::bind(addr)?;
+        let dashboard = DASHBOARD.get_or_init(Self::new);
+
+        info!("Serving the dashboard endpoint on http://{}", listener.local_addr()?);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                // Requests are answered one at a time, so a client that never finishes one mustn't hold up the rest
+                let stream = stream.and_then(|stream| {
+                    stream.set_read_timeout(Some(TIMEOUT))?;
+                    stream.set_write_timeout(Some(TIMEOUT))?;
+                    Ok(stream)
+                });
+
+                if let Err(e) = stream.map_err(Into::into).and_then(|stream| dashboard.answer(&stream)) {
+                    warn!("Dashboard request failed: {e}");
+                }
+            }
+        });
+
+        Ok(dashboard)
+    }
+
+    /// A round is starting
+    pub fn started(&self) {
+        if let Ok(mut rounds) = self.rounds.lock() {
+            rounds.running = true;
+            rounds.started = Some(Utc::now());
+        }
+    }
+
+    /// A round finished, with the error it failed with, and the next one is due at `next_check`
+    pub fn finished(&self, error: Option<String>, next_check: DateTime<Utc>) {
+        if let Ok(mut rounds) = self.rounds.lock() {
+            rounds.running = false;
+            rounds.finished = Some(Utc::now());
+            rounds.next_check = Some(next_check);
+            rounds.error = error;
+        }
+    }
+
+    /// Wait up to `timeout` for the next round, returning whether a `/trigger` request cut it short
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let Ok(triggered) = self.triggered.lock() else {
+            thread::sleep(timeout);
+            return false;
+        };
+
+        let early = match triggered.recv_timeout(timeout) {
+            Ok(()) => true,
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => false,
+        };
+
+        // Triggers that piled up during the wait are all answered by the one round
+        while triggered.try_recv().is_ok() {}
+
+        early
+    }
+
+    fn answer(&self, mut stream: &TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+
+        reader.read_line(&mut request)?;
+
+        // The headers say nothing these routes need, but have to be read before answering
+        let mut header = String::new();
+
+        while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+            header.clear();
+        }
+
+        let mut parts = request.split_whitespace();
+        let (status, body) = self.respond(parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+        write!(
+            stream,
+            "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            reason(status),
+            body.len()
+        )?;
+
+        Ok(())
+    }
+
+    /// The status code and JSON body for a request
+    fn respond(&self, method: &str, target: &str) -> (u16, String) {
+        let path = target.split('?').next().unwrap_or_default();
+
+        let Ok(rounds) = self.rounds.lock() else {
+            return (500, error_body("dashboard state unavailable"));
+        };
+
+        match (method, path) {
+            ("GET", "/status") => (200, json(&rounds.status())),
+            ("GET", "/report.json") => (200, json(&rounds.reports)),
+            ("POST", "/trigger") => {
+                let _ = self.trigger.send(());
+                (202, json(&serde_json::json!({ "triggered": true, "running": rounds.running })))
+            }
+            (_, "/status" | "/report.json" | "/trigger") => (405, error_body(&format!("{method} not allowed on {path}"))),
+            _ => (404, error_body(&format!("no {path}; try /status, /report.json or POST /trigger"))),
+        }
+    }
+}
+
+impl Rounds {
+    fn status(&self) -> Status {
+        Status {
+            running: self.running,
+            last_started: self.started,
+            last_finished: self.finished,
+            next_check: self.next_check,
+            last_error: self.error.clone(),
+            packages: self.reports.len(),
+            updated: self.reports.iter().filter(|report| report.updated()).count(),
+            failed: self.reports.iter().filter(|report| report.failed()).count(),
+        }
+    }
+}
+
+/// Keep a finished run's packages for `/report.json` and `/status`, when the endpoint is being served
+pub fn publish(packages: &[Package]) {
+    if let Some(dashboard) = DASHBOARD.get()
+        && let Ok(mut rounds) = dashboard.rounds.lock()
+    {
+        rounds.reports = packages.iter().map(UpdateReport::from).collect();
+    }
+}
+
+fn json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).unwrap_or_else(|e| error_body(&e.to_string()))
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Dashboard;
+
+    #[test]
+    fn routes_dashboard_requests() {
+        let dashboard = Dashboard::new();
+
+        let (status, body) = dashboard.respond("GET", "/status");
+        assert_eq!(status, 200);
+        assert!(body.contains("\"running\":false") && body.contains("\"packages\":0"));
+
+        assert_eq!(dashboard.respond("GET", "/report.json"), (200, "[]".to_string()));
+        assert_eq!(dashboard.respond("GET", "/trigger").0, 405);
+        assert_eq!(dashboard.respond("GET", "/nope").0, 404);
+
+        dashboard.started();
+        assert!(dashboard.respond("GET", "/status?pretty").1.contains("\"running\":true"));
+    }
+
+    #[test]
+    fn triggers_cut_the_wait_short() {
+        let dashboard = Dashboard::new();
+
+        assert!(!dashboard.wait(Duration::from_millis(10)));
+
+        assert_eq!(dashboard.respond("POST", "/trigger").0, 202);
+        assert_eq!(dashboard.respond("POST", "/trigger").0, 202);
+
+        assert!(dashboard.wait(Duration::from_secs(5)));
+        assert!(!dashboard.wait(Duration::from_millis(10)));
+    }
+}
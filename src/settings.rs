@@ -0,0 +1,235 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::package::PackageKind;
+
+/// Settings that can be given kind-wide under `[defaults.<kind>]` and overridden per package under `[packages.<name>]`
+///
+/// ```toml
+/// [defaults.cargo]
+/// allow_prereleases = false
+///
+/// [packages.some-tool]
+/// tag_prefix = "cli-v"
+/// branch = "stable-2.x"
+/// push = false
+/// build_timeout = 1800
+/// build_retries = 1
+/// checksums = true
+/// asset_wait = 600
+/// release_fallback = true
+/// inputs = ["crane"]
+///
+/// [packages.rust-tool-from-releases]
+/// kind = "github"
+///
+/// [packages.tag-pinned-tool]
+/// updater = "git"
+///
+/// [packages.some-npm-tool]
+/// dist_tag = "next"
+/// npm_name = "@some-org/tool"
+///
+/// [packages.self-hosted-tool]
+/// update_command = "./scripts/latest-self-hosted-tool.sh"
+///
+/// [packages.unusual-fetcher-tool]
+/// nix_update = true
+/// nix_update_args = ["--version=branch"]
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackageSettings {
+    /// Accept prerelease versions as update targets
+    pub allow_prereleases: Option<bool>,
+
+    /// Prefix of the release tags to follow, stripped to get the version, e.g. `cli-v` for monorepo tags like `cli-v1.2.3`
+    pub tag_prefix: Option<String>,
+
+    /// Branch whose head commit git-tracking packages follow, e.g. `stable-2.x`, instead of the default branch
+    pub branch: Option<String>,
+
+    /// npm dist-tag (`latest`, `next`, `lts`, ...) whose release npm packages follow, instead of the head of their git
+    /// branch
+    pub dist_tag: Option<String>,
+
+    /// Name of the package on the npm registry, when it differs from the pname, e.g. `@scope/cli`
+    pub npm_name: Option<String>,
+
+    /// Path of package-lock.json within the repository, for npm packages whose lockfile isn't at the root or in their
+    /// `sourceRoot`, e.g. `packages/cli/package-lock.json`
+    pub package_lock: Option<String>,
+
+    /// Push successful builds to the binary cache (when caching is enabled for the run)
+    pub push: Option<bool>,
+
+    /// Seconds a `nix build` may run before it's killed and the package marked failed
+    pub build_timeout: Option<u64>,
+
+    /// How many times a failed or timed out build is retried
+    pub build_retries: Option<u32>,
+
+    /// Take release asset hashes from the release's checksum file (`checksums.txt`, `SHA256SUMS`, ...) instead of
+    /// downloading every asset
+    pub checksums: Option<bool>,
+
+    /// Seconds to keep waiting for the assets of a fresh GitHub release that CI is still uploading, before skipping the
+    /// package until the next run
+    pub asset_wait: Option<u64>,
+
+    /// When the latest GitHub release lacks an asset or can't be hashed, update to the newest older release that works
+    pub release_fallback: Option<bool>,
+
+    /// Shell command that resolves the latest release instead of the built-in updater, printing JSON with a
+    /// `version` and optionally `rev`, `url` and `hash`. The package is described in `NIX_UPDATER_*` variables.
+    pub update_command: Option<String>,
+
+    /// Leave version and hash updating to `nix-update`, for sources the built-in updaters don't understand
+    pub nix_update: Option<bool>,
+
+    /// Extra `nix-update` arguments, e.g. `["--version=branch"]`
+    pub nix_update_args: Option<Vec<String>>,
+
+    /// Which updater handles the package, when detection picks the wrong one (per package only)
+    pub kind: Option<PackageKind>,
+
+    /// The registered updater (`pypi`, `github`, `git`, ... or one added through the library) to use when several claim
+    /// the package, whatever their priorities
+    pub updater: Option<String>,
+
+    /// Flake inputs refreshed along with the package under `--update-inputs`, besides those its file refers to as
+    /// `inputs.<name>`
+    pub inputs: Option<Vec<String>>,
+}
+
+impl PackageSettings {
+    /// Layer `overrides` on top of these settings; anything set in `overrides` wins
+    #[must_use]
+    pub fn merge(&self, overrides: &Self) -> Self {
+        Self {
+            allow_prereleases: overrides.allow_prereleases.or(self.allow_prereleases),
+            tag_prefix: overrides.tag_prefix.clone().or_else(|| self.tag_prefix.clone()),
+            branch: overrides.branch.clone().or_else(|| self.branch.clone()),
+            dist_tag: overrides.dist_tag.clone().or_else(|| self.dist_tag.clone()),
+            npm_name: overrides.npm_name.clone().or_else(|| self.npm_name.clone()),
+            package_lock: overrides.package_lock.clone().or_else(|| self.package_lock.clone()),
+            push: overrides.push.or(self.push),
+            build_timeout: overrides.build_timeout.or(self.build_timeout),
+            build_retries: overrides.build_retries.or(self.build_retries),
+            checksums: overrides.checksums.or(self.checksums),
+            asset_wait: overrides.asset_wait.or(self.asset_wait),
+            release_fallback: overrides.release_fallback.or(self.release_fallback),
+            update_command: overrides.update_command.clone().or_else(|| self.update_command.clone()),
+            nix_update: overrides.nix_update.or(self.nix_update),
+            nix_update_args: overrides.nix_update_args.clone().or_else(|| self.nix_update_args.clone()),
+            kind: overrides.kind.or(self.kind),
+            updater: overrides.updater.clone().or_else(|| self.updater.clone()),
+            inputs: overrides.inputs.clone().or_else(|| self.inputs.clone()),
+        }
+    }
+
+    pub fn allow_prereleases(&self) -> bool {
+        self.allow_prereleases.unwrap_or(false)
+    }
+
+    pub fn push(&self) -> bool {
+        self.push.unwrap_or(true)
+    }
+
+    pub fn build_timeout(&self) -> Option<Duration> {
+        self.build_timeout.map(Duration::from_secs)
+    }
+
+    pub fn build_retries(&self) -> u32 {
+        self.build_retries.unwrap_or(0)
+    }
+
+    pub fn checksums(&self) -> bool {
+        self.checksums.unwrap_or(false)
+    }
+
+    pub fn asset_wait(&self) -> Duration {
+        Duration::from_secs(self.asset_wait.unwrap_or(0))
+    }
+
+    pub fn release_fallback(&self) -> bool {
+        self.release_fallback.unwrap_or(false)
+    }
+
+    pub fn nix_update(&self) -> bool {
+        self.nix_update.unwrap_or(false)
+    }
+
+    /// Name to look an npm package up by on the registry
+    pub fn npm_name<'a>(&'a self, pname: &'a str) -> &'a str {
+        self.npm_name.as_deref().unwrap_or(pname)
+    }
+
+    /// Strip the configured tag prefix from a release tag, if it has it
+    pub fn strip_tag_prefix<'a>(&self, tag: &'a str) -> &'a str {
+        self.tag_prefix.as_deref().and_then(|prefix| tag.strip_prefix(prefix)).unwrap_or(tag)
+    }
+}
+
+/// A named set of packages, `[groups.<name>]` in config.toml, selected with `--group <name>`
+///
+/// ```toml
+/// [groups.daily]
+/// packages = ["ripgrep", "fd", "jujutsu"]
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Group {
+    /// Package names, matched like those given on the command line
+    pub packages: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PackageSettings;
+
+    #[test]
+    fn package_settings_override_kind_defaults() {
+        let defaults = PackageSettings {
+            allow_prereleases: Some(true),
+            tag_prefix: Some("v".to_string()),
+            branch: Some("stable-2.x".to_string()),
+            dist_tag: None,
+            npm_name: None,
+            package_lock: None,
+            push: None,
+            build_timeout: Some(600),
+            build_retries: None,
+            checksums: Some(true),
+            asset_wait: None,
+            release_fallback: None,
+            update_command: None,
+            nix_update: Some(true),
+            nix_update_args: None,
+            kind: None,
+            updater: None,
+            inputs: Some(vec!["crane".to_string()]),
+        };
+
+        let overrides = PackageSettings {
+            tag_prefix: Some("cli-v".to_string()),
+            push: Some(false),
+            build_retries: Some(2),
+            ..PackageSettings::default()
+        };
+
+        let merged = defaults.merge(&overrides);
+
+        assert!(merged.allow_prereleases());
+        assert_eq!(merged.tag_prefix.as_deref(), Some("cli-v"));
+        assert!(!merged.push());
+        assert_eq!(merged.strip_tag_prefix("cli-v1.2.3"), "1.2.3");
+        assert_eq!(merged.branch.as_deref(), Some("stable-2.x"));
+        assert_eq!(merged.build_timeout(), Some(std::time::Duration::from_secs(600)));
+        assert_eq!(merged.build_retries(), 2);
+        assert!(merged.checksums());
+        assert!(merged.nix_update());
+        assert_eq!(merged.inputs, Some(vec!["crane".to_string()]));
+    }
+}
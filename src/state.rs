@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use rootcause::Result;
+use serde::{Deserialize, Serialize};
+
+use nix_package_updater::package::{Package, UpdateStatus};
+
+/// What happened to a package the last time it was processed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackageState {
+    pub checked_at: DateTime<Utc>,
+    pub updated_to: Option<String>,
+    pub built: bool,
+    pub failed: bool,
+
+    /// How long the most recent build took, kept across runs that didn't build
+    #[serde(default)]
+    pub build_seconds: Option<u64>,
+
+    /// The most recent build's output and its closure size, kept across runs that didn't build
+    #[serde(default)]
+    pub out_path: Option<String>,
+    #[serde(default)]
+    pub closure_size: Option<u64>,
+}
+
+/// Per-package results persisted between runs, so an interrupted run can pick up where it stopped
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RunState {
+    #[serde(skip)]
+    path: PathBuf,
+
+    packages: BTreeMap<String, PackageState>,
+}
+
+impl RunState {
+    /// Load the state file, starting empty if it doesn't exist or can't be parsed
+    pub fn load(path: &Path) -> Self {
+        let mut state = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Self>(&content).ok())
+            .unwrap_or_default();
+
+        state.path = path.to_path_buf();
+        state
+    }
+
+    /// The file and the flake attribute, so packages sharing a file each keep their own state
+    fn key(package: &Package) -> String {
+        let path = package.path.canonicalize().unwrap_or_else(|_| package.path.clone());
+
+        format!("{}#{}", path.display(), package.flake_attr())
+    }
+
+    /// Whether the package was successfully processed within the last `hours`
+    pub fn recently_processed(&self, package: &Package, hours: u64, now: DateTime<Utc>) -> bool {
+        let window = TimeDelta::hours(i64::try_from(hours).unwrap_or(i64::MAX));
+
+        self.packages.get(&Self::key(package)).is_some_and(|state| !state.failed && now - state.checked_at < window)
+    }
+
+    /// How long the package's last recorded build took
+    pub fn expected_build_time(&self, package: &Package) -> Option<Duration> {
+        self.packages.get(&Self::key(package))?.build_seconds.map(Duration::from_secs)
+    }
+
+    /// The output and closure size of the package's last recorded build
+    pub fn previous_closure(&self, package: &Package) -> Option<(String, u64)> {
+        let state = self.packages.get(&Self::key(package))?;
+
+        Some((state.out_path.clone()?, state.closure_size?))
+    }
+
+    pub fn record(&mut self, package: &Package) {
+        let status = &package.result.status;
+        let key = Self::key(package);
+
+        let build_seconds = match package.result.build_duration {
+            Some(duration) => Some(duration.as_secs()),
+            None => self.packages.get(&key).and_then(|state| state.build_seconds),
+        };
+
+        let (out_path, closure_size) = match &package.result.closure {
+            Some(closure) => (Some(closure.path.clone()), Some(closure.size)),
+            None => self.packages.get(&key).map_or((None, None), |state| (state.out_path.clone(), state.closure_size)),
+        };
+
+        self.packages.insert(
+            key,
+            PackageState {
+                checked_at: Utc::now(),
+                updated_to: package.result.new_version.clone().or_else(|| package.result.new_git_commit.clone()),
+                built: status.contains(&UpdateStatus::Built),
+                failed: status.contains(&UpdateStatus::Failed),
+                build_seconds,
+                out_path,
+                closure_size,
+            },
+        );
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+}
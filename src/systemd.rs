@@ -0,0 +1,139 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rootcause::Result;
+
+/// What goes into the generated units
+pub struct UnitOptions {
+    pub executable: PathBuf,
+    pub working_directory: PathBuf,
+    /// `OnCalendar=` expression, e.g. `daily` or `*-*-* 04:00`
+    pub calendar: String,
+    pub args: Vec<String>,
+    /// Variables the service runs with: `PATH`, so `nix`/`git`/`nurl` resolve as they do here, plus any `NIX_UPDATER_*`
+    pub environment: Vec<(String, String)>,
+    /// File with secrets such as `GITHUB_TOKEN`, kept out of the unit itself
+    pub environment_file: Option<PathBuf>,
+}
+
+impl UnitOptions {
+    /// Units running this executable in the current directory with the current `PATH` and `NIX_UPDATER_*` variables
+    pub fn current(calendar: String, args: Vec<String>, environment_file: Option<PathBuf>) -> Result<Self> {
+        let environment = env::vars()
+            .filter(|(name, _)| name == "PATH" || name == "NIX_PATH" || name.starts_with("NIX_UPDATER_"))
+            .collect();
+
+        Ok(Self {
+            executable: env::current_exe()?,
+            working_directory: env::current_dir()?,
+            calendar,
+            args,
+            environment,
+            environment_file,
+        })
+    }
+
+    pub fn service(&self) -> String {
+        let mut unit = String::from(
+            "[Unit]\n\
+             Description=Update Nix packages\n\
+             After=network-online.target\n\
+             Wants=network-online.target\n\n\
+             [Service]\n\
+             Type=oneshot\n",
+        );
+
+        // Unlike ExecStart= and Environment=, WorkingDirectory= takes the rest of the line as is
+        let _ = writeln!(unit, "WorkingDirectory={}", self.working_directory.display().to_string().replace('%', "%%"));
+
+        for (name, value) in &self.environment {
+            let _ = writeln!(unit, "Environment={}", quote(&format!("{name}={value}")));
+        }
+
+        if let Some(file) = &self.environment_file {
+            let _ = writeln!(unit, "EnvironmentFile={}", file.display());
+        }
+
+        let command = std::iter::once(self.executable.display().to_string())
+            .chain(self.args.iter().cloned())
+            .map(|arg| quote(&arg))
+            .collect::<Vec<_>>();
+
+        let _ = writeln!(unit, "ExecStart={}", command.join(" "));
+
+        unit
+    }
+
+    pub fn timer(&self) -> String {
+        format!(
+            "[Unit]\n\
+             Description=Update Nix packages on a schedule\n\n\
+             [Timer]\n\
+             OnCalendar={}\n\
+             RandomizedDelaySec=1h\n\
+             Persistent=true\n\n\
+             [Install]\n\
+             WantedBy=timers.target\n",
+            self.calendar
+        )
+    }
+}
+
+/// Quote a value for a unit file when it needs it, and escape `%` specifiers
+fn quote(value: &str) -> String {
+    let escaped = value.replace('%', "%%");
+
+    if escaped.chars().any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';')) {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+/// Print the service and timer units, or write them as `nix-package-updater.{service,timer}` into `output`
+pub fn generate_systemd(options: &UnitOptions, output: Option<&Path>) -> Result<()> {
+    let Some(output) = output else {
+        println!("# nix-package-updater.service\n{}\n# nix-package-updater.timer\n{}", options.service(), options.timer());
+        return Ok(());
+    };
+
+    fs::create_dir_all(output)?;
+
+    for (file, content) in [("nix-package-updater.service", options.service()), ("nix-package-updater.timer", options.timer())] {
+        let path = output.join(file);
+
+        fs::write(&path, content)?;
+        println!("Wrote {}", path.display());
+    }
+
+    println!("Enable with: systemctl --user daemon-reload && systemctl --user enable --now nix-package-updater.timer");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_units() {
+        let options = UnitOptions {
+            executable: PathBuf::from("/nix/store/abc-nix-package-updater/bin/nix-package-updater"),
+            working_directory: PathBuf::from("/home/me/my flake"),
+            calendar: "daily".to_string(),
+            args: vec!["update".to_string(), "--exclude".to_string(), "100%".to_string()],
+            environment: vec![("PATH".to_string(), "/run/current-system/sw/bin".to_string())],
+            environment_file: Some(PathBuf::from("/etc/nix-updater.env")),
+        };
+
+        let service = options.service();
+
+        assert!(service.contains("WorkingDirectory=/home/me/my flake\n"));
+        assert!(service.contains("Environment=PATH=/run/current-system/sw/bin\n"));
+        assert!(service.contains("EnvironmentFile=/etc/nix-updater.env\n"));
+        assert!(service.contains("ExecStart=/nix/store/abc-nix-package-updater/bin/nix-package-updater update --exclude 100%%\n"));
+        assert!(options.timer().contains("OnCalendar=daily\n"));
+    }
+}
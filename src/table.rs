@@ -0,0 +1,279 @@
+use clap::ValueEnum;
+use colored::{Color, ColoredString, Colorize};
+use serde::{Deserialize, Serialize};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// A column of the run summary, chosen with `--columns` or `columns` in config.toml
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Column {
+    Package,
+    Source,
+    Updated,
+    Built,
+    /// One Built column per system in `[build] systems`
+    Systems,
+    Checked,
+    /// Shown by default with `--check-reproducibility`
+    Reproducible,
+    Cached,
+    Details,
+}
+
+impl Column {
+    /// The columns shown unless configured otherwise
+    pub fn defaults(reproducible: bool) -> Vec<Self> {
+        [Self::Package, Self::Source, Self::Updated, Self::Built, Self::Systems, Self::Checked]
+            .into_iter()
+            .chain(reproducible.then_some(Self::Reproducible))
+            .chain([Self::Cached, Self::Details])
+            .collect()
+    }
+}
+
+/// Table cell text, measured before it's styled so escape sequences never count towards its width
+#[derive(Clone, Debug, Default)]
+pub struct Cell {
+    text: String,
+    color: Option<Color>,
+    link: Option<String>,
+}
+
+impl Cell {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Hyperlink the text (OSC 8) when output is colored
+    pub fn link(mut self, url: impl Into<String>) -> Self {
+        self.link = Some(url.into());
+        self
+    }
+
+    /// The cell's text cut down to `width`, styled
+    fn render(&self, width: usize) -> String {
+        let text = truncate(&self.text, width);
+
+        let styled = match self.color {
+            Some(color) => text.color(color).to_string(),
+            None => text.clone(),
+        };
+
+        match &self.link {
+            Some(url) if colored::control::SHOULD_COLORIZE.should_colorize() => format!("\x1B]8;;{url}\x1B\\{styled}\x1B]8;;\x1B\\"),
+            _ => styled,
+        }
+    }
+}
+
+impl From<ColoredString> for Cell {
+    fn from(colored: ColoredString) -> Self {
+        Self {
+            color: colored.fgcolor,
+            text: colored.input,
+            link: None,
+        }
+    }
+}
+
+/// A table whose last column wraps to the terminal width, one line per `\n`-separated part
+pub struct Table {
+    headers: Vec<(String, Option<usize>)>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    /// Columns titled `headers`, each with an optional maximum width past which its cells are truncated
+    pub fn new(headers: Vec<(String, Option<usize>)>) -> Self {
+        Self { headers, rows: Vec::new() }
+    }
+
+    pub fn row(&mut self, cells: Vec<Cell>) {
+        self.rows.push(cells);
+    }
+
+    /// Render the table to fit `max_width` columns, when known
+    pub fn render(&self, max_width: Option<usize>) -> String {
+        let Some(last) = self.headers.len().checked_sub(1) else {
+            return String::new();
+        };
+
+        // Columns are at least 8 wide, as wide as their widest cell, and at most their maximum
+        let widths = self
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(index, (title, max))| {
+                let widest = self
+                    .rows
+                    .iter()
+                    .filter_map(|row| row.get(index))
+                    .flat_map(|cell| cell.text.lines())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0);
+
+                let width = widest.max(title.width()).max(8);
+
+                max.map_or(width, |max| width.min(max))
+            })
+            .collect::<Vec<_>>();
+
+        let indent = widths[..last].iter().map(|width| width + 1).sum::<usize>();
+        let wrap_at = max_width.map(|max| max.saturating_sub(indent).max(20));
+
+        let mut out = String::new();
+
+        let header = self
+            .headers
+            .iter()
+            .zip(&widths)
+            .map(|((title, _), width)| pad(&title.bright_white().bold().to_string(), title.width(), *width))
+            .collect::<Vec<_>>();
+
+        out.push_str(header.join(" ").trim_end());
+        out.push('\n');
+        out.push_str(&"-".repeat(indent + wrap_at.unwrap_or(widths[last]).min(widths[last])));
+        out.push('\n');
+
+        for row in &self.rows {
+            let leading = (0..last)
+                .map(|index| {
+                    let cell = row.get(index).cloned().unwrap_or_default();
+                    let rendered = cell.render(widths[index]);
+
+                    pad(&rendered, truncate(&cell.text, widths[index]).width(), widths[index])
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            let last_cell = row.get(last).cloned().unwrap_or_default();
+
+            let lines = last_cell
+                .text
+                .lines()
+                .flat_map(|line| match wrap_at {
+                    Some(width) => wrap(line, width),
+                    None => vec![line.to_string()],
+                })
+                .collect::<Vec<_>>();
+
+            let mut lines = lines.into_iter();
+
+            let first = lines.next().map(|line| Cell { text: line, ..last_cell.clone() }.render(usize::MAX)).unwrap_or_default();
+
+            out.push_str(format!("{leading} {first}").trim_end());
+            out.push('\n');
+
+            for line in lines {
+                out.push_str(&" ".repeat(indent));
+                out.push_str(&Cell { text: line, ..last_cell.clone() }.render(usize::MAX));
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+}
+
+/// Pad already-styled text whose visible width is `visible` out to `width`
+fn pad(styled: &str, visible: usize, width: usize) -> String {
+    format!("{styled}{}", " ".repeat(width.saturating_sub(visible)))
+}
+
+/// Cut `text` to at most `width` columns, ending it with `…` when anything was cut
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+
+        if used + char_width + 1 > width {
+            break;
+        }
+
+        truncated.push(c);
+        used += char_width;
+    }
+
+    truncated.push('…');
+    truncated
+}
+
+/// Break `line` at spaces into lines of at most `width` columns; words longer than that are truncated
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for word in line.split(' ').filter(|word| !word.is_empty()) {
+        if !current.is_empty() && current.width() + 1 + word.width() > width {
+            lines.push(std::mem::take(&mut current));
+        }
+
+        if !current.is_empty() {
+            current.push(' ');
+        }
+
+        current.push_str(&truncate(word, width));
+    }
+
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` without SGR color codes, whether or not another test turned colors on
+    fn plain(text: &str) -> String {
+        let mut plain = String::new();
+        let mut chars = text.chars();
+
+        while let Some(c) = chars.next() {
+            if c == '\x1B' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                plain.push(c);
+            }
+        }
+
+        plain
+    }
+
+    #[test]
+    fn measures_wide_characters() {
+        let mut table = Table::new(vec![("Package".to_string(), Some(10)), ("Details".to_string(), None)]);
+
+        table.row(vec![Cell::new("漢字ツール"), Cell::new("1.0 → 1.1")]);
+        table.row(vec![Cell::new("a-very-long-package-name"), Cell::new("first part of the details\nsecond")]);
+
+        let rendered = plain(&table.render(Some(24)));
+        let lines = rendered.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "Package    Details");
+        assert_eq!(lines[2], "漢字ツール 1.0 → 1.1");
+        assert_eq!(lines[3], "a-very-lo… first part of the");
+        assert_eq!(lines[4], "           details");
+        assert_eq!(lines[5], "           second");
+    }
+
+    #[test]
+    fn truncates_and_wraps() {
+        assert_eq!(truncate("ripgrep", 7), "ripgrep");
+        assert_eq!(truncate("ripgrep-all", 7), "ripgre…");
+        assert_eq!(truncate("漢字漢字", 5), "漢字…");
+        assert_eq!(wrap("one two three", 7), ["one two", "three"]);
+    }
+}
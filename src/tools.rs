@@ -0,0 +1,300 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::iter;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::OnceLock;
+
+use itertools::Itertools;
+use rootcause::{Result, bail};
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display, EnumIter, IntoStaticStr};
+
+use crate::error::UpdateError;
+use crate::recording::{Body, Recording, recorded};
+
+static TOOLS: OnceLock<Tools> = OnceLock::new();
+
+/// External programs the updater shells out to
+#[derive(Clone, Copy, Debug, AsRefStr, Display, EnumIter, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum Tool {
+    Nix,
+    Nurl,
+    Cachix,
+    #[strum(serialize = "prefetch-npm-deps")]
+    PrefetchNpmDeps,
+    Git,
+    Jj,
+    #[strum(serialize = "nix-prefetch-git")]
+    NixPrefetchGit,
+    Hg,
+    #[strum(serialize = "nix-prefetch-hg")]
+    NixPrefetchHg,
+    #[strum(serialize = "nix-update")]
+    NixUpdate,
+    Gh,
+}
+
+impl Tool {
+    /// What the updater uses the tool for
+    pub fn purpose(self) -> &'static str {
+        match self {
+            Self::Nix => "builds packages and prefetches sources",
+            Self::Nurl => "hashes sources the built-in fetchers don't cover, and `prefetch`",
+            Self::Cachix => "pushes builds to a cachix cache",
+            Self::PrefetchNpmDeps => "computes npmDepsHash without a build",
+            Self::Git => "finds the latest commit of git sources",
+            Self::Jj => "commits and pushes `--branch` runs in Jujutsu repositories",
+            Self::NixPrefetchGit => "hashes git checkouts (submodules, non-GitHub hosts)",
+            Self::Hg => "finds the latest changeset of Mercurial sources not served by hgweb",
+            Self::NixPrefetchHg => "hashes Mercurial checkouts",
+            Self::NixUpdate => "updates packages with `nix_update = true`",
+            Self::Gh => "lends its GitHub login when no token is configured",
+        }
+    }
+
+    /// The nixpkgs attribute providing it
+    pub fn package(self) -> &'static str {
+        match self {
+            Self::Hg => "mercurial",
+            Self::Jj => "jujutsu",
+            _ => self.into(),
+        }
+    }
+
+    /// Whether nothing works without it
+    pub fn required(self) -> bool {
+        matches!(self, Self::Nix)
+    }
+}
+
+/// Where to find external tools, for schedulers and containers where they aren't on the default PATH
+///
+/// ```toml
+/// [tools]
+/// nix = "/nix/var/nix/profiles/default/bin/nix"
+/// path = ["/home/me/.nix-profile/bin"]
+/// devshell = ".#ci"
+/// build_args = ["--max-jobs", "4", "--option", "sandbox", "relaxed"]
+/// formatter = "nixfmt"
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tools {
+    pub nix: Option<PathBuf>,
+    pub nurl: Option<PathBuf>,
+    pub cachix: Option<PathBuf>,
+    pub prefetch_npm_deps: Option<PathBuf>,
+    pub git: Option<PathBuf>,
+    pub jj: Option<PathBuf>,
+    pub nix_prefetch_git: Option<PathBuf>,
+    pub hg: Option<PathBuf>,
+    pub nix_prefetch_hg: Option<PathBuf>,
+    pub nix_update: Option<PathBuf>,
+    pub gh: Option<PathBuf>,
+
+    /// Extra directories prepended to PATH for every external command
+    pub path: Vec<PathBuf>,
+
+    /// Run `nix build` inside `nix develop <devshell> --command`
+    pub devshell: Option<String>,
+
+    /// Extra arguments for every `nix build`, e.g. `--builders` or `--system aarch64-linux`
+    pub build_args: Vec<String>,
+
+    /// Shell command run on each `.nix` file the updater rewrites, with the file's path appended (e.g. `nixfmt` or
+    /// `alejandra --quiet`)
+    pub formatter: Option<String>,
+}
+
+impl Tools {
+    /// Make this the configuration used by [`command`] and [`nix_build`]. Only the first call has any effect.
+    pub fn install(self) {
+        let _ = TOOLS.set(self);
+    }
+
+    fn program(&self, tool: Tool) -> OsString {
+        let configured = match tool {
+            Tool::Nix => &self.nix,
+            Tool::Nurl => &self.nurl,
+            Tool::Cachix => &self.cachix,
+            Tool::PrefetchNpmDeps => &self.prefetch_npm_deps,
+            Tool::Git => &self.git,
+            Tool::Jj => &self.jj,
+            Tool::NixPrefetchGit => &self.nix_prefetch_git,
+            Tool::Hg => &self.hg,
+            Tool::NixPrefetchHg => &self.nix_prefetch_hg,
+            Tool::NixUpdate => &self.nix_update,
+            Tool::Gh => &self.gh,
+        };
+
+        configured.as_ref().map_or_else(|| tool.as_ref().into(), |path| path.clone().into_os_string())
+    }
+
+    fn search_path(&self) -> Option<OsString> {
+        if self.path.is_empty() {
+            return None;
+        }
+
+        let inherited = env::var_os("PATH").map(|path| env::split_paths(&path).collect::<Vec<_>>()).unwrap_or_default();
+
+        env::join_paths(self.path.iter().cloned().chain(inherited)).ok()
+    }
+}
+
+fn tools() -> &'static Tools {
+    TOOLS.get_or_init(Tools::default)
+}
+
+/// A `Command` for an external tool, honoring the configured binary path and extra PATH entries
+pub fn command(tool: Tool) -> Command {
+    let tools = tools();
+    let mut command = Command::new(tools.program(tool));
+
+    if let Some(path) = tools.search_path() {
+        command.env("PATH", path);
+    }
+
+    command
+}
+
+/// Where `tool` would be run from: its configured path if that exists, or the first match on the search path
+pub fn locate(tool: Tool) -> Option<PathBuf> {
+    let tools = tools();
+    let program = PathBuf::from(tools.program(tool));
+
+    if program.components().count() > 1 {
+        return program.is_file().then_some(program);
+    }
+
+    let path = tools.search_path().or_else(|| env::var_os("PATH")).unwrap_or_default();
+
+    env::split_paths(&path).map(|dir| dir.join(&program)).find(|candidate| candidate.is_file())
+}
+
+/// Whether `tool` can be run, see [`locate`]
+pub fn installed(tool: Tool) -> bool {
+    locate(tool).is_some()
+}
+
+/// An [`Output`] as `--record` keeps it
+#[derive(Serialize, Deserialize)]
+struct RecordedOutput {
+    /// The exit code, -1 when a signal ended the command
+    status: i32,
+    stdout: Body,
+    stderr: Body,
+}
+
+impl From<Output> for RecordedOutput {
+    fn from(output: Output) -> Self {
+        Self {
+            status: output.status.code().unwrap_or(-1),
+            stdout: output.stdout.into(),
+            stderr: output.stderr.into(),
+        }
+    }
+}
+
+impl From<RecordedOutput> for Output {
+    fn from(recorded: RecordedOutput) -> Self {
+        Self {
+            status: ExitStatus::from_raw((recorded.status & 0xff) << 8),
+            stdout: recorded.stdout.into(),
+            stderr: recorded.stderr.into(),
+        }
+    }
+}
+
+/// Run `command`, which runs `tool`, explaining how to provide the tool if it isn't installed. Recorded and replayed
+/// as the tool and its arguments, wherever the tool is installed.
+pub fn run(command: &mut Command, tool: Tool) -> Result<Output> {
+    let key = iter::once(tool.to_string())
+        .chain(command.get_args().map(|arg| arg.to_string_lossy().into_owned()))
+        .join(" ");
+
+    recorded(&key, || spawn(command, tool).map(RecordedOutput::from)).map(Output::from)
+}
+
+/// Run `command` like [`run`], for output that's a secret: it is never saved to a recording, and when replaying the
+/// command isn't run, failing like an interaction the recording doesn't have.
+pub fn run_secret(command: &mut Command, tool: Tool) -> Result<Output> {
+    if Recording::replaying() {
+        bail!("{tool} isn't run when replaying a recording");
+    }
+
+    spawn(command, tool)
+}
+
+fn spawn(command: &mut Command, tool: Tool) -> Result<Output> {
+    match command.output() {
+        Ok(output) => Ok(output),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(UpdateError::MissingTool {
+            tool: tool.to_string(),
+            hint: format!(
+                "add it to PATH (e.g. `nix profile install nixpkgs#{}`) or set `{}` under [tools]",
+                tool.package(),
+                tool.as_ref().replace('-', "_")
+            ),
+        }
+        .into()),
+        Err(e) => bail!("Failed to run {tool}: {e}"),
+    }
+}
+
+/// Run the configured formatter on a `.nix` file the updater just wrote, if there is one. The file must still parse
+/// afterwards; if the formatter fails or breaks it, the unformatted content is put back.
+pub fn format_nix(path: &Path) -> Result<()> {
+    let Some(formatter) = &tools().formatter else {
+        return Ok(());
+    };
+
+    let written = fs::read_to_string(path)?;
+
+    // Nothing to gain from formatting a file the formatter would reject anyway
+    if !rnix::Root::parse(&written).errors().is_empty() {
+        bail!("{} doesn't parse, so it wasn't formatted", path.display());
+    }
+
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(format!("{formatter} \"$1\"")).arg("sh").arg(path);
+
+    if let Some(search_path) = tools().search_path() {
+        shell.env("PATH", search_path);
+    }
+
+    let output = shell.output()?;
+
+    if !output.status.success() {
+        fs::write(path, &written)?;
+        bail!(
+            "{formatter} failed on {} ({}): {}",
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    if !rnix::Root::parse(&fs::read_to_string(path)?).errors().is_empty() {
+        fs::write(path, &written)?;
+        bail!("{formatter} left {} unparseable; kept it unformatted", path.display());
+    }
+
+    Ok(())
+}
+
+/// `nix build` with the given arguments, wrapped in `nix develop` when a devshell is configured
+pub fn nix_build(args: &[&str]) -> Command {
+    let mut command = command(Tool::Nix);
+
+    if let Some(devshell) = &tools().devshell {
+        command.args(["develop", devshell, "--command"]).arg(tools().program(Tool::Nix));
+    }
+
+    command.arg("build").args(args).args(&tools().build_args);
+    command
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rootcause::Result;
+use tracing::warn;
+
+use crate::backup::Backup;
+use crate::tools::format_nix;
+
+/// Staged file edits for a single package update.
+///
+/// Edits are held in memory until [`Transaction::commit`]. Files that must hit the disk early (e.g. so an
+/// intermediate `nix build` sees them) go through [`Transaction::write_through`], which remembers the original
+/// content so [`Transaction::rollback`] can restore it.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    staged: BTreeMap<PathBuf, String>,
+    originals: BTreeMap<PathBuf, Option<String>>,
+
+    /// Where the original content of committed files is saved for `revert`
+    pub backup: Option<Backup>,
+}
+
+impl Transaction {
+    /// Stage new content for a file, replacing anything staged for it earlier
+    pub fn stage(&mut self, path: &Path, content: impl Into<String>) {
+        self.staged.insert(path.to_path_buf(), content.into());
+    }
+
+    /// Write a file to disk before the transaction commits, keeping the original for rollback
+    pub fn write_through(&mut self, path: &Path, content: &str) -> Result<()> {
+        self.remember(path);
+        fs::write(path, content)?;
+
+        Ok(())
+    }
+
+    /// Write all staged edits to disk, running the configured formatter on `.nix` files, then let `check` judge the
+    /// result. If any write or the check fails, every file touched so far is restored; a formatter that fails only
+    /// leaves its file unformatted.
+    pub fn commit(&mut self, check: impl FnOnce() -> Result<()>) -> Result<()> {
+        let staged = std::mem::take(&mut self.staged);
+
+        for path in staged.keys() {
+            self.remember(path);
+        }
+
+        // Files written through are already on disk, so a backup that can't be saved undoes them like a failed write
+        if let Some(backup) = &self.backup
+            && let Err(e) = backup.save(&self.originals)
+        {
+            self.abandon();
+            return Err(e);
+        }
+
+        for (path, content) in staged {
+            if let Err(e) = fs::write(&path, content) {
+                self.abandon();
+                return Err(e.into());
+            }
+
+            if path.extension().is_some_and(|extension| extension == "nix")
+                && let Err(e) = format_nix(&path)
+            {
+                warn!(path = %path.display(), "{e}");
+            }
+        }
+
+        if let Err(e) = check() {
+            self.abandon();
+            return Err(e);
+        }
+
+        self.originals.clear();
+
+        Ok(())
+    }
+
+    /// Discard staged edits and restore every file written through to its original content
+    pub fn rollback(&mut self) {
+        self.staged.clear();
+
+        for (path, original) in std::mem::take(&mut self.originals) {
+            let result = match original {
+                Some(content) => fs::write(&path, content),
+                None => fs::remove_file(&path),
+            };
+
+            if let Err(e) = result {
+                warn!(path = %path.display(), "Failed to restore file: {e}");
+            }
+        }
+    }
+
+    /// Roll back a failed commit, whose backup is then of no use
+    fn abandon(&mut self) {
+        self.rollback();
+
+        if let Some(backup) = &self.backup {
+            backup.discard();
+        }
+    }
+
+    fn remember(&mut self, path: &Path) {
+        self.originals.entry(path.to_path_buf()).or_insert_with(|| fs::read_to_string(path).ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use rootcause::bail;
+    use tempfile::TempDir;
+
+    use super::Transaction;
+    use crate::backup::RunBackup;
+
+    /// A file holding `content`, in a directory removed when the returned `TempDir` is dropped
+    fn temp_file(name: &str, content: &str) -> (PathBuf, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+
+        fs::write(&path, content).unwrap();
+
+        (path, dir)
+    }
+
+    #[test]
+    fn staged_edits_only_land_on_commit() {
+        let (path, _dir) = temp_file("commit.nix", "old");
+        let mut transaction = Transaction::default();
+
+        transaction.stage(&path, "new");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        transaction.commit(|| Ok(())).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+
+    #[test]
+    fn failed_check_restores_committed_files() {
+        let (path, _dir) = temp_file("checked.nix", "old");
+        let mut transaction = Transaction::default();
+
+        transaction.stage(&path, "new");
+        assert!(transaction.commit(|| bail!("does not evaluate")).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+    }
+
+    #[test]
+    fn rollback_restores_written_through_files() {
+        let (path, _dir) = temp_file("rollback.nix", "old");
+        let created = path.with_extension("lock");
+        let mut transaction = Transaction::default();
+
+        transaction.write_through(&path, "intermediate").unwrap();
+        transaction.write_through(&created, "lock").unwrap();
+        transaction.stage(&path, "new");
+        transaction.rollback();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert!(!created.exists());
+    }
+
+    #[test]
+    fn failed_backup_restores_written_through_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("package-lock.json");
+        let nix = dir.path().join("default.nix");
+
+        // A file where the backups' directory should be, so saving one fails
+        let runs = dir.path().join("runs");
+        fs::write(&runs, "").unwrap();
+        fs::write(&path, "old").unwrap();
+        fs::write(&nix, "old").unwrap();
+
+        let mut transaction = Transaction {
+            backup: Some(RunBackup::start(&runs).package("tool", &nix)),
+            ..Transaction::default()
+        };
+
+        transaction.write_through(&path, "intermediate").unwrap();
+        transaction.stage(&nix, "new");
+
+        assert!(transaction.commit(|| Ok(())).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&nix).unwrap(), "old");
+    }
+}
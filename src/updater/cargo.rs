@@ -1,35 +1,200 @@
-use indicatif::ProgressBar;
-use rootcause::Result;
+use std::collections::BTreeMap;
+use std::fs;
 
-use crate::Config;
-use crate::clients::nix::Nix;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use rootcause::{Result, bail};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::clients::crates::newest_version;
+use crate::clients::nix::{FetchFlags, Nix};
 use crate::clients::{CratesIoClient, GitHubClient};
-use crate::nix::ast::Ast;
-use crate::package::Package;
-use crate::updater::{Updater, normalize_version, short_hash, version_is_greater};
+use crate::error::UpdateError;
+use crate::nix::ast::{Ast, Value};
+use crate::nix::fod::FixedOutput;
+use crate::package::{Package, PackageKind, UpdateStatus};
+use crate::progress::Progress;
+use crate::tools::Tool;
+use crate::updater::{Handles, UpdateOptions, Updater, Upstream, normalize_version, redated_version, repository_matches, short_hash, version_is_greater};
 
 pub struct Cargo {
     force: bool,
+    migrate_vendor: bool,
     github_client: GitHubClient,
     crates_client: CratesIoClient,
 }
 
+#[derive(Debug, Deserialize)]
+struct CargoLock {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+impl LockedPackage {
+    /// The `outputHashes` key importCargoLock looks this package up by
+    fn key(&self) -> String {
+        format!("{}-{}", self.name, self.version)
+    }
+
+    /// Repository URL and commit of a git dependency, from e.g. `git+https://github.com/o/r?branch=main#<sha>`
+    fn git_source(&self) -> Option<(&str, &str)> {
+        let (url, sha) = self.source.as_deref()?.strip_prefix("git+")?.split_once('#')?;
+
+        Some((url.split_once('?').map_or(url, |(url, _)| url), sha))
+    }
+}
+
+/// Hashes for every git dependency in `lock`, one per repository and commit: importCargoLock shares a hash between
+/// crates checked out from the same commit, so an existing entry for any of them is kept (and its hash reused while the
+/// commit is unchanged).
+fn output_hashes(lock: &CargoLock, old_lock: Option<&CargoLock>, old_hashes: &BTreeMap<String, Value>) -> Result<BTreeMap<String, Value>> {
+    let old_sources: BTreeMap<String, &str> = old_lock
+        .into_iter()
+        .flat_map(|lock| &lock.package)
+        .filter_map(|package| Some((package.key(), package.source.as_deref()?)))
+        .collect();
+
+    let mut hashes = BTreeMap::new();
+
+    let sources = lock.package.iter().filter_map(|package| Some((package.git_source()?, package))).into_group_map();
+
+    for ((url, sha), packages) in sources {
+        let key = packages
+            .iter()
+            .map(|package| package.key())
+            .find(|key| old_hashes.contains_key(key))
+            .unwrap_or_else(|| packages[0].key());
+
+        let unchanged = packages.iter().any(|package| old_sources.get(&package.key()).copied() == package.source.as_deref());
+
+        let hash = match old_hashes.get(&key) {
+            Some(hash) if unchanged => hash.clone(),
+            // importCargoLock checks git dependencies out with their submodules
+            _ => match Nix::hash_and_rev(
+                url,
+                Some(sha),
+                FetchFlags {
+                    submodules: true,
+                    ..FetchFlags::default()
+                },
+            )? {
+                Some((hash, _)) => Value::String(hash),
+                None => bail!("Failed to hash git dependency {url} at {sha}"),
+            },
+        };
+
+        hashes.insert(key, hash);
+    }
+
+    Ok(hashes)
+}
+
+/// How a Rust package declares its vendored dependencies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CargoDeps {
+    /// `cargoLock.lockFile`, hashed per git dependency in `outputHashes`
+    Lock,
+    /// `cargoHash`; fetchCargoVendor with `useFetchCargoVendor = true`, fetchCargoTarball otherwise
+    Hash { vendor: bool },
+    /// `cargoSha256`, only ever fetchCargoTarball
+    Sha256,
+    /// `cargoDeps = rustPlatform.fetchCargoVendor { ... }` (or `fetchCargoTarball`), hashed in that block
+    Deps { vendor: bool },
+}
+
+impl CargoDeps {
+    fn detect(ast: &Ast) -> Option<Self> {
+        let root = ast.node();
+
+        if ast.get_value(&["cargoLock"], "lockFile").is_some() {
+            Some(Self::Lock)
+        } else if Ast::contains_function_call(&root, "fetchCargoVendor") {
+            Some(Self::Deps { vendor: true })
+        } else if Ast::contains_function_call(&root, "fetchCargoTarball") {
+            Some(Self::Deps { vendor: false })
+        } else if ast.get_value(&[], "cargoSha256").is_some() {
+            Some(Self::Sha256)
+        } else if ast.get_value(&[], "cargoHash").is_some() {
+            Some(Self::Hash {
+                vendor: ast.get_value(&[], "useFetchCargoVendor") == Some(Value::Bool(true)),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Still vendored with fetchCargoTarball, which nixpkgs is phasing out
+    fn is_tarball(self) -> bool {
+        matches!(self, Self::Hash { vendor: false } | Self::Sha256 | Self::Deps { vendor: false })
+    }
+}
+
 fn cargo_vendor_needs_update(current_rev: Option<&str>, latest_rev: Option<&str>, current_version: &str, latest_version: &str) -> bool {
     current_rev != latest_rev || current_version != latest_version
 }
 
 impl Updater for Cargo {
-    fn new(config: &Config) -> Result<Self> {
+    fn new(options: &UpdateOptions) -> Result<Self> {
         Ok(Self {
-            force: config.force,
-            github_client: GitHubClient::new()?,
+            force: options.force,
+            migrate_vendor: options.migrate_cargo_vendor,
+            github_client: GitHubClient::shared()?,
             crates_client: CratesIoClient::new()?,
         })
     }
 
-    fn update(&self, package: &mut Package, pb: Option<&ProgressBar>) -> Result<()> {
+    fn handles(&self) -> Handles {
+        Handles {
+            kinds: &[PackageKind::Cargo],
+            priority: 50,
+            ..Handles::default()
+        }
+    }
+
+    fn last_activity(&self, package: &Package) -> Result<Option<DateTime<Utc>>> {
+        if Ast::contains_function_call(&package.ast().node(), "fetchCrate") {
+            Ok(self.crates_client.crate_info(&package.name)?.and_then(|info| info.crate_data.updated_at))
+        } else {
+            self.github_client.last_activity(&package.homepage)
+        }
+    }
+
+    fn latest(&self, package: &Package) -> Result<Option<Upstream>> {
+        if Ast::contains_function_call(&package.ast().node(), "fetchCrate") {
+            let (versions, _) = self.crates_client.versions(&package.name)?;
+
+            return Ok(newest_version(&versions, package.settings.allow_prereleases())
+                .0
+                .map(|newest| Upstream::version(&package.version, newest.num.clone())));
+        }
+
+        let current = package.ast().get_in(&["src"], "rev");
+
+        Ok(self
+            .github_client
+            .latest_commit(&package.homepage, package.settings.branch.as_deref())?
+            .map(|latest| Upstream::commit(current.as_deref(), &latest)))
+    }
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
+        // Migrating is a change of its own; the version is updated on the next run
+        if self.migrate_vendor
+            && let Some(deps) = CargoDeps::detect(&package.ast())
+            && deps.is_tarball()
+        {
+            return Self::migrate_vendor(package, deps, pb);
+        }
+
         // Detect if this is a fetchCrate package or git-based package
-        let root = package.ast.syntax();
+        let root = package.ast().node();
 
         if Ast::contains_function_call(&root, "fetchCrate") {
             self.update_fetch_crate(package, pb)
@@ -41,25 +206,55 @@ impl Updater for Cargo {
 
 impl Cargo {
     /// Update packages that use fetchCrate (from crates.io)
-    fn update_fetch_crate(&self, package: &mut Package, pb: Option<&ProgressBar>) -> Result<()> {
+    fn update_fetch_crate(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
         //
         // Query crates.io for latest version
         let Some(crate_info) = self.crates_client.crate_info(&package.name)? else {
-            package.result.failed("Crate not found on crates.io");
+            package.result.fail(UpdateError::upstream(package, "Crate not found on crates.io"));
+            return Ok(());
+        };
+
+        if let Some(source) = &crate_info.source {
+            package.result.source(source.clone());
+        }
+
+        if !repository_matches(&package.homepage, package.ast().source_url().as_deref(), crate_info.crate_data.urls()) {
+            warn!(
+                package = %package.name,
+                homepage = %package.homepage,
+                "crates.io crate does not reference this package's homepage - the lookup may have resolved a different project"
+            );
+        }
+
+        // The crate's max_version may have been yanked, so pick from its versions instead
+        let (versions, source) = self.crates_client.versions(&package.name)?;
+        package.result.source(source);
+
+        let (newest, yanked) = newest_version(&versions, package.settings.allow_prereleases());
+
+        let Some(newest) = newest else {
+            package.result.fail(UpdateError::upstream(package, "No release on crates.io that hasn't been yanked"));
             return Ok(());
         };
 
-        let latest_version = &crate_info.crate_data.max_version;
+        let latest_version = &newest.num;
 
-        // Skip if already up to date
-        if self.should_skip_update(self.force, &package.version, latest_version) {
+        // Skip if already up to date, or on a newer version that has since been yanked
+        if self.should_skip_update(self.force, &package.version, latest_version) || version_is_greater(&package.version, latest_version) {
             package.result.up_to_date();
+
+            if let Some(yanked) = yanked {
+                package.result.message(format!("Up to date (skipped yanked {})", yanked.num));
+            }
+
             return Ok(());
         }
 
         // Get new hash for the crate using nurl with fetchCrate fetcher
         let Some(new_hash) = Nix::prefetch_fetchcrate(&package.name, latest_version)? else {
-            package.result.failed("Failed to get hash for crate");
+            package
+                .result
+                .fail(UpdateError::no_hash(Tool::Nurl, &format!("https://crates.io/crates/{}/{latest_version}", package.name)));
             return Ok(());
         };
 
@@ -69,16 +264,25 @@ impl Cargo {
             ast.set("version", &package.version, latest_version)?;
         }
 
-        if let Some(old_hash) = ast.get("hash") {
-            ast.set("hash", &old_hash, &new_hash)?;
+        if let Some(old_hash) = ast.get_in(&["src"], "hash") {
+            ast.set_in(&["src"], "hash", &old_hash, &new_hash)?;
+        }
+
+        package
+            .result
+            .artifact(format!("https://crates.io/api/v1/crates/{}/{latest_version}/download", package.name), None, &new_hash);
+
+        package.result.released(newest.created_at);
+
+        if let Some(yanked) = yanked {
+            package.result.message(format!("Skipped yanked {}", yanked.num));
         }
 
         if cargo_vendor_needs_update(None, None, &package.version, latest_version) {
-            ast.clear_vendor_hash("cargo")?;
-            ast.update_vendor(package, "cargo", pb)?;
+            self.update_vendored(package, &mut ast, None, pb)?;
         }
 
-        package.write(&ast)?;
+        package.stage(&ast);
 
         package.result.version(Some(package.version.as_ref()), Some(latest_version));
 
@@ -86,18 +290,18 @@ impl Cargo {
     }
 
     /// Update packages that use git sources (fetchFromGitHub, etc.)
-    fn update_git_based(&self, package: &mut Package, pb: Option<&ProgressBar>) -> Result<()> {
+    fn update_git_based(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
         // Get current hash before any updates
         //
         let ast_tmp = package.ast();
 
-        let Some(current_git_commit) = ast_tmp.get("rev") else {
+        let Some(current_git_commit) = ast_tmp.get_in(&["src"], "rev") else {
             package.result.failed("Could not extract rev");
             return Ok(());
         };
 
-        let Some(latest_git_commit) = self.github_client.latest_commit(&package.homepage)? else {
-            package.result.failed("Failed to fetch latest commit");
+        let Some(latest_git_commit) = self.github_client.latest_commit(&package.homepage, package.settings.branch.as_deref())? else {
+            package.result.fail(UpdateError::upstream(package, "Failed to fetch latest commit"));
             return Ok(());
         };
 
@@ -107,8 +311,8 @@ impl Cargo {
         }
 
         // Update using nurl
-        let Some((new_hash, _)) = Nix::hash_and_rev(&package.homepage.to_string(), Some(&latest_git_commit))? else {
-            package.result.failed("Failed to get new hash");
+        let Some((new_hash, _)) = Nix::hash_and_rev(&package.homepage.to_string(), Some(&latest_git_commit), package.ast().fetch_flags())? else {
+            package.result.fail(UpdateError::no_hash(Tool::Nurl, &package.homepage.to_string()));
             return Ok(());
         };
 
@@ -117,13 +321,15 @@ impl Cargo {
         // Update rev and hash
         ast.update_git(Some(&current_git_commit), &latest_git_commit, &new_hash, None)?;
 
+        package.result.artifact(package.homepage.to_string(), Some(&latest_git_commit), &new_hash);
+
         // Get version from multiple sources and use the highest one
         let release_version = self
             .github_client
-            .latest_release(&package.homepage)
+            .latest_release(&package.homepage, package.settings.tag_prefix.as_deref())
             .ok()
             .flatten()
-            .map(|tag| normalize_version(&package.name, &tag));
+            .map(|release| normalize_version(&package.name, package.settings.strip_tag_prefix(&release.tag)));
 
         let cargo_version = self.github_client.cargo_version(&package.homepage, &latest_git_commit).ok().flatten();
 
@@ -136,10 +342,17 @@ impl Cargo {
             (Some(rel), None) => rel.clone(),
             (None, Some(cargo)) => cargo.clone(),
             (None, None) => {
-                // No version source found - only use short hash if current version is hash-like
+                // No version source found - a dated unstable version follows the commit's date, and only a hash-like
+                // version becomes the short hash
                 let is_semantic_version = package.version.contains('.') && package.version.chars().any(|c| c.is_ascii_digit());
 
-                if is_semantic_version { package.version.clone() } else { short_hash(&latest_git_commit) }
+                if let Some(redated) = redated_version(&self.github_client, &package.homepage, &package.version, &latest_git_commit)? {
+                    redated
+                } else if is_semantic_version {
+                    package.version.clone()
+                } else {
+                    short_hash(&latest_git_commit)
+                }
             }
         };
 
@@ -148,11 +361,10 @@ impl Cargo {
         }
 
         if cargo_vendor_needs_update(Some(&current_git_commit), Some(&latest_git_commit), &package.version, &latest_version) {
-            ast.clear_vendor_hash("cargo")?;
-            ast.update_vendor(package, "cargo", pb)?;
+            self.update_vendored(package, &mut ast, Some(&latest_git_commit), pb)?;
         }
 
-        package.write(&ast)?;
+        package.stage(&ast);
 
         package.result.git_commit(Some(current_git_commit.as_ref()), Some(latest_git_commit.as_ref()));
 
@@ -162,11 +374,125 @@ impl Cargo {
 
         Ok(())
     }
+
+    /// Recompute the vendored dependencies' hash, wherever the package declares it. `commit` is the new source revision,
+    /// needed to fetch its Cargo.lock for `cargoLock` packages.
+    fn update_vendored(&self, package: &mut Package, ast: &mut Ast, commit: Option<&str>, pb: Option<&Progress>) -> Result<()> {
+        match CargoDeps::detect(ast) {
+            Some(CargoDeps::Lock) => match commit {
+                Some(commit) => self.update_cargo_lock(package, ast, commit),
+                None => Ok(()),
+            },
+            Some(CargoDeps::Deps { .. }) => FixedOutput::CARGO_DEPS.resolve(ast, package, pb),
+            Some(CargoDeps::Sha256) => {
+                warn!(package = %package.name, "cargoSha256 is deprecated in nixpkgs; --migrate-cargo-vendor rewrites it to cargoHash");
+
+                FixedOutput::CARGO_SHA256.resolve(ast, package, pb)
+            }
+            Some(CargoDeps::Hash { .. }) => FixedOutput::CARGO.resolve(ast, package, pb),
+            None => Ok(()),
+        }
+    }
+
+    /// Rewrite a package vendored with fetchCargoTarball to use fetchCargoVendor, and compute its new hash
+    fn migrate_vendor(package: &mut Package, deps: CargoDeps, pb: Option<&Progress>) -> Result<()> {
+        let mut ast = package.ast();
+
+        match deps {
+            CargoDeps::Sha256 => {
+                ast.rename_attribute("cargoSha256", "cargoHash")?;
+                ast.insert_after("cargoHash", "useFetchCargoVendor", &Value::Bool(true))?;
+            }
+            CargoDeps::Hash { .. } => ast.insert_after("cargoHash", "useFetchCargoVendor", &Value::Bool(true))?,
+            CargoDeps::Deps { .. } => ast.rename_call("fetchCargoTarball", "fetchCargoVendor")?,
+            CargoDeps::Lock => return Ok(()),
+        }
+
+        // The vendored directory differs from the tarball, so the old hash can't be kept
+        if matches!(deps, CargoDeps::Deps { .. }) {
+            FixedOutput::CARGO_DEPS.resolve(&mut ast, package, pb)?;
+        } else {
+            FixedOutput::CARGO.resolve(&mut ast, package, pb)?;
+        }
+
+        package.stage(&ast);
+        package.result.status.insert(UpdateStatus::Updated);
+        package.result.message("Migrated to fetchCargoVendor");
+
+        Ok(())
+    }
+
+    /// For `cargoLock.lockFile` packages: vendor the upstream Cargo.lock at `commit` next to the package, when the file
+    /// points at a local copy, and bring `outputHashes` in line with its git dependencies
+    fn update_cargo_lock(&self, package: &mut Package, ast: &mut Ast, commit: &str) -> Result<()> {
+        let Some(content) = self.github_client.file_content(&package.homepage, "Cargo.lock", commit)? else {
+            bail!("No Cargo.lock in the repository at {commit}");
+        };
+
+        let lock: CargoLock = toml::from_str(&content)?;
+
+        // `./Cargo.lock` is a vendored copy; anything else (e.g. `"${src}/Cargo.lock"`) follows the source by itself
+        let local = match ast.get_value(&["cargoLock"], "lockFile") {
+            Some(Value::Expression(path)) if path.starts_with("./") => package.path.parent().map(|dir| dir.join(&path)),
+            _ => None,
+        };
+
+        let old_lock = local.as_ref().and_then(|path| toml::from_str::<CargoLock>(&fs::read_to_string(path).ok()?).ok());
+
+        if let Some(Value::AttrSet(old_hashes)) = ast.get_value(&["cargoLock"], "outputHashes") {
+            let hashes = output_hashes(&lock, old_lock.as_ref(), &old_hashes)?;
+
+            if hashes != old_hashes {
+                ast.set_value(&["cargoLock"], "outputHashes", &Value::AttrSet(hashes))?;
+            }
+        } else if lock.package.iter().any(|package| package.git_source().is_some()) && ast.get_value(&["cargoLock"], "allowBuiltinFetchGit").is_none() {
+            warn!(package = %package.name, "Cargo.lock has git dependencies but cargoLock has no outputHashes to record their hashes in");
+        }
+
+        if let Some(path) = local {
+            package.transaction.stage(&path, content);
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::cargo_vendor_needs_update;
+    use std::collections::BTreeMap;
+
+    use super::{CargoLock, cargo_vendor_needs_update, output_hashes};
+    use crate::nix::ast::Value;
+
+    const LOCK: &str = r#"
+[[package]]
+name = "serde"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "foo"
+version = "0.1.0"
+source = "git+https://github.com/o/foo?branch=main#abc123"
+
+[[package]]
+name = "foo-macros"
+version = "0.1.0"
+source = "git+https://github.com/o/foo?branch=main#abc123"
+"#;
+
+    #[test]
+    fn output_hashes_keep_one_entry_per_unchanged_git_source() {
+        let lock: CargoLock = toml::from_str(LOCK).unwrap();
+
+        assert_eq!(lock.package[1].git_source(), Some(("https://github.com/o/foo", "abc123")));
+        assert_eq!(lock.package[0].git_source(), None);
+
+        let old_hashes = BTreeMap::from([("foo-macros-0.1.0".to_string(), Value::String("sha256-foo".to_string()))]);
+        let hashes = output_hashes(&lock, Some(&lock), &old_hashes).unwrap();
+
+        assert_eq!(hashes, old_hashes);
+    }
 
     #[test]
     fn cargo_vendor_does_not_update_when_rev_and_version_are_unchanged() {
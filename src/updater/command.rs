@@ -0,0 +1,165 @@
+use rootcause::{Result, bail, report};
+use serde::Deserialize;
+
+use crate::clients::nix::Nix;
+use crate::hooks::shell;
+use crate::nix::ast::Ast;
+use crate::nix::fod::FixedOutput;
+use crate::package::Package;
+use crate::progress::Progress;
+use crate::updater::{Handles, UpdateOptions, Updater};
+
+/// Updates packages whose `update_command` resolves the latest release, for upstreams no built-in updater knows
+/// (self-hosted tarballs, FTP, API-gated downloads)
+pub struct CustomCommand {
+    force: bool,
+}
+
+/// What an `update_command` prints. Only `version` is required: without a `hash`, it is prefetched from `url`, the
+/// repository at `rev`, or failing both the `src` URL as it reads with the new version.
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+struct Resolved {
+    version: String,
+    rev: Option<String>,
+    url: Option<String>,
+    hash: Option<String>,
+}
+
+impl Updater for CustomCommand {
+    fn new(options: &UpdateOptions) -> Result<Self> {
+        Ok(Self { force: options.force })
+    }
+
+    fn handles(&self) -> Handles {
+        Handles {
+            priority: 100,
+            ..Handles::default()
+        }
+    }
+
+    /// Packages with an `update_command`, which knows the upstream better than any heuristic
+    fn claims(&self, package: &Package) -> bool {
+        package.settings.update_command.is_some()
+    }
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
+        let Some(command) = package.settings.update_command.clone() else {
+            bail!("No update_command configured for {}", package.name);
+        };
+
+        if let Some(pb) = pb {
+            pb.set_message(format!("{}: Running update_command ...", package.name()));
+        }
+
+        let output = shell(&command, package)?;
+
+        if !output.status.success() {
+            package
+                .result
+                .failed(format!("update_command failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+            return Ok(());
+        }
+
+        let resolved: Resolved = serde_json::from_slice(&output.stdout).map_err(|e| report!("update_command printed invalid JSON: {e}"))?;
+
+        let mut ast = package.ast();
+        let old_rev = ast.get_in(&["src"], "rev");
+
+        if self.should_skip_update(self.force, &package.version, &resolved.version) && (resolved.rev.is_none() || resolved.rev == old_rev) {
+            package.result.up_to_date();
+            return Ok(());
+        }
+
+        ast.set("version", &package.version, &resolved.version)?;
+
+        if let (Some(rev), Some(old_rev)) = (&resolved.rev, &old_rev)
+            && rev != old_rev
+        {
+            ast.set_in(&["src"], "rev", old_rev, rev)?;
+        }
+
+        if let Some(url) = &resolved.url
+            && let Some(old_url) = ast.get_in(&["src"], "url")
+            && old_url != *url
+        {
+            ast.set_in(&["src"], "url", &old_url, url)?;
+        }
+
+        // A literal URL left as it was still names the old release, whose hash would be recorded as the new one's
+        if resolved.hash.is_none()
+            && resolved.rev.is_none()
+            && resolved.version != package.version
+            && let Some(url) = ast.get_in(&["src"], "url").filter(|url| !url.contains("${"))
+            && package.ast().get_in(&["src"], "url").as_ref() == Some(&url)
+        {
+            package.result.failed(format!(
+                "update_command resolved {} but left the source at {url}; print the new release's url",
+                resolved.version
+            ));
+            return Ok(());
+        }
+
+        let Some((source, hash)) = source_hash(&ast, package, &resolved)? else {
+            package
+                .result
+                .failed("Could not hash the new source: update_command printed no hash, and no URL or rev to prefetch");
+            return Ok(());
+        };
+
+        ast.set_in(&["src"], "hash", &package.nix_hash, &hash)?;
+        package.result.artifact(source, resolved.rev.as_deref(), &hash);
+
+        for fod in FixedOutput::ALL {
+            if fod.declared(&ast) {
+                fod.resolve(&mut ast, package, pb)?;
+            }
+        }
+
+        package.stage(&ast);
+        package.result.version(Some(&package.version), Some(&resolved.version));
+
+        if resolved.rev.is_some() {
+            package.result.git_commit(old_rev.as_deref(), resolved.rev.as_deref());
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the new source comes from and its hash, as given or prefetched
+fn source_hash(ast: &Ast, package: &Package, resolved: &Resolved) -> Result<Option<(String, String)>> {
+    // The `src` URL now reads with the new version, when it interpolates it
+    let url = resolved.url.clone().or_else(|| ast.get_in(&["src"], "url")).filter(|url| !url.contains("${"));
+
+    let source = url.clone().or_else(|| resolved.rev.is_some().then(|| package.homepage.to_string())).unwrap_or_default();
+
+    let hash = match (&resolved.hash, &url, &resolved.rev) {
+        (Some(hash), _, _) => Some(hash.clone()),
+        (None, Some(url), _) if Ast::contains_function_call(&ast.node(), "fetchzip") => Nix::prefetch_unpacked_hash(url)?,
+        (None, Some(url), _) => Nix::prefetch_hash(url)?,
+        (None, None, Some(rev)) => Nix::hash_and_rev(&source, Some(rev), ast.fetch_flags())?.map(|(hash, _)| hash),
+        (None, None, None) => None,
+    };
+
+    Ok(hash.map(|hash| (source, hash)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolved;
+
+    #[test]
+    fn parses_update_command_output() {
+        let resolved: Resolved = serde_json::from_str(r#"{"version": "2.0", "url": "https://example.com/tool-2.0.tar.gz"}"#).unwrap();
+
+        assert_eq!(
+            resolved,
+            Resolved {
+                version: "2.0".to_string(),
+                rev: None,
+                url: Some("https://example.com/tool-2.0.tar.gz".to_string()),
+                hash: None,
+            }
+        );
+    }
+}
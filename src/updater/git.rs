@@ -1,28 +1,65 @@
-use indicatif::ProgressBar;
 use rootcause::Result;
 
-use crate::Config;
+use crate::clients::GitHubClient;
 use crate::clients::nix::Nix;
-use crate::package::Package;
-use crate::updater::Updater;
+use crate::error::UpdateError;
+use crate::nix::fod::FixedOutput;
+use crate::package::{Package, PackageKind};
+use crate::progress::Progress;
+use crate::tools::Tool;
+use crate::updater::{Handles, UpdateOptions, Updater, Upstream, python, redated_version};
 
 pub struct GitRepository {
     force: bool,
+    github_client: GitHubClient,
 }
 
 impl Updater for GitRepository {
-    fn new(config: &Config) -> Result<Self> {
-        Ok(Self { force: config.force })
+    fn new(options: &UpdateOptions) -> Result<Self> {
+        Ok(Self {
+            force: options.force,
+            github_client: GitHubClient::shared()?,
+        })
     }
 
-    fn update(&self, package: &mut Package, pb: Option<&ProgressBar>) -> Result<()> {
-        let Some((new_hash, new_rev)) = Nix::hash_and_rev(&package.homepage.to_string(), None)? else {
-            package.result.failed("nurl failed");
+    fn handles(&self) -> Handles {
+        Handles {
+            kinds: &[PackageKind::Git],
+            priority: 10,
+            ..Handles::default()
+        }
+    }
+
+    fn latest(&self, package: &Package) -> Result<Option<Upstream>> {
+        let reference = package.settings.branch.as_ref().map_or_else(|| "HEAD".to_string(), |branch| format!("refs/heads/{branch}"));
+        let current = package.ast().get_in(&["src"], "rev");
+
+        Ok(Nix::remote_ref(&package.homepage.to_string(), &reference)?.map(|latest| Upstream::commit(current.as_deref(), &latest)))
+    }
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
+        let url = package.homepage.to_string();
+
+        // Without a configured branch the fetcher follows the default one
+        let branch_head = match &package.settings.branch {
+            Some(branch) => {
+                let Some(head) = Nix::remote_ref(&url, &format!("refs/heads/{branch}"))? else {
+                    package.result.fail(UpdateError::upstream(package, format!("Branch {branch} not found")));
+                    return Ok(());
+                };
+
+                Some(head)
+            }
+            None => None,
+        };
+
+        let Some((new_hash, new_rev)) = Nix::hash_and_rev(&url, branch_head.as_deref(), package.ast().fetch_flags())? else {
+            package.result.fail(UpdateError::no_hash(Tool::Nurl, &url));
             return Ok(());
         };
 
         let mut ast = package.ast();
-        let old_rev = ast.get("rev");
+        let old_rev = ast.get_in(&["src"], "rev");
 
         if package.nix_hash == new_hash && old_rev == new_rev && !self.force {
             package.result.up_to_date();
@@ -32,13 +69,32 @@ impl Updater for GitRepository {
         // Update rev and hash
         ast.update_git(old_rev.as_deref(), &new_rev.clone().unwrap_or_default(), &new_hash, Some(&package.nix_hash))?;
 
-        ast.clear_vendor_hash("vendor")?;
+        package.result.artifact(package.homepage.to_string(), new_rev.as_deref(), &new_hash);
+
+        // Dated unstable versions follow the new commit's date
+        if let Some(rev) = &new_rev
+            && old_rev.as_ref() != Some(rev)
+            && let Some(new_version) = redated_version(&self.github_client, &package.homepage, &package.version, rev)?
+            && new_version != package.version
+        {
+            ast.set("version", &package.version, &new_version)?;
+            package.result.version(Some(&package.version), Some(&new_version));
+        }
+
+        if let Some(rev) = &new_rev
+            && package.homepage.host() == Some("github.com")
+        {
+            python::update_lockfiles(&self.github_client, package, &ast, rev, pb)?;
+        }
 
-        if ast.get("cargoHash").is_some() {
-            ast.update_vendor(package, "cargo", pb)?;
+        // Whatever builder the package uses, its dependency hashes follow the source
+        for fod in FixedOutput::ALL {
+            if fod.declared(&ast) {
+                fod.resolve(&mut ast, package, pb)?;
+            }
         }
 
-        package.write(&ast)?;
+        package.stage(&ast);
         package.result.git_commit(old_rev.as_deref(), new_rev.as_deref());
 
         Ok(())
@@ -1,18 +1,33 @@
-use indicatif::ProgressBar;
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use chrono::{DateTime, TimeDelta, Utc};
 use rootcause::Result;
+use tracing::{debug, warn};
 
-use crate::Config;
 use crate::clients::GitHubClient;
-use crate::clients::nix::Nix;
-use crate::package::Package;
-use crate::updater::{Updater, normalize_version};
+use crate::clients::github::Release;
+use crate::clients::http::Http;
+use crate::clients::nix::{FetchFlags, FetchKind, Nix};
+use crate::error::UpdateError;
+use crate::exclude::glob_matches;
+use crate::nix::ast::PlatformBlock;
+use crate::nix::fod::FixedOutput;
+use crate::package::{Package, PackageKind};
+use crate::progress::Progress;
+use crate::provenance::{Source, digest};
+use crate::tools::Tool;
+use crate::updater::{Handles, UpdateOptions, Updater, Upstream, normalize_version, python, version_is_greater};
 
 pub struct GitHubRelease {
     force: bool,
     client: GitHubClient,
+    http: Http,
 }
 
-fn release_asset_filename(package_name: &str, platform_name: &str, attributes: &std::collections::HashMap<String, String>) -> Option<String> {
+fn release_asset_filename(package_name: &str, platform_name: &str, attributes: &HashMap<String, String>) -> Option<String> {
     attributes.get("filename").cloned().or_else(|| {
         attributes.get("suffix").map(|suffix| {
             let target = if platform_name.split_once('-').is_some_and(|(arch, _)| suffix.starts_with(arch)) {
@@ -28,62 +43,406 @@ fn release_asset_filename(package_name: &str, platform_name: &str, attributes: &
     })
 }
 
+/// The one release asset matching a `filenamePattern`: a glob (`*` for any run of characters, `?` for one) in which
+/// `${version}` stands for the release's version. Otherwise all the assets that matched, empty when none did.
+fn matching_asset<'a>(pattern: &str, version: &str, assets: &'a [String]) -> std::result::Result<&'a String, Vec<&'a str>> {
+    let pattern = pattern.replace("${version}", version);
+    let matches = assets.iter().filter(|asset| glob_matches(&pattern, asset)).collect::<Vec<_>>();
+
+    match matches[..] {
+        [asset] => Ok(asset),
+        _ => Err(matches.into_iter().map(String::as_str).collect()),
+    }
+}
+
+/// The assets the platform blocks expect that the release doesn't have (yet): their filenames, or the patterns nothing
+/// matches
+fn missing_assets(package_name: &str, version: &str, blocks: &[PlatformBlock], assets: &[String]) -> Vec<String> {
+    blocks
+        .iter()
+        .filter_map(|block| match block.attributes.get("filenamePattern") {
+            Some(pattern) => matching_asset(pattern, version, assets)
+                .is_err_and(|candidates| candidates.is_empty())
+                .then(|| pattern.clone()),
+            None => release_asset_filename(package_name, &block.platform_name, &block.attributes).filter(|filename| !assets.contains(filename)),
+        })
+        .collect()
+}
+
+impl GitHubRelease {
+    /// The release's assets once every platform block's is there. A release published within the last day may still
+    /// be having its assets uploaded by CI, so it is listed again with backoff for up to the package's `asset_wait`;
+    /// `None` when they still aren't all there by then. Older releases are taken as they are.
+    fn published_assets(&self, package: &Package, release: &Release, version: &str, pb: Option<&Progress>) -> Result<Option<Vec<String>>> {
+        let tag = release.tag.as_str();
+        let blocks = package.ast().platforms();
+        let fresh = release.published_at.is_some_and(|published| Utc::now() - published < TimeDelta::days(1));
+
+        let deadline = Instant::now() + package.settings.asset_wait();
+        let mut assets = release.assets.clone();
+        let mut delay = Duration::from_secs(15);
+
+        loop {
+            let missing = missing_assets(&package.name, version, &blocks, &assets);
+
+            if missing.is_empty() || !fresh {
+                return Ok(Some(assets));
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                debug!(package = %package.name, tag, ?missing, "Release assets not yet published");
+                return Ok(None);
+            }
+
+            if let Some(pb) = pb {
+                pb.set_message(format!("{}: Waiting for {tag} assets to be published ({}) ...", package.name(), missing.join(", ")));
+            }
+
+            thread::sleep(delay.min(remaining));
+            delay = (delay * 2).min(Duration::from_mins(2));
+
+            assets = self.client.release_assets(&package.homepage, tag)?;
+        }
+    }
+
+    /// SRI hashes by filename from the checksum file among a release's `assets`, empty if it has none or it can't be downloaded
+    fn published_checksums(&self, package: &mut Package, assets: &[String], download: &str) -> Result<HashMap<String, String>> {
+        let Some(asset) = assets.iter().find(|asset| is_checksum_file(asset)) else {
+            return Ok(HashMap::new());
+        };
+
+        let url = format!("{download}/{asset}");
+        let response = self.http.fetch(&url)?;
+
+        if !response.status.is_success() {
+            warn!(package = %package.name, status = %response.status, "Could not download {asset}, prefetching assets instead");
+            return Ok(HashMap::new());
+        }
+
+        package.result.source(Source {
+            url,
+            digest: Some(digest(&response.body)),
+        });
+
+        Ok(parse_checksums(&String::from_utf8_lossy(&response.body)))
+    }
+}
+
+/// Whether a release asset lists the other assets' SHA-256 digests, e.g. `checksums.txt`, `tool_1.2.3_checksums.txt`
+/// or `SHA256SUMS` (but not its signature)
+fn is_checksum_file(asset: &str) -> bool {
+    let name = asset.to_lowercase();
+
+    (name.contains("checksums") || name.contains("sha256sums")) && ![".sig", ".asc", ".pem", ".minisig", ".sigstore", ".bundle"].iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// SRI hashes by filename from a checksum file, in either the `sha256sum` format (`<hex>  <file>`, `*` marking
+/// binary mode) or the BSD one (`SHA256 (<file>) = <hex>`). Lines that aren't SHA-256 digests are ignored.
+fn parse_checksums(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+
+            let (hex, file) = if let Some(rest) = line.strip_prefix("SHA256 (") {
+                let (file, hex) = rest.split_once(") = ")?;
+                (hex, file)
+            } else {
+                let (hex, file) = line.split_once(char::is_whitespace)?;
+                (hex, file.trim_start().trim_start_matches('*'))
+            };
+
+            // Some files list paths, e.g. `./dist/tool.tar.gz`
+            let file = file.rsplit('/').next()?;
+
+            Some((file.to_string(), sri_hash(hex)?))
+        })
+        .collect()
+}
+
+/// Convert a hex SHA-256 digest into an SRI hash (`sha256-<base64>`)
+fn sri_hash(hex: &str) -> Option<String> {
+    if hex.len() != 64 {
+        return None;
+    }
+
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(format!("sha256-{}", base64::engine::general_purpose::STANDARD.encode(bytes)))
+}
+
 impl Updater for GitHubRelease {
-    fn new(config: &Config) -> Result<Self> {
+    fn new(options: &UpdateOptions) -> Result<Self> {
         Ok(Self {
-            force: config.force,
-            client: GitHubClient::new()?,
+            force: options.force,
+            client: GitHubClient::shared()?,
+            http: Http::shared()?,
         })
     }
 
-    fn update(&self, package: &mut Package, _pb: Option<&ProgressBar>) -> Result<()> {
-        let Some(latest_tag) = self.client.latest_release(&package.homepage)? else {
+    fn handles(&self) -> Handles {
+        Handles {
+            kinds: &[PackageKind::GitHub],
+            priority: 40,
+            releases: true,
+            ..Handles::default()
+        }
+    }
+
+    /// Also sources on GitHub pinned to `v${version}`, of any kind: they follow releases, so only `version`, the tag's
+    /// hash and the hashes derived from the source change
+    fn claims(&self, package: &Package) -> bool {
+        self.handles().matches(package) || (package.homepage.host() == Some("github.com") && package.ast().pins_tag())
+    }
+
+    fn last_activity(&self, package: &Package) -> Result<Option<DateTime<Utc>>> {
+        self.client.last_activity(&package.homepage)
+    }
+
+    fn latest(&self, package: &Package) -> Result<Option<Upstream>> {
+        Ok(self
+            .client
+            .latest_release(&package.homepage, package.settings.tag_prefix.as_deref())?
+            .map(|release| Upstream::version(&package.version, normalize_version(&package.name, package.settings.strip_tag_prefix(&release.tag)))))
+    }
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
+        let Some(release) = self.client.latest_release(&package.homepage, package.settings.tag_prefix.as_deref())? else {
             package.result.message("No releases found on GitHub - keeping current version");
             return Ok(());
         };
 
-        let latest_version = normalize_version(&package.name, &latest_tag);
+        let latest_tag = release.tag.clone();
+
+        let latest_version = normalize_version(&package.name, package.settings.strip_tag_prefix(&latest_tag));
+
+        package.result.source(Source {
+            url: format!("https://api.github.com/repos/{}/releases/latest", package.homepage.path().trim_start_matches('/')),
+            digest: None,
+        });
 
         if self.should_skip_update(self.force, &package.version, &latest_version) {
             package.result.up_to_date();
             return Ok(());
         }
 
-        let mut ast = package.ast();
+        let assets = self.published_assets(package, &release, &latest_version, pb)?;
+
+        if !package.settings.release_fallback() {
+            let Some(assets) = assets else {
+                package.result.message(format!("Skipped: {latest_tag} assets not yet published"));
+                return Ok(());
+            };
+
+            if let Err(error) = self.apply_release(package, &release, &latest_version, &assets, pb)? {
+                package.result.fail(error);
+            }
+
+            return Ok(());
+        }
+
+        self.apply_newest_usable(package, (release, assets), pb)
+    }
+}
+
+impl GitHubRelease {
+    /// Walk back from the latest release to the newest one, still newer than the package's version, that has every
+    /// platform block's asset and whose hashes can all be prefetched, noting the releases passed over and why. A
+    /// release that fails for any reason but a rate limit is passed over, with its staged edits rolled back.
+    fn apply_newest_usable(&self, package: &mut Package, latest: (Release, Option<Vec<String>>), pb: Option<&Progress>) -> Result<()> {
+        let latest_tag = latest.0.tag.clone();
+
+        let older = self
+            .client
+            .releases(&package.homepage, package.settings.tag_prefix.as_deref())?
+            .into_iter()
+            .filter(|release| release.tag != latest_tag)
+            .map(|release| {
+                let assets = Some(release.assets.clone());
+                (release, assets)
+            });
+
+        let blocks = package.ast().platforms();
+        let mut passed_over = Vec::new();
 
-        ast.set("version", &package.version, &latest_version)?;
+        for (index, (release, assets)) in std::iter::once(latest).chain(older).enumerate() {
+            let version = normalize_version(&package.name, package.settings.strip_tag_prefix(&release.tag));
 
-        let new_hash = Nix::hash_and_rev(&format!("{}/archive/refs/tags/{latest_tag}.tar.gz", package.homepage), None)
-            .ok()
-            .flatten()
-            .map(|(new_hash, _)| new_hash);
+            if index > 0 && !version_is_greater(&version, &package.version) {
+                break;
+            }
+
+            let outcome = match assets {
+                None => Err(UpdateError::upstream(package, "Assets not yet published")),
+                Some(assets) => match missing_assets(&package.name, &version, &blocks, &assets)[..] {
+                    [] => match self.apply_release(package, &release, &version, &assets, pb) {
+                        Ok(outcome) => outcome,
+                        // A rate limit isn't the release's fault, and would turn the older ones down just the same
+                        Err(e) if matches!(UpdateError::from_report(&e), UpdateError::RateLimited { .. }) => return Err(e),
+                        // Anything else that stopped this release, e.g. prefetching a lockfile's dependencies, leaves
+                        // the older ones to try
+                        Err(e) => Err(UpdateError::from_report(&e)),
+                    },
+                    ref missing => Err(UpdateError::upstream(package, format!("No {}", missing.join(", ")))),
+                },
+            };
 
-        if let Some(new_h) = &new_hash {
-            ast.set("hash", &package.nix_hash, new_h)?;
+            match outcome {
+                Ok(()) => {
+                    if !passed_over.is_empty() {
+                        package.result.message(format!("Chose {version} over newer releases: {}", passed_over.join("; ")));
+                    }
+
+                    return Ok(());
+                }
+                Err(reason) => {
+                    // Nothing of a rejected release may carry over to the next one tried
+                    package.transaction.rollback();
+                    passed_over.push(format!("{}: {reason}", release.tag));
+                }
+            }
         }
 
+        package.result.fail(UpdateError::upstream(
+            package,
+            format!("No usable release newer than {}: {}", package.version, passed_over.join("; ")),
+        ));
+
+        Ok(())
+    }
+
+    /// Update the package to `release`, whose assets are `assets`. `Err` with the reason when the release can't be
+    /// used, e.g. the source's or an asset's hash couldn't be prefetched, leaving the package and its files untouched.
+    fn apply_release(&self, package: &mut Package, release: &Release, version: &str, assets: &[String], pb: Option<&Progress>) -> Result<std::result::Result<(), UpdateError>> {
+        let tag = release.tag.as_str();
+
+        let mut ast = package.ast();
+
+        ast.set("version", &package.version, version)?;
+
+        // Release tarballs have no submodules or .git, and a `fetchgit` checkout ignores `.gitattributes`, so those
+        // sources have to be cloned at the tag instead
+        let flags = ast.fetch_flags();
+
+        let (source_url, source_rev, flags) = if flags.needs_clone() || flags.kind == FetchKind::Checkout {
+            (package.homepage.to_string(), Some(tag), flags)
+        } else {
+            let archive = FetchFlags {
+                kind: FetchKind::Archive,
+                ..flags
+            };
+
+            (format!("{}/archive/refs/tags/{tag}.tar.gz", package.homepage), None, archive)
+        };
+
+        let new_hash = match Nix::hash_and_rev(&source_url, source_rev, flags) {
+            Ok(Some((new_hash, _))) => new_hash,
+            Ok(None) => return Ok(Err(UpdateError::no_hash(Tool::Nurl, &source_url))),
+            Err(e) => return Ok(Err(UpdateError::from_report(&e))),
+        };
+
+        ast.set_in(&["src"], "hash", &package.nix_hash, &new_hash)?;
+
+        // Recorded on the package only once the release turns out to be usable
+        let mut artifacts = vec![(source_url, source_rev.map(String::from), new_hash)];
+
         let platform_blocks = ast.platforms();
-        let repo_path = package.homepage.path();
+        let download = format!("https://github.com/{}/releases/download/{tag}", package.homepage.path().trim_start_matches('/'));
+
+        // Hashes published with the release spare downloading every asset
+        let checksums = if package.settings.checksums() && !platform_blocks.is_empty() {
+            self.published_checksums(package, assets, &download)?
+        } else {
+            HashMap::new()
+        };
+
+        let mut targets = Vec::new();
 
-        for block in platform_blocks {
-            if let Some(filename) = release_asset_filename(&package.name, &block.platform_name, &block.attributes)
+        for block in &platform_blocks {
+            // A pattern picks the asset out of the release, and the file's `filename` follows it
+            let filename = if let Some(pattern) = block.attributes.get("filenamePattern") {
+                match matching_asset(pattern, version, assets) {
+                    Ok(asset) => {
+                        if let Some(old_filename) = ast.get_in(&[&block.platform_name], "filename")
+                            && old_filename != *asset
+                        {
+                            ast.set_in(&[&block.platform_name], "filename", &old_filename, asset)?;
+                        }
+
+                        Some(asset.clone())
+                    }
+                    Err(candidates) => {
+                        return Ok(Err(UpdateError::upstream(
+                            package,
+                            format!(
+                                "{} release assets match {pattern} for {}{}",
+                                if candidates.is_empty() { "No" } else { "Several" },
+                                block.platform_name,
+                                if candidates.is_empty() { String::new() } else { format!(": {}", candidates.join(", ")) }
+                            ),
+                        )));
+                    }
+                }
+            } else {
+                release_asset_filename(&package.name, &block.platform_name, &block.attributes)
+            };
+
+            if let Some(filename) = filename
                 && let Some(old_hash) = block.attributes.get("hash")
             {
-                let url = format!("https://github.com/{repo_path}/releases/download/{latest_tag}/{filename}");
+                let url = format!("{download}/{filename}");
 
-                if let Some(new_hash) = Nix::prefetch_hash(&url)? {
-                    ast.set("hash", old_hash, &new_hash)?;
-                } else {
-                    package.result.failed(format!("Failed to get hash for {filename}"));
-                    break;
-                }
+                targets.push((block, old_hash, url, filename));
             }
         }
 
-        package.write(&ast)?;
-        package.result.version(Some(package.version.as_ref()), Some(latest_version.as_ref()));
+        // Everything the checksum file doesn't cover is downloaded, several assets at once
+        let urls = targets
+            .iter()
+            .filter(|(_, _, _, filename)| !checksums.contains_key(filename))
+            .map(|(_, _, url, _)| url.as_str())
+            .collect::<Vec<_>>();
+        let mut prefetched = Nix::prefetch_hashes(&urls).into_iter();
 
-        Ok(())
+        for (block, old_hash, url, filename) in &targets {
+            let new_hash = match checksums.get(filename) {
+                Some(hash) => hash.clone(),
+                None => match prefetched.next() {
+                    Some(Ok(Some(hash))) => hash,
+                    Some(Err(e)) => return Ok(Err(UpdateError::from_report(&e))),
+                    Some(Ok(None)) | None => return Ok(Err(UpdateError::no_hash(Tool::Nix, url))),
+                },
+            };
+
+            ast.set_in(&[&block.platform_name], "hash", old_hash, &new_hash)?;
+            artifacts.push((url.clone(), None, new_hash));
+        }
+
+        // Python applications resolve their dependencies from a lockfile, which moves with the release. It's written
+        // through, so it waits until nothing else can turn the release down.
+        python::update_lockfiles(&self.client, package, &ast, tag, pb)?;
+
+        // Whatever builder the package uses, its dependency hashes follow the source
+        for fod in FixedOutput::ALL {
+            if fod.declared(&ast) {
+                fod.resolve(&mut ast, package, pb)?;
+            }
+        }
+
+        for (url, rev, hash) in artifacts {
+            package.result.artifact(url, rev.as_deref(), hash);
+        }
+
+        package.stage(&ast);
+        package.result.version(Some(package.version.as_ref()), Some(version));
+        package.result.released(release.published_at);
+
+        Ok(Ok(()))
     }
 }
 
@@ -91,7 +450,8 @@ impl Updater for GitHubRelease {
 mod tests {
     use std::collections::HashMap;
 
-    use super::release_asset_filename;
+    use super::{glob_matches, is_checksum_file, matching_asset, missing_assets, parse_checksums, release_asset_filename};
+    use crate::nix::ast::PlatformBlock;
 
     #[test]
     fn release_asset_filename_uses_explicit_filename() {
@@ -109,4 +469,70 @@ mod tests {
             Some("icm-x86_64-unknown-linux-gnu.tar.gz")
         );
     }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("tool-*-x86_64-*.tar.gz", "tool-1.2.3-x86_64-unknown-linux-musl.tar.gz"));
+        assert!(glob_matches("tool-?.tar.gz", "tool-1.tar.gz"));
+        assert!(!glob_matches("tool-*.zip", "tool-1.2.3.tar.gz"));
+        assert!(!glob_matches("tool", "tool-1"));
+    }
+
+    #[test]
+    fn matching_asset_substitutes_version() {
+        let assets = ["tool-1.2.3-linux-amd64.tar.gz", "tool-1.2.3-linux-arm64.tar.gz", "tool-1.2.3-darwin-arm64.zip"].map(String::from);
+
+        assert_eq!(
+            matching_asset("tool-${version}-linux-amd64.*", "1.2.3", &assets).map(String::as_str),
+            Ok("tool-1.2.3-linux-amd64.tar.gz")
+        );
+        assert_eq!(matching_asset("tool-${version}-linux-*", "1.2.3", &assets).unwrap_err().len(), 2);
+        assert_eq!(matching_asset("tool-${version}-*", "2.0.0", &assets), Err(vec![]));
+    }
+
+    #[test]
+    fn finds_missing_assets() {
+        let block = |platform: &str, attribute: &str, value: &str| PlatformBlock {
+            platform_name: platform.to_string(),
+            attributes: HashMap::from([(attribute.to_string(), value.to_string())]),
+        };
+
+        let blocks = [
+            block("x86_64-linux", "suffix", "unknown-linux-musl"),
+            block("aarch64-darwin", "filenamePattern", "tool-${version}-*-darwin.zip"),
+        ];
+
+        assert_eq!(
+            missing_assets("tool", "1.2.3", &blocks, &[]),
+            ["tool-x86_64-unknown-linux-musl.tar.gz", "tool-${version}-*-darwin.zip"]
+        );
+
+        let assets = ["tool-x86_64-unknown-linux-musl.tar.gz", "tool-1.2.3-aarch64-darwin.zip"].map(String::from);
+
+        assert_eq!(missing_assets("tool", "1.2.3", &blocks, &assets), Vec::<String>::new());
+    }
+
+    #[test]
+    fn finds_checksum_files() {
+        assert!(is_checksum_file("checksums.txt"));
+        assert!(is_checksum_file("tool_1.2.3_checksums.txt"));
+        assert!(is_checksum_file("SHA256SUMS"));
+        assert!(!is_checksum_file("SHA256SUMS.asc"));
+        assert!(!is_checksum_file("tool-linux.tar.gz"));
+    }
+
+    #[test]
+    fn parses_checksum_files_into_sri_hashes() {
+        let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let sri = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+
+        let checksums = parse_checksums(&format!(
+            "{hex}  tool-linux.tar.gz\n{hex} *./dist/tool-darwin.zip\nSHA256 (tool.deb) = {hex}\nnot a checksum\n"
+        ));
+
+        assert_eq!(checksums.get("tool-linux.tar.gz").map(String::as_str), Some(sri));
+        assert_eq!(checksums.get("tool-darwin.zip").map(String::as_str), Some(sri));
+        assert_eq!(checksums.get("tool.deb").map(String::as_str), Some(sri));
+        assert_eq!(checksums.len(), 3);
+    }
 }
@@ -1,11 +1,14 @@
-use indicatif::ProgressBar;
+use chrono::{DateTime, Utc};
 use rootcause::Result;
 
-use crate::Config;
 use crate::clients::GitHubClient;
 use crate::clients::nix::Nix;
-use crate::package::Package;
-use crate::updater::{Updater, normalize_version};
+use crate::error::UpdateError;
+use crate::nix::fod::FixedOutput;
+use crate::package::{Package, PackageKind};
+use crate::progress::Progress;
+use crate::tools::Tool;
+use crate::updater::{Handles, UpdateOptions, Updater, Upstream, normalize_version};
 
 pub struct GoUpdater {
     force: bool,
@@ -17,19 +20,43 @@ fn go_package_is_current(force: bool, current_rev: Option<&str>, latest_rev: Opt
 }
 
 impl Updater for GoUpdater {
-    fn new(config: &Config) -> Result<Self> {
+    fn new(options: &UpdateOptions) -> Result<Self> {
         Ok(Self {
-            force: config.force,
-            github_client: GitHubClient::new()?,
+            force: options.force,
+            github_client: GitHubClient::shared()?,
         })
     }
 
-    fn update(&self, package: &mut Package, pb: Option<&ProgressBar>) -> Result<()> {
+    fn handles(&self) -> Handles {
+        Handles {
+            kinds: &[PackageKind::Go],
+            priority: 50,
+            ..Handles::default()
+        }
+    }
+
+    fn last_activity(&self, package: &Package) -> Result<Option<DateTime<Utc>>> {
+        self.github_client.last_activity(&package.homepage)
+    }
+
+    fn latest(&self, package: &Package) -> Result<Option<Upstream>> {
+        let current = package.ast().get_in(&["src"], "rev");
+
+        Ok(self
+            .github_client
+            .latest_commit(&package.homepage, package.settings.branch.as_deref())?
+            .map(|latest| Upstream::commit(current.as_deref(), &latest)))
+    }
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
         let ast_tmp = package.ast();
 
-        let current_git_commit = ast_tmp.get("rev");
-        let latest_git_commit = self.github_client.latest_commit(&package.homepage)?;
-        let latest_version = self.github_client.latest_release(&package.homepage)?.map(|tag| normalize_version(&package.name, &tag));
+        let current_git_commit = ast_tmp.get_in(&["src"], "rev");
+        let latest_git_commit = self.github_client.latest_commit(&package.homepage, package.settings.branch.as_deref())?;
+        let latest_version = self
+            .github_client
+            .latest_release(&package.homepage, package.settings.tag_prefix.as_deref())?
+            .map(|release| normalize_version(&package.name, package.settings.strip_tag_prefix(&release.tag)));
 
         if go_package_is_current(
             self.force,
@@ -44,13 +71,13 @@ impl Updater for GoUpdater {
 
         // If we have a new commit, proceed with update
         let Some(latest_commit) = latest_git_commit else {
-            package.result.failed("Could not get latest commit from GitHub");
+            package.result.fail(UpdateError::upstream(package, "Could not get latest commit from GitHub"));
             return Ok(());
         };
 
         // Get new hash using nurl
-        let Some((new_hash, _)) = Nix::hash_and_rev(&package.homepage.to_string(), Some(&latest_commit))? else {
-            package.result.failed("Failed to get new hash");
+        let Some((new_hash, _)) = Nix::hash_and_rev(&package.homepage.to_string(), Some(&latest_commit), package.ast().fetch_flags())? else {
+            package.result.fail(UpdateError::no_hash(Tool::Nurl, &package.homepage.to_string()));
             return Ok(());
         };
 
@@ -59,18 +86,20 @@ impl Updater for GoUpdater {
         // Update rev and hash (version is updated automatically if it contains the old rev)
         ast.update_git(current_git_commit.as_deref(), &latest_commit, &new_hash, None)?;
 
+        package.result.artifact(package.homepage.to_string(), Some(&latest_commit), &new_hash);
+
         if let Some(version) = &latest_version
             && package.version != *version
         {
             ast.set("version", &package.version, version)?;
         }
 
-        if current_git_commit.as_deref() != Some(latest_commit.as_str()) {
-            ast.clear_vendor_hash("vendor")?;
-            ast.update_vendor(package, "vendor", pb)?;
+        // Only `goModules` is built to learn the new hash, not the whole package
+        if current_git_commit.as_deref() != Some(latest_commit.as_str()) && FixedOutput::GO.declared(&ast) {
+            FixedOutput::GO.resolve(&mut ast, package, pb)?;
         }
 
-        package.write(&ast)?;
+        package.stage(&ast);
 
         if current_git_commit.as_deref() != Some(latest_commit.as_str()) {
             package.result.git_commit(current_git_commit.as_deref(), Some(&latest_commit));
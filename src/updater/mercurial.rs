@@ -0,0 +1,95 @@
+use rootcause::Result;
+
+use crate::clients::hg::MercurialClient;
+use crate::clients::nix::Nix;
+use crate::error::UpdateError;
+use crate::package::{Package, PackageKind};
+use crate::progress::Progress;
+use crate::tools::Tool;
+use crate::updater::{Handles, UpdateOptions, Updater, Upstream, unstable_version};
+
+/// Packages fetched with `fetchhg`, following the head of their branch
+pub struct MercurialRepository {
+    force: bool,
+    client: MercurialClient,
+}
+
+impl Updater for MercurialRepository {
+    fn new(options: &UpdateOptions) -> Result<Self> {
+        Ok(Self {
+            force: options.force,
+            client: MercurialClient::new()?,
+        })
+    }
+
+    fn handles(&self) -> Handles {
+        Handles {
+            kinds: &[PackageKind::Mercurial],
+            priority: 50,
+            ..Handles::default()
+        }
+    }
+
+    fn latest(&self, package: &Package) -> Result<Option<Upstream>> {
+        let ast = package.ast();
+
+        let Some(url) = ast.get_in(&["src"], "url") else {
+            return Ok(None);
+        };
+
+        let current = ast.get_in(&["src"], "rev");
+
+        Ok(self
+            .client
+            .latest_changeset(&url, package.settings.branch.as_deref())?
+            .map(|changeset| Upstream::commit(current.as_deref(), &changeset.node)))
+    }
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
+        let mut ast = package.ast();
+
+        let Some(url) = ast.get_in(&["src"], "url") else {
+            package.result.failed("No fetchhg url in src");
+            return Ok(());
+        };
+
+        if let Some(pb) = pb {
+            pb.set_message(format!("{}: Finding the latest changeset...", package.name()));
+        }
+
+        let Some(changeset) = self.client.latest_changeset(&url, package.settings.branch.as_deref())? else {
+            package.result.fail(UpdateError::upstream(package, "Could not find the latest changeset"));
+            return Ok(());
+        };
+
+        let old_rev = ast.get_in(&["src"], "rev");
+
+        if old_rev.as_deref() == Some(changeset.node.as_str()) && !self.force {
+            package.result.up_to_date();
+            return Ok(());
+        }
+
+        let Some(new_hash) = Nix::prefetch_hg(&url, &changeset.node)? else {
+            package.result.fail(UpdateError::no_hash(Tool::NixPrefetchHg, &url));
+            return Ok(());
+        };
+
+        ast.update_git(old_rev.as_deref(), &changeset.node, &new_hash, Some(&package.nix_hash))?;
+
+        package.result.artifact(url, Some(&changeset.node), &new_hash);
+
+        // Dated unstable versions follow the changeset's date, when hgweb told it
+        if let Some(date) = changeset.date
+            && let Some(new_version) = unstable_version(&package.version, date.date_naive())
+            && new_version != package.version
+        {
+            ast.set("version", &package.version, &new_version)?;
+            package.result.version(Some(&package.version), Some(&new_version));
+        }
+
+        package.stage(&ast);
+        package.result.git_commit(old_rev.as_deref(), Some(&changeset.node));
+
+        Ok(())
+    }
+}
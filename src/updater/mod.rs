@@ -1,23 +1,245 @@
 pub mod cargo;
+pub mod command;
 pub mod git;
 pub mod github;
 pub mod go;
+pub mod mercurial;
+pub mod nix_update;
 pub mod npm;
 pub mod pypi;
+pub mod python;
 
-use indicatif::ProgressBar;
-use rootcause::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use git_url_parse::GitUrl;
+use itertools::Itertools;
+use rootcause::{Result, report};
 
-use crate::Config;
-use crate::package::Package;
+use crate::clients::GitHubClient;
+use crate::nix::ast::Ast;
+use crate::package::{Package, PackageKind};
+use crate::progress::Progress;
+use crate::updater::cargo::Cargo;
+use crate::updater::command::CustomCommand;
+use crate::updater::git::GitRepository;
+use crate::updater::github::GitHubRelease;
+use crate::updater::go::GoUpdater;
+use crate::updater::mercurial::MercurialRepository;
+use crate::updater::nix_update::NixUpdate;
+use crate::updater::npm::NpmUpdater;
+use crate::updater::pypi::PyPiUpdater;
 
-pub trait Updater: Sized {
-    fn new(config: &Config) -> Result<Self>;
-    fn update(&self, package: &mut Package, pb: Option<&ProgressBar>) -> Result<()>;
+/// How the updaters behave, set once for a run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UpdateOptions {
+    /// Update even packages that are already at the latest version
+    pub force: bool,
+    /// Rewrite Rust packages still vendored with fetchCargoTarball to fetchCargoVendor
+    pub migrate_cargo_vendor: bool,
+}
+
+/// Updates one kind of package. Implementations are registered with [`Updaters`], which picks the updater for each
+/// package from what they declare in [`Updater::handles`].
+pub trait Updater: Send + Sync {
+    fn new(options: &UpdateOptions) -> Result<Self>
+    where
+        Self: Sized;
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()>;
+
+    /// The packages this updater is for, and how it ranks against other updaters claiming the same package
+    fn handles(&self) -> Handles;
+
+    /// Whether this updater can update the package: by default, when it matches what [`Updater::handles`] declares
+    fn claims(&self, package: &Package) -> bool {
+        self.handles().matches(package)
+    }
 
     fn should_skip_update(&self, force: bool, current: &str, latest: &str) -> bool {
         current == latest && !force
     }
+
+    /// When the upstream last published anything, if the source can tell cheaply. `None` means unknown.
+    fn last_activity(&self, _package: &Package) -> Result<Option<DateTime<Utc>>> {
+        Ok(None)
+    }
+
+    /// The newest upstream version (or commit) next to the package's, from registry or forge metadata alone: nothing is
+    /// prefetched or built. `None` when the source can't tell that cheaply.
+    fn latest(&self, _package: &Package) -> Result<Option<Upstream>> {
+        Ok(None)
+    }
+}
+
+/// What an updater declares it handles. A package is claimed when its kind is one of `kinds`, or (unless its settings
+/// force a `kind`) its file calls one of `fetchers`; `hosts` narrows either to packages whose homepage is on one of them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Handles {
+    /// Package kinds, as detected or forced with `kind`
+    pub kinds: &'static [PackageKind],
+    /// Fetcher or builder functions that claim a package whatever its detected kind, e.g. `fetchFromSourcehut`
+    pub fetchers: &'static [&'static str],
+    /// Homepage hosts, e.g. `github.com`; any host when empty
+    pub hosts: &'static [&'static str],
+    /// Which updater wins when several claim a package: the highest. The built-in ecosystem updaters use 50, GitHub
+    /// releases 40 and the generic git fallback 10; `update_command` and `nix_update`, which packages opt into, 100
+    /// and 90.
+    pub priority: i32,
+    /// Follows upstream releases, which ranks it just above the ecosystem updaters for GitHub sources pinned to a
+    /// release tag (`rev = "v${version}";`), whatever kind of package they are. Updaters packages opt into still win.
+    pub releases: bool,
+}
+
+impl Handles {
+    pub fn matches(&self, package: &Package) -> bool {
+        let claimed = self.kinds.contains(&package.kind)
+            || (package.settings.kind.is_none() && self.fetchers.iter().any(|fetcher| Ast::contains_function_call(&package.ast().node(), fetcher)));
+
+        claimed && (self.hosts.is_empty() || package.homepage.host().is_some_and(|host| self.hosts.contains(&host)))
+    }
+}
+
+/// What a package is at and what its upstream has, for `outdated`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upstream {
+    pub current: String,
+    pub latest: String,
+    pub outdated: bool,
+}
+
+impl Upstream {
+    /// Released versions, where a package already ahead of the upstream (a prerelease, say) isn't outdated
+    pub fn version(current: &str, latest: impl Into<String>) -> Self {
+        let latest = latest.into();
+
+        Self {
+            outdated: current != latest && !version_is_greater(current, &latest),
+            current: current.to_string(),
+            latest,
+        }
+    }
+
+    /// Commits, shown shortened
+    pub fn commit(current: Option<&str>, latest: &str) -> Self {
+        Self {
+            current: current.map(short_hash).unwrap_or_default(),
+            latest: short_hash(latest),
+            outdated: current != Some(latest),
+        }
+    }
+}
+
+/// An updater and the name a package's `updater` setting picks it by
+struct Registered {
+    name: String,
+    updater: Box<dyn Updater>,
+}
+
+/// The updaters a run can use, built once before the run and shared by every worker. Each package goes to the
+/// highest-priority updater claiming it.
+pub struct Updaters {
+    force: bool,
+    registered: Vec<Registered>,
+}
+
+impl Updaters {
+    /// The built-in updaters
+    pub fn new(options: &UpdateOptions) -> Result<Self> {
+        let mut updaters = Self::empty(options);
+
+        updaters
+            .register("command", CustomCommand::new(options)?)
+            .register("nix-update", NixUpdate::new(options)?)
+            .register("pypi", PyPiUpdater::new(options)?)
+            .register("cargo", Cargo::new(options)?)
+            .register("npm", NpmUpdater::new(options)?)
+            .register("go", GoUpdater::new(options)?)
+            .register("mercurial", MercurialRepository::new(options)?)
+            .register("github", GitHubRelease::new(options)?)
+            .register("git", GitRepository::new(options)?);
+
+        Ok(updaters)
+    }
+
+    /// No updaters yet, for automation that registers its own
+    pub fn empty(options: &UpdateOptions) -> Self {
+        Self {
+            force: options.force,
+            registered: Vec::new(),
+        }
+    }
+
+    /// Add an updater under `name`, replacing one already registered under it (a built-in, say)
+    pub fn register(&mut self, name: impl Into<String>, updater: impl Updater + 'static) -> &mut Self {
+        let name = name.into();
+        let updater = Box::new(updater);
+
+        match self.registered.iter_mut().find(|registered| registered.name == name) {
+            Some(registered) => registered.updater = updater,
+            None => self.registered.push(Registered { name, updater }),
+        }
+
+        self
+    }
+
+    /// The registered updaters' names, in registration order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.registered.iter().map(|registered| registered.name.as_str())
+    }
+
+    /// The name of the updater for a package: the one its `updater` setting names, else the highest-priority one
+    /// claiming it, the first registered on a tie
+    pub fn select(&self, package: &Package) -> Result<&str> {
+        Ok(self.updater(package)?.name.as_str())
+    }
+
+    fn updater(&self, package: &Package) -> Result<&Registered> {
+        if let Some(name) = &package.settings.updater {
+            return self
+                .registered
+                .iter()
+                .find(|registered| &registered.name == name)
+                .ok_or_else(|| report!("{}: no updater named {name} (registered: {})", package.name, self.names().join(", ")));
+        }
+
+        let pinned = package.homepage.host() == Some("github.com") && package.ast().pins_tag();
+
+        self.registered
+            .iter()
+            .filter(|registered| registered.updater.claims(package))
+            .rev()
+            .max_by_key(|registered| {
+                let handles = registered.updater.handles();
+
+                if handles.releases && pinned { handles.priority.max(51) } else { handles.priority }
+            })
+            .ok_or_else(|| report!("{}: no updater handles {} packages", package.name, package.kind))
+    }
+
+    /// Check a package for updates, skipping it when `since` is set and the upstream has been dormant since then
+    pub fn update(&self, package: &mut Package, pb: &Progress, since: Option<DateTime<Utc>>) -> Result<()> {
+        let updater = self.updater(package)?;
+
+        self.run(updater.updater.as_ref(), package, pb, since)
+    }
+
+    /// Look up a package's newest upstream version with the updater [`Updaters::update`] would use
+    pub fn latest(&self, package: &Package) -> Result<Option<Upstream>> {
+        self.updater(package)?.updater.latest(package)
+    }
+
+    fn run(&self, updater: &dyn Updater, package: &mut Package, pb: &Progress, since: Option<DateTime<Utc>>) -> Result<()> {
+        if let Some(cutoff) = since
+            && !self.force
+            && let Some(last_activity) = updater.last_activity(package)?
+            && last_activity < cutoff
+        {
+            package.result.dormant(last_activity);
+
+            return Ok(());
+        }
+
+        updater.update(package, Some(pb))
+    }
 }
 
 /// Create a short git hash (first 8 characters) from a full hash or revision
@@ -40,6 +262,34 @@ pub fn normalize_version(package_name: &str, version: &str) -> String {
         .to_string()
 }
 
+/// Where the date sits in a dated unstable version: `unstable-2024-01-05`, `0.1.0-unstable-2024-01-05` or
+/// `0-unstable-...`, and `0.pre+date=2024-01-05`
+fn unstable_date(version: &str) -> Option<std::ops::Range<usize>> {
+    ["unstable-", "date="].iter().find_map(|marker| {
+        let start = version.find(marker)? + marker.len();
+        let date = version.get(start..start + 10)?;
+
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").ok().map(|_| start..start + 10)
+    })
+}
+
+/// `version` with its unstable date replaced by `date`, or `None` when it isn't dated
+fn unstable_version(version: &str, date: NaiveDate) -> Option<String> {
+    let span = unstable_date(version)?;
+
+    Some(format!("{}{}{}", &version[..span.start], date.format("%Y-%m-%d"), &version[span.end..]))
+}
+
+/// For a dated unstable version (see [`unstable_date`]), the version dated by `commit` instead, looked up on GitHub.
+/// `None` when the version isn't dated or the commit date isn't known.
+pub fn redated_version(client: &GitHubClient, homepage: &GitUrl, version: &str, commit: &str) -> Result<Option<String>> {
+    if unstable_date(version).is_none() || homepage.host() != Some("github.com") {
+        return Ok(None);
+    }
+
+    Ok(client.commit_date(homepage, commit)?.and_then(|date| unstable_version(version, date.date_naive())))
+}
+
 /// Compare two semantic versions, returns true if a > b
 pub fn version_is_greater(a: &str, b: &str) -> bool {
     match (semver::Version::parse(a), semver::Version::parse(b)) {
@@ -48,9 +298,185 @@ pub fn version_is_greater(a: &str, b: &str) -> bool {
     }
 }
 
+/// Reduce a repository URL to `host/owner/repo` form so that scheme, `www.`, `.git` and case differences don't matter
+fn normalize_repository_url(url: &str) -> String {
+    let url = url.trim().to_lowercase();
+    let url = url.strip_prefix("git+").unwrap_or(&url);
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let url = url.strip_prefix("www.").unwrap_or(url);
+    let url = url.trim_end_matches('/');
+
+    url.strip_suffix(".git").unwrap_or(url).to_string()
+}
+
+/// Whether a registry entry points back at the package's homepage, or at the repository its `source` is fetched from.
+///
+/// Registries (PyPI, crates.io) list the repository a project was published from. If none of those URLs
+/// reference the homepage or the fetcher's repository, the lookup may have resolved a different, similarly named
+/// project. A homepage that's a docs site still matches through the fetcher. Registries that declare no URLs at all
+/// can't be checked and are trusted.
+pub fn repository_matches<'a>(homepage: &GitUrl, source: Option<&str>, candidates: impl IntoIterator<Item = &'a str>) -> bool {
+    let references = std::iter::once(homepage.to_string().as_str())
+        .chain(source)
+        .map(normalize_repository_url)
+        .collect_vec();
+    let mut candidates = candidates.into_iter().map(normalize_repository_url).peekable();
+
+    if candidates.peek().is_none() {
+        return true;
+    }
+
+    candidates.any(|candidate| {
+        references.iter().any(|reference| {
+            let (shorter, longer) = if candidate.len() <= reference.len() {
+                (&candidate, reference)
+            } else {
+                (reference, &candidate)
+            };
+
+            longer == shorter || longer.starts_with(&format!("{shorter}/"))
+        })
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use super::normalize_version;
+    use git_url_parse::GitUrl;
+
+    use std::fs;
+
+    use chrono::NaiveDate;
+    use rootcause::Result;
+
+    use super::{Ast, Handles, UpdateOptions, Updater, Updaters, Upstream, normalize_version, repository_matches, unstable_version};
+    use crate::exclude::Exclusions;
+    use crate::package::{Package, PackageKind};
+    use crate::progress::Progress;
+
+    struct Stub(Handles);
+
+    impl Updater for Stub {
+        fn new(_options: &UpdateOptions) -> Result<Self> {
+            Ok(Self(Handles::default()))
+        }
+
+        fn update(&self, _package: &mut Package, _pb: Option<&Progress>) -> Result<()> {
+            Ok(())
+        }
+
+        fn handles(&self) -> Handles {
+            self.0
+        }
+    }
+
+    #[test]
+    fn picks_the_highest_priority_claim() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry.nix");
+
+        fs::write(
+            &path,
+            r#"{ stdenv, fetchFromSourcehut }:
+stdenv.mkDerivation rec {
+  pname = "tool";
+  version = "1.0.0";
+  src = fetchFromSourcehut { owner = "~me"; repo = "tool"; rev = "v${version}"; hash = ""; };
+  meta = { homepage = "https://git.sr.ht/~me/tool"; };
+}"#,
+        )
+        .unwrap();
+
+        let mut package = Package::from_file(&path, &[], &Exclusions::default()).packages.pop().unwrap();
+
+        let mut updaters = Updaters::empty(&UpdateOptions::default());
+        updaters
+            .register(
+                "git",
+                Stub(Handles {
+                    kinds: &[PackageKind::Git],
+                    priority: 10,
+                    ..Handles::default()
+                }),
+            )
+            .register(
+                "sourcehut",
+                Stub(Handles {
+                    fetchers: &["fetchFromSourcehut"],
+                    hosts: &["git.sr.ht"],
+                    priority: 60,
+                    ..Handles::default()
+                }),
+            );
+
+        assert_eq!(package.kind, PackageKind::Git);
+        assert_eq!(updaters.select(&package).unwrap(), "sourcehut");
+
+        // A forced kind leaves fetchers out of it, and the updater setting overrides every claim
+        package.settings.kind = Some(PackageKind::Git);
+        assert_eq!(updaters.select(&package).unwrap(), "git");
+
+        package.settings.kind = None;
+        package.settings.updater = Some("git".to_string());
+        assert_eq!(updaters.select(&package).unwrap(), "git");
+
+        package.settings.updater = Some("gitlab".to_string());
+        assert!(updaters.select(&package).is_err());
+
+        package.settings.updater = None;
+        updaters.register(
+            "sourcehut",
+            Stub(Handles {
+                priority: 60,
+                ..Handles::default()
+            }),
+        );
+        assert_eq!(updaters.select(&package).unwrap(), "git");
+        assert_eq!(updaters.names().collect::<Vec<_>>(), ["git", "sourcehut"]);
+    }
+
+    #[test]
+    fn tag_pinned_github_sources_follow_releases_whatever_their_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pinned.nix");
+
+        fs::write(
+            &path,
+            r#"{ rustPlatform, fetchFromGitHub }:
+rustPlatform.buildRustPackage rec {
+  pname = "tool";
+  version = "1.0.0";
+  src = fetchFromGitHub { owner = "me"; repo = "tool"; rev = "v${version}"; hash = ""; };
+  cargoHash = "";
+  meta = { homepage = "https://github.com/me/tool"; };
+}"#,
+        )
+        .unwrap();
+
+        let package = Package::from_file(&path, &[], &Exclusions::default()).packages.pop().unwrap();
+
+        let mut updaters = Updaters::empty(&UpdateOptions::default());
+        updaters
+            .register(
+                "cargo",
+                Stub(Handles {
+                    kinds: &[PackageKind::Cargo],
+                    priority: 50,
+                    ..Handles::default()
+                }),
+            )
+            .register(
+                "github",
+                Stub(Handles {
+                    fetchers: &["fetchFromGitHub"],
+                    priority: 40,
+                    releases: true,
+                    ..Handles::default()
+                }),
+            );
+
+        assert_eq!(package.kind, PackageKind::Cargo);
+        assert_eq!(updaters.select(&package).unwrap(), "github");
+    }
 
     #[test]
     fn normalizes_package_prefixed_version() {
@@ -76,4 +502,64 @@ mod tests {
     fn keeps_unprefixed_version() {
         assert_eq!(normalize_version("example", "1.2.3"), "1.2.3");
     }
+
+    #[test]
+    fn compares_latest_versions_and_commits() {
+        assert!(Upstream::version("1.2.3", "1.3.0").outdated);
+        assert!(!Upstream::version("1.3.0", "1.3.0").outdated);
+        assert!(!Upstream::version("2.0.0-rc.1", "1.3.0").outdated);
+
+        let latest = Upstream::commit(Some("0123456789abcdef"), "fedcba9876543210");
+
+        assert_eq!((latest.current.as_str(), latest.latest.as_str(), latest.outdated), ("01234567", "fedcba98", true));
+        assert!(!Upstream::commit(Some("0123456789abcdef"), "0123456789abcdef").outdated);
+    }
+
+    #[test]
+    fn redates_unstable_versions() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 9).unwrap();
+
+        assert_eq!(unstable_version("unstable-2024-01-05", date).as_deref(), Some("unstable-2024-03-09"));
+        assert_eq!(unstable_version("0.1.0-unstable-2024-01-05", date).as_deref(), Some("0.1.0-unstable-2024-03-09"));
+        assert_eq!(unstable_version("0.pre+date=2024-01-05", date).as_deref(), Some("0.pre+date=2024-03-09"));
+        assert_eq!(unstable_version("1.2.3", date), None);
+        assert_eq!(unstable_version("unstable-abcdef12", date), None);
+    }
+
+    #[test]
+    fn repository_matches_ignores_scheme_and_suffix_differences() {
+        let homepage = GitUrl::parse("https://github.com/astral-sh/ruff").unwrap();
+
+        assert!(repository_matches(&homepage, None, ["git+https://www.GitHub.com/astral-sh/ruff.git"]));
+    }
+
+    #[test]
+    fn repository_matches_accepts_subpaths() {
+        let homepage = GitUrl::parse("https://github.com/astral-sh/ruff").unwrap();
+
+        assert!(repository_matches(&homepage, None, ["https://github.com/astral-sh/ruff/tree/main/crates/ruff"]));
+    }
+
+    #[test]
+    fn repository_matches_rejects_similarly_named_project() {
+        let homepage = GitUrl::parse("https://github.com/psf/requests").unwrap();
+
+        assert!(!repository_matches(&homepage, Some("https://github.com/psf/requests"), ["https://github.com/evil/reqeusts"]));
+    }
+
+    #[test]
+    fn repository_matches_trusts_registries_without_urls() {
+        let homepage = GitUrl::parse("https://github.com/psf/requests").unwrap();
+
+        assert!(repository_matches(&homepage, None, []));
+    }
+
+    #[test]
+    fn repository_matches_accepts_the_fetchers_repository() {
+        let homepage = GitUrl::parse("https://docs.pydantic.dev/latest").unwrap();
+        let source = Ast::from_ast(rnix::Root::parse(r#"{ src = fetchFromGitHub { owner = "pydantic"; repo = "pydantic"; rev = "v2.0"; }; }"#)).source_url();
+
+        assert!(!repository_matches(&homepage, None, ["https://github.com/pydantic/pydantic"]));
+        assert!(repository_matches(&homepage, source.as_deref(), ["https://github.com/pydantic/pydantic"]));
+    }
 }
@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::Path;
+
+use rootcause::Result;
+
+use crate::nix::ast::Ast;
+use crate::package::Package;
+use crate::progress::Progress;
+use crate::tools::{Tool, command, run};
+use crate::updater::{Handles, UpdateOptions, Updater};
+
+/// Hands version and hash updating to `nix-update`, for fetchers the built-in updaters don't understand. Discovery,
+/// building, caching and reporting stay with this tool.
+pub struct NixUpdate;
+
+impl Updater for NixUpdate {
+    fn new(_options: &UpdateOptions) -> Result<Self> {
+        Ok(Self)
+    }
+
+    fn handles(&self) -> Handles {
+        Handles {
+            priority: 90,
+            ..Handles::default()
+        }
+    }
+
+    /// Packages that opted into `nix_update`
+    fn claims(&self, package: &Package) -> bool {
+        package.settings.nix_update()
+    }
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
+        if let Some(pb) = pb {
+            pb.set_message(format!("{}: Running nix-update ...", package.name()));
+        }
+
+        let original = fs::read_to_string(&package.path)?;
+
+        let mut nix_update = command(Tool::NixUpdate);
+
+        if Path::new("flake.nix").is_file() {
+            nix_update.arg("--flake");
+        }
+
+        nix_update.args(package.settings.nix_update_args.iter().flatten()).arg(package.flake_attr());
+
+        let output = run(&mut nix_update, Tool::NixUpdate);
+
+        // nix-update edits the file in place; its edits go through the transaction like any other updater's
+        let updated = fs::read_to_string(&package.path)?;
+        fs::write(&package.path, &original)?;
+
+        let output = output?;
+
+        if !output.status.success() {
+            package
+                .result
+                .failed(format!("nix-update failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+            return Ok(());
+        }
+
+        if updated == original {
+            package.result.up_to_date();
+            return Ok(());
+        }
+
+        let ast = Ast::from_ast(rnix::Root::parse(&updated)).scoped(package.scope);
+
+        if let Some(hash) = ast.get_in(&["src"], "hash") {
+            package.result.artifact(package.homepage.to_string(), ast.get_in(&["src"], "rev").as_deref(), hash);
+        }
+
+        let old_rev = package.ast().get_in(&["src"], "rev");
+        let new_rev = ast.get_in(&["src"], "rev");
+
+        package.stage(&ast);
+
+        let new_version = ast.get("version");
+
+        if new_version.as_deref() != Some(package.version.as_str()) {
+            package.result.version(Some(&package.version), new_version.as_deref());
+        } else if old_rev != new_rev {
+            package.result.git_commit(old_rev.as_deref(), new_rev.as_deref());
+        } else {
+            package.result.hash(Some(&package.nix_hash), ast.get_in(&["src"], "hash").as_deref());
+        }
+
+        Ok(())
+    }
+}
@@ -1,14 +1,18 @@
 use std::fs;
-use std::path::Path;
 
-use indicatif::ProgressBar;
+use chrono::{DateTime, Utc};
 use rootcause::{Result, report};
+use tracing::warn;
 
-use crate::Config;
 use crate::clients::nix::Nix;
 use crate::clients::{GitHubClient, NpmClient};
-use crate::package::Package;
-use crate::updater::{Updater, short_hash};
+use crate::error::UpdateError;
+use crate::nix::fod::FixedOutput;
+use crate::package::{Package, PackageKind};
+use crate::progress::Progress;
+use crate::provenance::{Source, digest};
+use crate::tools::Tool;
+use crate::updater::{Handles, UpdateOptions, Updater, Upstream, redated_version, short_hash};
 
 pub struct NpmUpdater {
     force: bool,
@@ -17,19 +21,75 @@ pub struct NpmUpdater {
 }
 
 impl Updater for NpmUpdater {
-    fn new(config: &Config) -> Result<Self> {
+    fn new(options: &UpdateOptions) -> Result<Self> {
         Ok(Self {
-            force: config.force,
+            force: options.force,
             npm_client: NpmClient::new()?,
-            github_client: GitHubClient::new()?,
+            github_client: GitHubClient::shared()?,
         })
     }
 
-    fn update(&self, package: &mut Package, pb: Option<&ProgressBar>) -> Result<()> {
+    fn handles(&self) -> Handles {
+        Handles {
+            kinds: &[PackageKind::Npm],
+            priority: 50,
+            ..Handles::default()
+        }
+    }
+
+    fn last_activity(&self, package: &Package) -> Result<Option<DateTime<Utc>>> {
+        self.github_client.last_activity(&package.homepage)
+    }
+
+    fn latest(&self, package: &Package) -> Result<Option<Upstream>> {
+        if let Some(tag) = &package.settings.dist_tag {
+            return Ok(self
+                .npm_client
+                .dist_tag(package.settings.npm_name(&package.name), tag)?
+                .map(|release| Upstream::version(&package.version, release.version)));
+        }
+
+        let current = package.ast().get_in(&["src"], "rev");
+
+        Ok(self
+            .github_client
+            .latest_commit(&package.homepage, package.settings.branch.as_deref())?
+            .map(|latest| Upstream::commit(current.as_deref(), &latest)))
+    }
+
+    fn update(&self, package: &mut Package, pb: Option<&Progress>) -> Result<()> {
         let ast_tmp = package.ast();
 
-        let current_git_commit = ast_tmp.get("rev");
-        let latest_git_commit = self.github_client.latest_commit(&package.homepage)?;
+        let current_git_commit = ast_tmp.get_in(&["src"], "rev");
+
+        // Following a dist-tag, the version is the release's and the commit the one it was published from
+        let (latest_git_commit, tagged_version) = match package.settings.dist_tag.clone() {
+            Some(tag) => {
+                let Some(release) = self.npm_client.dist_tag(package.settings.npm_name(&package.name), &tag)? else {
+                    package.result.fail(UpdateError::upstream(package, format!("No '{tag}' dist-tag on the npm registry")));
+                    return Ok(());
+                };
+
+                if let Some(source) = release.source {
+                    package.result.source(source);
+                }
+
+                if self.should_skip_update(self.force, &package.version, &release.version) {
+                    package.result.up_to_date();
+                    return Ok(());
+                }
+
+                let Some(git_head) = release.git_head else {
+                    package
+                        .result
+                        .failed(format!("npm release {} doesn't record the git commit it was published from", release.version));
+                    return Ok(());
+                };
+
+                (Some(git_head), Some(release.version))
+            }
+            None => (self.github_client.latest_commit(&package.homepage, package.settings.branch.as_deref())?, None),
+        };
 
         if let (Some(current), Some(latest)) = (&current_git_commit, &latest_git_commit)
             && self.should_skip_update(self.force, current, latest)
@@ -40,33 +100,55 @@ impl Updater for NpmUpdater {
 
         // If we have a new commit, proceed with update
         let Some(latest_commit) = latest_git_commit else {
-            package.result.failed("Could not get latest commit from GitHub");
+            package.result.fail(UpdateError::upstream(package, "Could not get latest commit from GitHub"));
             return Ok(());
         };
 
         // Get new hash using nurl
-        let Some((new_hash, _)) = Nix::hash_and_rev(&package.homepage.to_string(), Some(&latest_commit))? else {
-            package.result.failed("Failed to get new hash");
+        let Some((new_hash, _)) = Nix::hash_and_rev(&package.homepage.to_string(), Some(&latest_commit), package.ast().fetch_flags())? else {
+            package.result.fail(UpdateError::no_hash(Tool::Nurl, &package.homepage.to_string()));
             return Ok(());
         };
 
-        // buildNpmPackage reads package-lock.json from src by default. Only fetch and vendor a
-        // standalone lockfile when the package definition references ./package-lock.json (upstreams
-        // that don't ship one). Otherwise bumping the source is sufficient.
-        if references_package_lock(ast_tmp.content()) {
-            if let Some(pb) = pb {
-                pb.set_message(format!("{}: Downloading package-lock.json...", package.name()));
-            }
+        // buildNpmPackage reads package-lock.json from src by default. Only vendor a standalone lockfile when the
+        // package definition references ./package-lock.json (upstreams that don't ship one). Otherwise bumping the
+        // source is sufficient, and the lockfile is only fetched to hash it.
+        let vendored = references_package_lock(ast_tmp.content());
+
+        if let Some(pb) = pb {
+            pb.set_message(format!("{}: Downloading package-lock.json...", package.name()));
+        }
 
-            // Use the specific commit hash to get the exact package-lock.json
-            let package_lock_url = format!("https://raw.githubusercontent.com/{}/{}/package-lock.json", package.homepage.path(), latest_commit);
+        // Use the specific commit hash to get the exact package-lock.json
+        let package_lock_url = format!(
+            "https://raw.githubusercontent.com/{}/{}/{}",
+            package.homepage.path().trim_start_matches('/'),
+            latest_commit,
+            package_lock_path(package.settings.package_lock.as_deref(), ast_tmp.get("sourceRoot").as_deref())
+        );
+
+        // Only a vendored lockfile is required; without one the npmDepsHash is found by building
+        let package_lock_content = match self.npm_client.download_package_lock(&package_lock_url) {
+            Ok(content) => content,
+            Err(e) if !vendored => {
+                warn!("{}: Could not download {package_lock_url}, building for npmDepsHash: {e}", package.name());
+                None
+            }
+            Err(e) => return Err(e),
+        };
 
-            let Some(package_lock_content) = self.npm_client.download_package_lock(&package_lock_url)? else {
-                package.result.failed("Could not download package-lock.json from repository");
+        if vendored {
+            let Some(content) = &package_lock_content else {
+                package.result.fail(UpdateError::upstream(package, "Could not download package-lock.json from repository"));
                 return Ok(());
             };
 
-            save_package_lock(&package.path, &package_lock_content)?;
+            save_package_lock(package, content)?;
+
+            package.result.source(Source {
+                url: package_lock_url,
+                digest: Some(digest(content.as_bytes())),
+            });
         }
 
         let mut ast = package.ast();
@@ -74,24 +156,35 @@ impl Updater for NpmUpdater {
         // Update rev and hash
         ast.update_git(current_git_commit.as_deref(), &latest_commit, &new_hash, None)?;
 
-        // Update version to include the commit hash
-        let latest_version = short_hash(&latest_commit);
+        package.result.artifact(package.homepage.to_string(), Some(&latest_commit), &new_hash);
 
-        // Check if version follows pattern "x.y.z-${rev}" and update accordingly
-        if let Some(base_version) = package.version.split('-').next() {
-            let new_version = format!("{base_version}-{latest_version}");
-            ast.set("version", &package.version, &new_version)?;
-        }
+        // A dist-tag release keeps its version; dated unstable versions follow the commit's date, others pattern "x.y.z-${rev}"
+        // take its short hash
+        let new_version = match tagged_version {
+            Some(version) => version,
+            None => match redated_version(&self.github_client, &package.homepage, &package.version, &latest_commit)? {
+                Some(redated) => redated,
+                None => format!("{}-{}", package.version.split('-').next().unwrap_or(&package.version), short_hash(&latest_commit)),
+            },
+        };
 
-        ast.clear_vendor_hash("npmDeps")?;
-        ast.update_vendor(package, "npmDeps", pb)?;
+        ast.set("version", &package.version, &new_version)?;
 
-        package.write(&ast)?;
+        // Hash the lockfile directly when possible; building to provoke a hash mismatch is the fallback
+        if let Some(new_hash) = package_lock_content.as_deref().and_then(|content| npm_deps_hash(package, content))
+            && let Some(old_hash) = ast.get("npmDepsHash")
+        {
+            ast.set("npmDepsHash", &old_hash, &new_hash)?;
+        } else if FixedOutput::NPM.declared(&ast) {
+            FixedOutput::NPM.resolve(&mut ast, package, pb)?;
+        }
 
-        package.result.git_commit(current_git_commit.as_deref(), Some(&latest_commit)).version(
-            Some(&package.version),
-            Some(&format!("{}-{latest_version}", package.version.split('-').next().unwrap_or(&package.version))),
-        );
+        package.stage(&ast);
+
+        package
+            .result
+            .git_commit(current_git_commit.as_deref(), Some(&latest_commit))
+            .version(Some(&package.version), Some(&new_version));
 
         Ok(())
     }
@@ -102,14 +195,54 @@ fn references_package_lock(content: &str) -> bool {
     content.contains("package-lock.json")
 }
 
-/// Save package-lock.json next to the Nix file
-fn save_package_lock(nix_path: &Path, content: &str) -> Result<()> {
-    let package_lock_path = nix_path
+/// Path of package-lock.json within the repository: the `package_lock` setting, else in the directory `sourceRoot`
+/// points at (which starts with the unpacked source's own directory, e.g. `source/packages/cli`), else the root
+fn package_lock_path(setting: Option<&str>, source_root: Option<&str>) -> String {
+    if let Some(path) = setting {
+        return path.trim_start_matches("./").to_string();
+    }
+
+    source_root
+        .and_then(|root| root.split_once('/').map(|(_, dir)| dir.trim_matches('/')))
+        .filter(|dir| !dir.is_empty() && *dir != ".")
+        .map_or_else(|| "package-lock.json".to_string(), |dir| format!("{dir}/package-lock.json"))
+}
+
+/// npmDepsHash for a lockfile, via `prefetch-npm-deps` on a temporary copy named after its content, so a recorded
+/// run replays the same command
+fn npm_deps_hash(package: &Package, content: &str) -> Option<String> {
+    let key = digest(content.as_bytes());
+    let key = key.trim_start_matches("sha256:").get(..16).unwrap_or_default();
+    let path = std::env::temp_dir().join(format!("nix-package-updater-{key}-{}-package-lock.json", package.name));
+
+    fs::write(&path, content).ok()?;
+    let hash = Nix::prefetch_npm_deps(&path);
+    let _ = fs::remove_file(&path);
+
+    hash
+}
+
+/// Save package-lock.json next to the Nix file. It is written through immediately since the npmDepsHash
+/// build reads it, and restored if the update is rolled back.
+fn save_package_lock(package: &mut Package, content: &str) -> Result<()> {
+    let package_lock_path = package
+        .path
         .parent()
         .ok_or_else(|| report!("Could not get parent directory of Nix file"))?
         .join("package-lock.json");
 
-    fs::write(&package_lock_path, content)?;
+    package.transaction.write_through(&package_lock_path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::package_lock_path;
 
-    Ok(())
+    #[test]
+    fn package_lock_is_found_in_the_source_root_unless_configured() {
+        assert_eq!(package_lock_path(None, None), "package-lock.json");
+        assert_eq!(package_lock_path(None, Some("source")), "package-lock.json");
+        assert_eq!(package_lock_path(None, Some("${src.name}/packages/cli")), "packages/cli/package-lock.json");
+        assert_eq!(package_lock_path(Some("./web/package-lock.json"), Some("source/cli")), "web/package-lock.json");
+    }
 }
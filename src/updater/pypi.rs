@@ -1,11 +1,18 @@
-use indicatif::ProgressBar;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
 use rootcause::Result;
+use tracing::warn;
 
-use crate::Config;
 use crate::clients::PyPiClient;
 use crate::clients::nix::Nix;
-use crate::package::Package;
-use crate::updater::Updater;
+use crate::clients::pypi::PyPiReleaseFile;
+use crate::error::UpdateError;
+use crate::nix::ast::{Ast, Value};
+use crate::package::{Package, PackageKind};
+use crate::progress::Progress;
+use crate::tools::Tool;
+use crate::updater::{Handles, UpdateOptions, Updater, Upstream, repository_matches};
 
 pub struct PyPiUpdater {
     force: bool,
@@ -13,19 +20,52 @@ pub struct PyPiUpdater {
 }
 
 impl Updater for PyPiUpdater {
-    fn new(config: &Config) -> Result<Self> {
+    fn new(options: &UpdateOptions) -> Result<Self> {
         Ok(Self {
-            force: config.force,
+            force: options.force,
             client: PyPiClient::new()?,
         })
     }
 
-    fn update(&self, package: &mut Package, _pb: Option<&ProgressBar>) -> Result<()> {
+    fn handles(&self) -> Handles {
+        Handles {
+            kinds: &[PackageKind::PyPi],
+            priority: 50,
+            ..Handles::default()
+        }
+    }
+
+    fn last_activity(&self, package: &Package) -> Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .client
+            .project(&package.name)?
+            .and_then(|data| data.urls.iter().filter_map(|file| file.upload_time_iso_8601).max()))
+    }
+
+    fn latest(&self, package: &Package) -> Result<Option<Upstream>> {
+        Ok(self.client.project(&package.name)?.map(|data| Upstream::version(&package.version, data.info.version)))
+    }
+
+    fn update(&self, package: &mut Package, _pb: Option<&Progress>) -> Result<()> {
         let Some(data) = self.client.project(&package.name)? else {
-            package.result.failed(format!("{}: Package not found on PyPI", package.name()));
+            package
+                .result
+                .fail(UpdateError::upstream(package, format!("{}: Package not found on PyPI", package.name())));
             return Ok(());
         };
 
+        if let Some(source) = &data.source {
+            package.result.source(source.clone());
+        }
+
+        if !repository_matches(&package.homepage, package.ast().source_url().as_deref(), data.info.urls()) {
+            warn!(
+                package = %package.name,
+                homepage = %package.homepage,
+                "PyPI project does not reference this package's homepage - the lookup may have resolved a different project"
+            );
+        }
+
         let latest_version = data.info.version;
 
         if self.should_skip_update(self.force, &package.version, &latest_version) {
@@ -35,26 +75,67 @@ impl Updater for PyPiUpdater {
 
         let mut ast = package.ast();
 
+        let platform_blocks = ast.platforms();
+
+        // A plain fetchPypi source has one hash, for the file its arguments name
+        if platform_blocks.is_empty()
+            && Ast::contains_function_call(&ast.node(), "fetchPypi")
+            && let Some(releases) = data.releases.get(&latest_version)
+        {
+            let source = FetchPypi::from_ast(&ast);
+
+            let Some(file) = releases.iter().find(|file| source.matches(file)) else {
+                package
+                    .result
+                    .fail(UpdateError::upstream(package, format!("No {} found for {latest_version}", source.describe())));
+                return Ok(());
+            };
+
+            let Some(new_hash) = Nix::prefetch_hash(&file.url)? else {
+                package.result.fail(UpdateError::no_hash(Tool::Nix, &file.url));
+                return Ok(());
+            };
+
+            if let Some(Value::String(old_hash)) = ast.get_value(&["src"], source.hash_attr) {
+                ast.set_in(&["src"], source.hash_attr, &old_hash, &new_hash)?;
+            }
+
+            package.result.artifact(&file.url, None, &new_hash);
+        }
+
         // Update platform hashes
         if let Some(releases) = data.releases.get(&latest_version) {
-            //
-            let platform_blocks = ast.platforms();
+            let mut targets = Vec::new();
 
-            for block in platform_blocks {
+            for block in &platform_blocks {
                 let (Some(platform_value), Some(old_hash)) = (block.attributes.get("platform"), block.attributes.get("hash")) else {
                     continue;
                 };
 
-                // Find matching wheel by platform
-                let Some(url) = releases.iter().find(|w| w.filename.contains(platform_value)).map(|w| &w.url) else {
-                    package.result.failed(format!("No wheel found for platform {platform_value}"));
-                    return Ok(());
-                };
+                let spec = WheelSpec::new(platform_value, &block.attributes);
+
+                match spec.select(releases) {
+                    Ok(wheel) => targets.push((block, old_hash, wheel.url.as_str())),
+                    Err(candidates) => {
+                        package.result.failed(format!(
+                            "{} wheels match {spec} for {}: {}",
+                            if candidates.is_empty() { "No" } else { "Several" },
+                            block.platform_name,
+                            candidates.join(", ")
+                        ));
+                        return Ok(());
+                    }
+                }
+            }
+
+            let urls = targets.iter().map(|(_, _, url)| *url).collect::<Vec<_>>();
 
-                if let Some(new_hash) = Nix::prefetch_hash(url)? {
-                    ast.set("hash", old_hash, &new_hash)?;
+            for ((block, old_hash, url), new_hash) in targets.iter().zip(Nix::prefetch_hashes(&urls)) {
+                if let Some(new_hash) = new_hash? {
+                    ast.set_in(&[&block.platform_name], "hash", old_hash, &new_hash)?;
+                    package.result.artifact(*url, None, &new_hash);
                 } else {
-                    package.result.failed(format!("Failed to get hash for platform {}", block.platform_name));
+                    package.result.fail(UpdateError::no_hash(Tool::Nix, url));
                     break;
                 }
             }
@@ -62,9 +143,236 @@ impl Updater for PyPiUpdater {
 
         ast.set("version", &package.version, &latest_version)?;
 
-        package.write(&ast)?;
+        // The first file uploaded for a release marks when it was published
+        package.result.released(
+            data.releases
+                .get(&latest_version)
+                .and_then(|files| files.iter().filter_map(|file| file.upload_time_iso_8601).min()),
+        );
+
+        package.stage(&ast);
         package.result.version(Some(package.version.as_ref()), Some(latest_version.as_ref()));
 
         Ok(())
     }
 }
+
+/// The compatibility tags of a wheel filename, `{name}-{version}(-{build})?-{python}-{abi}-{platform}.whl`.
+/// Each tag may be a `.`-separated set, e.g. `py2.py3` or `manylinux_2_17_x86_64.manylinux2014_x86_64`.
+#[derive(Debug, PartialEq, Eq)]
+struct WheelTags<'a> {
+    python: Vec<&'a str>,
+    abi: Vec<&'a str>,
+    platform: Vec<&'a str>,
+}
+
+impl<'a> WheelTags<'a> {
+    fn parse(filename: &'a str) -> Option<Self> {
+        let mut parts = filename.strip_suffix(".whl")?.rsplitn(4, '-');
+        let platform = parts.next()?.split('.').collect();
+        let abi = parts.next()?.split('.').collect();
+        let python = parts.next()?.split('.').collect();
+
+        // Name and version (and the optional build tag) must precede the tags
+        parts.next()?;
+
+        Some(Self { python, abi, platform })
+    }
+}
+
+/// Which wheel a platform block wants: its `platform` attribute is either a platform tag
+/// (`manylinux_2_17_x86_64`) or a full `python-abi-platform` triple (`cp312-abi3-manylinux_2_17_x86_64`),
+/// optionally narrowed by `python` and `abi` attributes.
+struct WheelSpec<'a> {
+    python: Option<&'a str>,
+    abi: Option<&'a str>,
+    platform: &'a str,
+}
+
+impl<'a> WheelSpec<'a> {
+    fn new(spec: &'a str, attributes: &'a HashMap<String, String>) -> Self {
+        let (python, abi, platform) = match spec.splitn(3, '-').collect::<Vec<_>>()[..] {
+            [python, abi, platform] => (Some(python), Some(abi), platform),
+            _ => (None, None, spec),
+        };
+
+        Self {
+            python: attributes.get("python").map(String::as_str).or(python),
+            abi: attributes.get("abi").map(String::as_str).or(abi),
+            platform,
+        }
+    }
+
+    /// The one wheel matching this spec, preferring exact platform tags over ones merely containing it
+    /// (e.g. `x86_64`). Otherwise the filenames of all candidates, empty when nothing matched.
+    fn select<'f>(&self, files: &'f [PyPiReleaseFile]) -> std::result::Result<&'f PyPiReleaseFile, Vec<&'f str>> {
+        let wheels = files
+            .iter()
+            .filter_map(|file| Some((file, WheelTags::parse(&file.filename)?)))
+            .filter(|(_, tags)| self.python.is_none_or(|python| tags.python.contains(&python)))
+            .filter(|(_, tags)| self.abi.is_none_or(|abi| tags.abi.contains(&abi)))
+            .collect::<Vec<_>>();
+
+        let exact = wheels.iter().filter(|(_, tags)| tags.platform.contains(&self.platform)).collect::<Vec<_>>();
+
+        let candidates = if exact.is_empty() {
+            wheels.iter().filter(|(_, tags)| tags.platform.iter().any(|tag| tag.contains(self.platform))).collect()
+        } else {
+            exact
+        };
+
+        match candidates[..] {
+            [(file, _)] => Ok(file),
+            _ => Err(candidates.iter().map(|(file, _)| file.filename.as_str()).collect()),
+        }
+    }
+}
+
+impl std::fmt::Display for WheelSpec<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}-{}", self.python.unwrap_or("*"), self.abi.unwrap_or("*"), self.platform)
+    }
+}
+
+/// The file a `fetchPypi` call downloads, from its `format`, `extension` and wheel tag arguments
+/// (defaulting like nixpkgs: an sdist `.tar.gz`, or a `py2.py3-none-any` wheel).
+struct FetchPypi {
+    wheel: bool,
+    extension: String,
+    python: String,
+    abi: String,
+    platform: String,
+
+    /// `hash`, or `sha256` in older expressions
+    hash_attr: &'static str,
+}
+
+impl FetchPypi {
+    fn from_ast(ast: &Ast) -> Self {
+        let arg = |name: &str| match ast.get_value(&["src"], name) {
+            Some(Value::String(value)) => Some(value),
+            _ => None,
+        };
+
+        let wheel = arg("format").is_some_and(|format| format == "wheel");
+        let python = arg("python").or_else(|| arg("dist")).unwrap_or_else(|| "py2.py3".to_string());
+
+        Self {
+            extension: arg("extension").unwrap_or_else(|| if wheel { "whl" } else { "tar.gz" }.to_string()),
+            python,
+            abi: arg("abi").unwrap_or_else(|| "none".to_string()),
+            platform: arg("platform").unwrap_or_else(|| "any".to_string()),
+            hash_attr: if arg("hash").is_none() && arg("sha256").is_some() { "sha256" } else { "hash" },
+            wheel,
+        }
+    }
+
+    fn matches(&self, file: &PyPiReleaseFile) -> bool {
+        if self.wheel {
+            file.filename.ends_with(&format!("-{}-{}-{}.{}", self.python, self.abi, self.platform, self.extension))
+        } else {
+            file.packagetype == "sdist" && file.filename.ends_with(&format!(".{}", self.extension))
+        }
+    }
+
+    fn describe(&self) -> String {
+        if self.wheel {
+            format!("{}-{}-{} wheel", self.python, self.abi, self.platform)
+        } else {
+            format!(".{} sdist", self.extension)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{FetchPypi, WheelSpec, WheelTags};
+    use crate::clients::pypi::PyPiReleaseFile;
+    use crate::nix::ast::Ast;
+
+    fn file(filename: &str, packagetype: &str) -> PyPiReleaseFile {
+        PyPiReleaseFile {
+            filename: filename.to_string(),
+            url: format!("https://files.pythonhosted.org/{filename}"),
+            packagetype: packagetype.to_string(),
+            upload_time_iso_8601: None,
+        }
+    }
+
+    #[test]
+    fn fetch_pypi_selects_file_from_arguments() {
+        let files = [
+            file("foo-1.0-py3-none-any.whl", "bdist_wheel"),
+            file("foo-1.0.zip", "sdist"),
+            file("foo-1.0.tar.gz", "sdist"),
+        ];
+        let find = |nix: &str| {
+            let source = FetchPypi::from_ast(&Ast::from_ast(rnix::Root::parse(nix)));
+            files.iter().find(|file| source.matches(file)).map(|file| file.filename.as_str())
+        };
+
+        assert_eq!(
+            find(r#"{ format = "pyproject"; src = fetchPypi { inherit pname version; hash = ""; }; }"#),
+            Some("foo-1.0.tar.gz")
+        );
+        assert_eq!(find(r#"{ src = fetchPypi { inherit pname version; extension = "zip"; }; }"#), Some("foo-1.0.zip"));
+        assert_eq!(
+            find(r#"{ src = fetchPypi { inherit pname version; format = "wheel"; dist = "py3"; python = "py3"; }; }"#),
+            Some("foo-1.0-py3-none-any.whl")
+        );
+        assert_eq!(find(r#"{ src = fetchPypi { inherit pname version; format = "wheel"; }; }"#), None);
+    }
+
+    #[test]
+    fn fetch_pypi_keeps_sha256_attribute() {
+        let source = FetchPypi::from_ast(&Ast::from_ast(rnix::Root::parse(r#"{ src = fetchPypi { sha256 = "abc"; }; }"#)));
+
+        assert_eq!(source.hash_attr, "sha256");
+    }
+
+    #[test]
+    fn parses_wheel_tags() {
+        assert_eq!(
+            WheelTags::parse("ruff-0.6.0-py3-none-manylinux_2_17_x86_64.manylinux2014_x86_64.whl"),
+            Some(WheelTags {
+                python: vec!["py3"],
+                abi: vec!["none"],
+                platform: vec!["manylinux_2_17_x86_64", "manylinux2014_x86_64"],
+            })
+        );
+        assert_eq!(WheelTags::parse("foo-1.0-1-cp312-abi3-macosx_11_0_arm64.whl").map(|tags| tags.abi), Some(vec!["abi3"]));
+        assert_eq!(WheelTags::parse("foo-1.0.tar.gz"), None);
+    }
+
+    #[test]
+    fn wheel_spec_selects_by_tags() {
+        let files = [
+            file("foo-1.0-cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64.whl", "bdist_wheel"),
+            file("foo-1.0-cp312-cp312-manylinux_2_17_x86_64.manylinux2014_x86_64.whl", "bdist_wheel"),
+            file("foo-1.0-cp312-cp312-musllinux_1_1_x86_64.whl", "bdist_wheel"),
+            file("foo-1.0-cp312-cp312-macosx_11_0_arm64.whl", "bdist_wheel"),
+            file("foo-1.0.tar.gz", "sdist"),
+        ];
+        let none = HashMap::new();
+        let select = |spec: &WheelSpec<'_>| spec.select(&files).map(|file| file.filename.as_str());
+
+        assert_eq!(select(&WheelSpec::new("macosx_11_0_arm64", &none)), Ok("foo-1.0-cp312-cp312-macosx_11_0_arm64.whl"));
+        assert_eq!(
+            select(&WheelSpec::new("cp312-cp312-manylinux2014_x86_64", &none)),
+            Ok("foo-1.0-cp312-cp312-manylinux_2_17_x86_64.manylinux2014_x86_64.whl")
+        );
+
+        let python = HashMap::from([("python".to_string(), "cp311".to_string())]);
+
+        assert_eq!(
+            select(&WheelSpec::new("manylinux_2_17_x86_64", &python)),
+            Ok("foo-1.0-cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64.whl")
+        );
+
+        // Ambiguous or missing matches report the candidates
+        assert_eq!(select(&WheelSpec::new("manylinux_2_17_x86_64", &none)).unwrap_err().len(), 2);
+        assert_eq!(select(&WheelSpec::new("win_amd64", &none)), Err(vec![]));
+    }
+}
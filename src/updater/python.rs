@@ -0,0 +1,66 @@
+use rootcause::{Result, bail, report};
+
+use crate::clients::GitHubClient;
+use crate::nix::ast::Ast;
+use crate::package::Package;
+use crate::progress::Progress;
+use crate::provenance::{Source, digest};
+
+/// Lockfiles that Python application builders (poetry2nix's `mkPoetryApplication`, uv2nix workspaces) resolve
+/// dependencies from
+const LOCKFILES: [&str; 2] = ["uv.lock", "poetry.lock"];
+
+/// Files that may be vendored next to the Nix file. `pyproject.toml` has to match the lockfile, so it follows too.
+const VENDORED: [&str; 3] = ["pyproject.toml", "uv.lock", "poetry.lock"];
+
+/// Whether the package is a Python application built from a uv or poetry lockfile
+pub fn uses_lockfile(ast: &Ast) -> bool {
+    LOCKFILES.iter().any(|lockfile| ast.content().contains(lockfile)) || Ast::contains_function_call(&ast.node(), "mkPoetryApplication")
+}
+
+/// Replace the vendored copies (`./uv.lock`, `./poetry.lock`, `./pyproject.toml`) of a lockfile-based Python
+/// application with the repository's at `commit`. They are written through immediately, since resolving the
+/// dependency hashes builds against them. Returns whether the package uses a lockfile at all.
+pub fn update_lockfiles(client: &GitHubClient, package: &mut Package, ast: &Ast, commit: &str, pb: Option<&Progress>) -> Result<bool> {
+    if !uses_lockfile(ast) {
+        return Ok(false);
+    }
+
+    let dir = package.path.parent().ok_or_else(|| report!("Could not get parent directory of Nix file"))?.to_path_buf();
+
+    for file in VENDORED.iter().filter(|file| ast.content().contains(&format!("./{file}"))) {
+        if let Some(pb) = pb {
+            pb.set_message(format!("{}: Downloading {file}...", package.name()));
+        }
+
+        let Some(content) = client.file_content(&package.homepage, file, commit)? else {
+            bail!("No {file} in the repository at {commit}");
+        };
+
+        package.transaction.write_through(&dir.join(file), &content)?;
+
+        package.result.source(Source {
+            url: format!("https://raw.githubusercontent.com/{}/{commit}/{file}", package.homepage.path().trim_start_matches('/')),
+            digest: Some(digest(content.as_bytes())),
+        });
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::uses_lockfile;
+    use crate::nix::ast::Ast;
+
+    #[test]
+    fn detects_lockfile_based_applications() {
+        let parse = |nix: &str| Ast::from_ast(rnix::Root::parse(nix));
+
+        assert!(uses_lockfile(&parse(r"{ app = mkPoetryApplication { projectDir = src; }; }")));
+        assert!(uses_lockfile(&parse(
+            r"{ workspace = uv2nix.lib.workspace.loadWorkspace { workspaceRoot = ./.; }; lock = ./uv.lock; }"
+        )));
+        assert!(!uses_lockfile(&parse(r#"{ pname = "tool"; src = fetchFromGitHub { }; }"#)));
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use nix_package_updater::clients::GitHubClient;
+
+/// How long to wait before the next `--watch` round: `interval`, give or take up to a tenth so that several instances
+/// don't hit the same upstreams in lockstep, or longer when an API quota won't have reset by then
+pub fn next_round(interval: Duration, quota_reset: Option<Duration>) -> Duration {
+    let jitter = interval / 10;
+    let wait = interval.saturating_sub(jitter) + jitter.mul_f64(2.0 * fastrand::f64());
+
+    quota_reset.map_or(wait, |reset| wait.max(reset))
+}
+
+/// Time until the GitHub quota resets, when less than a tenth of it is left for the next round
+pub fn github_quota_reset() -> Option<Duration> {
+    let rate = GitHubClient::shared().and_then(|client| client.rate_limit()).ok()?;
+
+    if rate.remaining >= rate.limit / 10 {
+        return None;
+    }
+
+    let reset = DateTime::from_timestamp(i64::try_from(rate.reset).ok()?, 0)?;
+
+    (reset - Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::next_round;
+
+    #[test]
+    fn jitters_around_the_interval() {
+        let interval = Duration::from_hours(6);
+
+        for _ in 0..100 {
+            let wait = next_round(interval, None);
+
+            assert!(wait >= Duration::from_mins(6 * 54) && wait <= Duration::from_mins(6 * 66));
+        }
+
+        assert_eq!(next_round(interval, Some(Duration::from_hours(24))), Duration::from_hours(24));
+    }
+}
@@ -0,0 +1,38 @@
+{
+  lib,
+  stdenvNoCC,
+  fetchurl,
+}:
+let
+  packages = {
+    aarch64-darwin = {
+      suffix = "aarch64-apple-darwin";
+      hash = "sha256-demo-bin-aarch64-apple-darwin-1.4.0";
+    };
+    x86_64-linux = {
+      suffix = "x86_64-unknown-linux-gnu";
+      hash = "sha256-demo-bin-x86_64-unknown-linux-gnu-1.4.0";
+    };
+  };
+
+  platform = packages.${stdenvNoCC.hostPlatform.system};
+in
+stdenvNoCC.mkDerivation rec {
+  pname = "demo-bin";
+  version = "1.4.0";
+
+  src = fetchurl {
+    url = "https://github.com/example/demo-bin/releases/download/v${version}/demo-bin-${platform.suffix}.tar.gz";
+    inherit (platform) hash;
+  };
+
+  installPhase = ''
+    install -Dm755 demo-bin $out/bin/demo-bin
+  '';
+
+  meta = {
+    description = "Prebuilt binary the GitHub release updater is tested against";
+    homepage = "https://github.com/example/demo-bin";
+    license = lib.licenses.mit;
+  };
+}
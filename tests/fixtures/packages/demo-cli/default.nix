@@ -0,0 +1,24 @@
+{
+  lib,
+  buildNpmPackage,
+  fetchFromGitHub,
+}:
+buildNpmPackage rec {
+  pname = "demo-cli";
+  version = "2.0.0";
+
+  src = fetchFromGitHub {
+    owner = "example";
+    repo = "demo-cli";
+    rev = "1111111111111111111111111111111111111111";
+    hash = "sha256-unpacked-1111111111111111111111111111111111111111.tar.gz";
+  };
+
+  npmDepsHash = "sha256-old-npm-deps";
+
+  meta = {
+    description = "npm package the npm updater is tested against";
+    homepage = "https://github.com/example/demo-cli";
+    license = lib.licenses.mit;
+  };
+}
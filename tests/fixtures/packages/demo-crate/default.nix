@@ -0,0 +1,22 @@
+{
+  lib,
+  rustPlatform,
+  fetchCrate,
+}:
+rustPlatform.buildRustPackage rec {
+  pname = "demo-crate";
+  version = "0.3.0";
+
+  src = fetchCrate {
+    inherit pname version;
+    hash = "sha256-demo-crate-0.3.0";
+  };
+
+  cargoHash = "sha256-vendored-0.3.0";
+
+  meta = {
+    description = "Rust package the crates.io updater is tested against";
+    homepage = "https://github.com/example/demo-crate";
+    license = lib.licenses.asl20;
+  };
+}
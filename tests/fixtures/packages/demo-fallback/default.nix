@@ -0,0 +1,35 @@
+{
+  lib,
+  stdenvNoCC,
+  fetchurl,
+}:
+let
+  packages = {
+    x86_64-linux = {
+      filenamePattern = "demo-fallback-*-x86_64-linux.tar.gz";
+      filename = "demo-fallback-1.4.0-x86_64-linux.tar.gz";
+      hash = "sha256-demo-fallback-1.4.0-x86_64-linux";
+    };
+  };
+
+  platform = packages.${stdenvNoCC.hostPlatform.system};
+in
+stdenvNoCC.mkDerivation rec {
+  pname = "demo-fallback";
+  version = "1.4.0";
+
+  src = fetchurl {
+    url = "https://github.com/example/demo-fallback/releases/download/v${version}/${platform.filename}";
+    inherit (platform) hash;
+  };
+
+  installPhase = ''
+    install -Dm755 demo-fallback $out/bin/demo-fallback
+  '';
+
+  meta = {
+    description = "Prebuilt binary whose newest release can't be hashed, so the updater falls back to the one before";
+    homepage = "https://github.com/example/demo-fallback";
+    license = lib.licenses.mit;
+  };
+}
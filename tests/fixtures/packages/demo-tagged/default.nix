@@ -0,0 +1,24 @@
+{
+  lib,
+  rustPlatform,
+  fetchFromGitHub,
+}:
+rustPlatform.buildRustPackage rec {
+  pname = "demo-tagged";
+  version = "1.0.0";
+
+  src = fetchFromGitHub {
+    owner = "example";
+    repo = "demo-tagged";
+    rev = "v${version}";
+    hash = "sha256-demo-tagged-1.0.0";
+  };
+
+  cargoHash = "sha256-vendored-1.0.0";
+
+  meta = {
+    description = "Tag-pinned Rust package the GitHub release updater is tested against";
+    homepage = "https://github.com/example/demo-tagged";
+    license = lib.licenses.mit;
+  };
+}
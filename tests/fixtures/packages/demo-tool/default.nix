@@ -0,0 +1,23 @@
+{
+  lib,
+  python3Packages,
+  fetchPypi,
+}:
+python3Packages.buildPythonApplication rec {
+  pname = "demo-tool";
+  version = "1.0.0";
+  pyproject = true;
+
+  src = fetchPypi {
+    inherit pname version;
+    hash = "sha256-demo_tool-1.0.0.tar.gz";
+  };
+
+  build-system = [ python3Packages.hatchling ];
+
+  meta = {
+    description = "Python package the PyPI updater is tested against";
+    homepage = "https://github.com/example/demo-tool";
+    license = lib.licenses.mit;
+  };
+}
@@ -0,0 +1,294 @@
+//! The updaters run end to end against fixture packages: PyPI, crates.io, npm and GitHub are a mock server the HTTP
+//! client reaches through `[http] mirrors`, and `nix` and `prefetch-npm-deps` are the scripts in `fixtures/bin`, so
+//! what gets written back to each `.nix` file can be checked without network access or a Nix install.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use nix_package_updater::clients::http::{Http, HttpConfig};
+use nix_package_updater::exclude::Exclusions;
+use nix_package_updater::package::{Package, UpdateStatus};
+use nix_package_updater::progress::Reporter;
+use nix_package_updater::tools::Tools;
+use nix_package_updater::{UpdateOptions, Updaters};
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// What the mock server stands in for, each served under its host name, e.g. `/pypi.org/pypi/<name>/json`
+const MOCKED: [&str; 6] = [
+    "https://pypi.org",
+    "https://crates.io",
+    "https://registry.npmjs.org",
+    "https://api.github.com",
+    "https://github.com",
+    "https://raw.githubusercontent.com",
+];
+
+struct Harness {
+    runtime: Runtime,
+    server: MockServer,
+}
+
+static HARNESS: OnceLock<Harness> = OnceLock::new();
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures")
+}
+
+/// The mock server, started once per test binary along with pointing the shared HTTP client and tools at the fakes
+fn harness() -> &'static Harness {
+    HARNESS.get_or_init(|| {
+        let runtime = Runtime::new().unwrap();
+        let server = runtime.block_on(MockServer::start());
+
+        let mirrors = MOCKED
+            .iter()
+            .map(|url| (url.to_string(), format!("{}/{}", server.uri(), url.trim_start_matches("https://"))))
+            .collect();
+
+        Http::configure(
+            &HttpConfig {
+                retries: 0,
+                cache_ttl: 0,
+                mirrors,
+                ..HttpConfig::default()
+            },
+            None,
+        )
+        .unwrap();
+
+        Tools {
+            nix: Some(fixtures().join("bin").join("nix")),
+            prefetch_npm_deps: Some(fixtures().join("bin").join("prefetch-npm-deps")),
+            ..Tools::default()
+        }
+        .install();
+
+        Harness { runtime, server }
+    })
+}
+
+impl Harness {
+    /// Answer GETs of `url`, on one of the [`MOCKED`] hosts, with `response`
+    fn serve(&self, url: &str, response: ResponseTemplate) {
+        let route = url.trim_start_matches("https://");
+        let route = route.split_once('?').map_or(route, |(route, _)| route);
+
+        self.runtime
+            .block_on(Mock::given(method("GET")).and(path(format!("/{route}"))).respond_with(response).mount(&self.server));
+    }
+
+    fn serve_json(&self, url: &str, body: &Value) {
+        self.serve(url, ResponseTemplate::new(200).set_body_json(body));
+    }
+}
+
+/// A fixture package, copied to a directory of its own so the updater can rewrite it, which is removed once the
+/// returned `TempDir` is dropped at the end of the test
+fn package(name: &str) -> (Package, TempDir) {
+    let dir = tempfile::Builder::new().prefix("nix-package-updater-tests-").tempdir().unwrap();
+    let path = dir.path().join("default.nix");
+
+    fs::copy(fixtures().join("packages").join(name).join("default.nix"), &path).unwrap();
+
+    (Package::from_file(&path, &[], &Exclusions::default()).packages.pop().unwrap(), dir)
+}
+
+/// Check `package` with the built-in updaters and land the edits, returning what its file then says
+fn update(package: &mut Package) -> String {
+    let updaters = Updaters::new(&UpdateOptions::default()).unwrap();
+    let reporter = Reporter::detect(1);
+
+    updaters.update(package, &reporter.add(), None).unwrap();
+
+    assert!(
+        package.result.status.contains(&UpdateStatus::Updated),
+        "{} wasn't updated: {:?}",
+        package.name,
+        package.result.message
+    );
+
+    package.transaction.commit(|| Ok(())).unwrap();
+
+    fs::read_to_string(&package.path).unwrap()
+}
+
+#[test]
+fn pypi_sdist() {
+    let harness = harness();
+
+    harness.serve_json(
+        "https://pypi.org/pypi/demo-tool/json",
+        &json!({
+            "info": {
+                "version": "1.1.0",
+                "home_page": null,
+                "project_urls": { "Source": "https://github.com/example/demo-tool" },
+            },
+            "releases": {
+                "1.1.0": [
+                    {
+                        "filename": "demo_tool-1.1.0-py3-none-any.whl",
+                        "url": "https://files.pythonhosted.org/packages/py3/d/demo-tool/demo_tool-1.1.0-py3-none-any.whl",
+                        "packagetype": "bdist_wheel",
+                        "upload_time_iso_8601": "2026-03-01T10:00:00Z",
+                    },
+                    {
+                        "filename": "demo_tool-1.1.0.tar.gz",
+                        "url": "https://files.pythonhosted.org/packages/source/d/demo-tool/demo_tool-1.1.0.tar.gz",
+                        "packagetype": "sdist",
+                        "upload_time_iso_8601": "2026-03-01T10:01:00Z",
+                    },
+                ],
+            },
+        }),
+    );
+
+    let (mut package, _dir) = package("demo-tool");
+    let content = update(&mut package);
+
+    assert!(content.contains(r#"version = "1.1.0";"#), "{content}");
+    assert!(content.contains(r#"hash = "sha256-demo_tool-1.1.0.tar.gz";"#), "{content}");
+    assert_eq!(package.result.new_version.as_deref(), Some("1.1.0"));
+}
+
+#[test]
+fn crates_io_skips_yanked_release() {
+    let harness = harness();
+
+    harness.serve_json(
+        "https://crates.io/api/v1/crates/demo-crate",
+        &json!({
+            "crate": {
+                "homepage": null,
+                "repository": "https://github.com/example/demo-crate",
+                "updated_at": "2026-04-02T08:00:00Z",
+            },
+        }),
+    );
+    harness.serve_json(
+        "https://crates.io/api/v1/crates/demo-crate/versions",
+        &json!({
+            "versions": [
+                { "num": "0.4.0", "yanked": true, "created_at": "2026-04-02T08:00:00Z" },
+                { "num": "0.3.1", "yanked": false, "created_at": "2026-03-20T08:00:00Z" },
+                { "num": "0.3.0", "yanked": false, "created_at": "2026-02-01T08:00:00Z" },
+            ],
+        }),
+    );
+
+    let (mut package, _dir) = package("demo-crate");
+    let content = update(&mut package);
+
+    assert!(content.contains(r#"version = "0.3.1";"#), "{content}");
+    assert!(content.contains(r#"hash = "sha256-unpacked-download";"#), "{content}");
+    assert!(content.contains(r#"cargoHash = "sha256-vendored";"#), "{content}");
+    assert_eq!(package.result.message.as_deref(), Some("Skipped yanked 0.4.0"));
+}
+
+#[test]
+fn npm_dist_tag() {
+    let harness = harness();
+    let commit = "2222222222222222222222222222222222222222";
+
+    harness.serve_json("https://registry.npmjs.org/demo-cli/latest", &json!({ "version": "2.1.0", "gitHead": commit }));
+    harness.serve(
+        &format!("https://raw.githubusercontent.com/example/demo-cli/{commit}/package-lock.json"),
+        ResponseTemplate::new(200).set_body_string(r#"{ "name": "demo-cli", "version": "2.1.0", "lockfileVersion": 3 }"#),
+    );
+
+    let (mut package, _dir) = package("demo-cli");
+    package.settings.dist_tag = Some("latest".to_string());
+
+    let content = update(&mut package);
+
+    assert!(content.contains(r#"version = "2.1.0";"#), "{content}");
+    assert!(content.contains(&format!(r#"rev = "{commit}";"#)), "{content}");
+    assert!(content.contains(&format!(r#"hash = "sha256-unpacked-{commit}.tar.gz";"#)), "{content}");
+    assert!(content.contains(r#"npmDepsHash = "sha256-npm-deps";"#), "{content}");
+}
+
+#[test]
+fn github_release_assets() {
+    let harness = harness();
+    let release: Value = serde_json::from_str(&fs::read_to_string(fixtures().join("github").join("release.json")).unwrap()).unwrap();
+
+    harness.serve_json("https://api.github.com/repos/example/demo-bin/releases/latest", &release);
+    harness.serve(
+        "https://github.com/example/demo-bin/releases/download/v1.5.0/checksums.txt",
+        ResponseTemplate::new(200).set_body_string(format!("{}  demo-bin-x86_64-unknown-linux-gnu.tar.gz\n", "ab".repeat(32))),
+    );
+
+    let (mut package, _dir) = package("demo-bin");
+    package.settings.checksums = Some(true);
+
+    let content = update(&mut package);
+
+    assert!(content.contains(r#"version = "1.5.0";"#), "{content}");
+
+    // From the release's checksum file
+    assert!(content.contains(r#"hash = "sha256-q6urq6urq6urq6urq6urq6urq6urq6urq6urq6urq6s=";"#), "{content}");
+
+    // Not in it, so prefetched
+    assert!(content.contains(r#"hash = "sha256-demo-bin-aarch64-apple-darwin.tar.gz";"#), "{content}");
+}
+
+#[test]
+fn github_release_fallback_past_unhashable_release() {
+    let harness = harness();
+    let template: Value = serde_json::from_str(&fs::read_to_string(fixtures().join("github").join("release.json")).unwrap()).unwrap();
+
+    // The newest release lists its asset, but downloading it fails
+    let release = |tag: &str, asset: &str| {
+        let mut release = template.clone();
+
+        release["tag_name"] = json!(tag);
+        release["name"] = json!(tag);
+        release["assets"] = json!([template["assets"][0].clone()]);
+        release["assets"][0]["name"] = json!(asset);
+        release["assets"][0]["browser_download_url"] = json!(format!("https://github.com/example/demo-fallback/releases/download/{tag}/{asset}"));
+        release
+    };
+    let newest = release("v1.6.0", "demo-fallback-1.6.0-unavailable-x86_64-linux.tar.gz");
+    let previous = release("v1.5.0", "demo-fallback-1.5.0-x86_64-linux.tar.gz");
+
+    harness.serve_json("https://api.github.com/repos/example/demo-fallback/releases/latest", &newest);
+    harness.serve_json("https://api.github.com/repos/example/demo-fallback/releases", &json!([newest, previous]));
+
+    let (mut package, _dir) = package("demo-fallback");
+    package.settings.release_fallback = Some(true);
+
+    let content = update(&mut package);
+
+    assert!(content.contains(r#"version = "1.5.0";"#), "{content}");
+    assert!(content.contains(r#"filename = "demo-fallback-1.5.0-x86_64-linux.tar.gz";"#), "{content}");
+    assert!(content.contains(r#"hash = "sha256-demo-fallback-1.5.0-x86_64-linux.tar.gz";"#), "{content}");
+
+    let message = package.result.message.unwrap_or_default();
+    assert!(message.starts_with("Chose 1.5.0 over newer releases: v1.6.0: "), "{message}");
+}
+
+#[test]
+fn github_release_tag_pinned_rust_package() {
+    let harness = harness();
+    let mut release: Value = serde_json::from_str(&fs::read_to_string(fixtures().join("github").join("release.json")).unwrap()).unwrap();
+
+    release["tag_name"] = json!("v1.1.0");
+    release["name"] = json!("v1.1.0");
+    release["assets"] = json!([]);
+
+    harness.serve_json("https://api.github.com/repos/example/demo-tagged/releases/latest", &release);
+
+    let (mut package, _dir) = package("demo-tagged");
+    let content = update(&mut package);
+
+    assert!(content.contains(r#"version = "1.1.0";"#), "{content}");
+    assert!(content.contains(r#"hash = "sha256-unpacked-v1.1.0.tar.gz";"#), "{content}");
+
+    // Vendored from the new source rather than left at the old release's
+    assert!(content.contains(r#"cargoHash = "sha256-vendored";"#), "{content}");
+}